@@ -0,0 +1,226 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use crypto::{digest::Digest, sha2::Sha256};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use crate::connection::{Connection, ExecutionResult};
+use crate::redact::Redactor;
+
+lazy_static! {
+    /// Audit file, if enabled when the provider is configured
+    static ref AUDIT_FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+tokio::task_local! {
+    /// Resource and phase the commands are executed for
+    static CONTEXT: (String, String);
+}
+
+/// Entry of the audit log, written as a single JSON line for each executed command
+#[derive(Debug, Serialize)]
+struct AuditEntry<'a> {
+    /// Start of the execution, in seconds since epoch
+    timestamp: f64,
+    /// Type of the resource or data source executing the command
+    ///
+    /// Terraform does not give the address of the resources to the providers.
+    resource: &'a str,
+    phase: &'a str,
+    target: String,
+    /// The command itself is not logged as it might contain secrets
+    command_sha256: String,
+    exit_code: Option<i32>,
+    duration_ms: u64,
+    error: Option<String>,
+}
+
+/// Enable the audit log, appending to the file at `path`, or disable it
+pub async fn configure(path: Option<&Path>) -> std::io::Result<()> {
+    let file = match path {
+        Some(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?,
+        ),
+        None => None,
+    };
+    *AUDIT_FILE.lock().await = file;
+    Ok(())
+}
+
+/// Variables describing the provider and the resource, given to every command before its own environment
+///
+/// Terraform does not give the address of the resources to the providers, so only their type is given.
+fn provenance_env(resource: &str) -> Vec<(String, String)> {
+    let mut env = vec![
+        (
+            "GENERIC_PROVIDER_VERSION".to_owned(),
+            env!("CARGO_PKG_VERSION").to_owned(),
+        ),
+        ("GENERIC_PROVIDER_RESOURCE".to_owned(), resource.to_owned()),
+    ];
+    if let Ok(workspace) = std::env::var("TF_WORKSPACE") {
+        env.push(("TF_WORKSPACE".to_owned(), workspace));
+    }
+    env
+}
+
+/// Execute a command over the connection on behalf of a resource
+///
+/// The command is recorded in the audit log by the connection itself.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute<'a, 'b, T, I, K, V>(
+    connect: &T,
+    config: &T::Config<'a>,
+    cmd: &str,
+    dir: &str,
+    env: I,
+    resource: &str,
+    phase: &str,
+) -> Result<ExecutionResult>
+where
+    T: Connection,
+    'a: 'b,
+    I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+    I::IntoIter: Send + Sync + 'b,
+    K: AsRef<str> + Send + Sync + 'b,
+    V: AsRef<str> + Send + Sync + 'b,
+{
+    execute_with_args(connect, config, cmd, dir, env, &[], resource, phase).await
+}
+
+/// Execute a command with positional parameters over the connection on behalf of a resource
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_with_args<'a, 'b, T, I, K, V>(
+    connect: &T,
+    config: &T::Config<'a>,
+    cmd: &str,
+    dir: &str,
+    env: I,
+    args: &[&str],
+    resource: &str,
+    phase: &str,
+) -> Result<ExecutionResult>
+where
+    T: Connection,
+    'a: 'b,
+    I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+    I::IntoIter: Send + Sync + 'b,
+    K: AsRef<str> + Send + Sync + 'b,
+    V: AsRef<str> + Send + Sync + 'b,
+{
+    let mut full_env = provenance_env(resource);
+    full_env.extend(
+        env.into_iter()
+            .map(|(k, v)| (k.as_ref().to_owned(), v.as_ref().to_owned())),
+    );
+    CONTEXT
+        .scope(
+            (resource.to_owned(), phase.to_owned()),
+            connect.execute_with_args(config, cmd, dir, full_env.iter().map(|(k, v)| (k, v)), args),
+        )
+        .await
+}
+
+/// Execute a command over the connection on behalf of a resource, streaming its stdout to `stdout`
+pub async fn execute_piped<T: Connection>(
+    connect: &T,
+    config: &T::Config<'_>,
+    cmd: &str,
+    resource: &str,
+    phase: &str,
+    stdout: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<ExecutionResult> {
+    let env = provenance_env(resource);
+    CONTEXT
+        .scope(
+            (resource.to_owned(), phase.to_owned()),
+            connect.execute_piped(config, cmd, "", env.iter().map(|(k, v)| (k, v)), stdout),
+        )
+        .await
+}
+
+/// Run the execution of a command, and record it in the audit log, if enabled
+///
+/// Every [`Connection`] records its executions through this function,
+/// so commands not executed on behalf of a resource are recorded as well.
+pub async fn record<T, F>(
+    config: &T::Config<'_>,
+    cmd: &str,
+    execution: F,
+) -> Result<ExecutionResult>
+where
+    T: Connection,
+    F: Future<Output = Result<ExecutionResult>>,
+{
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |time| time.as_secs_f64());
+    let start = Instant::now();
+    let result = execution.await;
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    let mut guard = AUDIT_FILE.lock().await;
+    let Some(file) = guard.as_mut() else {
+        return result;
+    };
+    let mut hasher = Sha256::new();
+    hasher.input_str(cmd);
+    let (exit_code, error) = match &result {
+        Ok(res) => (Some(res.status), None),
+        Err(err) => {
+            let redactor = Redactor::for_connection::<T>(config);
+            (None, Some(redactor.redact(&err.to_string()).into_owned()))
+        }
+    };
+    let (resource, phase) = CONTEXT.try_with(Clone::clone).unwrap_or_default();
+    let entry = AuditEntry {
+        timestamp,
+        resource: &resource,
+        phase: &phase,
+        target: T::target(config),
+        command_sha256: hasher.result_str(),
+        exit_code,
+        duration_ms,
+        error,
+    };
+    match serde_json::to_string(&entry) {
+        // A single write per entry keeps the lines whole
+        Ok(line) => {
+            let written = match file.write_all((line + "\n").as_bytes()).await {
+                Ok(()) => file.flush().await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = written {
+                log::error!("Could not write audit entry: {err}");
+            }
+        }
+        Err(err) => log::error!("Could not serialize audit entry: {err}"),
+    }
+    result
+}
@@ -0,0 +1,200 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
+};
+use tf_provider::value::{self, Value, ValueBool, ValueEmpty, ValueNumber, ValueString};
+use tf_provider::{map, AttributePath, DataSource, Diagnostics};
+
+use crate::audit;
+use crate::connection::Connection;
+
+#[derive(Debug, Default)]
+pub struct GenericCheckDataSource<T: Connection> {
+    pub(super) connect: T,
+}
+
+impl<T: Connection> GenericCheckDataSource<T> {
+    pub fn new(connect: T) -> Self {
+        Self { connect }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct DataSourceState<'a, T>
+where
+    T: Connection,
+{
+    #[serde(with = "value::serde_as_vec")]
+    pub connect: Value<T::Config<'a>>,
+    pub run_command: ValueBool,
+    pub faillible: ValueBool,
+    pub reachable: ValueBool,
+    pub latency_ms: ValueNumber,
+    #[serde(borrow = "'a")]
+    pub auth_method: ValueString<'a>,
+    pub error: ValueString<'a>,
+}
+
+#[async_trait]
+impl<T> DataSource for GenericCheckDataSource<T>
+where
+    T: Connection,
+    T: Debug,
+    T: Clone,
+{
+    type State<'a> = DataSourceState<'a, T>;
+    type ProviderMetaState<'a> = ValueEmpty;
+
+    fn schema(&self, _diags: &mut Diagnostics) -> Option<Schema> {
+        Some(Schema {
+            version: 1,
+            block: Block {
+                version: 1,
+                attributes: map! {
+                    "run_command" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Whether `true` should be executed once connected to check commands can be run"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "faillible" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("When enabled, an unreachable target is reported in `reachable` instead of being an error"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "reachable" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Whether the connection could be established"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "latency_ms" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Time taken to establish the connection (and run the command) in milliseconds"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "auth_method" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Authentication method used to establish the connection"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "error" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Error message if the connection could not be established"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                },
+                blocks: map! {
+                    "connect" => NestedBlock::Optional(Block {
+                        attributes: T::schema(),
+                        description: Description::plain("Connection configuration"),
+                        ..Default::default()
+                    }),
+                },
+                description: Description::plain("Checks that a connection can be established"),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn validate<'a>(&self, diags: &mut Diagnostics, config: Self::State<'a>) -> Option<()> {
+        if let Value::Value(connect) = &config.connect {
+            _ = self
+                .connect
+                .validate(diags, AttributePath::new("connect").index(0), connect)
+                .await;
+        }
+
+        if diags.errors.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn read<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        config: Self::State<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<Self::State<'a>> {
+        let default_connect_config = Default::default();
+        let connect_config = config.connect.as_ref().unwrap_or(&default_connect_config);
+
+        let start = Instant::now();
+        let mut result = self.connect.connect(connect_config).await;
+        if result.is_ok() && config.run_command.unwrap_or(false) {
+            let env: [(&str, &str); 0] = [];
+            result = match audit::execute(
+                &self.connect,
+                connect_config,
+                "true",
+                "",
+                env.iter().map(|(k, v)| (k, v)),
+                &format!("{}_check", T::NAME),
+                "read",
+            )
+            .await
+            {
+                Ok(res) if res.status == 0 => result,
+                Ok(res) => Err(anyhow::anyhow!(
+                    "`true` failed with status code {}: {}",
+                    res.status,
+                    res.stderr
+                )),
+                Err(err) => Err(err),
+            };
+        }
+        let latency = start.elapsed().as_millis() as i64;
+
+        let mut output = config;
+        output.latency_ms = Value::Value(latency);
+        match result {
+            Ok(auth_method) => {
+                output.reachable = Value::Value(true);
+                output.auth_method = Value::Value(auth_method.into());
+                output.error = Value::Null;
+            }
+            Err(err) => {
+                if !output.faillible.unwrap_or(false) {
+                    diags.error(
+                        "Target is unreachable",
+                        format!("Could not establish the connection: {err}"),
+                        AttributePath::new("connect").index(0),
+                    );
+                    return None;
+                }
+                output.reachable = Value::Value(false);
+                output.auth_method = Value::Null;
+                output.error = Value::Value(err.to_string().into());
+            }
+        }
+
+        Some(output)
+    }
+}
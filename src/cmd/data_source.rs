@@ -14,27 +14,57 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
+use crypto::{digest::Digest, sha2::Sha256};
+use lazy_static::lazy_static;
+use tokio::sync::Mutex;
 
-use tf_provider::value::{Value, ValueEmpty};
+use tf_provider::value::{Value, ValueEmpty, ValueMap, ValueNumber, ValueString};
 use tf_provider::{schema::Schema, AttributePath, DataSource, Diagnostics};
 
 use crate::connection::Connection;
+use crate::options::SharedOptions;
 use crate::utils::WithSchema;
 
 use super::prepare_envs;
 use super::state::DataSourceState;
 
+/// Result of a read shared by the data sources with the same `cache_key`
+#[derive(Debug)]
+struct CachedRead {
+    time: Instant,
+    outputs: ValueMap<'static, ValueString<'static>>,
+    stderr: ValueMap<'static, ValueString<'static>>,
+    exit_code: ValueMap<'static, ValueNumber>,
+}
+
+type CacheEntry = Arc<Mutex<Option<CachedRead>>>;
+
+/// Connection type, target, `cache_key` and digest of the read
+type CacheKey = (&'static str, String, String, String);
+
+lazy_static! {
+    /// Cached reads by connection type, target, `cache_key` and digest of the read
+    ///
+    /// The provider process only lives for a single Terraform operation, and so does the cache.
+    static ref CACHE: std::sync::Mutex<HashMap<CacheKey, CacheEntry>> = Default::default();
+}
+
 #[derive(Debug, Default)]
 pub struct GenericCmdDataSource<T: Connection> {
+    pub(super) options: SharedOptions,
     pub(super) connect: T,
 }
 
 impl<T: Connection> GenericCmdDataSource<T> {
-    pub fn new(connect: T) -> Self {
-        Self { connect }
+    pub fn new(options: SharedOptions, connect: T) -> Self {
+        Self { options, connect }
     }
 }
 
@@ -69,7 +99,10 @@ where
         config: Self::State<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<Self::State<'a>> {
-        let state_env = prepare_envs(&[(&config.inputs, "INPUT_")]);
+        let state_env = prepare_envs(
+            &[(&config.env, ""), (&config.inputs, "INPUT_")],
+            config.input_names.as_str() == "escape",
+        );
 
         let mut state = config.clone();
 
@@ -83,8 +116,96 @@ where
                 .collect(),
         );
 
-        state.read(diags, &self.connect, &state_env).await;
+        let timeout = match config.timeout {
+            Value::Value(timeout) => Some(Duration::from_secs(timeout as u64)),
+            _ => self.options.get().data_source_timeout,
+        };
+
+        let Value::Value(cache_key) = &config.cache_key else {
+            state.read(diags, &self.connect, &state_env, timeout).await;
+            return Some(state);
+        };
+
+        let connection_default = Default::default();
+        let connection = config.connect.as_ref().unwrap_or(&connection_default);
+        let entry = {
+            let mut cache = match CACHE.lock() {
+                Ok(cache) => cache,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            cache
+                .entry((
+                    T::NAME,
+                    T::target(connection),
+                    cache_key.to_string(),
+                    read_digest(&config),
+                ))
+                .or_default()
+                .clone()
+        };
+
+        // Concurrent data sources with the same key wait for the first read instead of executing it again
+        let mut cached = entry.lock().await;
+        let ttl = config.cache_ttl.as_ref_option().map(|&ttl| ttl as u64);
+        if let Some(read) = cached.as_ref() {
+            if ttl.is_none_or(|ttl| read.time.elapsed() < Duration::from_secs(ttl)) {
+                state.outputs = read.outputs.clone();
+                state.stderr = read.stderr.clone();
+                state.exit_code = read.exit_code.clone();
+                return Some(state);
+            }
+        }
+
+        let errors = diags.errors.len();
+        let time = Instant::now();
+        state.read(diags, &self.connect, &state_env, timeout).await;
+        // Failed reads are not cached so they are retried
+        *cached = (diags.errors.len() == errors).then(|| CachedRead {
+            time,
+            outputs: owned_map(&state.outputs, owned_string),
+            stderr: owned_map(&state.stderr, owned_string),
+            exit_code: owned_map(&state.exit_code, |&exit_code| exit_code),
+        });
 
         Some(state)
     }
 }
+
+/// Digest of everything the outputs depend on: inputs, environment, `read` blocks and connection
+///
+/// Data sources sharing a `cache_key` only share their outputs if they would execute the same commands.
+fn read_digest<T: Connection>(config: &DataSourceState<'_, T>) -> String {
+    let read = (
+        &config.env,
+        &config.inputs,
+        &config.sensitive_inputs,
+        &config.input_names,
+        &config.read,
+        &config.capture_stderr,
+        &config.connect,
+    );
+    let mut digest = Sha256::new();
+    digest.input_str(&serde_json::to_string(&read).unwrap_or_default());
+    digest.result_str()
+}
+
+/// Copy a map so it does not borrow from the request
+fn owned_map<V, W>(map: &ValueMap<'_, V>, owned: impl Fn(&V) -> W) -> ValueMap<'static, W> {
+    match map {
+        Value::Value(map) => Value::Value(
+            map.iter()
+                .map(|(name, value)| (Cow::Owned(name.to_string()), owned(value)))
+                .collect(),
+        ),
+        Value::Null => Value::Null,
+        Value::Unknown => Value::Unknown,
+    }
+}
+
+fn owned_string(value: &ValueString<'_>) -> ValueString<'static> {
+    match value {
+        Value::Value(value) => Value::Value(Cow::Owned(value.to_string())),
+        Value::Null => Value::Null,
+        Value::Unknown => Value::Unknown,
+    }
+}
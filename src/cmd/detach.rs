@@ -0,0 +1,146 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+
+use crate::audit;
+use crate::connection::{with_positional_args, Connection, ExecutionResult};
+use crate::utils::WithCmd;
+
+use super::job;
+
+/// Launch the job in the background with `nohup`, and record its pid
+fn launcher() -> String {
+    format!(
+        r#"nohup "$shell" -c '{}' "$shell" "$state" < /dev/null > /dev/null 2>&1 &
+echo $! > "$state/pid"
+"#,
+        job::RUN
+    )
+}
+
+/// Exit with 0 if the job completed, 1 if it is still running, and 2 if it vanished
+const CHECK: &str = r#"[ -e "$state/status" ] && exit 0
+kill -0 "$(cat "$state/pid" 2>/dev/null)" 2>/dev/null && exit 1
+[ -e "$state/status" ] && exit 0
+exit 2
+"#;
+
+/// Number of consecutive polls that can fail before giving up
+const POLL_ATTEMPTS: usize = 5;
+
+/// Execute a command in the background, and poll for its completion
+///
+/// The connection is only used to launch the command and to check periodically whether it completed,
+/// so long commands do not depend on a long-lived session.
+pub(super) async fn execute<'a, 'b, T, C, I, K, V>(
+    connect: &T,
+    config: &T::Config<'a>,
+    block: &C,
+    env: I,
+    resource: &str,
+    phase: &str,
+) -> Result<ExecutionResult>
+where
+    T: Connection,
+    C: WithCmd + Sync,
+    'a: 'b,
+    I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+    I::IntoIter: Send + Sync + 'b,
+    K: AsRef<str> + Send + Sync + 'b,
+    V: AsRef<str> + Send + Sync + 'b,
+{
+    let Some((interval, timeout)) = block.detach() else {
+        return Err(anyhow!("The command is not detached"));
+    };
+    let id = job::new_id();
+    let cmd = block.prioritized_cmd();
+    let args = block.args();
+    let cmd = with_positional_args(&cmd, &args);
+    let launch_env = env
+        .into_iter()
+        .map(|(k, v)| (k.as_ref(), v.as_ref()))
+        .chain([("TF_JOB_ID", id.as_str()), ("TF_JOB_CMD", cmd.as_ref())])
+        .collect::<Vec<_>>();
+    let launched = audit::execute(
+        connect,
+        config,
+        &job::launch(&launcher()),
+        block.dir(),
+        launch_env.iter().map(|(k, v)| (k, v)),
+        resource,
+        phase,
+    )
+    .await?;
+    if launched.status != 0 {
+        return Ok(launched);
+    }
+
+    let poll_env = [("TF_JOB_ID", id.as_str())];
+    let check = job::script(CHECK);
+    let start = Instant::now();
+    let mut failures = 0;
+    loop {
+        tokio::time::sleep(interval).await;
+        let check = audit::execute(
+            connect,
+            config,
+            &check,
+            "",
+            poll_env.iter().map(|(k, v)| (k, v)),
+            resource,
+            phase,
+        )
+        .await;
+        match check {
+            Ok(check) if check.status == 0 => break,
+            Ok(check) if check.status == 1 => failures = 0,
+            Ok(check) => {
+                return Err(anyhow!(
+                    "Detached command {id} stopped without an exit code (status code {}): {}",
+                    check.status,
+                    check.stderr
+                ))
+            }
+            Err(err) if failures + 1 < POLL_ATTEMPTS => {
+                failures += 1;
+                log::warn!("Could not poll detached command {id}: {err}");
+            }
+            Err(err) => return Err(err),
+        }
+        if let Some(timeout) = timeout {
+            if start.elapsed() >= timeout {
+                return Err(anyhow!(
+                    "Detached command {id} did not complete within {}s: it is left running, with its output in the `tf-generic/{id}` directory of `$XDG_STATE_HOME` (default: `~/.local/state`)",
+                    timeout.as_secs()
+                ));
+            }
+        }
+    }
+
+    audit::execute(
+        connect,
+        config,
+        &job::script(job::COLLECT),
+        "",
+        poll_env.iter().map(|(k, v)| (k, v)),
+        resource,
+        phase,
+    )
+    .await
+}
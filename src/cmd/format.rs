@@ -0,0 +1,129 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde_json::{Map, Value};
+
+use crate::parse::{ini, unquote, yaml};
+
+/// Formats of the output of a read command, converted to JSON
+pub(super) const FORMATS: [&str; 4] = ["json", "yaml", "kv", "ini"];
+
+/// Parse the output of a read command in the given format, and write it as JSON
+pub(super) fn parse(format: &str, output: &str) -> Result<String, String> {
+    let value = match format {
+        "json" => serde_json::from_str(output)
+            .map_err(|err| format!("The output is not valid JSON: {err}"))?,
+        "yaml" => yaml::parse(output)?,
+        "kv" => parse_kv(output)?,
+        "ini" => parse_ini(output)?,
+        _ => return Err(format!("Unknown format `{format}`.")),
+    };
+    Ok(value.to_string())
+}
+
+/// Parse `key=value` lines, like the output of `systemctl show` or `/etc/os-release`
+///
+/// Empty lines and lines starting with `#` are ignored, and quoted values are unquoted.
+fn parse_kv(output: &str) -> Result<Value, String> {
+    let mut map = Map::new();
+    for (i, line) in output.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("Line {}: expected `key=value`, but got `{line}`.", i + 1))?;
+        map.insert(
+            key.trim().to_owned(),
+            Value::String(unquote(value.trim()).into_owned()),
+        );
+    }
+    Ok(Value::Object(map))
+}
+
+/// Parse an INI document: keys before any `[section]` are at the top level, and sections are nested objects
+fn parse_ini(output: &str) -> Result<Value, String> {
+    let mut root = Map::new();
+    let mut section = None;
+    for line in ini::lines(output) {
+        let (number, line) = line?;
+        let line = match line {
+            ini::Line::Section(name) => {
+                root.entry(name)
+                    .or_insert_with(|| Value::Object(Map::new()));
+                section = Some(name);
+                continue;
+            }
+            ini::Line::Entry(line) => line,
+        };
+        let (key, value) = line
+            .split_once(['=', ':'])
+            .ok_or_else(|| format!("Line {number}: expected `key = value`, but got `{line}`."))?;
+        let map = match section {
+            Some(name) => match root.get_mut(name) {
+                Some(Value::Object(map)) => map,
+                _ => {
+                    return Err(format!(
+                        "Line {number}: `{name}` is both a key and a section."
+                    ))
+                }
+            },
+            None => &mut root,
+        };
+        map.insert(
+            key.trim().to_owned(),
+            Value::String(unquote(value.trim()).into_owned()),
+        );
+    }
+    Ok(Value::Object(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn kv() {
+        let output = "# os-release\nNAME=\"Debian GNU/Linux\"\nVERSION_ID='12'\n\nID=debian\n";
+        assert_eq!(
+            parse("kv", output).unwrap(),
+            r#"{"ID":"debian","NAME":"Debian GNU/Linux","VERSION_ID":"12"}"#
+        );
+        assert!(parse("kv", "no separator").is_err());
+    }
+
+    #[test]
+    fn ini() {
+        let output = "top = 1\n[server]\nhost: example.com\nport = \"22\"\n";
+        assert_eq!(
+            parse("ini", output).unwrap(),
+            r#"{"server":{"host":"example.com","port":"22"},"top":"1"}"#
+        );
+        assert!(parse("ini", "a = 1\n[a]\nb = 2\n").is_err());
+    }
+
+    #[test]
+    fn yaml_and_json() {
+        assert_eq!(
+            parse("yaml", "a: [1, 'b, c']\n").unwrap(),
+            r#"{"a":[1,"b, c"]}"#
+        );
+        assert_eq!(parse("json", r#"{"a": 1}"#).unwrap(), r#"{"a":1}"#);
+        assert!(parse("json", "{").is_err());
+        assert!(parse("xml", "").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+/// Directory of a job, where its command, output and exit code are kept until they are collected
+///
+/// It lives in the state directory of the user: temporary directories can be private to a session,
+/// or removed when the command that created them completes.
+const STATE: &str = r#"state="${XDG_STATE_HOME:-$HOME/.local/state}/tf-generic/$TF_JOB_ID"
+"#;
+
+/// Create the directory of the job and write its command, before `launcher` starts it
+const PREPARE: &str = r#"umask 077
+mkdir -p "${state%/*}" && mkdir "$state" || exit
+printf '%s\n' "$TF_JOB_CMD" > "$state/cmd" || exit
+unset TF_JOB_CMD
+shell="${BASH:-/bin/sh}"
+"#;
+
+/// Execute the command of the job, and record its output and exit code
+///
+/// It is run with `"$shell" -c`, with the shell as `$0` and the directory of the job as `$1`.
+pub(super) const RUN: &str = r#""$0" "$1/cmd" > "$1/stdout" 2> "$1/stderr"; echo $? > "$1/status.tmp"; mv "$1/status.tmp" "$1/status""#;
+
+/// Forward the output and exit code of the completed job, and remove its directory
+pub(super) const COLLECT: &str = r#"cat "$state/stdout"
+cat "$state/stderr" >&2
+status=$(cat "$state/status")
+rm -rf "$state"
+exit "$status"
+"#;
+
+/// Random identifier of a job, also valid as a systemd unit name
+pub(super) fn new_id() -> String {
+    format!(
+        "tf-generic-{}",
+        thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(12)
+            .map(char::from)
+            .collect::<String>()
+            .to_lowercase()
+    )
+}
+
+/// Script starting the job with `launcher`, given `TF_JOB_ID` and `TF_JOB_CMD` in the environment
+pub(super) fn launch(launcher: &str) -> String {
+    format!("{STATE}{PREPARE}{launcher}")
+}
+
+/// Script operating on the job, given `TF_JOB_ID` in the environment
+pub(super) fn script(body: &str) -> String {
+    format!("{STATE}{body}")
+}
@@ -0,0 +1,107 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use tf_provider::value::Value;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::connection::{Connection, ExecutionResult};
+
+use super::state::ResourceState;
+
+/// Size of a log file above which it is rotated, when `log_file_max_size` is not set
+const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+lazy_static! {
+    /// Resources can share a log file, so their entries must not be interleaved
+    static ref LOG_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Log file of a resource, where the full output of its commands is appended
+#[derive(Debug, Default, Clone)]
+pub(super) struct LogFile {
+    path: Option<String>,
+    max_size: u64,
+    target: String,
+}
+
+impl<'a, T: Connection> ResourceState<'a, T> {
+    pub(super) fn log_file(&self) -> LogFile {
+        let connection_default = Default::default();
+        let connection = self.connect.as_ref().unwrap_or(&connection_default);
+        LogFile {
+            path: self.log_file.as_deref_option().map(str::to_owned),
+            max_size: match self.log_file_max_size {
+                Value::Value(max_size) if max_size > 0 => max_size as u64,
+                _ => DEFAULT_MAX_SIZE,
+            },
+            target: T::target(connection),
+        }
+    }
+}
+
+impl LogFile {
+    /// Append the output of a command, rotating the file to `<path>.1` once it exceeds its maximum size
+    ///
+    /// Errors are only logged: the log file must not make the command fail.
+    pub(super) async fn append(&self, phase: &str, res: &ExecutionResult) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let _guard = LOG_LOCK.lock().await;
+        if let Err(err) = self.write_entry(path, phase, res).await {
+            log::warn!("Could not write to log file {path}: {err}");
+        }
+    }
+
+    async fn write_entry(
+        &self,
+        path: &str,
+        phase: &str,
+        res: &ExecutionResult,
+    ) -> std::io::Result<()> {
+        if tokio::fs::metadata(path)
+            .await
+            .is_ok_and(|metadata| metadata.len() >= self.max_size)
+        {
+            tokio::fs::rename(path, format!("{path}.1")).await?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let mut entry = format!(
+            "=== {timestamp} {phase} on {}: exit code {}\n",
+            self.target, res.status
+        );
+        for (name, output) in [("stdout", &res.stdout), ("stderr", &res.stderr)] {
+            entry += &format!("--- {name}\n{output}");
+            if !output.is_empty() && !output.ends_with('\n') {
+                entry.push('\n');
+            }
+        }
+        file.write_all(entry.as_bytes()).await?;
+        file.flush().await
+    }
+}
@@ -16,24 +16,44 @@
 
 use std::borrow::Cow;
 
-use tf_provider::value::{ValueMap, ValueString};
+use anyhow::{anyhow, Result};
+use tf_provider::value::{Value, ValueList, ValueMap, ValueSet, ValueString};
+
+use crate::audit;
+use crate::connection::ssh::{ConnectionSsh, Tunnel};
+use crate::connection::{with_positional_args, Connection, ExecutionResult};
+use crate::redact::Redactor;
+use crate::utils::WithCmd;
 
 mod data_source;
+mod detach;
+mod format;
+mod job;
+mod log_file;
 mod normalize;
 mod read;
 mod resource;
 mod state;
+mod transform;
+mod transient;
 mod validate;
 
 pub use data_source::GenericCmdDataSource;
 pub use resource::GenericCmdResource;
+pub(crate) use state::StateTunnel;
 
 fn prepare_envs<'a>(
     envs: &[(&'a ValueMap<'a, ValueString<'a>>, &'a str)],
+    escape: bool,
 ) -> Vec<(Cow<'a, str>, Cow<'a, str>)> {
     envs.iter()
         .flat_map(|(env, prefix)| {
-            env.iter().flatten().filter_map(|(k, v)| {
+            env.iter().flatten().filter_map(move |(k, v)| {
+                let k = if escape {
+                    escape_env_name(k)
+                } else {
+                    k.clone()
+                };
                 Some((
                     Cow::Owned(format!("{}{}", *prefix, k)),
                     Cow::Borrowed(v.as_deref_option()?),
@@ -43,6 +63,40 @@ fn prepare_envs<'a>(
         .collect()
 }
 
+/// Check a name can be used as an environment variable name in any shell
+fn is_valid_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Turn a name into a valid environment variable name
+///
+/// Invalid characters are replaced with `_`, and a leading digit is prefixed with `_`.
+fn escape_env_name(name: &str) -> Cow<'_, str> {
+    if is_valid_env_name(name) {
+        return Cow::Borrowed(name);
+    }
+    let mut escaped = String::with_capacity(name.len() + 1);
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        escaped.push('_');
+    }
+    escaped.extend(
+        name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }),
+    );
+    Cow::Owned(escaped)
+}
+
+/// Detach the environment from the state it was built from
+fn owned_envs<'a>(envs: Vec<(Cow<'_, str>, Cow<'_, str>)>) -> Vec<(Cow<'a, str>, Cow<'a, str>)> {
+    envs.into_iter()
+        .map(|(k, v)| (Cow::Owned(k.into_owned()), Cow::Owned(v.into_owned())))
+        .collect()
+}
+
 fn with_env<'a>(
     base_env: &'a [(Cow<'a, str>, Cow<'a, str>)],
     extra_env: &'a ValueMap<'a, ValueString<'a>>,
@@ -54,3 +108,140 @@ fn with_env<'a>(
             .filter_map(|(k, v)| Some((k, v.as_ref_option()?))),
     )
 }
+
+/// Execute a command block, with its tunnels opened for the duration of the command
+async fn execute_block<'a, 'b, T, C, I, K, V>(
+    connect: &T,
+    config: &T::Config<'a>,
+    block: &C,
+    env: I,
+    phase: &str,
+) -> Result<ExecutionResult>
+where
+    T: Connection,
+    C: WithCmd + Sync,
+    'a: 'b,
+    I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+    I::IntoIter: Send + Sync + 'b,
+    K: AsRef<str> + Send + Sync + 'b,
+    V: AsRef<str> + Send + Sync + 'b,
+{
+    let _tunnels = open_tunnels(block.tunnels()).await?;
+    let cmd = block.prioritized_cmd();
+    let args = block.args();
+    let resource = format!("{}_cmd", T::NAME);
+    if block.detach().is_some() {
+        detach::execute(connect, config, block, env, &resource, phase).await
+    } else if block.transient_unit() {
+        // The command is run by a script of the provider, so it gets its arguments from `set --`
+        let cmd = with_positional_args(&cmd, &args);
+        transient::execute(connect, config, &cmd, block.dir(), env, &resource, phase).await
+    } else {
+        audit::execute_with_args(
+            connect,
+            config,
+            &cmd,
+            block.dir(),
+            env,
+            &args,
+            &resource,
+            phase,
+        )
+        .await
+    }
+}
+
+/// Tunnels of a command, closed when dropped
+struct Tunnels {
+    // Tunnels are closed before the SSH connections they go through
+    _tunnels: Vec<Tunnel>,
+    _ssh: ConnectionSsh,
+}
+
+async fn open_tunnels(tunnels: &ValueList<Value<StateTunnel<'_>>>) -> Result<Tunnels> {
+    let ssh = ConnectionSsh::default();
+    let mut opened = Vec::new();
+    for tunnel in tunnels.iter().flatten().flatten() {
+        let Value::Value(connect) = &tunnel.connect else {
+            return Err(anyhow!("Tunnel has no `connect` block"));
+        };
+        let local_port = tunnel.local_port.unwrap_or_default() as u16;
+        let remote_port = tunnel.remote_port.unwrap_or_default() as u16;
+        opened.push(
+            ssh.open_tunnel(
+                connect,
+                local_port,
+                tunnel.remote_host.as_str(),
+                remote_port,
+            )
+            .await?,
+        );
+    }
+    Ok(Tunnels {
+        _tunnels: opened,
+        _ssh: ssh,
+    })
+}
+
+/// Number of lines of stdout/stderr kept in failure diagnostics
+const TAIL_LINES: usize = 20;
+
+/// Variables whose name contains one of these words are considered secret
+const SENSITIVE_NAMES: [&str; 4] = ["PASSWORD", "SECRET", "TOKEN", "KEY"];
+
+/// Keep only the last lines of an output
+fn tail(output: &str) -> &str {
+    let output = output.trim_end();
+    match output.rmatch_indices('\n').nth(TAIL_LINES - 1) {
+        Some((i, _)) => &output[i + 1..],
+        None => output,
+    }
+}
+
+/// Build the redactor of a command
+///
+/// Secrets are the connection secrets, the inputs listed in `sensitive_inputs`,
+/// and the variables whose name looks sensitive.
+fn redactor<'a, T, K, V>(
+    connect: &T::Config<'a>,
+    sensitive_inputs: &ValueSet<ValueString<'a>>,
+    env: impl Iterator<Item = (K, V)>,
+) -> Redactor
+where
+    T: Connection,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut redactor = Redactor::for_connection::<T>(connect);
+    let sensitive_inputs = sensitive_inputs.as_ref_option();
+    for (k, v) in env {
+        let k = k.as_ref();
+        let input = k
+            .strip_prefix("INPUT_")
+            .or_else(|| k.strip_prefix("PREVIOUS_"));
+        let is_sensitive_input = input.is_some_and(|input| {
+            sensitive_inputs.is_some_and(|inputs| {
+                inputs
+                    .iter()
+                    .flatten()
+                    .any(|name| escape_env_name(name) == input)
+            })
+        });
+        let k = k.to_uppercase();
+        if is_sensitive_input || SENSITIVE_NAMES.iter().any(|name| k.contains(name)) {
+            redactor.add(v.as_ref());
+        }
+    }
+    redactor
+}
+
+/// Describe a failed command with everything needed to debug it
+fn failure_details(cmd: &str, dir: &str, target: &str, res: &ExecutionResult) -> String {
+    let dir = if dir.is_empty() { "(default)" } else { dir };
+    format!(
+        "Command: {cmd}\nWorking directory: {dir}\nTarget: {target}\nExit code: {}\nStdout (last {TAIL_LINES} lines):\n{}\nStderr (last {TAIL_LINES} lines):\n{}",
+        res.status,
+        tail(&res.stdout),
+        tail(&res.stderr),
+    )
+}
@@ -18,7 +18,7 @@ use tf_provider::{value::Value, Diagnostics};
 
 use crate::{connection::Connection, utils::WithNormalize};
 
-use super::state::ResourceState;
+use super::state::{ResourceState, StateCmd, StateRead};
 
 impl<'a, T: Connection> WithNormalize for ResourceState<'a, T> {
     fn normalize(&mut self, _diags: &mut Diagnostics) {
@@ -39,3 +39,35 @@ impl<'a, T: Connection> WithNormalize for ResourceState<'a, T> {
         }
     }
 }
+
+impl<'a> WithNormalize for StateCmd<'a> {
+    fn normalize(&mut self, _diags: &mut Diagnostics) {
+        if self.dir.is_null() {
+            self.dir = Value::Value(Default::default());
+        }
+        // Null variables are not exported, so they are equivalent to missing ones
+        match &mut self.env {
+            Value::Value(env) => env.retain(|_, value| !value.is_null()),
+            Value::Null => self.env = Value::Value(Default::default()),
+            Value::Unknown => (),
+        }
+    }
+}
+
+impl<'a> WithNormalize for StateRead<'a> {
+    fn normalize(&mut self, diags: &mut Diagnostics) {
+        self.cmd.normalize(diags);
+        if self.faillible.is_null() {
+            self.faillible = Value::Value(false);
+        }
+        if self.strip_trailing_newline.is_null() {
+            self.strip_trailing_newline = Value::Value(true);
+        }
+        if self.pattern.is_null() {
+            self.pattern = Value::Value(Default::default());
+        }
+        if self.trim.is_null() {
+            self.trim = Value::Value(false);
+        }
+    }
+}
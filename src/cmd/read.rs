@@ -15,19 +15,28 @@
 // limitations under the License.
 
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
 
+use anyhow::{anyhow, Result};
 use futures::{stream, StreamExt};
-use tf_provider::value::{Value, ValueMap, ValueNumber, ValueString};
+use regex::Regex;
+use tf_provider::value::{Value, ValueList, ValueMap, ValueNumber, ValueSet, ValueString};
 use tf_provider::{AttributePath, Diagnostics};
 
 use crate::{
-    connection::Connection,
-    utils::{WithEnv, WithRead},
+    connection::{shell_quote, Connection, ExecutionResult, ShellKind},
+    file, limits,
+    utils::{WithCmd, WithEnv, WithRead},
 };
 
 use super::{
-    state::{DataSourceState, ResourceState},
-    with_env,
+    execute_block, failure_details, format,
+    log_file::LogFile,
+    redactor,
+    state::{DataSourceState, ResourceState, StateTunnel},
+    transform, with_env,
 };
 
 impl<'a, T: Connection> ResourceState<'a, T> {
@@ -38,18 +47,101 @@ impl<'a, T: Connection> ResourceState<'a, T> {
         env: &[(Cow<'b, str>, Cow<'b, str>)],
         faillibe: bool,
     ) -> Option<()> {
+        // Outputs listed by `describe` without a `read` block have no value
+        if let (Value::Value(_), Value::Value(outputs)) = (&self.describe, &mut self.state) {
+            for (name, value) in outputs.iter_mut() {
+                if value.is_unknown()
+                    && !self
+                        .read
+                        .as_ref_option()
+                        .is_some_and(|reads| reads.contains_key(name))
+                {
+                    *value = Value::Null;
+                }
+            }
+        }
+        let log_file = self.log_file();
         read_all(
             diags,
             connect,
             &self.connect,
+            &self.sensitive_inputs,
             &self.read,
             &mut self.state,
             env,
             faillibe,
             self.command_concurrency,
+            None,
+            None,
+            Some(&log_file),
         )
         .await
     }
+
+    /// Prior `state` values given to the `read` commands on refresh
+    pub fn read_state(&self) -> ValueMap<'a, ValueString<'a>> {
+        let Value::Value(state) = &self.state else {
+            return self.state.clone();
+        };
+        let contains = |names: &ValueSet<ValueString<'a>>, name: &str| {
+            names.iter().flatten().any(|other| other.as_str() == name)
+        };
+        Value::Value(
+            state
+                .iter()
+                .filter(|(name, _)| {
+                    (self.read_state_include.is_null() || contains(&self.read_state_include, name))
+                        && !contains(&self.read_state_exclude, name)
+                })
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        )
+    }
+
+    /// Record the outputs watched by `repair` whose value changed since the previous read
+    ///
+    /// Drifted outputs are accumulated until the repair is applied.
+    pub fn detect_drift(&mut self, previous: Option<&BTreeMap<Cow<'a, str>, ValueString<'a>>>) {
+        let (Value::Value(repair), Value::Value(state), Some(previous)) =
+            (&mut self.repair, &self.state, previous)
+        else {
+            return;
+        };
+        let mut drifted = repair.drifted.as_ref_option().cloned().unwrap_or_default();
+        for name in repair.watch.iter().flatten() {
+            let name_str = name.as_str();
+            if let (Some(before), Some(after)) = (previous.get(name_str), state.get(name_str)) {
+                if before != after {
+                    drifted.insert(name.clone());
+                }
+            }
+        }
+        repair.drifted = if drifted.is_empty() {
+            Value::Null
+        } else {
+            Value::Value(drifted)
+        };
+    }
+
+    /// Add the outputs listed by `describe` that are missing from `state`
+    ///
+    /// They keep their previous value if they had one, and are unknown otherwise.
+    pub fn add_described(
+        &mut self,
+        names: Vec<String>,
+        previous: Option<&BTreeMap<Cow<'a, str>, ValueString<'a>>>,
+    ) {
+        let Value::Value(state) = &mut self.state else {
+            return;
+        };
+        for name in names {
+            let value = previous
+                .and_then(|previous| previous.get(name.as_str()))
+                .cloned()
+                .unwrap_or(Value::Unknown);
+            state.entry(Cow::Owned(name)).or_insert(value);
+        }
+    }
 }
 
 impl<'a, T: Connection> DataSourceState<'a, T> {
@@ -58,18 +150,38 @@ impl<'a, T: Connection> DataSourceState<'a, T> {
         diags: &mut Diagnostics,
         connect: &T,
         env: &[(Cow<'b, str>, Cow<'b, str>)],
+        timeout: Option<Duration>,
     ) -> Option<()> {
-        read_all(
+        let mut captured = self.capture_stderr.unwrap_or(false).then(BTreeMap::new);
+        let result = read_all(
             diags,
             connect,
             &self.connect,
+            &self.sensitive_inputs,
             &self.read,
             &mut self.outputs,
             env,
             false,
             self.command_concurrency,
+            timeout,
+            captured.as_mut(),
+            None,
         )
-        .await
+        .await;
+
+        (self.stderr, self.exit_code) = match captured {
+            Some(captured) => {
+                let mut stderr = BTreeMap::new();
+                let mut exit_code = BTreeMap::new();
+                for (name, res) in captured {
+                    stderr.insert(name.clone(), Value::Value(res.stderr.into()));
+                    exit_code.insert(name, Value::Value(res.status.into()));
+                }
+                (Value::Value(stderr), Value::Value(exit_code))
+            }
+            None => (Value::Null, Value::Null),
+        };
+        result
     }
 }
 
@@ -78,15 +190,19 @@ async fn read_all<'a, 'b, C, R>(
     diags: &mut Diagnostics,
     connect: &C,
     connect_config: &Value<C::Config<'a>>,
+    sensitive_inputs: &ValueSet<ValueString<'a>>,
     reads: &ValueMap<'a, Value<R>>,
     outputs: &mut ValueMap<'a, ValueString<'a>>,
     env: &[(Cow<'b, str>, Cow<'b, str>)],
     faillibe: bool,
     concurrency: ValueNumber,
+    timeout: Option<Duration>,
+    mut captured: Option<&mut BTreeMap<Cow<'a, str>, ExecutionResult>>,
+    log_file: Option<&LogFile>,
 ) -> Option<()>
 where
     C: Connection,
-    R: WithRead + WithEnv<Env = ValueMap<'a, ValueString<'a>>>,
+    R: WithRead + WithEnv<Env = ValueMap<'a, ValueString<'a>>> + Sync,
 {
     let outputs = outputs.as_mut_option()?;
 
@@ -98,28 +214,26 @@ where
 
     let concurrency = concurrency.unwrap_or(4) as usize;
 
-    let mut read_tasks = Vec::new();
+    // Outputs read with the same command are grouped to execute the command only once
+    let mut groups: Vec<(&R, Vec<_>)> = Vec::new();
 
     for (name, value) in outputs.iter_mut() {
         if !value.is_unknown() {
             continue;
         }
         if let Some(Value::Value(read)) = reads.get(name) {
-            let cmd = read.cmd();
-            let dir = read.dir();
-
-            read_tasks.push(async move {
-                let result = connect
-                    .execute(connect_config, cmd, dir, with_env(env, read.env()))
-                    .await;
-                (
-                    name,
-                    value,
-                    faillibe || read.faillible(),
-                    read.strip_trailing_newline(),
-                    result,
-                )
-            });
+            let member = (name, value, faillibe || read.faillible(), read);
+            match groups.iter_mut().find(|(other, _)| {
+                other.prioritized_cmd() == read.prioritized_cmd()
+                    && other.args() == read.args()
+                    && other.dir() == read.dir()
+                    && other.env() == read.env()
+                    && other.tunnels() == read.tunnels()
+                    && other.to_file() == read.to_file()
+            }) {
+                Some((_, members)) => members.push(member),
+                None => groups.push((read, vec![member])),
+            }
         } else {
             diags.error(
                     "Unknown output has no `read` block associated",
@@ -129,63 +243,263 @@ where
         }
     }
 
-    for (name, value, faillible, strip_trailing_newline, result) in
-        stream::iter(read_tasks.into_iter())
-            .buffer_unordered(concurrency)
-            .collect::<Vec<_>>()
-            .await
-    {
-        let attr_path = AttributePath::new("read")
-            .key(name.to_string())
-            .attribute("cmd");
-        *value = Value::Null;
-        let report: fn(&mut Diagnostics, String, String, AttributePath) = if faillible {
-            Diagnostics::warning
-        } else {
-            Diagnostics::error
+    let read_tasks = groups.into_iter().map(|(read, members)| async move {
+        let redactor =
+            redactor::<C, _, _>(connect_config, sensitive_inputs, with_env(env, read.env()));
+        // Waiting for the turn of the host does not count in the timeout
+        let _permit = limits::acquire_read(&C::host(connect_config)).await;
+        let execution = async {
+            match read.to_file() {
+                "" => execute_block(
+                    connect,
+                    connect_config,
+                    read,
+                    with_env(env, read.env()),
+                    "read",
+                )
+                .await
+                .map(|res| (res, None)),
+                to_file => execute_to_file(
+                    connect,
+                    connect_config,
+                    read,
+                    with_env(env, read.env()),
+                    to_file,
+                )
+                .await
+                .map(|(res, written)| (res, Some(written))),
+            }
         };
-        match result {
-            Ok(res) => {
-                if res.status == 0 {
-                    if !res.stderr.is_empty() {
-                        diags.warning(
-                            "`read` succeeded but stderr was not empty",
-                            res.stderr,
-                            attr_path,
-                        );
-                    }
-                    let mut stdout: Cow<'_, _> = res.stdout.into();
-
-                    if strip_trailing_newline && stdout.as_bytes().last().copied() == Some(b'\n') {
-                        stdout = match stdout {
-                            Cow::Borrowed(s) => Cow::Borrowed(&s[0..s.len() - 1]),
-                            Cow::Owned(mut s) => {
-                                s.pop();
-                                Cow::Owned(s)
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, execution)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(anyhow!(
+                        "Command did not complete within {} seconds",
+                        timeout.as_secs()
+                    ))
+                }),
+            None => execution.await,
+        };
+        let (result, written) = match result {
+            Ok((res, written)) => (Ok(res), written),
+            Err(err) => (Err(err), None),
+        };
+        (read, redactor, members, result, written)
+    });
+    // The tasks are created eagerly: a lazy `map` holding borrowed groups is not `Send`
+    let read_tasks = read_tasks.collect::<Vec<_>>();
+
+    for (read, redactor, members, result, written) in stream::iter(read_tasks)
+        .buffer_unordered(concurrency)
+        .collect::<Vec<_>>()
+        .await
+    {
+        if let (Some(log_file), Ok(res)) = (log_file, &result) {
+            let names = members
+                .iter()
+                .map(|(name, ..)| name.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            log_file
+                .append(
+                    &format!("read {names}"),
+                    &redactor.redact_result(res.clone()),
+                )
+                .await;
+        }
+        for (name, value, faillible, read_block) in members {
+            if let (Some(captured), Ok(res)) = (captured.as_deref_mut(), &result) {
+                captured.insert(name.clone(), res.clone());
+            }
+            let attr_path = AttributePath::new("read")
+                .key(name.to_string())
+                .attribute("cmd");
+            *value = Value::Null;
+            let report: fn(&mut Diagnostics, String, String, AttributePath) = if faillible {
+                Diagnostics::warning
+            } else {
+                Diagnostics::error
+            };
+            match &result {
+                Ok(res) => {
+                    if res.status == 0 {
+                        if !res.stderr.is_empty() {
+                            diags.warning(
+                                "`read` succeeded but stderr was not empty",
+                                redactor.redact(&res.stderr).into_owned(),
+                                attr_path.clone(),
+                            );
+                        }
+                        if let Some(written) = &written {
+                            match written {
+                                Ok(digest) => *value = Value::Value(digest.clone().into()),
+                                Err(err) => report(
+                                    diags,
+                                    "Failed to write `read` output to file".to_string(),
+                                    format!("{}: {err:#}", read_block.to_file()),
+                                    AttributePath::new("read")
+                                        .key(name.to_string())
+                                        .attribute("to_file"),
+                                ),
                             }
+                            continue;
+                        }
+                        match extract_output(read_block, &res.stdout) {
+                            Ok(output) => *value = Value::Value(output.into()),
+                            Err((attribute, detail)) => report(
+                                diags,
+                                format!("`read` output does not match `{attribute}`"),
+                                redactor.redact(&detail).into_owned(),
+                                AttributePath::new("read")
+                                    .key(name.to_string())
+                                    .attribute(attribute),
+                            ),
                         }
+                    } else {
+                        let cmd = redactor.redact(read.cmd());
+                        report(
+                            diags,
+                            format!("`read` failed with status code: {}", res.status),
+                            failure_details(
+                                &cmd,
+                                read.dir(),
+                                &C::target(connect_config),
+                                &redactor.redact_result(res.clone()),
+                            ),
+                            attr_path,
+                        );
                     }
-
-                    *value = Value::Value(stdout);
-                } else {
+                }
+                Err(err) => {
                     report(
                         diags,
-                        format!("`read` failed with status code: {}", res.status),
-                        res.stderr,
+                        "Failed to read resource state".to_string(),
+                        format!(
+                            "Target: {}\n{}",
+                            C::target(connect_config),
+                            redactor.redact(&err.to_string())
+                        ),
                         attr_path,
                     );
                 }
             }
-            Err(err) => {
-                report(
-                    diags,
-                    "Failed to read resource state".to_string(),
-                    err.to_string(),
-                    attr_path,
-                );
-            }
         }
     }
 
     Some(())
 }
+
+/// Execute a read command with its stdout redirected to a remote temporary file, streamed to the local file `to_file`
+///
+/// Returns the result of the command, and the SHA256 of the output once written.
+async fn execute_to_file<'a, 'b, C, R, I, K, V>(
+    connect: &C,
+    config: &C::Config<'a>,
+    read: &R,
+    env: I,
+    to_file: &str,
+) -> Result<(ExecutionResult, Result<String>)>
+where
+    C: Connection,
+    R: WithCmd + Sync,
+    'a: 'b,
+    I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+    I::IntoIter: Send + Sync + 'b,
+    K: AsRef<str> + Send + Sync + 'b,
+    V: AsRef<str> + Send + Sync + 'b,
+{
+    if connect.capabilities(config).await?.shell == ShellKind::PowerShell {
+        return Err(anyhow!("`to_file` is not supported on PowerShell targets"));
+    }
+    let output = connect.make_temp(config, "", false).await?;
+    let redirected = Redirected {
+        block: read,
+        cmd: format!(
+            "{{\n{}\n}} > {}",
+            read.prioritized_cmd(),
+            shell_quote(&output)
+        ),
+    };
+    let result = execute_block(connect, config, &redirected, env, "read").await;
+    let written = match &result {
+        Ok(res) if res.status == 0 => {
+            file::fetch(connect, config, &output, Path::new(to_file)).await
+        }
+        _ => Ok(String::new()),
+    };
+    if let Err(err) = connect.delete(config, &output).await {
+        log::warn!("Could not delete the temporary output {output}: {err}");
+    }
+    Ok((result?, written))
+}
+
+/// Command block whose command is replaced, eg: to redirect its output
+struct Redirected<'r, R> {
+    block: &'r R,
+    cmd: String,
+}
+
+impl<R: WithCmd> WithCmd for Redirected<'_, R> {
+    fn cmd(&self) -> &str {
+        self.block.cmd()
+    }
+    fn dir(&self) -> &str {
+        self.block.dir()
+    }
+    fn prioritized_cmd(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.cmd)
+    }
+    fn args(&self) -> Vec<&str> {
+        self.block.args()
+    }
+    fn transient_unit(&self) -> bool {
+        self.block.transient_unit()
+    }
+    fn detach(&self) -> Option<(Duration, Option<Duration>)> {
+        self.block.detach()
+    }
+    fn tunnels(&self) -> &ValueList<Value<StateTunnel<'_>>> {
+        self.block.tunnels()
+    }
+}
+
+/// Compute the value of an output from the stdout of its read command
+///
+/// On failure, returns the attribute of the read block that could not be satisfied, with an explanation.
+fn extract_output<R: WithRead>(read: &R, stdout: &str) -> Result<String, (&'static str, String)> {
+    let mut output = stdout;
+    if read.strip_trailing_newline() {
+        output = output.strip_suffix('\n').unwrap_or(output);
+    }
+    // Checked after stripping, so a lone newline also counts as no output
+    if let (true, Some(default)) = (output.is_empty(), read.default_value()) {
+        return Ok(default.to_owned());
+    }
+    if !read.pattern().is_empty() {
+        let mismatch = || {
+            (
+                "pattern",
+                format!("The output does not match `{}`.", read.pattern()),
+            )
+        };
+        let regex = Regex::new(read.pattern()).map_err(|err| ("pattern", err.to_string()))?;
+        let captures = regex.captures(output).ok_or_else(mismatch)?;
+        output = captures.get(1).map_or("", |m| m.as_str());
+    }
+    if read.trim() {
+        output = output.trim();
+    }
+    let parsed;
+    if !read.format().is_empty() {
+        parsed = format::parse(read.format(), output).map_err(|err| ("format", err))?;
+        output = &parsed;
+    }
+    let transformed;
+    if !read.transform().is_empty() {
+        transformed =
+            transform::apply(read.transform(), output).map_err(|err| ("transform", err))?;
+        output = &transformed;
+    }
+    Ok(output.to_owned())
+}
@@ -19,26 +19,190 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 
 use async_trait::async_trait;
+use crypto::{digest::Digest, sha2::Sha256};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use serde_json::json;
 
 use tf_provider::value::{Value, ValueEmpty, ValueList, ValueMap, ValueNumber, ValueString};
 use tf_provider::{schema::Schema, AttributePath, Diagnostics, Resource};
 
-use crate::connection::Connection;
+use crate::audit;
+use crate::connection::{parse_import_connection, Connection};
+use crate::options::{report_read_only, SharedOptions};
+use crate::redact::Redactor;
+use crate::timeouts;
 use crate::utils::{WithCmd, WithEnv, WithNormalize, WithSchema};
 
 use super::state::{ResourceState, StateUpdate};
-use super::{prepare_envs, with_env};
+use super::{execute_block, failure_details, owned_envs, prepare_envs, redactor, with_env};
 
 #[derive(Debug, Default)]
 pub struct GenericCmdResource<T: Connection> {
+    pub(super) options: SharedOptions,
     pub(super) connect: T,
 }
 
 impl<T: Connection> GenericCmdResource<T> {
-    pub fn new(connect: T) -> Self {
-        Self { connect }
+    pub fn new(options: SharedOptions, connect: T) -> Self {
+        Self { options, connect }
+    }
+
+    /// Run `prevent_destroy_cmd`, and fail if it does not allow the destruction of the resource
+    async fn check_prevent_destroy<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: &ResourceState<'a, T>,
+        cmd: &str,
+    ) -> Option<()> {
+        let attr_path = AttributePath::new("prevent_destroy_cmd");
+        let connection_default = Default::default();
+        let connection = state.connect.as_ref().unwrap_or(&connection_default);
+        let mut state_env = prepare_envs(
+            &[
+                (&state.env, ""),
+                (&state.inputs, "INPUT_"),
+                (&state.state, "STATE_"),
+            ],
+            state.escape_names(),
+        );
+        state_env.push((Cow::from("ID"), Cow::from(state.id.as_str())));
+        let redactor = redactor::<T, _, _>(
+            connection,
+            &state.sensitive_inputs,
+            with_env(&state_env, state.destroy.env()),
+        );
+        let resource = format!("{}_cmd", T::NAME);
+        match audit::execute(
+            &self.connect,
+            connection,
+            cmd,
+            state.destroy.dir(),
+            with_env(&state_env, state.destroy.env()),
+            &resource,
+            "prevent_destroy",
+        )
+        .await
+        {
+            Ok(res) if res.status == 0 => Some(()),
+            Ok(res) => {
+                let reason = if res.stderr.trim().is_empty() {
+                    format!(
+                        "`prevent_destroy_cmd` exited with status code {}.",
+                        res.status
+                    )
+                } else {
+                    res.stderr.trim().to_owned()
+                };
+                diags.error(
+                    "Destroy prevented by `prevent_destroy_cmd`",
+                    redactor.redact(&reason).into_owned(),
+                    attr_path,
+                );
+                None
+            }
+            Err(err) => {
+                diags.error(
+                    "Failed to run `prevent_destroy_cmd`",
+                    format!(
+                        "Target: {}\n{}",
+                        T::target(connection),
+                        redactor.redact(&err.to_string())
+                    ),
+                    attr_path,
+                );
+                None
+            }
+        }
+    }
+
+    /// Names of the outputs printed by the `describe` command, one per line
+    ///
+    /// Returns `None` without a `describe` block, or when it cannot be executed yet because of unknown values.
+    async fn describe<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: &ResourceState<'a, T>,
+    ) -> Option<Vec<String>> {
+        let Value::Value(describe) = &state.describe else {
+            return None;
+        };
+        // Commands are not executed in dry-run mode
+        if self.options.get().dry_run
+            || describe.cmd.is_unknown()
+            || state.connect.is_unknown()
+            || state.connect.as_ref_option().is_some_and(T::has_unknown)
+            || state
+                .env
+                .iter()
+                .flatten()
+                .any(|(_, value)| value.is_unknown())
+            || !state.inputs.is_value()
+            || state
+                .inputs
+                .iter()
+                .flatten()
+                .any(|(_, value)| value.is_unknown())
+        {
+            return None;
+        }
+
+        let connection_default = Default::default();
+        let connection = state.connect.as_ref().unwrap_or(&connection_default);
+        let state_env = prepare_envs(
+            &[(&state.env, ""), (&state.inputs, "INPUT_")],
+            state.escape_names(),
+        );
+        let redactor = redactor::<T, _, _>(
+            connection,
+            &state.sensitive_inputs,
+            with_env(&state_env, describe.env()),
+        );
+        let attr_path = AttributePath::new("describe").index(0).attribute("cmd");
+        match execute_block(
+            &self.connect,
+            connection,
+            describe,
+            with_env(&state_env, describe.env()),
+            "describe",
+        )
+        .await
+        {
+            Ok(res) if res.status == 0 => Some(
+                res.stdout
+                    .lines()
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+            ),
+            Ok(res) => {
+                let cmd = redactor.redact(describe.cmd());
+                diags.error(
+                    format!("`describe` failed with status code: {}", res.status),
+                    failure_details(
+                        &cmd,
+                        describe.dir(),
+                        &T::target(connection),
+                        &redactor.redact_result(res),
+                    ),
+                    attr_path,
+                );
+                None
+            }
+            Err(err) => {
+                diags.error(
+                    "Failed to describe resource",
+                    format!(
+                        "Target: {}\n{}",
+                        T::target(connection),
+                        redactor.redact(&err.to_string())
+                    ),
+                    attr_path,
+                );
+                None
+            }
+        }
     }
 }
 
@@ -74,33 +238,61 @@ where
         private_state: Self::PrivateState<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
-        let version = match private_state {
-            Value::Value(version) => version.to_string(),
-            Value::Null => String::new(),
-            // Resource has been imported, but not yet updated
-            Value::Unknown => return Some((state, private_state)),
-        };
+        let timeout = timeouts::get(&state.timeouts, "read");
+        let result = timeouts::run(timeout, async {
+            let version = match private_state {
+                Value::Value(version) => version.to_string(),
+                Value::Null => String::new(),
+                // Resource has been imported, but not yet updated
+                Value::Unknown => return Some((state, private_state)),
+            };
 
-        let mut state_env = prepare_envs(&[(&state.inputs, "INPUT_"), (&state.state, "STATE_")]);
-        state_env.push((Cow::from("ID"), Cow::from(state.id.as_str())));
-        state_env.push((Cow::from("VERSION"), Cow::from(version)));
+            // Reads are not executed in dry-run mode: previous values are kept
+            if self.options.get().dry_run {
+                return Some((state, private_state));
+            }
 
-        let mut state = state.clone();
-        state.normalize(diags);
+            let read_state = state.read_state();
+            let mut state_env = owned_envs(prepare_envs(
+                &[
+                    (&state.env, ""),
+                    (&state.inputs, "INPUT_"),
+                    (&read_state, "STATE_"),
+                ],
+                state.escape_names(),
+            ));
+            state_env.push((Cow::from("ID"), Cow::from(state.id.as_str().to_owned())));
+            state_env.push((Cow::from("VERSION"), Cow::from(version)));
 
-        // Mark all values unknown to force their read
-        state.state = Value::Value(
-            state
-                .read
-                .iter()
-                .flatten()
-                .map(|(name, _)| (name.clone(), Value::Unknown))
-                .collect(),
-        );
+            let mut state = state;
+            let previous = state.state.clone();
+            state.normalize(diags);
 
-        state.read(diags, &self.connect, &state_env, true).await;
+            // Mark all values unknown to force their read
+            state.state = Value::Value(
+                state
+                    .read
+                    .iter()
+                    .flatten()
+                    .map(|(name, _)| (name.clone(), Value::Unknown))
+                    .collect(),
+            );
+            // Outputs listed by `describe` without a `read` block are kept as is
+            if let (Value::Value(_), Value::Value(outputs), Some(previous)) =
+                (&state.describe, &mut state.state, previous.as_ref_option())
+            {
+                for (name, value) in previous {
+                    outputs.entry(name.clone()).or_insert_with(|| value.clone());
+                }
+            }
 
-        Some((state, private_state))
+            state.read(diags, &self.connect, &state_env, true).await;
+            state.detect_drift(previous.as_ref_option());
+
+            Some((state, private_state))
+        })
+        .await;
+        timeouts::report(diags, "read", result)
     }
 
     async fn plan_create<'a>(
@@ -110,10 +302,13 @@ where
         _config_state: Self::State<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
-        let mut state = proposed_state.clone();
+        let mut state = proposed_state;
         state.id = ValueString::Unknown;
         state.state = Value::Unknown;
         state.normalize(diags);
+        if let Some(names) = self.describe(diags, &state).await {
+            state.add_described(names, None);
+        }
 
         Some((state, Default::default()))
     }
@@ -133,7 +328,13 @@ where
         let value_map_default = Default::default();
         // Resource has been imported, but not yet updated.
         // The state is read from the config, before planning the update.
-        let prior_state = if prior_private_state.is_unknown() {
+        // The target is not known yet, for instance when its address comes from a resource being created
+        let connect_unknown = proposed_state.connect.is_unknown()
+            || proposed_state
+                .connect
+                .as_ref_option()
+                .is_some_and(T::has_unknown);
+        let prior_state = if prior_private_state.is_unknown() && !connect_unknown {
             self.read(
                 diags,
                 ResourceState {
@@ -165,9 +366,14 @@ where
             prior_state
         };
 
-        let mut state = proposed_state.clone();
+        let modified = find_modified(&prior_state.inputs, &proposed_state.inputs);
+
+        let mut state = proposed_state;
         state.normalize(diags);
 
+        // Outputs that will be read again during apply, with the reason, reported in the plan
+        let mut reread = serde_json::Map::new();
+
         let previous_state = prior_state.state.as_ref().unwrap_or(&value_map_default);
         let previous_reads_default = Default::default();
         let previous_reads = prior_state.read.as_ref().unwrap_or(&previous_reads_default);
@@ -182,12 +388,22 @@ where
                             (
                                 name.clone(),
                                 match (previous_reads.get(name), previous_state.get(name)) {
-                                    (_, None) => Value::Unknown,
+                                    (_, None) => {
+                                        reread.insert(
+                                            name.to_string(),
+                                            json!({"reason": "new_read"}),
+                                        );
+                                        Value::Unknown
+                                    }
                                     (None, Some(val)) => val.clone(),
                                     (Some(previous_read), Some(val)) => {
-                                        if previous_read == read {
+                                        if equivalent(diags, previous_read, read) {
                                             val.clone()
                                         } else {
+                                            reread.insert(
+                                                name.to_string(),
+                                                json!({"reason": "changed_read"}),
+                                            );
                                             Value::Unknown
                                         }
                                     }
@@ -206,7 +422,40 @@ where
             }
         }
 
-        let modified = find_modified(&prior_state.inputs, &proposed_state.inputs);
+        // Drifted outputs are repaired and read again during apply
+        let drifted = prior_state
+            .repair
+            .as_ref_option()
+            .and_then(|repair| repair.drifted.as_ref_option())
+            .filter(|drifted| !drifted.is_empty());
+        if let (Value::Value(repair), Some(drifted)) = (&mut state.repair, drifted) {
+            repair.drifted = Value::Null;
+            if let Value::Value(outputs) = &mut state.state {
+                for name in drifted {
+                    if let Some(value) = outputs.get_mut(name.as_str()) {
+                        *value = Value::Unknown;
+                        reread.insert(name.as_str().to_owned(), json!({"reason": "drift"}));
+                    }
+                }
+            }
+        }
+
+        if let Some(names) = self.describe(diags, &state).await {
+            state.add_described(names, Some(previous_state));
+        }
+
+        // Outputs will be read from the new target
+        if connect_unknown {
+            if let (Value::Value(outputs), Value::Value(reads)) = (&mut state.state, &state.read) {
+                for (name, value) in outputs.iter_mut() {
+                    if reads.contains_key(name) {
+                        *value = Value::Unknown;
+                        reread.insert(name.to_string(), json!({"reason": "connection"}));
+                    }
+                }
+            }
+        }
+
         let mut trigger_replace = Default::default();
 
         if let Some((update, _)) = find_update(&mut state.update, &modified) {
@@ -215,9 +464,17 @@ where
                 if let Value::Value(outputs) = &mut state.state {
                     let reloads_default = Default::default();
                     let reloads = update.reloads.as_ref().unwrap_or(&reloads_default);
+                    let inputs = modified
+                        .iter()
+                        .map(|name| name.as_str().to_owned())
+                        .collect::<Vec<_>>();
                     for name in reloads {
                         if let Some(value) = outputs.get_mut(name.as_str()) {
                             *value = Value::Unknown;
+                            reread.insert(
+                                name.as_str().to_owned(),
+                                json!({"reason": "reload", "inputs": inputs}),
+                            );
                         }
                     }
                 }
@@ -229,16 +486,28 @@ where
                 .collect();
         }
 
+        if !reread.is_empty() {
+            diags.warning(
+                "Outputs will be read again",
+                serde_json::Value::Object(reread).to_string(),
+                AttributePath::new("state"),
+            );
+        }
+
         Some((state, prior_private_state, trigger_replace))
     }
 
     async fn plan_destroy<'a>(
         &self,
         diags: &mut Diagnostics,
-        _prior_state: Self::State<'a>,
+        prior_state: Self::State<'a>,
         prior_private_state: Self::PrivateState<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<Self::PrivateState<'a>> {
+        if let Value::Value(prevent_destroy_cmd) = &prior_state.prevent_destroy_cmd {
+            self.check_prevent_destroy(diags, &prior_state, prevent_destroy_cmd)
+                .await?;
+        }
         if prior_private_state.is_unknown() {
             diags.root_warning(
                 "Destroy ignored on newly imported resource",
@@ -256,77 +525,178 @@ where
         mut private_state: Self::PrivateState<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
-        let mut state = planned_state.clone();
-        state.normalize(diags);
+        let timeout = timeouts::get(&planned_state.timeouts, "create");
+        let result = timeouts::run(timeout, async {
+            let mut state = planned_state;
+            state.normalize(diags);
 
-        let version = private_state.unwrap_or_default() + 1;
-        private_state = Value::from(version);
+            let version = private_state.unwrap_or_default() + 1;
+            private_state = Value::from(version);
 
-        let id = state.extract_id();
+            let id = state.extract_id();
+            let options = self.options.get();
+            if options.read_only && !options.dry_run {
+                report_read_only(diags, "create");
+                return None;
+            }
+            let dry_run = options.dry_run;
+            let log_file = state.log_file();
 
-        let connection_default = Default::default();
-        let connection = planned_state
-            .connect
-            .as_ref()
-            .unwrap_or(&connection_default);
-
-        let mut state_env = prepare_envs(&[(&planned_state.inputs, "INPUT_")]);
-        state_env.push((Cow::from("ID"), Cow::from(id.as_ref())));
-        state_env.push((Cow::from("VERSION"), Cow::from(version.to_string())));
-
-        let create_cmd = state.create.cmd();
-        let create_dir = state.create.dir();
-        if !create_cmd.is_empty() {
-            let attr_path = AttributePath::new("create").index(0).attribute("cmd");
-            match self
-                .connect
-                .execute(
+            let connection_default = Default::default();
+            let connection = state.connect.as_ref().unwrap_or(&connection_default);
+
+            let mut state_env = owned_envs(prepare_envs(
+                &[(&state.env, ""), (&state.inputs, "INPUT_")],
+                state.escape_names(),
+            ));
+            state_env.push((Cow::from("ID"), Cow::from(id.to_string())));
+            state_env.push((Cow::from("VERSION"), Cow::from(version.to_string())));
+
+            let mut adopted = false;
+            let check_cmd = state.check.cmd();
+            let check_dir = state.check.dir();
+            if !check_cmd.is_empty() {
+                let attr_path = AttributePath::new("check").index(0).attribute("cmd");
+                let redactor = redactor::<T, _, _>(
                     connection,
-                    create_cmd,
-                    create_dir,
-                    with_env(&state_env, state.create.env()),
-                )
-                .await
-            {
-                Ok(res) => {
-                    if !res.stdout.is_empty() {
-                        diags.warning(
-                            "`create` stdout was not empty",
-                            res.stdout,
-                            attr_path.clone(),
-                        );
+                    &state.sensitive_inputs,
+                    with_env(&state_env, state.check.env()),
+                );
+                if dry_run {
+                    report_dry_run(
+                        diags,
+                        "check",
+                        &redactor.redact(check_cmd),
+                        check_dir,
+                        attr_path,
+                    );
+                } else {
+                    let result = execute_block(
+                        &self.connect,
+                        connection,
+                        &state.check,
+                        with_env(&state_env, state.check.env()),
+                        "check",
+                    )
+                    .await;
+                    if let Ok(res) = &result {
+                        log_file
+                            .append("check", &redactor.redact_result(res.clone()))
+                            .await;
                     }
-                    if res.status == 0 {
-                        if !res.stderr.is_empty() {
+                    match result {
+                        // The object does not exist yet: it must be created
+                        Ok(res) if res.status != 0 => (),
+                        Ok(_) => {
+                            adopted = true;
                             diags.warning(
-                                "`create` succeeded but stderr was not empty",
-                                res.stderr,
+                                "Existing resource adopted",
+                                "`check` succeeded, so the resource already exists and `create` was not executed.",
                                 attr_path,
                             );
                         }
-                    } else {
-                        diags.error(
-                            format!("`create` failed with status code: {}", res.status),
-                            res.stderr,
-                            attr_path,
-                        );
+                        Err(err) => {
+                            diags.error(
+                                "Failed to check resource",
+                                format!(
+                                    "Target: {}\n{}",
+                                    T::target(connection),
+                                    redactor.redact(&err.to_string())
+                                ),
+                                attr_path,
+                            );
+                            return None;
+                        }
                     }
                 }
-                Err(err) => {
-                    diags.error("Failed to create resource", err.to_string(), attr_path);
+            }
+
+            let create_cmd = state.create.cmd();
+            let create_dir = state.create.dir();
+            if !create_cmd.is_empty() && !adopted {
+                let attr_path = AttributePath::new("create").index(0).attribute("cmd");
+                let redactor = redactor::<T, _, _>(
+                    connection,
+                    &state.sensitive_inputs,
+                    with_env(&state_env, state.create.env()),
+                );
+                if dry_run {
+                    report_dry_run(
+                        diags,
+                        "create",
+                        &redactor.redact(create_cmd),
+                        create_dir,
+                        attr_path,
+                    );
+                } else {
+                    let result = execute_block(
+                        &self.connect,
+                        connection,
+                        &state.create,
+                        with_env(&state_env, state.create.env()),
+                        "create",
+                    )
+                    .await
+                    .map(|res| redactor.redact_result(res));
+                    if let Ok(res) = &result {
+                        log_file.append("create", res).await;
+                    }
+                    match result {
+                        Ok(res) if res.status != 0 => {
+                            let cmd = redactor.redact(create_cmd);
+                            diags.error(
+                                format!("`create` failed with status code: {}", res.status),
+                                failure_details(&cmd, create_dir, &T::target(connection), &res),
+                                attr_path,
+                            );
+                        }
+                        Ok(res) => {
+                            if !res.stdout.is_empty() {
+                                diags.warning(
+                                    "`create` stdout was not empty",
+                                    res.stdout,
+                                    attr_path.clone(),
+                                );
+                            }
+                            if !res.stderr.is_empty() {
+                                diags.warning(
+                                    "`create` succeeded but stderr was not empty",
+                                    res.stderr,
+                                    attr_path,
+                                );
+                            }
+                        }
+                        Err(err) => {
+                            diags.error(
+                                "Failed to create resource",
+                                format!(
+                                    "Target: {}\n{}",
+                                    T::target(connection),
+                                    redactor.redact(&err.to_string())
+                                ),
+                                attr_path,
+                            );
+                        }
+                    }
                 }
             }
-        }
 
-        if !diags.errors.is_empty() {
-            return None;
-        }
+            if !diags.errors.is_empty() {
+                return None;
+            }
 
-        state.read(diags, &self.connect, &state_env, false).await;
+            if dry_run {
+                report_dry_run_unchanged(diags, "create");
+                return None;
+            }
+            state.read(diags, &self.connect, &state_env, false).await;
 
-        state.id = Value::Value(id);
+            state.id = Value::Value(id);
 
-        Some((state, private_state))
+            Some((state, private_state))
+        })
+        .await;
+        timeouts::report(diags, "create", result)
     }
     async fn update<'a>(
         &self,
@@ -337,101 +707,221 @@ where
         mut private_state: Self::PrivateState<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
-        let connection_default = Default::default();
-        let connection = planned_state
-            .connect
-            .as_ref()
-            .unwrap_or(&connection_default);
+        let timeout = timeouts::get(&planned_state.timeouts, "update");
+        let result = timeouts::run(timeout, async {
+            let version = private_state.unwrap_or_default() + 1;
+            private_state = Value::from(version);
+
+            let mut state = planned_state;
+            state.normalize(diags);
+            let id = state.extract_id();
+            let options = self.options.get();
+            if options.read_only && !options.dry_run {
+                report_read_only(diags, "update");
+                return None;
+            }
+            let dry_run = options.dry_run;
+            let log_file = state.log_file();
 
-        let version = private_state.unwrap_or_default() + 1;
-        private_state = Value::from(version);
+            let connection_default = Default::default();
+            let connection = state.connect.as_ref().unwrap_or(&connection_default);
 
-        let mut state = planned_state.clone();
-        state.normalize(diags);
-        let id = state.extract_id();
-
-        let mut state_env = prepare_envs(&[
-            (&planned_state.inputs, "INPUT_"),
-            (&prior_state.inputs, "PREVIOUS_"),
-            (&prior_state.state, "STATE_"),
-        ]);
-        state_env.push((Cow::from("ID"), Cow::from(id.as_ref())));
-        state_env.push((Cow::from("VERSION"), Cow::from(version.to_string())));
-
-        let mut updates_default = Default::default();
-        for (i, update) in state
-            .update
-            .as_mut()
-            .unwrap_or(&mut updates_default)
-            .iter_mut()
-            .enumerate()
-        {
-            let Value::Value(
-                update @ StateUpdate {
-                    update_triggered: Value::Unknown,
-                    ..
-                },
-            ) = update
-            else {
-                continue;
-            };
+            let mut state_env = owned_envs(prepare_envs(
+                &[(&state.env, ""), (&state.inputs, "INPUT_")],
+                state.escape_names(),
+            ));
+            state_env.extend(prepare_envs(
+                &[
+                    (&prior_state.inputs, "PREVIOUS_"),
+                    (&prior_state.state, "STATE_"),
+                ],
+                prior_state.escape_names(),
+            ));
+            state_env.push((Cow::from("ID"), Cow::from(id.to_string())));
+            state_env.push((Cow::from("VERSION"), Cow::from(version.to_string())));
 
-            let attr_path = AttributePath::new("update")
-                .index(i as i64)
-                .attribute("cmd");
-            update.update_triggered = Value::Null;
-            let update_cmd = update.cmd();
-            let update_dir = update.dir();
-            if !update_cmd.is_empty() {
-                match self
-                    .connect
-                    .execute(
+            let repair_needed = prior_state
+                .repair
+                .as_ref_option()
+                .and_then(|repair| repair.drifted.as_ref_option())
+                .is_some_and(|drifted| !drifted.is_empty());
+            if let (Value::Value(repair), true) = (&state.repair, repair_needed) {
+                let attr_path = AttributePath::new("repair").index(0).attribute("cmd");
+                let redactor = redactor::<T, _, _>(
+                    connection,
+                    &state.sensitive_inputs,
+                    with_env(&state_env, repair.env()),
+                );
+                let repair_cmd = repair.cmd();
+                let repair_dir = repair.dir();
+                if dry_run {
+                    report_dry_run(
+                        diags,
+                        "repair",
+                        &redactor.redact(repair_cmd),
+                        repair_dir,
+                        attr_path,
+                    );
+                } else {
+                    let result = execute_block(
+                        &self.connect,
                         connection,
-                        update_cmd,
-                        update_dir,
-                        with_env(&state_env, update.env()),
+                        repair,
+                        with_env(&state_env, repair.env()),
+                        "repair",
                     )
                     .await
-                {
-                    Ok(res) => {
-                        if !res.stdout.is_empty() {
-                            diags.warning(
-                                "`update` stdout was not empty",
-                                res.stdout,
-                                attr_path.clone(),
+                    .map(|res| redactor.redact_result(res));
+                    if let Ok(res) = &result {
+                        log_file.append("repair", res).await;
+                    }
+                    match result {
+                        Ok(res) if res.status != 0 => {
+                            let cmd = redactor.redact(repair_cmd);
+                            diags.error(
+                                format!("`repair` failed with status code: {}", res.status),
+                                failure_details(&cmd, repair_dir, &T::target(connection), &res),
+                                attr_path,
                             );
                         }
-                        if res.status == 0 {
+                        Ok(res) => {
+                            if !res.stdout.is_empty() {
+                                diags.warning(
+                                    "`repair` stdout was not empty",
+                                    res.stdout,
+                                    attr_path.clone(),
+                                );
+                            }
                             if !res.stderr.is_empty() {
                                 diags.warning(
-                                    "`update` succeeded but stderr was not empty",
+                                    "`repair` succeeded but stderr was not empty",
                                     res.stderr,
                                     attr_path,
                                 );
                             }
-                        } else {
+                        }
+                        Err(err) => {
                             diags.error(
-                                format!("`update` failed with status code: {}", res.status),
-                                res.stderr,
+                                "Failed to repair resource",
+                                format!(
+                                    "Target: {}\n{}",
+                                    T::target(connection),
+                                    redactor.redact(&err.to_string())
+                                ),
                                 attr_path,
                             );
                         }
                     }
-                    Err(err) => {
-                        diags.error("Failed to update resource", err.to_string(), attr_path);
+                }
+            }
+
+            let mut updates_default = Default::default();
+            for (i, update) in state
+                .update
+                .as_mut()
+                .unwrap_or(&mut updates_default)
+                .iter_mut()
+                .enumerate()
+            {
+                let Value::Value(
+                    update @ StateUpdate {
+                        update_triggered: Value::Unknown,
+                        ..
+                    },
+                ) = update
+                else {
+                    continue;
+                };
+
+                let attr_path = AttributePath::new("update")
+                    .index(i as i64)
+                    .attribute("cmd");
+                let redactor = redactor::<T, _, _>(
+                    connection,
+                    &state.sensitive_inputs,
+                    with_env(&state_env, update.env()),
+                );
+                update.update_triggered = Value::Null;
+                let update_cmd = update.cmd();
+                let update_dir = update.dir();
+                if !update_cmd.is_empty() {
+                    if dry_run {
+                        report_dry_run(
+                            diags,
+                            "update",
+                            &redactor.redact(update_cmd),
+                            update_dir,
+                            attr_path,
+                        );
+                    } else {
+                        let result = execute_block(
+                            &self.connect,
+                            connection,
+                            &*update,
+                            with_env(&state_env, update.env()),
+                            "update",
+                        )
+                        .await
+                        .map(|res| redactor.redact_result(res));
+                        if let Ok(res) = &result {
+                            log_file.append("update", res).await;
+                        }
+                        match result {
+                            Ok(res) if res.status != 0 => {
+                                let cmd = redactor.redact(update_cmd);
+                                diags.error(
+                                    format!("`update` failed with status code: {}", res.status),
+                                    failure_details(&cmd, update_dir, &T::target(connection), &res),
+                                    attr_path,
+                                );
+                            }
+                            Ok(res) => {
+                                if !res.stdout.is_empty() {
+                                    diags.warning(
+                                        "`update` stdout was not empty",
+                                        res.stdout,
+                                        attr_path.clone(),
+                                    );
+                                }
+                                if !res.stderr.is_empty() {
+                                    diags.warning(
+                                        "`update` succeeded but stderr was not empty",
+                                        res.stderr,
+                                        attr_path,
+                                    );
+                                }
+                            }
+                            Err(err) => {
+                                diags.error(
+                                    "Failed to update resource",
+                                    format!(
+                                        "Target: {}\n{}",
+                                        T::target(connection),
+                                        redactor.redact(&err.to_string())
+                                    ),
+                                    attr_path,
+                                );
+                            }
+                        }
                     }
+                } else {
+                    diags.error_short("`update` cmd should not be null or empty", attr_path);
+                    return None;
                 }
-            } else {
-                diags.error_short("`update` cmd should not be null or empty", attr_path);
-                return None;
             }
-        }
 
-        state.read(diags, &self.connect, &state_env, false).await;
+            if dry_run {
+                report_dry_run_unchanged(diags, "update");
+                return None;
+            }
+            state.read(diags, &self.connect, &state_env, false).await;
 
-        state.id = Value::Value(id);
+            state.id = Value::Value(id);
 
-        Some((state, private_state))
+            Some((state, private_state))
+        })
+        .await;
+        timeouts::report(diags, "update", result)
     }
     async fn destroy<'a>(
         &self,
@@ -440,68 +930,203 @@ where
         planned_private_state: Self::PrivateState<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<()> {
-        let connection_default = Default::default();
-        let connection = state.connect.as_ref().unwrap_or(&connection_default);
+        let timeout = timeouts::get(&state.timeouts, "delete");
+        let result = timeouts::run(timeout, async {
+            let options = self.options.get();
+            if options.read_only && !options.dry_run {
+                report_read_only(diags, "destroy");
+                return None;
+            }
+            let dry_run = options.dry_run;
+            let log_file = state.log_file();
+            let connection_default = Default::default();
+            let connection = state.connect.as_ref().unwrap_or(&connection_default);
 
-        let mut state_env = prepare_envs(&[(&state.inputs, "INPUT_"), (&state.state, "STATE_")]);
-        state_env.push((Cow::from("ID"), Cow::from(state.id.as_str())));
-        state_env.push((
-            Cow::from("Version"),
-            Cow::from(planned_private_state.unwrap_or(0).to_string()),
-        ));
-
-        let destroy_cmd = state.destroy.cmd();
-        let destroy_dir = state.destroy.dir();
-        if !destroy_cmd.is_empty() {
-            let attr_path = AttributePath::new("destroy").index(0).attribute("cmd");
-            match self
-                .connect
-                .execute(
+            if options.force_destroy_when_unreachable && !dry_run {
+                if let Err(err) = self.connect.connect(connection).await {
+                    let redactor = Redactor::for_connection::<T>(connection);
+                    diags.root_warning(
+                        "Target is unreachable, resource removed from the state",
+                        format!(
+                            "Target: {}\n{}",
+                            T::target(connection),
+                            redactor.redact(&err.to_string())
+                        ),
+                    );
+                    return Some(());
+                }
+            }
+
+            let mut state_env = prepare_envs(
+                &[
+                    (&state.env, ""),
+                    (&state.inputs, "INPUT_"),
+                    (&state.state, "STATE_"),
+                ],
+                state.escape_names(),
+            );
+            state_env.push((Cow::from("ID"), Cow::from(state.id.as_str())));
+            state_env.push((
+                Cow::from("Version"),
+                Cow::from(planned_private_state.unwrap_or(0).to_string()),
+            ));
+
+            let destroy_cmd = state.destroy.cmd();
+            let destroy_dir = state.destroy.dir();
+            let ignore_missing = state.ignore_missing_on_destroy.unwrap_or(false);
+            let check_cmd = state.check.cmd();
+            let mut missing = false;
+            if ignore_missing && !check_cmd.is_empty() && !destroy_cmd.is_empty() && !dry_run {
+                let attr_path = AttributePath::new("check").index(0).attribute("cmd");
+                let redactor = redactor::<T, _, _>(
                     connection,
-                    destroy_cmd,
-                    destroy_dir,
-                    with_env(&state_env, state.destroy.env()),
+                    &state.sensitive_inputs,
+                    with_env(&state_env, state.check.env()),
+                );
+                let result = execute_block(
+                    &self.connect,
+                    connection,
+                    &state.check,
+                    with_env(&state_env, state.check.env()),
+                    "check",
                 )
-                .await
-            {
-                Ok(res) => {
-                    if !res.stdout.is_empty() {
+                .await;
+                if let Ok(res) = &result {
+                    log_file
+                        .append("check", &redactor.redact_result(res.clone()))
+                        .await;
+                }
+                match result {
+                    // The object does not exist anymore: there is nothing to destroy
+                    Ok(res) if res.status != 0 => {
+                        missing = true;
                         diags.warning(
-                            "`destroy` stdout was not empty",
-                            res.stdout,
-                            attr_path.clone(),
+                            "Resource already gone, `destroy` skipped",
+                            "`check` failed, so the resource does not exist anymore and `destroy` was not executed.",
+                            attr_path,
                         );
                     }
-                    if res.status == 0 {
-                        if !res.stderr.is_empty() {
-                            diags.warning(
-                                "`destroy` succeeded but stderr was not empty",
-                                res.stderr,
-                                attr_path,
-                            );
-                        }
-                    } else {
+                    Ok(_) => (),
+                    Err(err) => {
                         diags.error(
-                            format!("`destroy` failed with status code: {}", res.status),
-                            res.stderr,
+                            "Failed to check resource",
+                            format!(
+                                "Target: {}\n{}",
+                                T::target(connection),
+                                redactor.redact(&err.to_string())
+                            ),
                             attr_path,
                         );
+                        return None;
                     }
                 }
-                Err(err) => {
-                    diags.error("Failed to destroy resource", err.to_string(), attr_path);
+            }
+            if !destroy_cmd.is_empty() && !missing {
+                let attr_path = AttributePath::new("destroy").index(0).attribute("cmd");
+                let redactor = redactor::<T, _, _>(
+                    connection,
+                    &state.sensitive_inputs,
+                    with_env(&state_env, state.destroy.env()),
+                );
+                if dry_run {
+                    report_dry_run(
+                        diags,
+                        "destroy",
+                        &redactor.redact(destroy_cmd),
+                        destroy_dir,
+                        attr_path,
+                    );
+                } else {
+                    let result = execute_block(
+                        &self.connect,
+                        connection,
+                        &state.destroy,
+                        with_env(&state_env, state.destroy.env()),
+                        "destroy",
+                    )
+                    .await
+                    .map(|res| redactor.redact_result(res));
+                    if let Ok(res) = &result {
+                        log_file.append("destroy", res).await;
+                    }
+                    match result {
+                        // Without `check`, a failing `destroy` is how a missing object is detected
+                        Ok(res) if res.status != 0 && ignore_missing && check_cmd.is_empty() => {
+                            let cmd = redactor.redact(destroy_cmd);
+                            diags.warning(
+                                format!(
+                                    "`destroy` failed with status code: {}, resource assumed gone",
+                                    res.status
+                                ),
+                                failure_details(&cmd, destroy_dir, &T::target(connection), &res),
+                                attr_path,
+                            );
+                        }
+                        Ok(res) if res.status != 0 => {
+                            let cmd = redactor.redact(destroy_cmd);
+                            diags.error(
+                                format!("`destroy` failed with status code: {}", res.status),
+                                failure_details(&cmd, destroy_dir, &T::target(connection), &res),
+                                attr_path,
+                            );
+                        }
+                        Ok(res) => {
+                            if !res.stdout.is_empty() {
+                                diags.warning(
+                                    "`destroy` stdout was not empty",
+                                    res.stdout,
+                                    attr_path.clone(),
+                                );
+                            }
+                            if !res.stderr.is_empty() {
+                                diags.warning(
+                                    "`destroy` succeeded but stderr was not empty",
+                                    res.stderr,
+                                    attr_path,
+                                );
+                            }
+                        }
+                        Err(err) => {
+                            diags.error(
+                                "Failed to destroy resource",
+                                format!(
+                                    "Target: {}\n{}",
+                                    T::target(connection),
+                                    redactor.redact(&err.to_string())
+                                ),
+                                attr_path,
+                            );
+                        }
+                    }
                 }
             }
-        }
-        Some(())
+            if dry_run {
+                report_dry_run_unchanged(diags, "destroy");
+                return None;
+            }
+            Some(())
+        })
+        .await;
+        timeouts::report(diags, "delete", result)
     }
     async fn import<'a>(
         &self,
         diags: &mut Diagnostics,
         id: String,
     ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let (connect, vars) = match parse_import_connection::<T>(&id) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                diags.root_error(
+                    "Invalid import id",
+                    format!("Import id should be `[<connection json>,]<key>=<value>,...`: {err}"),
+                );
+                return None;
+            }
+        };
+
         let mut state = BTreeMap::new();
-        for var in id.split(',') {
+        for var in vars.split(',') {
             if var.is_empty() {
                 continue;
             }
@@ -515,14 +1140,28 @@ where
 
         let mut state = Self::State {
             id: Value::Null,
+            env: Value::Null,
             inputs: Value::Value(Default::default()),
+            sensitive_inputs: Value::Null,
+            input_names: Value::Null,
             state: Value::Value(state),
             read: Value::Value(Default::default()),
+            describe: Value::Null,
+            check: Value::Null,
             create: Value::Null,
             destroy: Value::Null,
+            repair: Value::Null,
             update: Value::Value(Default::default()),
-            connect: Value::Null,
+            connect,
+            timeouts: Value::Null,
             command_concurrency: Value::Null,
+            id_scheme: Value::Null,
+            read_state_include: Value::Null,
+            read_state_exclude: Value::Null,
+            log_file: Value::Null,
+            log_file_max_size: Value::Null,
+            ignore_missing_on_destroy: Value::Null,
+            prevent_destroy_cmd: Value::Null,
         };
         state.id = Value::Value(state.extract_id());
         state.normalize(diags);
@@ -530,43 +1169,58 @@ where
     }
 }
 
-fn find_modified<'a>(
-    state: &'a ValueMap<'a, ValueString<'a>>,
-    plan: &'a ValueMap<'a, ValueString<'a>>,
-) -> BTreeSet<ValueString<'a>> {
+fn find_modified<'a, 'b>(
+    state: &ValueMap<'a, ValueString<'a>>,
+    plan: &ValueMap<'a, ValueString<'a>>,
+) -> BTreeSet<ValueString<'b>> {
     match (state, plan) {
         (Value::Value(state), Value::Value(plan)) => {
             let mut modified = BTreeSet::new();
 
+            // A null input is equivalent to a missing one
             for (k, x) in state {
-                if let Some(y) = plan.get(k) {
-                    if x != y {
-                        modified.insert(Value::Value(Cow::from(k.as_ref())));
-                    }
-                } else {
-                    modified.insert(Value::Value(Cow::from(k.as_ref())));
+                if plan.get(k).unwrap_or(&Value::Null) != x {
+                    modified.insert(Value::Value(Cow::Owned(k.to_string())));
                 }
             }
-            for k in plan.keys() {
-                if !state.contains_key(k) {
-                    modified.insert(Value::Value(Cow::from(k.as_ref())));
+            for (k, y) in plan {
+                if !y.is_null() && !state.contains_key(k) {
+                    modified.insert(Value::Value(Cow::Owned(k.to_string())));
                 }
             }
 
             modified
         }
         (_, Value::Value(plan)) => plan
-            .keys()
-            .map(|k| Value::Value(Cow::from(k.as_ref())))
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, _)| Value::Value(Cow::Owned(k.to_string())))
             .collect(),
         (Value::Value(state), _) => state
-            .keys()
-            .map(|k| Value::Value(Cow::from(k.as_ref())))
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, _)| Value::Value(Cow::Owned(k.to_string())))
             .collect(),
         _ => Default::default(),
     }
 }
 
+/// Check if two blocks are identical once normalized
+fn equivalent<N>(diags: &mut Diagnostics, a: &Value<N>, b: &Value<N>) -> bool
+where
+    N: WithNormalize + Clone + PartialEq,
+{
+    match (a, b) {
+        (Value::Value(a), Value::Value(b)) => {
+            let (mut a, mut b) = (a.clone(), b.clone());
+            a.normalize(diags);
+            b.normalize(diags);
+            a == b
+        }
+        _ => a == b,
+    }
+}
+
 fn find_update<'a, 'b, 'c>(
     updates: &'b mut ValueList<Value<StateUpdate<'a>>>,
     modified: &'c BTreeSet<ValueString<'c>>,
@@ -600,9 +1254,15 @@ fn find_update<'a, 'b, 'c>(
 }
 
 impl<'a, T: Connection> ResourceState<'a, T> {
+    fn escape_names(&self) -> bool {
+        self.input_names.as_str() == "escape"
+    }
+
     fn extract_id(&mut self) -> Cow<'a, str> {
         if let Value::Value(id) = std::mem::take(&mut self.id) {
             id
+        } else if self.id_scheme.as_str() == "hash" {
+            self.hash_id()
         } else {
             thread_rng()
                 .sample_iter(&Alphanumeric)
@@ -611,4 +1271,49 @@ impl<'a, T: Connection> ResourceState<'a, T> {
                 .collect()
         }
     }
+
+    /// Derive a stable id from the connection target and the inputs
+    fn hash_id(&self) -> Cow<'a, str> {
+        let connection_default = Default::default();
+        let connection = self.connect.as_ref().unwrap_or(&connection_default);
+
+        let mut digest = Sha256::new();
+        digest.input_str(&T::target(connection));
+        for (name, value) in self.inputs.iter().flatten() {
+            digest.input_str("\0");
+            digest.input_str(name);
+            digest.input_str("=");
+            digest.input_str(value.as_str());
+        }
+
+        let mut id = digest.result_str();
+        id.truncate(30);
+        Cow::Owned(id)
+    }
+}
+
+/// Report a command that would have been executed if dry-run mode were disabled
+fn report_dry_run(
+    diags: &mut Diagnostics,
+    phase: &str,
+    cmd: &str,
+    dir: &str,
+    attr_path: AttributePath,
+) {
+    let dir = if dir.is_empty() { "(default)" } else { dir };
+    diags.warning(
+        format!("Dry run: `{phase}` was not executed"),
+        format!("Command: {cmd}\nWorking directory: {dir}"),
+        attr_path,
+    );
+}
+
+/// Report that a resource is left unchanged in dry-run mode
+///
+/// This must be an error, otherwise Terraform records the planned state as applied.
+fn report_dry_run_unchanged(diags: &mut Diagnostics, phase: &str) {
+    diags.root_error(
+        format!("Dry run: `{phase}` was not applied"),
+        "The provider is configured with `dry_run`: the commands reported above were not executed, and the resource is left unchanged in the state.",
+    );
 }
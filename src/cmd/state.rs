@@ -14,6 +14,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
+use std::time::Duration;
+
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 
@@ -26,7 +29,11 @@ use tf_provider::value::{
 };
 
 use crate::{
-    connection::Connection,
+    connection::{
+        ssh::{ConnectionSsh, ConnectionSshConfig},
+        Connection,
+    },
+    timeouts::{StateTimeouts, TIMEOUTS_BLOCK},
     utils::{WithCmd, WithEnv, WithRead, WithSchema},
 };
 
@@ -37,17 +44,35 @@ where
 {
     #[serde(borrow = "'a")]
     pub id: ValueString<'a>,
+    pub env: ValueMap<'a, ValueString<'a>>,
     pub inputs: ValueMap<'a, ValueString<'a>>,
+    pub sensitive_inputs: ValueSet<ValueString<'a>>,
+    pub input_names: ValueString<'a>,
     pub state: ValueMap<'a, ValueString<'a>>,
     pub read: ValueMap<'a, Value<StateRead<'a>>>,
     #[serde(with = "value::serde_as_vec")]
+    pub describe: Value<StateDescribe<'a>>,
+    #[serde(with = "value::serde_as_vec")]
+    pub check: Value<StateCheck<'a>>,
+    #[serde(with = "value::serde_as_vec")]
     pub create: Value<StateCreate<'a>>,
     #[serde(with = "value::serde_as_vec")]
     pub destroy: Value<StateDestroy<'a>>,
+    #[serde(with = "value::serde_as_vec")]
+    pub repair: Value<StateRepair<'a>>,
     pub update: ValueList<Value<StateUpdate<'a>>>,
     #[serde(with = "value::serde_as_vec")]
     pub connect: Value<T::Config<'a>>,
+    #[serde(with = "value::serde_as_vec")]
+    pub timeouts: Value<StateTimeouts<'a>>,
     pub command_concurrency: ValueNumber,
+    pub id_scheme: ValueString<'a>,
+    pub read_state_include: ValueSet<ValueString<'a>>,
+    pub read_state_exclude: ValueSet<ValueString<'a>>,
+    pub log_file: ValueString<'a>,
+    pub log_file_max_size: ValueNumber,
+    pub ignore_missing_on_destroy: ValueBool,
+    pub prevent_destroy_cmd: ValueString<'a>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -56,12 +81,21 @@ where
     T: Connection,
 {
     #[serde(borrow = "'a")]
+    pub env: ValueMap<'a, ValueString<'a>>,
     pub inputs: ValueMap<'a, ValueString<'a>>,
+    pub sensitive_inputs: ValueSet<ValueString<'a>>,
+    pub input_names: ValueString<'a>,
     pub outputs: ValueMap<'a, ValueString<'a>>,
     pub read: ValueMap<'a, Value<StateRead<'a>>>,
+    pub timeout: ValueNumber,
+    pub capture_stderr: ValueBool,
+    pub stderr: ValueMap<'a, ValueString<'a>>,
+    pub exit_code: ValueMap<'a, ValueNumber>,
     #[serde(with = "value::serde_as_vec")]
     pub connect: Value<T::Config<'a>>,
     pub command_concurrency: ValueNumber,
+    pub cache_key: ValueString<'a>,
+    pub cache_ttl: ValueNumber,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -70,6 +104,32 @@ pub struct StateCmd<'a> {
     pub cmd: ValueString<'a>,
     pub dir: ValueString<'a>,
     pub env: ValueMap<'a, ValueString<'a>>,
+    pub args: ValueList<ValueString<'a>>,
+    pub nice: ValueNumber,
+    pub ionice: ValueString<'a>,
+    pub cpulimit: ValueNumber,
+    pub transient_unit: ValueBool,
+    pub detach: ValueBool,
+    #[serde(with = "value::serde_as_vec")]
+    pub poll: Value<StatePoll>,
+    #[serde(rename = "tunnel")]
+    pub tunnels: ValueList<Value<StateTunnel<'a>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct StatePoll {
+    pub interval: ValueNumber,
+    pub timeout: ValueNumber,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct StateTunnel<'a> {
+    pub local_port: ValueNumber,
+    #[serde(borrow = "'a")]
+    pub remote_host: ValueString<'a>,
+    pub remote_port: ValueNumber,
+    #[serde(with = "value::serde_as_vec")]
+    pub connect: Value<ConnectionSshConfig<'a>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -82,6 +142,15 @@ pub struct StateUpdate<'a> {
     pub update_triggered: ValueEmpty,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct StateRepair<'a> {
+    #[serde(borrow = "'a")]
+    #[serde(flatten)]
+    pub cmd: StateCmd<'a>,
+    pub watch: ValueSet<ValueString<'a>>,
+    pub drifted: ValueSet<ValueString<'a>>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct StateRead<'a> {
     #[serde(borrow = "'a")]
@@ -89,8 +158,16 @@ pub struct StateRead<'a> {
     pub cmd: StateCmd<'a>,
     pub faillible: ValueBool,
     pub strip_trailing_newline: ValueBool,
+    pub pattern: ValueString<'a>,
+    pub trim: ValueBool,
+    pub format: ValueString<'a>,
+    pub transform: ValueString<'a>,
+    pub default: ValueString<'a>,
+    pub to_file: ValueString<'a>,
 }
 
+pub type StateDescribe<'a> = StateCmd<'a>;
+pub type StateCheck<'a> = StateCmd<'a>;
 pub type StateCreate<'a> = StateCmd<'a>;
 pub type StateDestroy<'a> = StateCmd<'a>;
 
@@ -113,11 +190,102 @@ lazy_static! {
         constraint: AttributeConstraint::Optional,
         ..Default::default()
     };
+    static ref ARGS_ATTRIBUTE: Attribute = Attribute {
+        attr_type: AttributeType::List(AttributeType::String.into()),
+        description: Description::plain("Arguments given to the command as `$1`, `$2`..., never interpreted by the shell: they are passed as arguments of the local shell, and quoted for remote shells. Requires a POSIX shell"),
+        constraint: AttributeConstraint::Optional,
+        ..Default::default()
+    };
+    static ref NICE_ATTRIBUTE: Attribute = Attribute {
+        attr_type: AttributeType::Number,
+        description: Description::plain("Niceness of the command, from -20 (highest priority) to 19 (lowest priority). Requires `renice` on the target"),
+        constraint: AttributeConstraint::Optional,
+        ..Default::default()
+    };
+    static ref IONICE_ATTRIBUTE: Attribute = Attribute {
+        attr_type: AttributeType::String,
+        description: Description::plain("IO scheduling class of the command: `idle`, `best-effort` or `realtime`. Requires `ionice` on the target"),
+        constraint: AttributeConstraint::Optional,
+        ..Default::default()
+    };
+    static ref CPULIMIT_ATTRIBUTE: Attribute = Attribute {
+        attr_type: AttributeType::Number,
+        description: Description::plain("Maximum CPU usage of the command and its children, in percent of a core. Requires `cpulimit` on the target"),
+        constraint: AttributeConstraint::Optional,
+        ..Default::default()
+    };
+    static ref TRANSIENT_UNIT_ATTRIBUTE: Attribute = Attribute {
+        attr_type: AttributeType::Bool,
+        description: Description::plain("When enabled, the command runs in a `systemd-run` transient unit, so it survives the loss of the connection: the provider reconnects and waits for its completion. Requires `systemd-run` on the target, and the permission to start system units"),
+        constraint: AttributeConstraint::Optional,
+        ..Default::default()
+    };
+    static ref DETACH_ATTRIBUTE: Attribute = Attribute {
+        attr_type: AttributeType::Bool,
+        description: Description::plain("When enabled, the command is launched in the background with `nohup`, and the provider polls for its completion according to the `poll` block"),
+        constraint: AttributeConstraint::Optional,
+        ..Default::default()
+    };
+    static ref POLL_BLOCK: NestedBlock = NestedBlock::Optional(Block {
+        attributes: map! {
+            "interval" => Attribute {
+                attr_type: AttributeType::Number,
+                description: Description::plain("Number of seconds between two checks of the completion of a detached command (default: 10)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "timeout" => Attribute {
+                attr_type: AttributeType::Number,
+                description: Description::plain("Number of seconds after which a detached command is considered failed, and left running (default: no timeout)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+        },
+        description: Description::plain("Polling of the completion of the command, when `detach` is enabled"),
+        ..Default::default()
+    });
+    static ref TUNNEL_BLOCK: NestedBlock = NestedBlock::List(Block {
+        attributes: map! {
+            "local_port" => Attribute {
+                attr_type: AttributeType::Number,
+                description: Description::plain("Local port to listen on"),
+                constraint: AttributeConstraint::Required,
+                ..Default::default()
+            },
+            "remote_host" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Host to forward the connections to, as seen from the SSH host"),
+                constraint: AttributeConstraint::Required,
+                ..Default::default()
+            },
+            "remote_port" => Attribute {
+                attr_type: AttributeType::Number,
+                description: Description::plain("Port to forward the connections to"),
+                constraint: AttributeConstraint::Required,
+                ..Default::default()
+            },
+        },
+        blocks: map! {
+            "connect" => NestedBlock::Optional(Block {
+                attributes: ConnectionSsh::schema(),
+                description: Description::plain("SSH host the connections are forwarded through"),
+                ..Default::default()
+            }),
+        },
+        description: Description::plain("Local port forwarded through an SSH host while the command is executed"),
+        ..Default::default()
+    });
     static ref READ_BLOCK: NestedBlock = NestedBlock::Map(Block {
         attributes: map! {
             "cmd" => CMD_ATTRIBUTE.clone(),
             "dir" => DIR_ATTRIBUTE.clone(),
             "env" => ENV_ATTRIBUTE.clone(),
+            "args" => ARGS_ATTRIBUTE.clone(),
+            "nice" => NICE_ATTRIBUTE.clone(),
+            "ionice" => IONICE_ATTRIBUTE.clone(),
+            "cpulimit" => CPULIMIT_ATTRIBUTE.clone(),
+            "transient_unit" => TRANSIENT_UNIT_ATTRIBUTE.clone(),
+            "detach" => DETACH_ATTRIBUTE.clone(),
             "faillible" => Attribute {
                 attr_type: AttributeType::Bool,
                 description: Description::plain(
@@ -134,8 +302,60 @@ lazy_static! {
                 constraint: AttributeConstraint::Optional,
                 ..Default::default()
             },
+            "pattern" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain(
+                    "Regular expression with one capture group: the value is the text captured in the output",
+                ),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "trim" => Attribute {
+                attr_type: AttributeType::Bool,
+                description: Description::plain(
+                    "When enabled, remove leading and trailing whitespaces from the value",
+                ),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "format" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain(
+                    "Format of the output, parsed and written as JSON before `transform`: `json`, `yaml`, `kv` (`key=value` lines, eg: `systemctl show`) or `ini` (sections are nested objects)",
+                ),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "transform" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain(
+                    "Steps applied to the value before checking its type, separated by `|`: `trim`, `lower`, `upper`, `first_line`, `last_line`, and `json_path <path>` (eg: `json_path .items[0].name | lower`)",
+                ),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "default" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain(
+                    "Value used when the command outputs nothing, once the trailing newline is stripped, instead of an empty string",
+                ),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "to_file" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain(
+                    "Local file where the stdout of the command is written as is, instead of the state. The output only holds the SHA256 (hex) of the content, eg: for kubeconfigs or large reports. The stdout is streamed through a temporary file on the target, which requires a POSIX shell",
+                ),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+        },
+        blocks: map! {
+            "tunnel" => TUNNEL_BLOCK.clone(),
+            "poll" => POLL_BLOCK.clone(),
         },
-        description: Description::plain("Command to execute to get the value of the output",),
+        description: Description::plain("Command to execute to get the value of the output. Values in `state` are always strings: convert them with `tonumber()`, `tobool()` or `jsondecode()`",),
         ..Default::default()
     });
 }
@@ -152,16 +372,40 @@ where
                 attributes: map! {
                     "id" => Attribute {
                         attr_type: AttributeType::String,
-                        description: Description::plain("Random id for the command"),
+                        description: Description::plain("Id for the command, generated according to `id_scheme`"),
                         constraint: AttributeConstraint::Computed,
                         ..Default::default()
                     },
+                    "id_scheme" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("How the id is generated: `random` (default), or `hash` of the connection target and the inputs"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "env" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::String.into()),
+                        description: Description::plain("Environment of all the commands, overridden by the `env` of each block"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
                     "inputs" => Attribute {
                         attr_type: AttributeType::Map(AttributeType::String.into()),
                         description: Description::plain("Inputs to the commands"),
                         constraint: AttributeConstraint::OptionalComputed,
                         ..Default::default()
                     },
+                    "sensitive_inputs" => Attribute {
+                        attr_type: AttributeType::Set(AttributeType::String.into()),
+                        description: Description::plain("Names of the inputs whose values are redacted from diagnostics"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "input_names" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("How input names are turned into environment variables: `warn` (default) passes them as is but warns about names that are not valid variable names, `strict` rejects them, `escape` replaces invalid characters with `_`"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
                     "state" => Attribute {
                         attr_type: AttributeType::Map(AttributeType::String.into()),
                         description: Description::plain("State of the resource"),
@@ -174,14 +418,102 @@ where
                         constraint: AttributeConstraint::Optional,
                         ..Default::default()
                     },
+                    "read_state_include" => Attribute {
+                        attr_type: AttributeType::Set(AttributeType::String.into()),
+                        description: Description::plain("Names of the prior `state` values given to the `read` commands on refresh as `STATE_*` (default: all)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "read_state_exclude" => Attribute {
+                        attr_type: AttributeType::Set(AttributeType::String.into()),
+                        description: Description::plain("Names of the prior `state` values not given to the `read` commands on refresh"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "log_file" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Local file where the full stdout and stderr of every command of the resource are appended"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "log_file_max_size" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Size in bytes above which `log_file` is rotated to `<log_file>.1` (default: 10 MiB)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "ignore_missing_on_destroy" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Succeed with a warning when the object has already been removed, eg: by a host cleanup: `check` is executed first and `destroy` is skipped if it fails, or without `check`, a `destroy` exiting with a non-zero status is not an error. Unreachable targets are handled by the `force_destroy_when_unreachable` provider option (default: false)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "prevent_destroy_cmd" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Command run when planning the destruction of the resource, in the `destroy` directory and environment: a non-zero exit code blocks the destroy, with stderr as the reason"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
                 },
                 blocks: map! {
                     "read" => READ_BLOCK.clone(),
+                    "describe" => NestedBlock::Optional(Block {
+                        attributes: map! {
+                            "cmd" => CMD_ATTRIBUTE.clone(),
+                            "dir" => DIR_ATTRIBUTE.clone(),
+                            "env" => ENV_ATTRIBUTE.clone(),
+                            "args" => ARGS_ATTRIBUTE.clone(),
+                            "nice" => NICE_ATTRIBUTE.clone(),
+                            "ionice" => IONICE_ATTRIBUTE.clone(),
+                            "cpulimit" => CPULIMIT_ATTRIBUTE.clone(),
+                            "transient_unit" => TRANSIENT_UNIT_ATTRIBUTE.clone(),
+                            "detach" => DETACH_ATTRIBUTE.clone(),
+                        },
+                        blocks: map! {
+                            "tunnel" => TUNNEL_BLOCK.clone(),
+                            "poll" => POLL_BLOCK.clone(),
+                        },
+                        description: Description::plain(
+                            "Command executed when planning, printing the names of the outputs of the resource, one per line. They are added to `state` before being read, and outputs without a `read` block are null",
+                        ),
+                        ..Default::default()
+                    }),
+                    "check" => NestedBlock::Optional(Block {
+                        attributes: map! {
+                            "cmd" => CMD_ATTRIBUTE.clone(),
+                            "dir" => DIR_ATTRIBUTE.clone(),
+                            "env" => ENV_ATTRIBUTE.clone(),
+                            "args" => ARGS_ATTRIBUTE.clone(),
+                            "nice" => NICE_ATTRIBUTE.clone(),
+                            "ionice" => IONICE_ATTRIBUTE.clone(),
+                            "cpulimit" => CPULIMIT_ATTRIBUTE.clone(),
+                            "transient_unit" => TRANSIENT_UNIT_ATTRIBUTE.clone(),
+                            "detach" => DETACH_ATTRIBUTE.clone(),
+                        },
+                        blocks: map! {
+                            "tunnel" => TUNNEL_BLOCK.clone(),
+                            "poll" => POLL_BLOCK.clone(),
+                        },
+                        description: Description::plain(
+                            "Command to execute before `create`: if it succeeds, the resource already exists and is adopted without executing `create`",
+                        ),
+                        ..Default::default()
+                    }),
                     "create" => NestedBlock::Optional(Block {
                         attributes: map! {
                             "cmd" => CMD_ATTRIBUTE.clone(),
                             "dir" => DIR_ATTRIBUTE.clone(),
                             "env" => ENV_ATTRIBUTE.clone(),
+                            "args" => ARGS_ATTRIBUTE.clone(),
+                            "nice" => NICE_ATTRIBUTE.clone(),
+                            "ionice" => IONICE_ATTRIBUTE.clone(),
+                            "cpulimit" => CPULIMIT_ATTRIBUTE.clone(),
+                            "transient_unit" => TRANSIENT_UNIT_ATTRIBUTE.clone(),
+                            "detach" => DETACH_ATTRIBUTE.clone(),
+                        },
+                        blocks: map! {
+                            "tunnel" => TUNNEL_BLOCK.clone(),
+                            "poll" => POLL_BLOCK.clone(),
                         },
                         description: Description::plain(
                             "Command to execute to create the resource",
@@ -193,17 +525,70 @@ where
                             "cmd" => CMD_ATTRIBUTE.clone(),
                             "dir" => DIR_ATTRIBUTE.clone(),
                             "env" => ENV_ATTRIBUTE.clone(),
+                            "args" => ARGS_ATTRIBUTE.clone(),
+                            "nice" => NICE_ATTRIBUTE.clone(),
+                            "ionice" => IONICE_ATTRIBUTE.clone(),
+                            "cpulimit" => CPULIMIT_ATTRIBUTE.clone(),
+                            "transient_unit" => TRANSIENT_UNIT_ATTRIBUTE.clone(),
+                            "detach" => DETACH_ATTRIBUTE.clone(),
+                        },
+                        blocks: map! {
+                            "tunnel" => TUNNEL_BLOCK.clone(),
+                            "poll" => POLL_BLOCK.clone(),
                         },
                         description: Description::plain(
                             "Command to execute to destroy the resource",
                         ),
                         ..Default::default()
                     }),
+                    "repair" => NestedBlock::Optional(Block {
+                        attributes: map! {
+                            "cmd" => CMD_ATTRIBUTE.clone(),
+                            "dir" => DIR_ATTRIBUTE.clone(),
+                            "env" => ENV_ATTRIBUTE.clone(),
+                            "args" => ARGS_ATTRIBUTE.clone(),
+                            "nice" => NICE_ATTRIBUTE.clone(),
+                            "ionice" => IONICE_ATTRIBUTE.clone(),
+                            "cpulimit" => CPULIMIT_ATTRIBUTE.clone(),
+                            "transient_unit" => TRANSIENT_UNIT_ATTRIBUTE.clone(),
+                            "detach" => DETACH_ATTRIBUTE.clone(),
+                            "watch" => Attribute {
+                                attr_type: AttributeType::Set(AttributeType::String.into()),
+                                description: Description::plain(
+                                    "Outputs whose change during refresh is a drift to repair",
+                                ),
+                                constraint: AttributeConstraint::Required,
+                                ..Default::default()
+                            },
+                            "drifted" => Attribute {
+                                attr_type: AttributeType::Set(AttributeType::String.into()),
+                                description: Description::plain(
+                                    "Watched outputs that drifted since the last apply",
+                                ),
+                                constraint: AttributeConstraint::Computed,
+                                ..Default::default()
+                            },
+                        },
+                        blocks: map! {
+                            "tunnel" => TUNNEL_BLOCK.clone(),
+                            "poll" => POLL_BLOCK.clone(),
+                        },
+                        description: Description::plain(
+                            "Command to execute to converge the resource back when a watched output drifted, instead of replacing it",
+                        ),
+                        ..Default::default()
+                    }),
                     "update" => NestedBlock::List(Block {
                         attributes: map! {
                             "cmd" => CMD_ATTRIBUTE.clone(),
                             "dir" => DIR_ATTRIBUTE.clone(),
                             "env" => ENV_ATTRIBUTE.clone(),
+                            "args" => ARGS_ATTRIBUTE.clone(),
+                            "nice" => NICE_ATTRIBUTE.clone(),
+                            "ionice" => IONICE_ATTRIBUTE.clone(),
+                            "cpulimit" => CPULIMIT_ATTRIBUTE.clone(),
+                            "transient_unit" => TRANSIENT_UNIT_ATTRIBUTE.clone(),
+                            "detach" => DETACH_ATTRIBUTE.clone(),
                             "triggers" => Attribute {
                                 attr_type: AttributeType::Set(AttributeType::String.into()),
                                 description: Description::plain(
@@ -227,6 +612,10 @@ where
                                 ..Default::default()
                             },
                         },
+                        blocks: map! {
+                            "tunnel" => TUNNEL_BLOCK.clone(),
+                            "poll" => POLL_BLOCK.clone(),
+                        },
                         description: Description::plain(
                             "Command to execute when an input changes",
                         ),
@@ -237,6 +626,7 @@ where
                         description: Description::plain("Connection configuration"),
                         ..Default::default()
                     }),
+                    "timeouts" => TIMEOUTS_BLOCK.clone(),
                 },
                 description: Description::plain("Custom resource managed with local commands"),
                 deprecated: false,
@@ -255,18 +645,72 @@ where
             block: Block {
                 version: 1,
                 attributes: map! {
+                    "env" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::String.into()),
+                        description: Description::plain("Environment of all the commands, overridden by the `env` of each block"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
                     "inputs" => Attribute {
                         attr_type: AttributeType::Map(AttributeType::String.into()),
                         description: Description::plain("Inputs to the commands"),
                         constraint: AttributeConstraint::OptionalComputed,
                         ..Default::default()
                     },
+                    "sensitive_inputs" => Attribute {
+                        attr_type: AttributeType::Set(AttributeType::String.into()),
+                        description: Description::plain("Names of the inputs whose values are redacted from diagnostics"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "input_names" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("How input names are turned into environment variables: `warn` (default) passes them as is but warns about names that are not valid variable names, `strict` rejects them, `escape` replaces invalid characters with `_`"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
                     "outputs" => Attribute {
                         attr_type: AttributeType::Map(AttributeType::String.into()),
                         description: Description::plain("Outputs to the commands"),
                         constraint: AttributeConstraint::Computed,
                         ..Default::default()
                     },
+                    "timeout" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Timeout in seconds of each read command (default: `data_source_timeout` of the provider)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "capture_stderr" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("When enabled, expose the stderr and the exit code of each read in `stderr` and `exit_code`"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "cache_key" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("When set, data sources with the same `cache_key` on the same target and with the same `read` blocks and inputs share the result of a single read during a Terraform operation"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "cache_ttl" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Duration in seconds a cached result is reused (default: until the end of the Terraform operation)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "stderr" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::String.into()),
+                        description: Description::plain("Stderr of the read commands, when `capture_stderr` is enabled"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "exit_code" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::Number.into()),
+                        description: Description::plain("Exit code of the read commands, when `capture_stderr` is enabled"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
                     "command_concurrency" => Attribute {
                         attr_type: AttributeType::Number,
                         description: Description::plain("Number of conccurent commands spawned in parallel"),
@@ -297,6 +741,59 @@ impl<'a> WithCmd for StateCmd<'a> {
     fn dir(&self) -> &str {
         self.dir.as_str()
     }
+
+    /// Prefix the command with settings applied to the current shell, so they are inherited by the command
+    fn prioritized_cmd(&self) -> Cow<'_, str> {
+        let mut prefix = String::new();
+        if let Value::Value(nice) = self.nice {
+            prefix += &format!("renice -n {nice} -p $$ >/dev/null\n");
+        }
+        let ionice_class = match self.ionice.as_str() {
+            "realtime" => Some(1),
+            "best-effort" => Some(2),
+            "idle" => Some(3),
+            _ => None,
+        };
+        if let Some(class) = ionice_class {
+            prefix += &format!("ionice -c {class} -p $$\n");
+        }
+        if let Value::Value(cpulimit) = self.cpulimit {
+            prefix += &format!("cpulimit -b -i -l {cpulimit} -p $$ >/dev/null\n");
+        }
+        if prefix.is_empty() {
+            Cow::Borrowed(self.cmd())
+        } else {
+            Cow::Owned(prefix + self.cmd())
+        }
+    }
+
+    fn args(&self) -> Vec<&str> {
+        self.args.iter().flatten().map(|arg| arg.as_str()).collect()
+    }
+
+    fn transient_unit(&self) -> bool {
+        self.transient_unit.unwrap_or(false)
+    }
+
+    fn detach(&self) -> Option<(Duration, Option<Duration>)> {
+        if !self.detach.unwrap_or(false) {
+            return None;
+        }
+        let poll = self.poll.as_ref_option();
+        let interval = match poll.map(|poll| poll.interval) {
+            Some(Value::Value(interval)) if interval > 0 => interval as u64,
+            _ => 10,
+        };
+        let timeout = match poll.map(|poll| poll.timeout) {
+            Some(Value::Value(timeout)) if timeout > 0 => Some(Duration::from_secs(timeout as u64)),
+            _ => None,
+        };
+        Some((Duration::from_secs(interval), timeout))
+    }
+
+    fn tunnels(&self) -> &ValueList<Value<StateTunnel<'_>>> {
+        &self.tunnels
+    }
 }
 impl<'a> WithCmd for StateUpdate<'a> {
     fn cmd(&self) -> &str {
@@ -305,6 +802,44 @@ impl<'a> WithCmd for StateUpdate<'a> {
     fn dir(&self) -> &str {
         self.cmd.dir()
     }
+    fn prioritized_cmd(&self) -> Cow<'_, str> {
+        self.cmd.prioritized_cmd()
+    }
+    fn args(&self) -> Vec<&str> {
+        self.cmd.args()
+    }
+    fn transient_unit(&self) -> bool {
+        self.cmd.transient_unit()
+    }
+    fn detach(&self) -> Option<(Duration, Option<Duration>)> {
+        self.cmd.detach()
+    }
+    fn tunnels(&self) -> &ValueList<Value<StateTunnel<'_>>> {
+        self.cmd.tunnels()
+    }
+}
+impl<'a> WithCmd for StateRepair<'a> {
+    fn cmd(&self) -> &str {
+        self.cmd.cmd()
+    }
+    fn dir(&self) -> &str {
+        self.cmd.dir()
+    }
+    fn prioritized_cmd(&self) -> Cow<'_, str> {
+        self.cmd.prioritized_cmd()
+    }
+    fn args(&self) -> Vec<&str> {
+        self.cmd.args()
+    }
+    fn transient_unit(&self) -> bool {
+        self.cmd.transient_unit()
+    }
+    fn detach(&self) -> Option<(Duration, Option<Duration>)> {
+        self.cmd.detach()
+    }
+    fn tunnels(&self) -> &ValueList<Value<StateTunnel<'_>>> {
+        self.cmd.tunnels()
+    }
 }
 impl<'a> WithCmd for StateRead<'a> {
     fn cmd(&self) -> &str {
@@ -313,6 +848,21 @@ impl<'a> WithCmd for StateRead<'a> {
     fn dir(&self) -> &str {
         self.cmd.dir()
     }
+    fn prioritized_cmd(&self) -> Cow<'_, str> {
+        self.cmd.prioritized_cmd()
+    }
+    fn args(&self) -> Vec<&str> {
+        self.cmd.args()
+    }
+    fn transient_unit(&self) -> bool {
+        self.cmd.transient_unit()
+    }
+    fn detach(&self) -> Option<(Duration, Option<Duration>)> {
+        self.cmd.detach()
+    }
+    fn tunnels(&self) -> &ValueList<Value<StateTunnel<'_>>> {
+        self.cmd.tunnels()
+    }
 }
 impl<'a> WithRead for StateRead<'a> {
     fn strip_trailing_newline(&self) -> bool {
@@ -321,6 +871,24 @@ impl<'a> WithRead for StateRead<'a> {
     fn faillible(&self) -> bool {
         self.faillible.unwrap_or(false)
     }
+    fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+    fn trim(&self) -> bool {
+        self.trim.unwrap_or(false)
+    }
+    fn format(&self) -> &str {
+        self.format.as_str()
+    }
+    fn transform(&self) -> &str {
+        self.transform.as_str()
+    }
+    fn default_value(&self) -> Option<&str> {
+        self.default.as_deref_option()
+    }
+    fn to_file(&self) -> &str {
+        self.to_file.as_str()
+    }
 }
 
 impl<'a> WithEnv for StateCmd<'a> {
@@ -337,6 +905,13 @@ impl<'a> WithEnv for StateUpdate<'a> {
         &self.cmd.env
     }
 }
+impl<'a> WithEnv for StateRepair<'a> {
+    type Env = ValueMap<'a, ValueString<'a>>;
+
+    fn env(&self) -> &Self::Env {
+        &self.cmd.env
+    }
+}
 impl<'a> WithEnv for StateRead<'a> {
     type Env = ValueMap<'a, ValueString<'a>>;
 
@@ -0,0 +1,144 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Step of a `transform` pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step<'a> {
+    Trim,
+    Lower,
+    Upper,
+    FirstLine,
+    LastLine,
+    JsonPath(Vec<Segment<'a>>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// Parse a pipeline of steps separated by `|`, eg: `json_path .items[0].name | trim | lower`
+fn parse(transform: &str) -> Result<Vec<Step<'_>>, String> {
+    transform
+        .split('|')
+        .map(|step| {
+            let step = step.trim();
+            let (name, arg) = step
+                .split_once(char::is_whitespace)
+                .map_or((step, ""), |(name, arg)| (name, arg.trim()));
+            match (name, arg) {
+                ("trim", "") => Ok(Step::Trim),
+                ("lower", "") => Ok(Step::Lower),
+                ("upper", "") => Ok(Step::Upper),
+                ("first_line", "") => Ok(Step::FirstLine),
+                ("last_line", "") => Ok(Step::LastLine),
+                ("json_path", path) => parse_json_path(path).map(Step::JsonPath),
+                ("trim" | "lower" | "upper" | "first_line" | "last_line", _) => {
+                    Err(format!("`{name}` does not take any argument, but got `{arg}`."))
+                }
+                ("", _) => Err("Empty step in `transform`.".to_owned()),
+                _ => Err(format!("Unknown step `{name}`: it must be one of `trim`, `lower`, `upper`, `first_line`, `last_line` or `json_path`.")),
+            }
+        })
+        .collect()
+}
+
+/// Parse a path like `.items[0].name` or `$["a key"][1]`
+fn parse_json_path(path: &str) -> Result<Vec<Segment<'_>>, String> {
+    let invalid = |reason: &str| format!("Invalid json path `{path}`: {reason}.");
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        // The leading `.` can be omitted
+        let key = match rest.strip_prefix('.') {
+            Some(after) => Some(after),
+            None if segments.is_empty() && !rest.starts_with('[') => Some(rest),
+            None => None,
+        };
+        if let Some(after) = key {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(invalid("empty key"));
+            }
+            segments.push(Segment::Key(&after[..end]));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(|| invalid("missing `]`"))?;
+            let inner = &after[..end];
+            if let Some(key) = inner
+                .strip_prefix('"')
+                .and_then(|key| key.strip_suffix('"'))
+            {
+                segments.push(Segment::Key(key));
+            } else {
+                let index = inner
+                    .parse()
+                    .map_err(|_| invalid("index must be a number or a quoted key"))?;
+                segments.push(Segment::Index(index));
+            }
+            rest = &after[end + 1..];
+        } else {
+            return Err(invalid("expected `.` or `[`"));
+        }
+    }
+    Ok(segments)
+}
+
+/// Check a `transform` expression is valid
+pub(super) fn validate(transform: &str) -> Result<(), String> {
+    parse(transform).map(|_| ())
+}
+
+/// Apply a `transform` expression to a value
+pub(super) fn apply(transform: &str, value: &str) -> Result<String, String> {
+    let mut value = value.to_owned();
+    for step in parse(transform)? {
+        value = match step {
+            Step::Trim => value.trim().to_owned(),
+            Step::Lower => value.to_lowercase(),
+            Step::Upper => value.to_uppercase(),
+            Step::FirstLine => value.lines().next().unwrap_or_default().to_owned(),
+            Step::LastLine => value.lines().next_back().unwrap_or_default().to_owned(),
+            Step::JsonPath(segments) => {
+                let json = serde_json::from_str::<serde_json::Value>(&value)
+                    .map_err(|err| format!("The value is not valid JSON: {err}"))?;
+                let mut current = &json;
+                for segment in &segments {
+                    current = match segment {
+                        Segment::Key(key) => current.get(key),
+                        Segment::Index(index) => current.get(index),
+                    }
+                    .ok_or_else(|| format!("The JSON value has no {segment}."))?;
+                }
+                match current {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                }
+            }
+        };
+    }
+    Ok(value)
+}
+
+impl std::fmt::Display for Segment<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Segment::Key(key) => write!(f, "key `{key}`"),
+            Segment::Index(index) => write!(f, "index {index}"),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::audit;
+use crate::connection::{Connection, ExecutionResult};
+
+use super::job;
+
+/// Start the job in a transient service, with the environment and directory of the current shell
+fn launcher() -> String {
+    format!(
+        r#"export -p > "$state/env" || exit
+exec systemd-run --quiet --collect --unit "$TF_JOB_ID" --working-directory "$PWD" \
+  "$shell" -c '. "$1/env"; {}' \
+  "$shell" "$state"
+"#,
+        job::RUN
+    )
+}
+
+/// Wait for the transient service to complete, before collecting the job
+const WAIT: &str = r#"while [ ! -e "$state/status" ]; do
+  if ! systemctl is-active --quiet "$TF_JOB_ID" && [ ! -e "$state/status" ]; then
+    echo "Transient unit $TF_JOB_ID stopped before the command completed" >&2
+    rm -rf "$state"
+    exit 1
+  fi
+  sleep 1
+done
+"#;
+
+/// Number of times the provider re-attaches to a transient unit after losing the connection
+const REATTACH_ATTEMPTS: usize = 5;
+const REATTACH_DELAY: Duration = Duration::from_secs(5);
+
+/// Execute a command in a `systemd-run` transient unit, so it survives the loss of the connection
+///
+/// If the connection is lost while the command is running, the provider reconnects and waits again for the unit.
+pub(super) async fn execute<'a, 'b, T, I, K, V>(
+    connect: &T,
+    config: &T::Config<'a>,
+    cmd: &str,
+    dir: &str,
+    env: I,
+    resource: &str,
+    phase: &str,
+) -> Result<ExecutionResult>
+where
+    T: Connection,
+    'a: 'b,
+    I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+    I::IntoIter: Send + Sync + 'b,
+    K: AsRef<str> + Send + Sync + 'b,
+    V: AsRef<str> + Send + Sync + 'b,
+{
+    let unit = job::new_id();
+    let start_env = env
+        .into_iter()
+        .map(|(k, v)| (k.as_ref(), v.as_ref()))
+        .chain([("TF_JOB_ID", unit.as_str()), ("TF_JOB_CMD", cmd)])
+        .collect::<Vec<_>>();
+    let started = audit::execute(
+        connect,
+        config,
+        &job::launch(&launcher()),
+        dir,
+        start_env.iter().map(|(k, v)| (k, v)),
+        resource,
+        phase,
+    )
+    .await?;
+    if started.status != 0 {
+        return Ok(started);
+    }
+
+    let wait_env = [("TF_JOB_ID", unit.as_str())];
+    let wait = job::script(&format!("{WAIT}{}", job::COLLECT));
+    let mut attempts = 0;
+    loop {
+        match audit::execute(
+            connect,
+            config,
+            &wait,
+            "",
+            wait_env.iter().map(|(k, v)| (k, v)),
+            resource,
+            phase,
+        )
+        .await
+        {
+            Err(err) if attempts < REATTACH_ATTEMPTS => {
+                attempts += 1;
+                log::warn!("Lost transient unit {unit}, re-attaching: {err}");
+                tokio::time::sleep(REATTACH_DELAY).await;
+            }
+            result => return result,
+        }
+    }
+}
@@ -16,12 +16,18 @@
 
 use std::collections::{BTreeMap, BTreeSet};
 
-use tf_provider::{value::Value, AttributePath, Diagnostics};
+use regex::Regex;
+use tf_provider::{
+    value::{Value, ValueMap, ValueString},
+    AttributePath, Diagnostics,
+};
 
 use crate::connection::Connection;
+use crate::timeouts;
 use crate::utils::DisplayJoinable;
 
 use super::{
+    escape_env_name, is_valid_env_name,
     state::{DataSourceState, ResourceState, StateUpdate},
     GenericCmdDataSource,
 };
@@ -31,6 +37,7 @@ impl super::state::StateCmd<'_> {
         if self.dir.is_unknown() {
             diags.warning("`dir` is not known during planning", "It is recommended that the command does not depend on any resource, and use variables instead.", attr_path.clone().attribute("dir"));
         }
+        let block_path = attr_path.clone();
         attr_path.add_attribute("cmd");
         match self.cmd.as_ref() {
             Value::Value(cmd) => {
@@ -45,6 +52,147 @@ impl super::state::StateCmd<'_> {
                 diags.warning("`cmd` is not known during planning", "It is recommended that the command does not depend on any resource, and use variables instead.", attr_path);
             }
         }
+        for (i, arg) in self.args.iter().flatten().enumerate() {
+            if arg.is_null() {
+                diags.error_short(
+                    "`args` should not contain null values",
+                    block_path.clone().attribute("args").index(i as i64),
+                );
+            }
+        }
+        if let Value::Value(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                diags.error(
+                    "Invalid `nice`",
+                    format!("`nice` must be between -20 and 19, but was {nice}."),
+                    block_path.clone().attribute("nice"),
+                );
+            }
+        }
+        if let Value::Value(ionice) = &self.ionice {
+            if !matches!(ionice.as_ref(), "idle" | "best-effort" | "realtime") {
+                diags.error(
+                    "Invalid `ionice`",
+                    format!("`ionice` must be one of `idle`, `best-effort` or `realtime`, but was `{ionice}`."),
+                    block_path.clone().attribute("ionice"),
+                );
+            }
+        }
+        if let Value::Value(cpulimit) = self.cpulimit {
+            if cpulimit <= 0 {
+                diags.error(
+                    "Invalid `cpulimit`",
+                    format!("`cpulimit` must be positive, but was {cpulimit}."),
+                    block_path.clone().attribute("cpulimit"),
+                );
+            }
+        }
+        if let Value::Value(poll) = &self.poll {
+            for (name, seconds) in [("interval", poll.interval), ("timeout", poll.timeout)] {
+                if let Value::Value(seconds) = seconds {
+                    if seconds <= 0 {
+                        diags.error(
+                            format!("Invalid `{name}`"),
+                            format!("`{name}` must be positive, but was {seconds}."),
+                            block_path.clone().attribute("poll").attribute(name),
+                        );
+                    }
+                }
+            }
+            if !self.detach.unwrap_or(false) {
+                diags.warning(
+                    "`poll` is ignored",
+                    "`poll` is only used when `detach` is enabled.",
+                    block_path.clone().attribute("poll"),
+                );
+            }
+        }
+        if self.detach.unwrap_or(false) && self.transient_unit.unwrap_or(false) {
+            diags.error_short(
+                "`detach` and `transient_unit` cannot be enabled together",
+                block_path.clone().attribute("detach"),
+            );
+        }
+        for (i, tunnel) in self.tunnels.iter().flatten().enumerate() {
+            let Value::Value(tunnel) = tunnel else {
+                continue;
+            };
+            let attr_path = block_path.clone().attribute("tunnel").index(i as i64);
+            for (name, port) in [
+                ("local_port", tunnel.local_port),
+                ("remote_port", tunnel.remote_port),
+            ] {
+                if let Value::Value(port) = port {
+                    if !(1..=65535).contains(&port) {
+                        diags.error(
+                            format!("Invalid `{name}`"),
+                            format!("Port must be between 1 and 65535, but was {port}."),
+                            attr_path.clone().attribute(name),
+                        );
+                    }
+                }
+            }
+            if tunnel.connect.is_null() {
+                diags.error_short(
+                    "`tunnel` requires a `connect` block",
+                    attr_path.attribute("connect"),
+                );
+            }
+        }
+    }
+}
+
+impl super::state::StateRead<'_> {
+    async fn validate(&self, diags: &mut Diagnostics, attr_path: AttributePath) {
+        self.cmd.validate(diags, attr_path.clone()).await;
+        if let Value::Value(pattern) = &self.pattern {
+            let attr_path = attr_path.clone().attribute("pattern");
+            match Regex::new(pattern) {
+                Ok(regex) if regex.captures_len() == 2 => (),
+                Ok(regex) => diags.error(
+                    "Invalid `pattern`",
+                    format!(
+                        "`pattern` must have exactly one capture group, but has {}.",
+                        regex.captures_len() - 1
+                    ),
+                    attr_path,
+                ),
+                Err(err) => diags.error("Invalid `pattern`", err.to_string(), attr_path),
+            }
+        }
+        if let Value::Value(format) = &self.format {
+            if !super::format::FORMATS.contains(&format.as_ref()) {
+                diags.error(
+                    "Invalid `format`",
+                    format!("`format` must be one of `json`, `yaml`, `kv` or `ini`, but was `{format}`."),
+                    attr_path.clone().attribute("format"),
+                );
+            }
+        }
+        if let Value::Value(transform) = &self.transform {
+            if let Err(err) = super::transform::validate(transform) {
+                diags.error(
+                    "Invalid `transform`",
+                    err,
+                    attr_path.clone().attribute("transform"),
+                );
+            }
+        }
+        if let Value::Value(to_file) = &self.to_file {
+            let ignored = [
+                ("pattern", self.pattern.is_value()),
+                ("format", self.format.is_value()),
+                ("transform", self.transform.is_value()),
+                ("default", self.default.is_value()),
+            ];
+            for (name, _) in ignored.into_iter().filter(|(_, set)| *set) {
+                diags.error(
+                    format!("`{name}` cannot be used with `to_file`"),
+                    format!("The output is written as is to `{to_file}`, and `state` only holds its SHA256."),
+                    attr_path.clone().attribute(name),
+                );
+            }
+        }
     }
 }
 
@@ -115,16 +263,68 @@ impl<T: Connection> super::resource::GenericCmdResource<T> {
                 );
             }
         }
+        validate_input_names(diags, &config.input_names, attr_path.clone());
+        validate_env_names(
+            diags,
+            &config.inputs,
+            config.input_names.as_str(),
+            attr_path.clone().attribute("inputs"),
+        );
+        validate_env_names(
+            diags,
+            &config.read,
+            config.input_names.as_str(),
+            attr_path.clone().attribute("read"),
+        );
+        if let Value::Value(id_scheme) = &config.id_scheme {
+            if !matches!(id_scheme.as_ref(), "random" | "hash") {
+                diags.error(
+                    "Invalid `id_scheme`",
+                    format!("Id scheme must be either `random` or `hash`, but was `{id_scheme}`."),
+                    attr_path.clone().attribute("id_scheme"),
+                );
+            }
+        }
+        if config.log_file.as_deref_option() == Some("") {
+            diags.error_short(
+                "`log_file` should not be empty",
+                attr_path.clone().attribute("log_file"),
+            );
+        }
+        if let Value::Value(max_size) = config.log_file_max_size {
+            if max_size <= 0 {
+                diags.error(
+                    "Invalid `log_file_max_size`",
+                    format!("`log_file_max_size` must be positive, but was {max_size}."),
+                    attr_path.clone().attribute("log_file_max_size"),
+                );
+            }
+        }
         if let Value::Value(connection) = &config.connect {
             _ = self
                 .connect
                 .validate(
                     diags,
-                    attr_path.clone().attribute("connection").index(0),
+                    attr_path.clone().attribute("connect").index(0),
                     connection,
                 )
                 .await;
         }
+        timeouts::validate(
+            diags,
+            attr_path.clone().attribute("timeouts").index(0),
+            &config.timeouts,
+        );
+        if let Value::Value(describe) = &config.describe {
+            describe
+                .validate(diags, attr_path.clone().attribute("describe").index(0))
+                .await;
+        }
+        if let Value::Value(check) = &config.check {
+            check
+                .validate(diags, attr_path.clone().attribute("check").index(0))
+                .await;
+        }
         if let Value::Value(create) = &config.create {
             create
                 .validate(diags, attr_path.clone().attribute("create").index(0))
@@ -135,13 +335,27 @@ impl<T: Connection> super::resource::GenericCmdResource<T> {
                 .validate(diags, attr_path.clone().attribute("destroy").index(0))
                 .await;
         }
+        if let Value::Value(repair) = &config.repair {
+            let attr_path = attr_path.clone().attribute("repair").index(0);
+            repair.cmd.validate(diags, attr_path.clone()).await;
+            if let (Value::Value(watch), Value::Value(reads)) = (&repair.watch, &config.read) {
+                for name in watch {
+                    if !reads.contains_key(name.as_str()) {
+                        diags.error(
+                            "`repair.watch` is invalid",
+                            format!("The `repair` block watches `{name}`, but there is no `read` block with this name."),
+                            attr_path.clone().attribute("watch").key(name.to_string()),
+                        );
+                    }
+                }
+            }
+        }
         match &config.read {
             Value::Value(read) => {
                 let attr_path = attr_path.clone().attribute("read");
                 for (name, read) in read {
                     if let Value::Value(read) = read {
-                        read.cmd
-                            .validate(diags, attr_path.clone().key(name.to_string()))
+                        read.validate(diags, attr_path.clone().key(name.to_string()))
                             .await;
                     }
                 }
@@ -208,12 +422,50 @@ impl<T: Connection> GenericCmdDataSource<T> {
                 );
             }
         }
+        if let Value::Value(timeout) = config.timeout {
+            if timeout <= 0 {
+                diags.error(
+                    "Invalid `timeout`",
+                    format!("Timeout must be positive, but was {timeout}."),
+                    attr_path.clone().attribute("timeout"),
+                );
+            }
+        }
+        if config.cache_key.as_deref_option() == Some("") {
+            diags.error_short(
+                "`cache_key` should not be empty",
+                attr_path.clone().attribute("cache_key"),
+            );
+        }
+        if let Value::Value(ttl) = config.cache_ttl {
+            if ttl < 0 {
+                diags.error(
+                    "Invalid `cache_ttl`",
+                    format!("Cache duration must not be negative, but was {ttl}."),
+                    attr_path.clone().attribute("cache_ttl"),
+                );
+            }
+            if config.cache_key.is_null() {
+                diags.warning(
+                    "Unused `cache_ttl`",
+                    "`cache_ttl` is ignored when `cache_key` is not given.",
+                    attr_path.clone().attribute("cache_ttl"),
+                );
+            }
+        }
+        validate_input_names(diags, &config.input_names, attr_path.clone());
+        validate_env_names(
+            diags,
+            &config.inputs,
+            config.input_names.as_str(),
+            attr_path.clone().attribute("inputs"),
+        );
         if let Value::Value(connection) = &config.connect {
             _ = self
                 .connect
                 .validate(
                     diags,
-                    attr_path.clone().attribute("connection").index(0),
+                    attr_path.clone().attribute("connect").index(0),
                     connection,
                 )
                 .await;
@@ -224,8 +476,7 @@ impl<T: Connection> GenericCmdDataSource<T> {
                 let attr_path = AttributePath::new("read");
                 for (name, read) in read {
                     if let Value::Value(read) = read {
-                        read.cmd
-                            .validate(diags, attr_path.clone().key(name.to_string()))
+                        read.validate(diags, attr_path.clone().key(name.to_string()))
                             .await;
                     }
                 }
@@ -242,6 +493,60 @@ impl<T: Connection> GenericCmdDataSource<T> {
     }
 }
 
+fn validate_input_names(
+    diags: &mut Diagnostics,
+    input_names: &ValueString<'_>,
+    attr_path: AttributePath,
+) {
+    if let Value::Value(input_names) = input_names {
+        if !matches!(input_names.as_ref(), "warn" | "strict" | "escape") {
+            diags.error(
+                "Invalid `input_names`",
+                format!(
+                    "`input_names` must be either `warn`, `strict` or `escape`, but was `{input_names}`."
+                ),
+                attr_path.attribute("input_names"),
+            );
+        }
+    }
+}
+
+/// Check the keys of a map can be exported as environment variables
+///
+/// Invalid names are only reported as warnings, unless `mode` is `strict`.
+/// When names are escaped, check instead that no two keys are escaped into the same name.
+fn validate_env_names<V>(
+    diags: &mut Diagnostics,
+    map: &ValueMap<'_, V>,
+    mode: &str,
+    attr_path: AttributePath,
+) {
+    let Value::Value(map) = map else {
+        return;
+    };
+    let mut escaped_names = BTreeMap::new();
+    for name in map.keys() {
+        if mode == "escape" {
+            let escaped = escape_env_name(name);
+            if let Some(other) = escaped_names.insert(escaped.clone(), name) {
+                diags.error(
+                    "Conflicting names",
+                    format!("`{other}` and `{name}` are both exported as the environment variable suffix `{escaped}`."),
+                    attr_path.clone().key(name.to_string()),
+                );
+            }
+        } else if !is_valid_env_name(name) {
+            let detail = format!("`{name}` cannot be used as an environment variable name: it must only contain letters, digits and `_`, and must not start with a digit. Set `input_names = \"escape\"` to replace invalid characters with `_`.");
+            let attr_path = attr_path.clone().key(name.to_string());
+            if mode == "strict" {
+                diags.error("Invalid name", detail, attr_path);
+            } else {
+                diags.warning("Invalid name", detail, attr_path);
+            }
+        }
+    }
+}
+
 fn ensure_unambiguous_updates<'a>(diags: &mut Diagnostics, updates: &'a [Value<StateUpdate<'a>>]) {
     let default_triggers = Default::default();
     let mut seen = BTreeSet::new();
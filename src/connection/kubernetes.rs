@@ -0,0 +1,668 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::process::{Output, Stdio};
+use std::task::{ready, Context, Poll};
+
+use crate::{
+    audit,
+    connection::{
+        command_wrapper_attribute, pipe_output, shell_quote, validate_command_wrapper,
+        wrap_command, Capabilities, Connection, ExecutionResult, FileInfo, FileType, ShellKind,
+    },
+    fault,
+    utils::AsyncDrop,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tf_provider::schema::{Attribute, AttributeConstraint, AttributeType, Description};
+use tf_provider::value::{Value, ValueString};
+use tf_provider::{map, AttributePath, Diagnostics};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::process::{ChildStdin, ChildStdout, Command};
+use tokio::task::JoinHandle;
+
+/// Connection executing the commands in a container of a Kubernetes pod, with `kubectl exec`
+///
+/// Files are transferred through the standard streams of `cat`, so the container needs a POSIX shell and coreutils.
+#[derive(Debug, PartialEq, Eq, Default, Clone)]
+pub struct ConnectionKubernetes {}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Default, Clone)]
+pub struct ConnectionKubernetesConfig<'a> {
+    pub pod: ValueString<'a>,
+    pub namespace: ValueString<'a>,
+    pub container: ValueString<'a>,
+    pub context: ValueString<'a>,
+    pub kubeconfig: ValueString<'a>,
+    pub kubectl: ValueString<'a>,
+    pub dir: ValueString<'a>,
+    pub command_wrapper: ValueString<'a>,
+}
+
+impl ConnectionKubernetesConfig<'_> {
+    /// `kubectl exec` of a shell in the container, given the arguments `args`
+    fn shell(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(match self.kubectl.as_str() {
+            "" => "kubectl",
+            kubectl => kubectl,
+        });
+        if let Some(kubeconfig) = self.kubeconfig.as_deref_option() {
+            command.arg("--kubeconfig").arg(kubeconfig);
+        }
+        if let Some(context) = self.context.as_deref_option() {
+            command.arg("--context").arg(context);
+        }
+        command.arg("exec").arg("-i");
+        if let Some(namespace) = self.namespace.as_deref_option() {
+            command.arg("--namespace").arg(namespace);
+        }
+        command.arg(self.pod.as_str());
+        if let Some(container) = self.container.as_deref_option() {
+            command.arg("--container").arg(container);
+        }
+        command.arg("--").arg("sh").args(args);
+        command.kill_on_drop(true);
+        command
+    }
+
+    /// Spawn a shell in the container with the arguments `args`, and return its stdin
+    ///
+    /// Without arguments, the shell reads its script from stdin.
+    fn spawn(&self, args: &[&str], stdout: Stdio) -> Result<(tokio::process::Child, ChildStdin)> {
+        let mut child = self
+            .shell(args)
+            .stdin(Stdio::piped())
+            .stdout(stdout)
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("kubectl stdin is not available"))?;
+        Ok((child, stdin))
+    }
+
+    /// Run a script in the container, and collect its output
+    async fn run(&self, script: &str) -> Result<ExecutionResult> {
+        self.spawn_script(script)
+            .await?
+            .wait_with_output()
+            .await?
+            .try_into()
+    }
+
+    /// Start a script in the container, with its stdout piped
+    ///
+    /// The script is parsed as a whole before being executed, with its stdin closed.
+    async fn spawn_script(&self, script: &str) -> Result<tokio::process::Child> {
+        let (child, mut stdin) = self.spawn(&[], Stdio::piped())?;
+        stdin
+            .write_all(format!("{{\n{script}\n}} </dev/null\n").as_bytes())
+            .await?;
+        drop(stdin);
+        Ok(child)
+    }
+
+    /// Run a script in the container, and fail if it does not succeed
+    async fn run_checked(&self, script: &str) -> Result<String> {
+        let res = self.run(script).await?;
+        if res.status == 0 {
+            Ok(res.stdout)
+        } else {
+            Err(failure(&res.stderr).into())
+        }
+    }
+}
+
+/// Script executing `cmd` in `dir` with the environment `env`
+///
+/// kubectl cannot set the environment of the command: it is exported by the script.
+fn command_script<'b, I, K, V>(
+    config: &ConnectionKubernetesConfig,
+    cmd: &str,
+    dir: &str,
+    env: I,
+) -> Result<String>
+where
+    I: IntoIterator<Item = (&'b K, &'b V)>,
+    K: AsRef<str> + 'b,
+    V: AsRef<str> + 'b,
+{
+    if cmd.is_empty() {
+        return Err(anyhow!("Command must not be empty"));
+    }
+    let dir = if dir.is_empty() {
+        config.dir.as_str()
+    } else {
+        dir
+    };
+    let mut script = String::new();
+    for (k, v) in env {
+        let (k, v) = (k.as_ref(), v.as_ref());
+        if k.is_empty()
+            || k.starts_with(|c: char| c.is_ascii_digit())
+            || !k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(anyhow!("Invalid environment variable name `{k}`"));
+        }
+        script += &format!("export {k}={}\n", shell_quote(v));
+    }
+    if !dir.is_empty() {
+        script += &format!("cd {} || exit 1\n", shell_quote(dir));
+    }
+    let cmd = wrap_command(config.command_wrapper.as_deref_option(), cmd);
+    script += &format!("sh -c {}", shell_quote(&cmd));
+    Ok(script)
+}
+
+/// Error of a failed command, with the kind of the common failures
+fn failure(stderr: &str) -> std::io::Error {
+    let kind = if stderr.contains("No such file") {
+        ErrorKind::NotFound
+    } else if stderr.contains("Permission denied") {
+        ErrorKind::PermissionDenied
+    } else if stderr.contains("File exists") {
+        ErrorKind::AlreadyExists
+    } else {
+        ErrorKind::Other
+    };
+    std::io::Error::new(kind, stderr.trim().to_owned())
+}
+
+/// Format of `stat` parsed by `parse_stat`: the type never contains `/`, and the name comes last
+const STAT_FORMAT: &str = "'%s %a %Y %F/%n'";
+
+/// Parse a line of `stat` with `STAT_FORMAT` into the info and the name of the file
+fn parse_stat(line: &str) -> Option<(FileInfo, &str)> {
+    let (info, name) = line.split_once('/')?;
+    let mut fields = info.splitn(4, ' ');
+    let size = fields.next()?.parse().ok()?;
+    let mode = u32::from_str_radix(fields.next()?, 8).ok()?;
+    let mtime = fields.next()?.parse().ok()?;
+    let file_type = match fields.next()? {
+        "regular file" | "regular empty file" => FileType::File,
+        "directory" => FileType::Dir,
+        "symbolic link" => FileType::Symlink,
+        _ => FileType::Other,
+    };
+    Some((
+        FileInfo {
+            size,
+            mode,
+            mtime,
+            file_type,
+        },
+        name,
+    ))
+}
+
+/// Command waited for in the background, collecting its output
+type Status = Option<JoinHandle<std::io::Result<Output>>>;
+
+fn wait(child: tokio::process::Child) -> Status {
+    Some(tokio::spawn(child.wait_with_output()))
+}
+
+/// Check the exit status of a command waited for in the background
+///
+/// The status is only reported once.
+fn poll_status(status: &mut Status, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+    let Some(handle) = status else {
+        return Poll::Ready(Ok(()));
+    };
+    let output = ready!(Pin::new(handle).poll(cx));
+    *status = None;
+    let output = output.map_err(std::io::Error::other)??;
+    Poll::Ready(if output.status.success() {
+        Ok(())
+    } else {
+        Err(failure(&String::from_utf8_lossy(&output.stderr)))
+    })
+}
+
+/// File of the container read through the stdout of `cat`
+///
+/// Errors of `cat` are reported at the end of the stream.
+pub struct KubernetesReader {
+    stdout: ChildStdout,
+    status: Status,
+}
+
+impl AsyncRead for KubernetesReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.stdout).poll_read(cx, buf))?;
+        if buf.filled().len() > filled {
+            return Poll::Ready(Ok(()));
+        }
+        poll_status(&mut self.status, cx)
+    }
+}
+
+impl AsyncDrop for KubernetesReader {}
+
+/// File of the container written through the stdin of `cat`
+///
+/// Errors of `cat` are reported when the writer is shut down.
+pub struct KubernetesWriter {
+    stdin: Option<ChildStdin>,
+    status: Status,
+}
+
+impl KubernetesWriter {
+    fn stdin(&mut self) -> std::io::Result<Pin<&mut ChildStdin>> {
+        match &mut self.stdin {
+            Some(stdin) => Ok(Pin::new(stdin)),
+            None => Err(std::io::Error::new(
+                ErrorKind::BrokenPipe,
+                "File has already been closed",
+            )),
+        }
+    }
+}
+
+impl AsyncWrite for KubernetesWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.stdin()?.poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.stdin()?.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if self.stdin.is_some() {
+            ready!(self.stdin()?.poll_shutdown(cx))?;
+            self.stdin = None;
+        }
+        poll_status(&mut self.status, cx)
+    }
+}
+
+#[async_trait]
+impl AsyncDrop for KubernetesWriter {
+    async fn async_drop(&mut self) {
+        if let Err(err) = self.shutdown().await {
+            log::warn!("Could not write file in the container: {err}");
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for ConnectionKubernetes {
+    const NAME: &'static str = "k8s";
+    type Config<'a> = ConnectionKubernetesConfig<'a>;
+    type Reader = KubernetesReader;
+    type Writer = KubernetesWriter;
+
+    async fn execute<'a, 'b, I, K, V>(
+        &self,
+        config: &Self::Config<'a>,
+        cmd: &str,
+        dir: &str,
+        env: I,
+    ) -> Result<ExecutionResult>
+    where
+        'a: 'b,
+        I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+        I::IntoIter: Send + Sync + 'b,
+        K: AsRef<str> + Send + Sync + 'b,
+        V: AsRef<str> + Send + Sync + 'b,
+    {
+        audit::record::<Self, _>(config, cmd, async move {
+            if let Some(result) = fault::inject(&Self::target(config), cmd).await {
+                return result;
+            }
+            config.run(&command_script(config, cmd, dir, env)?).await
+        })
+        .await
+    }
+
+    async fn execute_piped<'a, 'b, I, K, V>(
+        &self,
+        config: &Self::Config<'a>,
+        cmd: &str,
+        dir: &str,
+        env: I,
+        stdout: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<ExecutionResult>
+    where
+        'a: 'b,
+        I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+        I::IntoIter: Send + Sync + 'b,
+        K: AsRef<str> + Send + Sync + 'b,
+        V: AsRef<str> + Send + Sync + 'b,
+    {
+        audit::record::<Self, _>(config, cmd, async move {
+            if let Some(result) = fault::inject(&Self::target(config), cmd).await {
+                return result;
+            }
+            let script = command_script(config, cmd, dir, env)?;
+            pipe_output(config.spawn_script(&script).await?, stdout).await
+        })
+        .await
+    }
+
+    /// Return a reader to read a remote file
+    async fn read<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<Self::Reader> {
+        // The script is given as argument, so the stdin of `cat` is closed right away
+        let (mut child, stdin) =
+            config.spawn(&["-c", r#"exec cat -- "$1""#, "sh", path], Stdio::piped())?;
+        drop(stdin);
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("kubectl stdout is not available"))?;
+        Ok(KubernetesReader {
+            stdout,
+            status: wait(child),
+        })
+    }
+
+    /// Return a writer to write a remote file
+    async fn write<'a>(
+        &self,
+        config: &Self::Config<'a>,
+        path: &str,
+        mode: u32,
+        overwrite: bool,
+    ) -> Result<Self::Writer> {
+        if !overwrite {
+            match self.stat(config, path).await {
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::AlreadyExists,
+                        "File already exists",
+                    )
+                    .into())
+                }
+                Err(err)
+                    if err
+                        .downcast_ref::<std::io::Error>()
+                        .is_some_and(|err| err.kind() == ErrorKind::NotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        // The script is given as argument, so stdin only carries the content
+        let noclobber = if overwrite { "" } else { "set -C; " };
+        let script = format!(r#"set -e; {noclobber}(umask 077; cat > "$1"); chmod "$2" "$1""#);
+        let mode = format!("{mode:o}");
+        let (child, stdin) = config.spawn(&["-c", &script, "sh", path, &mode], Stdio::null())?;
+        Ok(KubernetesWriter {
+            stdin: Some(stdin),
+            status: wait(child),
+        })
+    }
+
+    /// Get the information of a remote file
+    async fn stat<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<FileInfo> {
+        let stdout = config
+            .run_checked(&format!(
+                "stat -L -c {STAT_FORMAT} -- {}",
+                shell_quote(path)
+            ))
+            .await?;
+        parse_stat(stdout.trim_end())
+            .map(|(info, _)| info)
+            .ok_or_else(|| anyhow!("Unexpected output of stat: {stdout}"))
+    }
+
+    /// List the entries of a directory
+    async fn read_dir<'a>(
+        &self,
+        config: &Self::Config<'a>,
+        path: &str,
+    ) -> Result<Vec<(String, FileInfo)>> {
+        let path = shell_quote(path);
+        let stdout = config
+            .run_checked(&format!(
+                "[ -d {path} ] || {{ printf '%s: No such file or directory\\n' {path} >&2; exit 1; }}\nfind {path} -mindepth 1 -maxdepth 1 -exec stat -c {STAT_FORMAT} {{}} +"
+            ))
+            .await?;
+        stdout
+            .lines()
+            .map(|line| {
+                let (info, name) =
+                    parse_stat(line).ok_or_else(|| anyhow!("Unexpected output of stat: {line}"))?;
+                let name = name.rsplit('/').next().unwrap_or(name);
+                Ok((name.to_owned(), info))
+            })
+            .collect()
+    }
+
+    /// Rename a file
+    async fn rename<'a>(&self, config: &Self::Config<'a>, from: &str, to: &str) -> Result<()> {
+        config
+            .run_checked(&format!(
+                "mv -f -- {} {}",
+                shell_quote(from),
+                shell_quote(to)
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Copy a file
+    async fn copy<'a>(&self, config: &Self::Config<'a>, from: &str, to: &str) -> Result<()> {
+        config
+            .run_checked(&format!("cp -- {} {}", shell_quote(from), shell_quote(to)))
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a file
+    async fn delete<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<()> {
+        config
+            .run_checked(&format!("rm -- {}", shell_quote(path)))
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a file, or a directory with all its content
+    async fn delete_recursive<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<()> {
+        config
+            .run_checked(&format!("rm -r -- {}", shell_quote(path)))
+            .await?;
+        Ok(())
+    }
+
+    /// Create a unique temporary file or directory, and return its path
+    async fn make_temp<'a>(
+        &self,
+        config: &Self::Config<'a>,
+        base: &str,
+        directory: bool,
+    ) -> Result<String> {
+        let base = if base.is_empty() {
+            "\"${TMPDIR:-/tmp}\"".to_owned()
+        } else {
+            shell_quote(base)
+        };
+        let flags = if directory { "-d " } else { "" };
+        let stdout = config
+            .run_checked(&format!("mktemp {flags}{base}/tf-generic.XXXXXXXXXX"))
+            .await?;
+        Ok(stdout.trim_end().to_owned())
+    }
+
+    /// Establish the connection, and return the authentication method that was used
+    async fn connect<'a>(&self, config: &Self::Config<'a>) -> Result<&'static str> {
+        config.run_checked("true").await?;
+        Ok("kubeconfig")
+    }
+
+    /// Discover what the connection target supports
+    async fn capabilities<'a>(&self, config: &Self::Config<'a>) -> Result<Capabilities> {
+        let uid = config.run_checked("id -u").await?;
+        Ok(Capabilities {
+            file_transfer: true,
+            chown: uid.trim() == "0",
+            symlink: true,
+            shell: ShellKind::Posix,
+        })
+    }
+
+    fn target<'a>(config: &Self::Config<'a>) -> String {
+        let namespace = match config.namespace.as_str() {
+            "" => "default",
+            namespace => namespace,
+        };
+        match config.container.as_deref_option() {
+            Some(container) => format!("k8s://{namespace}/{}/{container}", config.pod.as_str()),
+            None => format!("k8s://{namespace}/{}", config.pod.as_str()),
+        }
+    }
+
+    fn secrets<'a, 'b>(_config: &'b Self::Config<'a>) -> Vec<Cow<'b, str>> {
+        Vec::new()
+    }
+
+    fn has_unknown<'a>(config: &Self::Config<'a>) -> bool {
+        [
+            &config.pod,
+            &config.namespace,
+            &config.container,
+            &config.context,
+            &config.kubeconfig,
+            &config.kubectl,
+            &config.dir,
+            &config.command_wrapper,
+        ]
+        .into_iter()
+        .any(Value::is_unknown)
+    }
+
+    /// Validate the state is valid
+    async fn validate<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        attr_path: AttributePath,
+        config: &Self::Config<'a>,
+    ) -> Option<()> {
+        match &config.pod {
+            Value::Value(pod) if pod.is_empty() => {
+                diags.error_short("`pod` cannot be empty", attr_path.attribute("pod"));
+                return None;
+            }
+            Value::Null => {
+                diags.error_short("`pod` cannot be null", attr_path.attribute("pod"));
+                return None;
+            }
+            _ => (),
+        }
+        validate_command_wrapper(diags, attr_path, &config.command_wrapper);
+        Some(())
+    }
+
+    fn schema() -> HashMap<String, Attribute> {
+        map! {
+            "pod" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Name of the pod where the commands are executed"),
+                constraint: AttributeConstraint::Required,
+                ..Default::default()
+            },
+            "namespace" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Namespace of the pod (default: namespace of the kubeconfig context)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "container" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Container of the pod where the commands are executed (default: default container of the pod)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "context" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Context of the kubeconfig to use (default: current context)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "kubeconfig" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Path of the kubeconfig file (default: `KUBECONFIG` or `~/.kube/config`)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "kubectl" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Path of the `kubectl` executable used to reach the pod (default: `kubectl` from `PATH`)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "dir" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Default directory where the commands are executed"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "command_wrapper" => command_wrapper_attribute(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_stat;
+    use crate::connection::{FileInfo, FileType};
+
+    #[test]
+    fn stat_lines() {
+        assert_eq!(
+            parse_stat("1024 644 1700000000 regular file//etc/my hosts"),
+            Some((
+                FileInfo {
+                    size: 1024,
+                    mode: 0o644,
+                    mtime: 1700000000,
+                    file_type: FileType::File,
+                },
+                "/etc/my hosts"
+            ))
+        );
+        assert_eq!(
+            parse_stat("4096 1777 0 directory/tmp").map(|(info, _)| (info.mode, info.file_type)),
+            Some((0o1777, FileType::Dir))
+        );
+        assert_eq!(
+            parse_stat("0 777 0 symbolic link/lib").map(|(info, _)| info.file_type),
+            Some(FileType::Symlink)
+        );
+        assert_eq!(
+            parse_stat("0 600 0 socket/run/docker.sock").map(|(info, _)| info.file_type),
+            Some(FileType::Other)
+        );
+        assert_eq!(parse_stat("stat: cannot stat 'missing'"), None);
+        assert_eq!(parse_stat("x 644 0 regular file/a"), None);
+    }
+}
@@ -14,27 +14,131 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::{Output, Stdio};
+use std::time::UNIX_EPOCH;
 
 use crate::{
-    connection::{Connection, ExecutionResult},
+    audit,
+    connection::{
+        command_wrapper_attribute, pipe_output, validate_command_wrapper, validate_temp_dir,
+        with_positional_args, wrap_command, Capabilities, Connection, ExecutionResult, FileInfo,
+        FileType, ShellKind,
+    },
+    fault,
     utils::AsyncDrop,
 };
 use anyhow::{anyhow, Error, Result};
-use async_process::{Command, Output};
 use async_trait::async_trait;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use serde::{Deserialize, Serialize};
-use tf_provider::{schema::Attribute, AttributePath, Diagnostics};
+use tf_provider::schema::{Attribute, AttributeConstraint, AttributeType, Description};
+use tf_provider::value::{Value, ValueBool, ValueList, ValueString};
+use tf_provider::{map, AttributePath, Diagnostics};
 use tokio::{
     fs::{File, OpenOptions},
-    io::AsyncWriteExt,
+    io::{AsyncWrite, AsyncWriteExt},
+    process::Command,
 };
 
 #[derive(Debug, PartialEq, Eq, Default, Clone)]
 pub struct ConnectionLocal {}
 
+/// Shell running `cmd` in `dir` with the environment of the configuration and `env`
+///
+/// The command is killed if it is dropped before completing, eg: on timeout.
+fn shell_command<'b, I, K, V>(
+    config: &ConnectionLocalConfig,
+    cmd: &str,
+    dir: &str,
+    temp_dir: Option<&str>,
+    env: I,
+    args: &[&str],
+) -> Result<Command>
+where
+    I: IntoIterator<Item = (&'b K, &'b V)>,
+    K: AsRef<str> + 'b,
+    V: AsRef<str> + 'b,
+{
+    let shell = if config.login_shell.unwrap_or(false) {
+        "bash"
+    } else {
+        "sh"
+    };
+    let mut command = Command::new(shell);
+    if shell == "bash" {
+        command.arg("-l");
+    }
+    if !dir.is_empty() {
+        command.current_dir(dir);
+    }
+    command.arg("-c").arg(&*wrap_command(
+        config.command_wrapper.as_deref_option(),
+        cmd,
+    ));
+    // Arguments after the script are its positional parameters, following `$0`
+    if !args.is_empty() {
+        command.arg(shell).args(args);
+    }
+    let locale = config.locale.as_str();
+    if !locale.is_empty() {
+        command.env("LANG", locale).env("LC_ALL", locale);
+    }
+    if let Some(temp_dir) = temp_dir {
+        if cfg!(target_family = "windows") {
+            command.env("TEMP", temp_dir).env("TMP", temp_dir);
+        } else {
+            command.env("TMPDIR", temp_dir);
+        }
+    }
+    let mut path = std::env::var_os("PATH");
+    for (k, v) in env {
+        if k.as_ref() == "PATH" {
+            path = Some(v.as_ref().into());
+        }
+        command.env(k.as_ref(), v.as_ref());
+    }
+    if let Some(path) = prepend_path(config, path)? {
+        command.env("PATH", path);
+    }
+    command.kill_on_drop(true);
+    Ok(command)
+}
+
+/// `PATH` of a command with the directories of `path_prepend` in front, if any
+fn prepend_path(
+    config: &ConnectionLocalConfig,
+    path: Option<OsString>,
+) -> Result<Option<OsString>> {
+    let path_prepend = config
+        .path_prepend
+        .iter()
+        .flatten()
+        .filter_map(|dir| dir.as_deref_option())
+        .collect::<Vec<_>>();
+    if path_prepend.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(std::env::join_paths(
+        path_prepend
+            .into_iter()
+            .map(PathBuf::from)
+            .chain(path.iter().flat_map(std::env::split_paths)),
+    )?))
+}
+
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Default, Clone)]
-pub struct ConnectionLocalConfig {}
+pub struct ConnectionLocalConfig<'a> {
+    pub dir: ValueString<'a>,
+    pub locale: ValueString<'a>,
+    pub path_prepend: ValueList<ValueString<'a>>,
+    pub login_shell: ValueBool,
+    pub command_wrapper: ValueString<'a>,
+    pub temp_dir: ValueString<'a>,
+}
 
 impl TryFrom<Output> for ExecutionResult {
     type Error = Error;
@@ -47,16 +151,50 @@ impl TryFrom<Output> for ExecutionResult {
     }
 }
 
+impl From<std::fs::Metadata> for FileInfo {
+    fn from(value: std::fs::Metadata) -> Self {
+        let file_type = if value.is_file() {
+            FileType::File
+        } else if value.is_dir() {
+            FileType::Dir
+        } else if value.is_symlink() {
+            FileType::Symlink
+        } else {
+            FileType::Other
+        };
+        #[cfg(target_family = "unix")]
+        let mode = std::os::unix::fs::PermissionsExt::mode(&value.permissions()) & 0o7777;
+        #[cfg(target_family = "windows")]
+        let mode = if value.permissions().readonly() {
+            0o444
+        } else {
+            0o666
+        };
+        let mtime = value
+            .modified()
+            .ok()
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |mtime| mtime.as_secs());
+
+        Self {
+            size: value.len(),
+            mode,
+            mtime,
+            file_type,
+        }
+    }
+}
+
 #[async_trait]
 impl Connection for ConnectionLocal {
     const NAME: &'static str = "local";
-    type Config<'a> = ConnectionLocalConfig;
+    type Config<'a> = ConnectionLocalConfig<'a>;
     type Reader = File;
     type Writer = File;
 
     async fn execute<'a, 'b, I, K, V>(
         &self,
-        _config: &Self::Config<'a>,
+        config: &Self::Config<'a>,
         cmd: &str,
         dir: &str,
         env: I,
@@ -68,20 +206,117 @@ impl Connection for ConnectionLocal {
         K: AsRef<str> + Send + Sync + 'b,
         V: AsRef<str> + Send + Sync + 'b,
     {
-        if !cmd.is_empty() {
-            let mut command = Command::new("sh");
-            eprintln!("Workdir: {dir}");
-            if !dir.is_empty() {
-                command.current_dir(dir);
+        self.execute_with_args(config, cmd, dir, env, &[]).await
+    }
+
+    /// Execute a command, with `args` given as arguments of the shell
+    ///
+    /// With a `command_wrapper`, the arguments cannot be forwarded to the wrapped shell, so they are quoted in the command instead.
+    async fn execute_with_args<'a, 'b, I, K, V>(
+        &self,
+        config: &Self::Config<'a>,
+        cmd: &str,
+        dir: &str,
+        env: I,
+        args: &[&str],
+    ) -> Result<ExecutionResult>
+    where
+        'a: 'b,
+        I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+        I::IntoIter: Send + Sync + 'b,
+        K: AsRef<str> + Send + Sync + 'b,
+        V: AsRef<str> + Send + Sync + 'b,
+    {
+        audit::record::<Self, _>(config, cmd, async move {
+            if let Some(result) = fault::inject(&Self::target(config), cmd).await {
+                return result;
             }
-            command.arg("-c").arg(cmd);
-            for (k, v) in env {
-                command.env(k.as_ref(), v.as_ref());
+            if !cmd.is_empty() {
+                let (cmd, args) = if config.command_wrapper.is_value() {
+                    (with_positional_args(cmd, args), &[][..])
+                } else {
+                    (cmd.into(), args)
+                };
+                let dir = if dir.is_empty() {
+                    config.dir.as_str()
+                } else {
+                    dir
+                };
+                eprintln!("Workdir: {dir}");
+                // Commands get their own temporary directory, removed once they complete
+                let temp_dir = match config.temp_dir.as_deref_option() {
+                    Some(base) => Some(self.make_temp(config, base, true).await?),
+                    None => None,
+                };
+                let output = match shell_command(config, &cmd, dir, temp_dir.as_deref(), env, args)
+                {
+                    Ok(mut command) => command.output().await.map_err(Error::from),
+                    Err(err) => Err(err),
+                };
+                if let Some(temp_dir) = &temp_dir {
+                    if let Err(err) = tokio::fs::remove_dir_all(temp_dir).await {
+                        log::warn!("Could not remove temporary directory {temp_dir}: {err}");
+                    }
+                }
+                Ok(output?.try_into()?)
+            } else {
+                Err(anyhow!("Command must not be empty"))
             }
-            Ok(command.output().await?.try_into()?)
-        } else {
-            Err(anyhow!("Command must not be empty"))
-        }
+        })
+        .await
+    }
+
+    async fn execute_piped<'a, 'b, I, K, V>(
+        &self,
+        config: &Self::Config<'a>,
+        cmd: &str,
+        dir: &str,
+        env: I,
+        stdout: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<ExecutionResult>
+    where
+        'a: 'b,
+        I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+        I::IntoIter: Send + Sync + 'b,
+        K: AsRef<str> + Send + Sync + 'b,
+        V: AsRef<str> + Send + Sync + 'b,
+    {
+        audit::record::<Self, _>(config, cmd, async move {
+            if let Some(result) = fault::inject(&Self::target(config), cmd).await {
+                return result;
+            }
+            if cmd.is_empty() {
+                return Err(anyhow!("Command must not be empty"));
+            }
+            let dir = if dir.is_empty() {
+                config.dir.as_str()
+            } else {
+                dir
+            };
+            let temp_dir = match config.temp_dir.as_deref_option() {
+                Some(base) => Some(self.make_temp(config, base, true).await?),
+                None => None,
+            };
+            let result = match shell_command(config, cmd, dir, temp_dir.as_deref(), env, &[]) {
+                Ok(mut command) => match command
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                {
+                    Ok(child) => pipe_output(child, stdout).await,
+                    Err(err) => Err(err.into()),
+                },
+                Err(err) => Err(err),
+            };
+            if let Some(temp_dir) = &temp_dir {
+                if let Err(err) = tokio::fs::remove_dir_all(temp_dir).await {
+                    log::warn!("Could not remove temporary directory {temp_dir}: {err}");
+                }
+            }
+            result
+        })
+        .await
     }
 
     /// Return a reader to read a remote file
@@ -133,23 +368,178 @@ impl Connection for ConnectionLocal {
             .map_err(Into::into)
     }
 
+    /// Get the information of a remote file
+    async fn stat<'a>(&self, _config: &Self::Config<'a>, path: &str) -> Result<FileInfo> {
+        Ok(tokio::fs::metadata(path).await?.into())
+    }
+
+    /// List the entries of a directory
+    async fn read_dir<'a>(
+        &self,
+        _config: &Self::Config<'a>,
+        path: &str,
+    ) -> Result<Vec<(String, FileInfo)>> {
+        let mut entries = Vec::new();
+        let mut dir = tokio::fs::read_dir(path).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            entries.push((name, entry.metadata().await?.into()));
+        }
+        Ok(entries)
+    }
+
+    /// Rename a file
+    async fn rename<'a>(&self, _config: &Self::Config<'a>, from: &str, to: &str) -> Result<()> {
+        tokio::fs::rename(from, to).await.map_err(Into::into)
+    }
+
+    /// Copy a file
+    async fn copy<'a>(&self, _config: &Self::Config<'a>, from: &str, to: &str) -> Result<()> {
+        tokio::fs::copy(from, to).await?;
+        Ok(())
+    }
+
     /// Delete a file
     async fn delete<'a>(&self, _config: &Self::Config<'a>, path: &str) -> Result<()> {
         tokio::fs::remove_file(path).await.map_err(Into::into)
     }
 
+    /// Delete a file, or a directory with all its content
+    async fn delete_recursive<'a>(&self, _config: &Self::Config<'a>, path: &str) -> Result<()> {
+        if tokio::fs::symlink_metadata(path).await?.is_dir() {
+            tokio::fs::remove_dir_all(path).await?;
+        } else {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    /// Create a unique temporary file or directory, and return its path
+    async fn make_temp<'a>(
+        &self,
+        _config: &Self::Config<'a>,
+        base: &str,
+        directory: bool,
+    ) -> Result<String> {
+        let base = if base.is_empty() {
+            std::env::temp_dir()
+        } else {
+            PathBuf::from(base)
+        };
+        loop {
+            let name = thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(char::from)
+                .collect::<String>();
+            let path = base.join(format!("tf-generic.{name}"));
+            let created = if directory {
+                tokio::fs::create_dir(&path).await
+            } else {
+                tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .await
+                    .map(|_| ())
+            };
+            match created {
+                Ok(()) => return Ok(path.to_string_lossy().into_owned()),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Establish the connection, and return the authentication method that was used
+    async fn connect<'a>(&self, _config: &Self::Config<'a>) -> Result<&'static str> {
+        Ok("none")
+    }
+
+    /// Discover what the connection target supports
+    async fn capabilities<'a>(&self, _config: &Self::Config<'a>) -> Result<Capabilities> {
+        #[cfg(target_family = "unix")]
+        let chown = match Command::new("id").arg("-u").output().await {
+            Ok(output) => {
+                output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "0"
+            }
+            Err(_) => false,
+        };
+        #[cfg(target_family = "windows")]
+        let chown = false;
+
+        Ok(Capabilities {
+            file_transfer: true,
+            chown,
+            symlink: cfg!(target_family = "unix"),
+            shell: ShellKind::Posix,
+        })
+    }
+
+    fn target<'a>(_config: &Self::Config<'a>) -> String {
+        "localhost".to_owned()
+    }
+
+    fn secrets<'a, 'b>(_config: &'b Self::Config<'a>) -> Vec<Cow<'b, str>> {
+        Vec::new()
+    }
+
+    fn has_unknown<'a>(config: &Self::Config<'a>) -> bool {
+        config.dir.is_unknown()
+            || config.locale.is_unknown()
+            || config.path_prepend.is_unknown()
+            || config.path_prepend.iter().flatten().any(Value::is_unknown)
+            || config.login_shell.is_unknown()
+            || config.command_wrapper.is_unknown()
+            || config.temp_dir.is_unknown()
+    }
+
     /// Validate the state is valid
     async fn validate<'a>(
         &self,
-        _diags: &mut Diagnostics,
-        _attr_path: AttributePath,
-        _config: &Self::Config<'a>,
+        diags: &mut Diagnostics,
+        attr_path: AttributePath,
+        config: &Self::Config<'a>,
     ) -> Option<()> {
+        validate_command_wrapper(diags, attr_path.clone(), &config.command_wrapper);
+        validate_temp_dir(diags, attr_path, &config.temp_dir);
         Some(())
     }
 
     fn schema() -> HashMap<String, Attribute> {
-        Default::default()
+        map! {
+            "dir" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Default directory where the commands are executed"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "locale" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Locale of the commands, set in `LANG` and `LC_ALL` (eg: `C.UTF-8`)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "path_prepend" => Attribute {
+                attr_type: AttributeType::List(AttributeType::String.into()),
+                description: Description::plain("Directories added in front of the `PATH` of the commands"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "login_shell" => Attribute {
+                attr_type: AttributeType::Bool,
+                description: Description::plain("Run the commands in a login shell (`bash -l`), so the profile of the user is loaded (default: false)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "command_wrapper" => command_wrapper_attribute(),
+            "temp_dir" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Base directory where every command gets its own temporary directory in `TMPDIR` (`TEMP` and `TMP` on Windows), removed when the command completes"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+        }
     }
 }
 
@@ -14,26 +14,91 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use tf_provider::{schema::Attribute, AttributePath, Diagnostics};
+use tf_provider::schema::{Attribute, AttributeConstraint, AttributeType, Description};
+use tf_provider::value::{Value, ValueString};
+use tf_provider::{AttributePath, Diagnostics};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::process::Child;
 
-use crate::utils::AsyncDrop;
+use crate::utils::{collect_stderr, AsyncDrop};
 
+pub mod kubernetes;
 pub mod local;
 pub mod ssh;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ExecutionResult {
     pub status: i32,
     pub stdout: String,
     pub stderr: String,
 }
 
+/// The session could not be opened, so the operation did not start on the target
+///
+/// Operations failing with this error can be retried on a new connection.
+#[derive(Debug)]
+pub struct SessionLost(pub anyhow::Error);
+
+impl std::fmt::Display for SessionLost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not open session: {:#}", self.0)
+    }
+}
+
+impl std::error::Error for SessionLost {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub size: u64,
+    pub mode: u32,
+    /// Modification time in seconds since epoch
+    pub mtime: u64,
+    pub file_type: FileType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellKind {
+    Posix,
+    PowerShell,
+    Unknown,
+}
+
+impl std::fmt::Display for ShellKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShellKind::Posix => f.write_str("posix"),
+            ShellKind::PowerShell => f.write_str("powershell"),
+            ShellKind::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Files can be transferred (SFTP subsystem for ssh)
+    pub file_transfer: bool,
+    /// Ownership of files can be changed
+    pub chown: bool,
+    /// Symbolic links can be created
+    pub symlink: bool,
+    /// Kind of shell used to execute commands
+    pub shell: ShellKind,
+}
+
 #[async_trait]
 pub trait Connection: Send + Sync + 'static + Default {
     const NAME: &'static str;
@@ -48,6 +113,8 @@ pub trait Connection: Send + Sync + 'static + Default {
     type Writer: AsyncWrite + Send + AsyncDrop;
 
     /// execute a command over the connection
+    ///
+    /// Implementations record the execution in the audit log with [`crate::audit::record`].
     async fn execute<'a, 'b, I, K, V>(
         &self,
         config: &Self::Config<'a>,
@@ -62,10 +129,42 @@ pub trait Connection: Send + Sync + 'static + Default {
         K: AsRef<str> + Send + Sync + 'b,
         V: AsRef<str> + Send + Sync + 'b;
 
+    /// Execute a command with positional parameters, available as `$1`, `$2`...
+    ///
+    /// By default, the parameters are quoted in a `set --` line before the command, which requires a POSIX shell.
+    async fn execute_with_args<'a, 'b, I, K, V>(
+        &self,
+        config: &Self::Config<'a>,
+        cmd: &str,
+        dir: &str,
+        env: I,
+        args: &[&str],
+    ) -> Result<ExecutionResult>
+    where
+        'a: 'b,
+        I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+        I::IntoIter: Send + Sync + 'b,
+        K: AsRef<str> + Send + Sync + 'b,
+        V: AsRef<str> + Send + Sync + 'b,
+    {
+        if args.is_empty() {
+            return self.execute(config, cmd, dir, env).await;
+        }
+        if self.capabilities(config).await?.shell == ShellKind::PowerShell {
+            return Err(anyhow!(
+                "`args` require a POSIX shell, but the target uses PowerShell"
+            ));
+        }
+        self.execute(config, &with_positional_args(cmd, args), dir, env)
+            .await
+    }
+
     /// Return a reader to read a remote file
     async fn read<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<Self::Reader>;
 
     /// Return a writer to write a remote file
+    ///
+    /// The writer must be shut down to know whether the file has actually been written.
     async fn write<'a>(
         &self,
         config: &Self::Config<'a>,
@@ -74,9 +173,87 @@ pub trait Connection: Send + Sync + 'static + Default {
         overwrite: bool,
     ) -> Result<Self::Writer>;
 
+    /// Get the information of a remote file
+    ///
+    /// Fails with an `std::io::Error` of kind `NotFound` if the file does not exist
+    async fn stat<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<FileInfo>;
+
+    /// List the entries of a remote directory, without `.` and `..`
+    ///
+    /// Symbolic links are not followed: their entries have the type `Symlink`
+    async fn read_dir<'a>(
+        &self,
+        config: &Self::Config<'a>,
+        path: &str,
+    ) -> Result<Vec<(String, FileInfo)>>;
+
+    /// Rename a file
+    async fn rename<'a>(&self, config: &Self::Config<'a>, from: &str, to: &str) -> Result<()>;
+
+    /// Copy a file
+    ///
+    /// No resource copies files yet, this is meant for backups and moves.
+    #[allow(dead_code)]
+    async fn copy<'a>(&self, config: &Self::Config<'a>, from: &str, to: &str) -> Result<()>;
+
     /// Delete a file
     async fn delete<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<()>;
 
+    /// Delete a file, or a directory with all its content
+    ///
+    /// Symbolic links are deleted, not followed.
+    async fn delete_recursive<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<()>;
+
+    /// Create a unique temporary file or directory, and return its path
+    ///
+    /// It is created in `base`, or in the temporary directory of the target if `base` is empty.
+    async fn make_temp<'a>(
+        &self,
+        config: &Self::Config<'a>,
+        base: &str,
+        directory: bool,
+    ) -> Result<String>;
+
+    /// Execute a command like `execute`, streaming its stdout as raw bytes to `stdout` instead of collecting it
+    ///
+    /// The `stdout` of the result is empty.
+    async fn execute_piped<'a, 'b, I, K, V>(
+        &self,
+        config: &Self::Config<'a>,
+        cmd: &str,
+        dir: &str,
+        env: I,
+        stdout: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<ExecutionResult>
+    where
+        'a: 'b,
+        I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+        I::IntoIter: Send + Sync + 'b,
+        K: AsRef<str> + Send + Sync + 'b,
+        V: AsRef<str> + Send + Sync + 'b;
+
+    /// Establish the connection, and return the authentication method that was used
+    async fn connect<'a>(&self, config: &Self::Config<'a>) -> Result<&'static str>;
+
+    /// Discover what the connection target supports
+    async fn capabilities<'a>(&self, config: &Self::Config<'a>) -> Result<Capabilities>;
+
+    /// Human readable description of the connection target, without any credential
+    fn target<'a>(config: &Self::Config<'a>) -> String;
+
+    /// Host reached by the connection, whose limits are shared by all the connections reaching it
+    fn host<'a>(config: &Self::Config<'a>) -> String {
+        Self::target(config)
+    }
+
+    /// Secret values of the configuration that must never appear in diagnostics
+    ///
+    /// Secrets that are not given directly by the configuration, eg: from the environment, are owned.
+    fn secrets<'a, 'b>(config: &'b Self::Config<'a>) -> Vec<Cow<'b, str>>;
+
+    /// Whether some values of the configuration are unknown, so the target cannot be reached while planning
+    fn has_unknown<'a>(config: &Self::Config<'a>) -> bool;
+
     /// Validate the state is valid
     async fn validate<'a>(
         &self,
@@ -88,3 +265,160 @@ pub trait Connection: Send + Sync + 'static + Default {
     /// Get the schema for the connection block
     fn schema() -> HashMap<String, Attribute>;
 }
+
+/// Placeholder of the command in `command_wrapper`
+const WRAPPED_COMMAND: &str = "%cmd%";
+
+/// Schema of the `command_wrapper` attribute of the connections
+fn command_wrapper_attribute() -> Attribute {
+    Attribute {
+        attr_type: AttributeType::String,
+        description: Description::plain("Template applied to every executed command, where `%cmd%` is replaced by the command quoted as a single shell word (eg: `firejail --quiet -- sh -c %cmd%`)"),
+        constraint: AttributeConstraint::Optional,
+        ..Default::default()
+    }
+}
+
+fn validate_temp_dir(diags: &mut Diagnostics, attr_path: AttributePath, temp_dir: &ValueString) {
+    if temp_dir.as_deref_option() == Some("") {
+        diags.error_short(
+            "`temp_dir` should not be empty",
+            attr_path.attribute("temp_dir"),
+        );
+    }
+}
+
+fn validate_command_wrapper(
+    diags: &mut Diagnostics,
+    attr_path: AttributePath,
+    wrapper: &ValueString,
+) {
+    if let Value::Value(wrapper) = wrapper {
+        if !wrapper.contains(WRAPPED_COMMAND) {
+            diags.error(
+                "Invalid `command_wrapper`",
+                format!("Command wrapper must contain `{WRAPPED_COMMAND}`, but was `{wrapper}`."),
+                attr_path.attribute("command_wrapper"),
+            );
+        }
+    }
+}
+
+/// Quote a string as a single word for a POSIX shell
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Prefix a command with a `set --` line, so `args` are its positional parameters in a POSIX shell
+pub fn with_positional_args<'c>(cmd: &'c str, args: &[&str]) -> Cow<'c, str> {
+    if args.is_empty() {
+        return Cow::Borrowed(cmd);
+    }
+    let mut prefixed = String::from("set --");
+    for arg in args {
+        prefixed += " ";
+        prefixed += &shell_quote(arg);
+    }
+    prefixed += "\n";
+    prefixed += cmd;
+    Cow::Owned(prefixed)
+}
+
+/// Stream the stdout of a child spawned with its stdout and stderr piped, and wait for it
+async fn pipe_output(
+    mut child: Child,
+    stdout: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<ExecutionResult> {
+    let mut output = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("The stdout of the command is not piped"))?;
+    let stderr = collect_stderr(&mut child);
+    tokio::io::copy(&mut output, stdout).await?;
+    let status = child.wait().await?;
+    Ok(ExecutionResult {
+        status: status.code().ok_or(anyhow!("invalid status code"))?,
+        stdout: String::new(),
+        stderr: String::from_utf8(stderr.await?)?,
+    })
+}
+
+/// Apply a `command_wrapper` template to a command
+fn wrap_command<'b>(wrapper: Option<&str>, cmd: &'b str) -> Cow<'b, str> {
+    match wrapper {
+        Some(wrapper) => Cow::Owned(wrapper.replace(WRAPPED_COMMAND, &shell_quote(cmd))),
+        None => Cow::Borrowed(cmd),
+    }
+}
+
+/// Split an import id `[<connection json>,]<rest>` into its connection configuration and the rest of the id
+///
+/// Attributes missing from the connection json are null.
+pub fn parse_import_connection<'a, T: Connection>(
+    id: &str,
+) -> Result<(Value<T::Config<'a>>, &str)> {
+    if !id.starts_with('{') {
+        return Ok((Value::Null, id));
+    }
+    let mut stream = serde_json::Deserializer::from_str(id).into_iter::<serde_json::Value>();
+    let connect = match stream.next() {
+        Some(Ok(serde_json::Value::Object(connect))) => connect,
+        Some(Ok(_)) => return Err(anyhow!("connection should be a json object")),
+        Some(Err(err)) => return Err(err.into()),
+        None => return Err(anyhow!("missing connection")),
+    };
+    let rest = &id[stream.byte_offset()..];
+    let rest = match rest.strip_prefix(',') {
+        Some(rest) => rest,
+        None if rest.is_empty() => rest,
+        None => return Err(anyhow!("connection should be followed by `,`")),
+    };
+
+    let mut nulls = serde_json::Map::new();
+    for name in T::schema().into_keys() {
+        nulls.insert(name, serde_json::Value::Null);
+    }
+    let mut config = nulls.clone();
+    for (name, value) in connect {
+        if !config.contains_key(&name) {
+            return Err(anyhow!("unknown connection attribute `{name}`"));
+        }
+        config.insert(name, value);
+    }
+    match serde_json::from_value(serde_json::Value::Object(config.clone())) {
+        Ok(config) => Ok((Value::Value(config), rest)),
+        // serde errors may quote the offending value, which could be a password:
+        // only report the attribute that failed to deserialize
+        Err(_) => {
+            let invalid = config.into_iter().find(|(name, value)| {
+                let mut single = nulls.clone();
+                single.insert(name.clone(), value.clone());
+                serde_json::from_value::<T::Config<'a>>(serde_json::Value::Object(single)).is_err()
+            });
+            match invalid {
+                Some((name, _)) => Err(anyhow!("invalid value for connection attribute `{name}`")),
+                None => Err(anyhow!("invalid connection")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_import_connection, ssh::ConnectionSsh};
+
+    #[test]
+    fn import_connection_errors_hide_values() {
+        let err = parse_import_connection::<ConnectionSsh>(
+            r#"{"host":"example.com","port":"hunter2","password":"hunter2"},/etc/hosts"#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(err, "invalid value for connection attribute `port`");
+
+        let (_, rest) =
+            parse_import_connection::<ConnectionSsh>(r#"{"host":"example.com","port":22},/tmp"#)
+                .unwrap();
+        assert_eq!(rest, "/tmp");
+    }
+}
@@ -14,85 +14,336 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::{
+    borrow::Cow,
+    future::Future,
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use crate::connection::ExecutionResult;
+use crate::connection::{
+    local::ConnectionLocal, Capabilities, Connection, ExecutionResult, SessionLost, ShellKind,
+};
+use crate::redact::Redactor;
 use anyhow::{anyhow, Error, Result};
 use async_trait::async_trait;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use rusftp::client::SftpClient;
 use rusftp::russh::{
     self,
-    client::{Config, Handle, Handler},
+    client::{Config, Handle, Handler, Msg},
+    Channel,
 };
+use serde::Deserialize;
 use tf_provider::value::Value;
 use tokio::{
-    io::AsyncWriteExt,
-    sync::mpsc::{error::SendError, Sender},
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{
+        mpsc::{error::SendError, Sender},
+        Mutex, OnceCell,
+    },
 };
 
-use super::ConnectionSshConfig;
+use super::{ssh_config::HostConfig, ConnectionSshConfig};
+
+/// Maximum number of SFTP sessions opened per client
+///
+/// SFTP requests are multiplexed over a session, so a few sessions are enough,
+/// and keep the number of channels well below the `MaxSessions` of sshd.
+const SFTP_POOL_SIZE: usize = 2;
+
+/// Session channel of a command, killed and closed if the command is dropped before completion
+///
+/// When a timeout cancels the execution, the remote command would otherwise keep running.
+struct CommandChannel(Option<Channel<Msg>>);
+
+impl CommandChannel {
+    fn new(channel: Channel<Msg>) -> Self {
+        Self(Some(channel))
+    }
+
+    async fn close(mut self) -> Result<()> {
+        if let Some(channel) = self.0.take() {
+            channel.close().await?;
+        }
+        Ok(())
+    }
+}
+
+impl Deref for CommandChannel {
+    type Target = Channel<Msg>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().expect("channel already closed")
+    }
+}
+
+impl DerefMut for CommandChannel {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut().expect("channel already closed")
+    }
+}
+
+impl Drop for CommandChannel {
+    fn drop(&mut self) {
+        let Some(channel) = self.0.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        runtime.spawn(async move {
+            _ = channel.signal(russh::Sig::KILL).await;
+            _ = channel.eof().await;
+            _ = channel.close().await;
+        });
+    }
+}
 
 pub(super) struct Client {
     pub(super) handle: Handle<ClientHandler>,
+    pub(super) auth_method: &'static str,
+    pub(super) windows: bool,
+    login_shell: bool,
+    capabilities: OnceCell<Capabilities>,
+    sftp_pool: Mutex<Vec<SftpClient>>,
+    sftp_next: AtomicUsize,
+    /// Client of the `ProxyJump` host, kept alive as long as the connection tunneled through it
+    jump: Option<Box<Client>>,
 }
 
 impl Client {
     pub(super) async fn connect<'a>(config: &ConnectionSshConfig<'a>) -> Result<Self> {
-        let russh_config = Arc::new(Config::default());
-        let hostname = config.host.as_str();
-        let port = config.port.unwrap_or_default();
-        let port = if port == 0 { 22 } else { port };
-        let client_handler = ClientHandler {};
+        let mut russh_config = Config::default();
+        if let Value::Value(size) = config.window_size {
+            russh_config.window_size = size as u32;
+        }
+        if let Value::Value(size) = config.max_packet_size {
+            russh_config.maximum_packet_size = size as u32;
+        }
+        let russh_config = Arc::new(russh_config);
+        let host_config = if config.use_ssh_config.unwrap_or(false) {
+            HostConfig::load(config.host.as_str()).await?
+        } else {
+            HostConfig::default()
+        };
+
+        // Values given explicitly take precedence over the ssh config
+        let hostname = host_config
+            .hostname
+            .as_deref()
+            .unwrap_or(config.host.as_str());
+        let port = match config.port.unwrap_or_default() {
+            0 => host_config.port.unwrap_or(22),
+            port => port,
+        };
+        let client_handler = ClientHandler {
+            host: hostname.to_owned(),
+            port,
+            tofu: config.tofu.unwrap_or(false),
+            known_hosts: config.known_hosts_file.as_deref_option().map(Into::into),
+            banner: Default::default(),
+        };
+        let banner = client_handler.banner.clone();
 
-        let mut handle =
-            russh::client::connect(russh_config, (hostname, port), client_handler).await?;
+        let (mut handle, jump) = match &host_config.proxy_jump {
+            Some(proxy_jump) => {
+                let jump = Self::connect_boxed(config.jump_config(proxy_jump)?).await?;
+                let channel = jump
+                    .handle
+                    .channel_open_direct_tcpip(hostname, port as u32, "127.0.0.1", 0)
+                    .await?;
+                let handle = russh::client::connect_stream(
+                    russh_config,
+                    channel.into_stream(),
+                    client_handler,
+                )
+                .await?;
+                (handle, Some(Box::new(jump)))
+            }
+            None => (
+                russh::client::connect(russh_config, (hostname, port), client_handler).await?,
+                None,
+            ),
+        };
+
+        let credentials = match config.credential_cmd.as_str() {
+            "" => Credentials::default(),
+            cmd => Credentials::fetch(cmd).await?,
+        };
 
-        let password = config.password.as_str();
+        let password = credentials
+            .password
+            .as_deref()
+            .unwrap_or(config.password.as_str());
         let password = if password.is_empty() {
             None
         } else {
             Some(password)
         };
+        let passphrase = credentials.passphrase.as_deref().or(password);
 
-        let private_key = match (&config.key, &config.keyfile) {
-            (Value::Value(_), Value::Value(_)) => {
+        let key = match &credentials.key {
+            Some(key) => Some(key.as_str()),
+            None => config.key.as_deref_option(),
+        };
+        let keyfile = match &config.keyfile {
+            Value::Value(keyfile) => Some(Cow::Borrowed(keyfile.as_ref())),
+            // Identity files of the ssh config are only used when no key is given
+            _ if key.is_none() => host_config
+                .identity_files
+                .iter()
+                .find(|path| std::path::Path::new(path).exists())
+                .map(|path| Cow::Borrowed(path.as_str())),
+            _ => None,
+        };
+        let private_key = match (key, keyfile) {
+            (Some(_), Some(_)) => {
                 return Err(anyhow!("Both private key and private key file were given"));
             }
-            (Value::Value(key), _) => Some(russh_keys::decode_secret_key(key.as_ref(), password)?),
-            (_, Value::Value(keyfile)) => {
-                Some(russh_keys::load_secret_key(keyfile.as_ref(), password)?)
+            (Some(key), _) => Some(russh_keys::decode_secret_key(key, passphrase)?),
+            (None, Some(keyfile)) => {
+                Some(russh_keys::load_secret_key(keyfile.as_ref(), passphrase)?)
             }
             _ => None,
         };
 
-        let username = config.user.as_str();
-        let username = if username.is_empty() {
-            "root"
-        } else {
-            username
+        let username = match config.user.as_str() {
+            "" => host_config.user.as_deref().unwrap_or("root"),
+            username => username,
         };
 
-        let authenticated = match (private_key, password) {
+        let (authenticated, auth_method, attempt) = match (private_key, password) {
             (Some(private_key), _) => {
-                handle
-                    .authenticate_publickey(username, Arc::new(private_key))
-                    .await?
+                let attempt = match private_key.clone_public_key() {
+                    Ok(key) => format!("publickey ({} {})", key.name(), key.fingerprint()),
+                    Err(_) => "publickey".to_owned(),
+                };
+                (
+                    handle
+                        .authenticate_publickey(username, Arc::new(private_key))
+                        .await?,
+                    "publickey",
+                    attempt,
+                )
             }
-            (None, Some(password)) => handle.authenticate_password(username, password).await?,
-            (None, None) => handle.authenticate_none(username).await?,
+            (None, Some(password)) => (
+                handle.authenticate_password(username, password).await?,
+                "password",
+                "password".to_owned(),
+            ),
+            (None, None) => (
+                handle.authenticate_none(username).await?,
+                "none",
+                "none (no key nor password was given)".to_owned(),
+            ),
         };
+        log::debug!(
+            "SSH authentication of {username}@{hostname}:{port} with {attempt}: {}",
+            if authenticated {
+                "accepted"
+            } else {
+                "rejected"
+            }
+        );
 
         if !authenticated {
-            return Err(anyhow!("Authentication failure"));
+            let mut details = format!(
+                "Authentication failure of user `{username}` on {hostname}:{port}\nAttempted method: {attempt} (rejected by the server)"
+            );
+            let banner = banner
+                .lock()
+                .map(|banner| banner.clone())
+                .unwrap_or_default();
+            if !banner.trim().is_empty() {
+                details += &format!("\nServer banner:\n{}", banner.trim_end());
+            }
+            return Err(anyhow!(details));
+        }
+
+        Ok(Client {
+            handle,
+            auth_method,
+            windows: config.target_os.as_str() == "windows",
+            login_shell: config.login_shell.unwrap_or(false),
+            capabilities: OnceCell::new(),
+            sftp_pool: Mutex::new(Vec::new()),
+            sftp_next: AtomicUsize::new(0),
+            jump,
+        })
+    }
+
+    /// Connect to a jump host
+    ///
+    /// The future is boxed as the connection to a jump host might itself go through another jump host.
+    fn connect_boxed(
+        config: ConnectionSshConfig<'static>,
+    ) -> Pin<Box<dyn Future<Output = Result<Self>> + Send>> {
+        Box::pin(async move { Self::connect(&config).await })
+    }
+
+    /// Get an SFTP session from the pool, opening a new one if the pool is not full yet
+    pub(super) async fn sftp(&self) -> Result<SftpClient> {
+        let mut pool = self.sftp_pool.lock().await;
+        if pool.len() < SFTP_POOL_SIZE {
+            let sftp = SftpClient::new(&self.handle).await?;
+            pool.push(sftp.clone());
+            Ok(sftp)
+        } else {
+            let i = self.sftp_next.fetch_add(1, Ordering::Relaxed) % pool.len();
+            Ok(pool[i].clone())
         }
+    }
+
+    /// Probe what the remote host supports, the result is cached for the lifetime of the client
+    pub(super) async fn capabilities(&self) -> Capabilities {
+        *self
+            .capabilities
+            .get_or_init(|| async {
+                let file_transfer = self.sftp().await.is_ok();
+                let (shell, chown) = if self.windows {
+                    (ShellKind::PowerShell, false)
+                } else {
+                    let mut uid = Vec::new();
+                    let result = self
+                        .execute(
+                            "id -u",
+                            "",
+                            std::iter::empty::<(&String, &String)>(),
+                            &mut uid,
+                        )
+                        .await;
+                    match result {
+                        Ok(res) if res.status == 0 => (ShellKind::Posix, uid.trim_ascii() == b"0"),
+                        _ => (ShellKind::Unknown, false),
+                    }
+                };
+                log::debug!("SSH capabilities: sftp={file_transfer} shell={shell} chown={chown}");
 
-        Ok(Client { handle })
+                Capabilities {
+                    file_transfer,
+                    chown,
+                    symlink: file_transfer,
+                    shell,
+                }
+            })
+            .await
     }
 
+    /// Execute a command, streaming its stdout as raw bytes to `stdout`
+    ///
+    /// The `stdout` of the result is empty.
     pub(super) async fn execute<'a, I, K, V>(
         &self,
         command: &str,
         dir: &str,
         env: I,
+        stdout: &mut (dyn AsyncWrite + Send + Unpin),
     ) -> Result<ExecutionResult>
     where
         I: IntoIterator<Item = (&'a K, &'a V)> + Send + Sync + 'a,
@@ -100,11 +351,30 @@ impl Client {
         K: AsRef<str> + Send + Sync + 'a,
         V: AsRef<str> + Send + Sync + 'a,
     {
-        let mut stdout = Vec::new();
+        let env = env
+            .into_iter()
+            .map(|(name, value)| (name.as_ref(), value.as_ref()))
+            .collect::<Vec<_>>();
         let mut stderr = Vec::new();
-        let mut channel = self.handle.channel_open_session().await?;
+        // Nothing has been executed yet if the session cannot be opened
+        let mut channel = self
+            .handle
+            .channel_open_session()
+            .await
+            .map(CommandChannel::new)
+            .map_err(|err| Error::new(SessionLost(err.into())))?;
 
-        channel.exec(false, "/bin/sh").await?;
+        if self.windows {
+            // The whole script is given on the command line, so stdin is left empty
+            channel
+                .exec(
+                    false,
+                    powershell_command(command, dir, &env, self.login_shell),
+                )
+                .await?;
+        } else {
+            channel.exec(false, "/bin/sh").await?;
+        }
 
         let (tx, mut rx) = tokio::sync::mpsc::channel(10);
 
@@ -115,38 +385,47 @@ impl Client {
             ) -> Result<(), SendError<&'a str>> {
                 tx.send(msg).await
             }
-            // Helper to read input
-            send(&tx, "newline='\n'\nread_stdin() {\nvalue=\nwhile IFS= read -r line; do\nvalue=\"$value$line$newline\"\ndone\nvalue=\"$value$line\"\n}\n").await?;
+            if !self.windows {
+                // Helper to read input
+                send(&tx, "newline='\n'\nread_stdin() {\nvalue=\nwhile IFS= read -r line; do\nvalue=\"$value$line$newline\"\ndone\nvalue=\"$value$line\"\n}\n").await?;
 
-            // Change dir
-            send(&tx, "read_stdin << '__!@#$END_OF_WORKDIR$#@!__'\n").await?;
-            if !dir.is_empty() {
-                send(&tx, dir).await?;
-            }
-            send(&tx, "\n__!@#$END_OF_WORKDIR$#@!__\nvalue=\"${value%%?}\"\n[ -z \"$value\" ] || cd \"$value\"\n").await?;
-
-            // Export env
-            for (name, value) in env {
-                let value = value.as_ref();
-                send(&tx, "read_stdin << '__!@#$END_OF_VARIABLE$#@!__'\n").await?;
-                if !value.is_empty() {
-                    send(&tx, value).await?;
+                // Change dir
+                send(&tx, "read_stdin << '__!@#$END_OF_WORKDIR$#@!__'\n").await?;
+                if !dir.is_empty() {
+                    send(&tx, dir).await?;
                 }
-                send(&tx, "\n__!@#$END_OF_VARIABLE$#@!__\nexport ").await?;
-                send(&tx, name.as_ref()).await?;
-                send(&tx, "=\"${value%%?}\"\n").await?;
-            }
+                send(&tx, "\n__!@#$END_OF_WORKDIR$#@!__\nvalue=\"${value%%?}\"\n[ -z \"$value\" ] || cd \"$value\"\n").await?;
 
-            // Execute command
-            send(
-                &tx,
-                "exec /usr/bin/env bash << '__!@#$END_OF_SCRIPT$#@!__'\n",
-            )
-            .await?;
-            if !command.is_empty() {
-                send(&tx, command).await?;
+                // Export env
+                for &(name, value) in &env {
+                    send(&tx, "read_stdin << '__!@#$END_OF_VARIABLE$#@!__'\n").await?;
+                    if !value.is_empty() {
+                        send(&tx, value).await?;
+                    }
+                    send(&tx, "\n__!@#$END_OF_VARIABLE$#@!__\nexport ").await?;
+                    send(&tx, name).await?;
+                    send(&tx, "=\"${value%%?}\"\n").await?;
+                }
+
+                // Execute command
+                if self.login_shell {
+                    send(
+                        &tx,
+                        "exec /usr/bin/env bash -l << '__!@#$END_OF_SCRIPT$#@!__'\n",
+                    )
+                    .await?;
+                } else {
+                    send(
+                        &tx,
+                        "exec /usr/bin/env bash << '__!@#$END_OF_SCRIPT$#@!__'\n",
+                    )
+                    .await?;
+                }
+                if !command.is_empty() {
+                    send(&tx, command).await?;
+                }
+                send(&tx, "\n__!@#$END_OF_SCRIPT$#@!__\n").await?;
             }
-            send(&tx, "\n__!@#$END_OF_SCRIPT$#@!__\n").await?;
             send(&tx, "").await?; // EOF
             Result::<(), SendError<&'a str>>::Ok(())
         };
@@ -165,9 +444,10 @@ impl Client {
                     msg = channel.wait() => {
                         let Some(msg) = msg else {
                             if let Some(status) = status {
+                                stdout.flush().await?;
                                 return Ok(ExecutionResult {
                                     status,
-                                    stdout: String::from_utf8(stdout)?,
+                                    stdout: String::new(),
                                     stderr: String::from_utf8(stderr)?,
                                 })
                             } else {
@@ -206,25 +486,157 @@ impl Client {
         receive_result
     }
 
-    pub(super) async fn disconnect(&self) -> Result<()> {
-        self.handle
-            .disconnect(russh::Disconnect::ByApplication, "", "")
-            .await
-            .map_err(Into::into)
+    /// Disconnect the client, and then its jump host if any
+    ///
+    /// The future is boxed as the jump host might itself go through another jump host.
+    pub(super) fn disconnect(&self) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+        Box::pin(async move {
+            self.handle
+                .disconnect(russh::Disconnect::ByApplication, "", "")
+                .await?;
+            if let Some(jump) = &self.jump {
+                jump.disconnect().await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Secrets given by the `credential_cmd` of the connection
+#[derive(Debug, Default, Deserialize)]
+struct Credentials {
+    password: Option<String>,
+    key: Option<String>,
+    passphrase: Option<String>,
+}
+
+impl Credentials {
+    /// Run the credential command locally, and parse its JSON output
+    async fn fetch(cmd: &str) -> Result<Self> {
+        let result = ConnectionLocal::default()
+            .execute(
+                &Default::default(),
+                cmd,
+                "",
+                std::iter::empty::<(&String, &String)>(),
+            )
+            .await?;
+        if result.status != 0 {
+            return Err(anyhow!(
+                "Credential command failed with status code {}: {}",
+                result.status,
+                result.stderr
+            ));
+        }
+        // The output is not included in the error as it contains secrets
+        let credentials: Self = serde_json::from_str(&result.stdout)
+            .map_err(|err| anyhow!("Credential command output is not valid JSON: {err}"))?;
+        // The secrets are not part of the connection configuration, so `secrets()` cannot see them
+        for secret in [
+            &credentials.password,
+            &credentials.key,
+            &credentials.passphrase,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            Redactor::add_fetched(secret);
+        }
+        Ok(credentials)
+    }
+}
+
+/// Build a PowerShell invocation that changes directory, sets the environment, and runs the command
+///
+/// The script is passed with `-EncodedCommand` (base64 of UTF-16LE) to avoid any quoting issue.
+/// The profile is only loaded for login shells.
+fn powershell_command(command: &str, dir: &str, env: &[(&str, &str)], login: bool) -> String {
+    fn quote(s: &str) -> String {
+        format!("'{}'", s.replace('\'', "''"))
+    }
+
+    let mut script = String::from("$ErrorActionPreference = 'Stop'\n");
+    if !dir.is_empty() {
+        script += &format!("Set-Location -LiteralPath {}\n", quote(dir));
+    }
+    for (name, value) in env {
+        script += &format!(
+            "[Environment]::SetEnvironmentVariable({}, {})\n",
+            quote(name),
+            quote(value)
+        );
     }
+    script += "& {\n";
+    script += command;
+    script += "\n}\nexit $LASTEXITCODE\n";
+
+    let utf16 = script
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect::<Vec<_>>();
+    format!(
+        "powershell {}-NonInteractive -EncodedCommand {}",
+        if login { "" } else { "-NoProfile " },
+        BASE64_STANDARD.encode(utf16)
+    )
 }
 
 #[derive(Clone)]
-pub(super) struct ClientHandler {}
+pub(super) struct ClientHandler {
+    host: String,
+    port: u16,
+    /// Record the host key on first connection, and reject any other key afterwards
+    tofu: bool,
+    /// Known hosts file used by `tofu` (default: `~/.ssh/known_hosts`)
+    known_hosts: Option<PathBuf>,
+    /// Banner sent by the server before authentication
+    banner: Arc<std::sync::Mutex<String>>,
+}
 
 #[async_trait]
 impl Handler for ClientHandler {
     type Error = Error;
 
+    async fn auth_banner(
+        &mut self,
+        banner: &str,
+        _session: &mut russh::client::Session,
+    ) -> Result<(), Self::Error> {
+        log::debug!("SSH banner of {}:{}: {banner}", self.host, self.port);
+        if let Ok(mut buffer) = self.banner.lock() {
+            buffer.push_str(banner);
+        }
+        Ok(())
+    }
+
     async fn check_server_key(
         &mut self,
-        _server_public_key: &russh_keys::key::PublicKey,
+        server_public_key: &russh_keys::key::PublicKey,
     ) -> Result<bool, Self::Error> {
-        Ok(true)
+        if !self.tofu {
+            return Ok(true);
+        }
+        let (host, port) = (self.host.as_str(), self.port);
+        let known = match &self.known_hosts {
+            Some(path) => russh_keys::check_known_hosts_path(host, port, server_public_key, path),
+            None => russh_keys::check_known_hosts(host, port, server_public_key),
+        };
+        match known {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                match &self.known_hosts {
+                    Some(path) => {
+                        russh_keys::learn_known_hosts_path(host, port, server_public_key, path)?
+                    }
+                    None => russh_keys::learn_known_hosts(host, port, server_public_key)?,
+                }
+                log::info!("Recorded the host key of {host}:{port}");
+                Ok(true)
+            }
+            Err(russh_keys::Error::KeyChanged { line }) => Err(anyhow!(
+                "Host key of {host}:{port} does not match the key recorded at line {line} of the known hosts file"
+            )),
+            Err(err) => Err(err.into()),
+        }
     }
 }
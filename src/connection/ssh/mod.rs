@@ -14,62 +14,438 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::HashMap, pin::Pin, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use crate::{
-    connection::{Connection, ExecutionResult},
+    audit,
+    connection::{
+        command_wrapper_attribute, validate_command_wrapper, validate_temp_dir, wrap_command,
+        Capabilities, Connection, ExecutionResult, FileInfo, FileType, SessionLost,
+    },
+    fault, limits,
     utils::AsyncDrop,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use futures::Future;
+use lazy_static::lazy_static;
 use rusftp::{
-    client::{Error, File, SftpClient},
+    client::{Error, File},
     message::{Attrs, PFlags, Permisions, Status, StatusCode},
 };
 use serde::{Deserialize, Serialize};
 use tf_provider::schema::{Attribute, AttributeConstraint, AttributeType, Description};
-use tf_provider::value::{Value, ValueString};
+use tf_provider::value::{Value, ValueBool, ValueList, ValueNumber, ValueString};
 use tf_provider::{map, AttributePath, Diagnostics};
-use tokio::sync::Mutex;
+use tokio::io::{AsyncSeekExt, AsyncWrite};
+use tokio::sync::{Mutex, OnceCell, OwnedSemaphorePermit};
 
 mod client;
+mod reader;
+pub(crate) mod ssh_config;
+mod writer;
 
 use client::Client;
+pub use reader::SftpReader;
+pub use writer::SftpWriter;
+
+/// Default number of retries of the SFTP operations interrupted by a transient error
+const DEFAULT_SFTP_RETRIES: u32 = 3;
+/// Delay before the first retry, doubled at each subsequent retry
+const SFTP_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+const MAKE_TEMP_POSIX: &str =
+    r#"mktemp $TEMP_KIND "${TEMP_BASE:-${TMPDIR:-/tmp}}/tf-generic.XXXXXXXXXX""#;
+const MAKE_TEMP_POWERSHELL: &str = r#"$base = if ($env:TEMP_BASE) { $env:TEMP_BASE } else { [IO.Path]::GetTempPath() }
+$path = Join-Path $base "tf-generic.$([guid]::NewGuid().ToString('N').Substring(0, 10))"
+(New-Item -ItemType $env:TEMP_KIND -Path $path).FullName"#;
 
 #[derive(Default, Clone)]
 pub struct ConnectionSsh {
-    clients: Arc<Mutex<HashMap<ConnectionSshConfig<'static>, Arc<Client>>>>,
+    _pool: Arc<PoolUser>,
+}
+
+/// Number of `ConnectionSsh` using the client pool, which is disconnected when the last one is dropped
+static POOL_USERS: AtomicUsize = AtomicUsize::new(0);
+/// Number of SSH connections established by the pool
+static POOL_CONNECTS: AtomicUsize = AtomicUsize::new(0);
+/// Number of operations that reused a connection of the pool
+static POOL_REUSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Registration of a `ConnectionSsh` (and its clones) to the client pool
+struct PoolUser;
+
+impl Default for PoolUser {
+    fn default() -> Self {
+        POOL_USERS.fetch_add(1, Ordering::SeqCst);
+        PoolUser
+    }
 }
 
+impl Drop for PoolUser {
+    fn drop(&mut self) {
+        if POOL_USERS.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+        // Blocking on the pool here would stall (or deadlock) the runtime worker dropping the last user:
+        // the clients are released in the background instead
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        runtime.spawn(async {
+            let clients = {
+                let mut clients = CLIENTS.lock().await;
+                // A new user may have registered in the meantime, and could already use the pool
+                if POOL_USERS.load(Ordering::SeqCst) != 0 {
+                    return;
+                }
+                std::mem::take(&mut *clients)
+            };
+            for client in clients.into_values().filter_map(|slot| slot.get().cloned()) {
+                _ = client.disconnect().await;
+            }
+        });
+    }
+}
+
+lazy_static! {
+    /// SSH clients shared by all the resources and provider instances, keyed by their connection configuration
+    ///
+    /// Identical configurations from different aliases or modules reuse the same connection.
+    /// Clients are connected in their own slot, so the map is not locked while connecting.
+    static ref CLIENTS: Mutex<HashMap<ConnectionSshConfig<'static>, ClientSlot>> =
+        Default::default();
+}
+
+/// Client of the pool, connected by the first operation needing it
+type ClientSlot = Arc<OnceCell<Arc<Client>>>;
+
 impl ConnectionSsh {
+    /// Wait until an operation is allowed to run on the target host, according to `max_concurrency`
+    async fn acquire_host<'a>(config: &ConnectionSshConfig<'a>) -> Option<OwnedSemaphorePermit> {
+        let limit = match config.max_concurrency {
+            Value::Value(limit) if limit > 0 => limit as usize,
+            Value::Value(_) => return None,
+            _ => match std::env::var("GENERIC_PROVIDER_SSH_MAX_CONCURRENCY")
+                .ok()
+                .and_then(|limit| limit.parse().ok())
+            {
+                Some(limit) if limit > 0 => limit,
+                _ => return None,
+            },
+        };
+        limits::acquire_operation(&Self::host(config), limit).await
+    }
+
     fn get_client<'a>(
         &'a self,
         config: &ConnectionSshConfig<'a>,
     ) -> impl Future<Output = Result<Arc<Client>>> + Send + 'a {
-        let config = config.clone();
+        let config = config.client_key();
         async move {
-            let mut clients = self.clients.lock().await;
-            let client = match clients.entry(config.extend()) {
-                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
-                std::collections::hash_map::Entry::Vacant(entry) => {
-                    let client = Client::connect(entry.key()).await?;
-                    entry.insert(Arc::new(client))
+            let (slot, count) = {
+                let mut clients = CLIENTS.lock().await;
+                let slot = clients.entry(config.clone()).or_default();
+                if slot.get().is_some_and(|client| client.handle.is_closed()) {
+                    log::warn!(
+                        "SSH connection to {} was closed, reconnecting",
+                        config.host.as_str()
+                    );
+                    *slot = Default::default();
                 }
+                (slot.clone(), clients.len())
             };
 
-            Ok(client.clone())
+            if slot.initialized() {
+                POOL_REUSES.fetch_add(1, Ordering::Relaxed);
+            }
+            // Concurrent operations on the same target wait for the same connection
+            let client = slot
+                .get_or_try_init(|| async {
+                    POOL_CONNECTS.fetch_add(1, Ordering::Relaxed);
+                    Client::connect(&config).await.map(Arc::new)
+                })
+                .await?
+                .clone();
+            log::debug!(
+                "SSH pool: {} clients, {} connections established, {} reuses, {} users",
+                count,
+                POOL_CONNECTS.load(Ordering::Relaxed),
+                POOL_REUSES.load(Ordering::Relaxed),
+                POOL_USERS.load(Ordering::Relaxed),
+            );
+
+            Ok(client)
         }
     }
+
+    /// Open a remote file for writing with a given client
+    async fn write_with<'a>(
+        ssh: &Client,
+        config: &ConnectionSshConfig<'a>,
+        path: &str,
+        mode: u32,
+        overwrite: bool,
+    ) -> Result<SftpWriter> {
+        let sftp = ssh.sftp().await?;
+
+        let mut flags = PFlags::WRITE | PFlags::CREATE;
+        if overwrite {
+            flags |= PFlags::TRUNCATE;
+        } else {
+            // Check if file exist in case the EXCLUDE flag is not taken into account
+            match sftp.lstat(path).await {
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        "File already exists",
+                    )
+                    .into())
+                }
+                Err(Error::Sftp(Status {
+                    code: StatusCode::NoSuchFile,
+                    ..
+                })) => (),
+                Err(err) => {
+                    return Err(err.into());
+                }
+            }
+            flags |= PFlags::EXCLUDE;
+        }
+
+        // Windows has no POSIX permissions
+        let perms = if config.is_windows() {
+            None
+        } else {
+            Some(Permisions::from_bits_retain(mode))
+        };
+        let file = sftp
+            .open_with_flags_attrs(
+                path,
+                flags,
+                Attrs {
+                    perms,
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        let buffer_size = match config.write_buffer_size {
+            Value::Value(size) if size > 0 => size as usize,
+            _ => writer::DEFAULT_BUFFER_SIZE,
+        };
+        Ok(SftpWriter::new(file, buffer_size))
+    }
+
+    /// Remove a client from the pool, unless it has already been replaced
+    async fn forget_client<'a>(&self, config: &ConnectionSshConfig<'a>, client: &Arc<Client>) {
+        let mut clients = CLIENTS.lock().await;
+        let key = config.client_key();
+        if clients
+            .get(&key)
+            .and_then(|slot| slot.get())
+            .is_some_and(|cached| Arc::ptr_eq(cached, client))
+        {
+            clients.remove(&key);
+        }
+    }
+
+    /// Run an operation with a client of the pool, once the target host allows it according to `max_concurrency`
+    async fn with_client<'a, T, F, Fut>(
+        &self,
+        config: &ConnectionSshConfig<'a>,
+        idempotent: bool,
+        op: F,
+    ) -> Result<T>
+    where
+        F: Fn(Arc<Client>) -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send,
+        T: Send,
+    {
+        let _permit = Self::acquire_host(&config.with_preset()).await;
+        self.with_client_permitted(config, idempotent, op).await
+    }
+
+    /// Run an operation with a client of the pool, reconnecting and retrying if the connection was lost
+    ///
+    /// Operations that are not `idempotent` are only retried once, and only if they did not start on the remote host.
+    /// Idempotent operations are retried up to `sftp_retries` times on transient errors, with an exponential backoff.
+    /// The caller holds the permit of the target host, if any.
+    async fn with_client_permitted<'a, T, F, Fut>(
+        &self,
+        config: &ConnectionSshConfig<'a>,
+        idempotent: bool,
+        op: F,
+    ) -> Result<T>
+    where
+        F: Fn(Arc<Client>) -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send,
+        T: Send,
+    {
+        let config = &config.with_preset();
+        let retries = if idempotent { config.sftp_retries() } else { 1 };
+        let mut client = self.get_client(config).await?;
+        let mut attempt = 0;
+        loop {
+            let err = match op(client.clone()).await {
+                Ok(result) => return Ok(result),
+                Err(err) => err,
+            };
+            let lost = err.is::<SessionLost>() || (idempotent && client.handle.is_closed());
+            if attempt >= retries || !(lost || (idempotent && is_transient(&err))) {
+                return Err(err);
+            }
+            attempt += 1;
+            if lost {
+                log::warn!(
+                    "SSH connection to {} lost, reconnecting: {err}",
+                    config.host.as_str()
+                );
+                self.forget_client(config, &client).await;
+            } else {
+                log::warn!(
+                    "Transient SFTP error on {}, retrying ({attempt}/{retries}): {err}",
+                    config.host.as_str()
+                );
+            }
+            if idempotent {
+                tokio::time::sleep(SFTP_RETRY_DELAY * 2u32.pow((attempt - 1).min(6))).await;
+            }
+            client = self.get_client(config).await?;
+        }
+    }
+
+    /// Open a remote file for reading at the given offset
+    ///
+    /// The reader holds the permit of the target host for the whole transfer.
+    async fn open_read<'a>(
+        &self,
+        config: &ConnectionSshConfig<'a>,
+        path: &str,
+        offset: u64,
+    ) -> Result<File> {
+        self.with_client_permitted(config, true, |ssh| async move {
+            let sftp = ssh.sftp().await?;
+            let mut file = match sftp.open_with_flags(path, PFlags::READ).await {
+                Ok(file) => file,
+                Err(Error::Sftp(Status {
+                    code: StatusCode::NoSuchFile,
+                    ..
+                })) => {
+                    return Err(
+                        std::io::Error::new(std::io::ErrorKind::NotFound, "No such file").into(),
+                    )
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if offset > 0 {
+                file.seek(std::io::SeekFrom::Start(offset)).await?;
+            }
+            Ok(file)
+        })
+        .await
+    }
+}
+
+/// Check if an error is worth retrying, because the connection or the channel was interrupted
+fn is_transient(err: &anyhow::Error) -> bool {
+    if err.is::<SessionLost>() {
+        return true;
+    }
+    if let Some(err) = err.downcast_ref::<std::io::Error>() {
+        return is_transient_io(err);
+    }
+    matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::Sftp(Status {
+            code: StatusCode::NoConnection | StatusCode::ConnectionLost,
+            ..
+        }))
+    )
 }
 
-impl Drop for ConnectionSsh {
+/// Check if an I/O error is worth retrying, like an EOF in the middle of a transfer
+fn is_transient_io(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::UnexpectedEof
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::BrokenPipe
+        | ErrorKind::NotConnected
+        | ErrorKind::TimedOut => true,
+        _ => err.get_ref().is_some_and(|inner| {
+            matches!(
+                inner.downcast_ref::<Error>(),
+                Some(Error::Sftp(Status {
+                    code: StatusCode::NoConnection | StatusCode::ConnectionLost,
+                    ..
+                }))
+            )
+        }),
+    }
+}
+
+/// Local port forwarded through an SSH connection, closed when dropped
+pub struct Tunnel {
+    listener: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for Tunnel {
     fn drop(&mut self) {
-        let clients = Pin::new(futures::executor::block_on(self.clients.lock()));
+        self.listener.abort();
+    }
+}
 
-        for (_, client) in clients.iter() {
-            _ = futures::executor::block_on(client.disconnect());
-        }
+impl ConnectionSsh {
+    /// Forward the connections to `local_port` to `remote_host:remote_port`, until the tunnel is dropped
+    pub async fn open_tunnel<'a>(
+        &self,
+        config: &ConnectionSshConfig<'a>,
+        local_port: u16,
+        remote_host: &str,
+        remote_port: u16,
+    ) -> Result<Tunnel> {
+        let client = self.get_client(config).await?;
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", local_port))
+            .await
+            .map_err(|err| anyhow!("Could not listen on local port {local_port}: {err}"))?;
+        let remote_host = remote_host.to_owned();
+
+        let listener = tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let client = client.clone();
+                let remote_host = remote_host.clone();
+                tokio::spawn(async move {
+                    let channel = client
+                        .handle
+                        .channel_open_direct_tcpip(
+                            remote_host.as_str(),
+                            remote_port as u32,
+                            "127.0.0.1",
+                            local_port as u32,
+                        )
+                        .await;
+                    match channel {
+                        Ok(channel) => {
+                            let mut stream = channel.into_stream();
+                            _ = tokio::io::copy_bidirectional(&mut socket, &mut stream).await;
+                        }
+                        Err(err) => log::warn!(
+                            "Could not forward local port {local_port} to {remote_host}:{remote_port}: {err}"
+                        ),
+                    }
+                });
+            }
+        });
+        Ok(Tunnel { listener })
     }
 }
 
@@ -81,6 +457,23 @@ pub struct ConnectionSshConfig<'a> {
     pub password: ValueString<'a>,
     pub key: ValueString<'a>,
     pub keyfile: ValueString<'a>,
+    pub credential_cmd: ValueString<'a>,
+    pub dir: ValueString<'a>,
+    pub write_buffer_size: ValueNumber,
+    pub max_concurrency: ValueNumber,
+    pub window_size: ValueNumber,
+    pub max_packet_size: ValueNumber,
+    pub sftp_retries: ValueNumber,
+    pub target_os: ValueString<'a>,
+    pub locale: ValueString<'a>,
+    pub path_prepend: ValueList<ValueString<'a>>,
+    pub login_shell: ValueBool,
+    pub tofu: ValueBool,
+    pub known_hosts_file: ValueString<'a>,
+    pub use_ssh_config: ValueBool,
+    pub command_wrapper: ValueString<'a>,
+    pub temp_dir: ValueString<'a>,
+    pub preset: ValueString<'a>,
 }
 
 impl<'a> ConnectionSshConfig<'a> {
@@ -92,6 +485,188 @@ impl<'a> ConnectionSshConfig<'a> {
             password: self.password.extend(),
             key: self.key.extend(),
             keyfile: self.keyfile.extend(),
+            credential_cmd: self.credential_cmd.extend(),
+            dir: self.dir.extend(),
+            write_buffer_size: self.write_buffer_size,
+            max_concurrency: self.max_concurrency,
+            window_size: self.window_size,
+            max_packet_size: self.max_packet_size,
+            sftp_retries: self.sftp_retries,
+            target_os: self.target_os.extend(),
+            locale: self.locale.extend(),
+            path_prepend: match self.path_prepend {
+                Value::Value(dirs) => {
+                    Value::Value(dirs.into_iter().map(|dir| dir.extend()).collect())
+                }
+                Value::Null => Value::Null,
+                Value::Unknown => Value::Unknown,
+            },
+            login_shell: self.login_shell,
+            tofu: self.tofu,
+            known_hosts_file: self.known_hosts_file.extend(),
+            use_ssh_config: self.use_ssh_config,
+            command_wrapper: self.command_wrapper.extend(),
+            temp_dir: self.temp_dir.extend(),
+            preset: self.preset.extend(),
+        }
+    }
+
+    /// Configuration with the attributes missing from the block taken from the `preset`
+    ///
+    /// With `preset = "env:PREFIX"`, the variables `PREFIX_HOST`, `PREFIX_PORT`, `PREFIX_USER`,
+    /// `PREFIX_PASSWORD`, `PREFIX_KEY` and `PREFIX_KEYFILE` are read when connecting.
+    fn with_preset(&self) -> ConnectionSshConfig<'static> {
+        let mut config = self.clone().extend();
+        let Some(prefix) = self
+            .preset
+            .as_deref_option()
+            .and_then(|preset| preset.strip_prefix("env:"))
+        else {
+            return config;
+        };
+        let var = |name: &str| std::env::var(format!("{prefix}_{name}")).ok();
+        for (name, value) in [
+            ("HOST", &mut config.host),
+            ("USER", &mut config.user),
+            ("PASSWORD", &mut config.password),
+            ("KEY", &mut config.key),
+            ("KEYFILE", &mut config.keyfile),
+        ] {
+            if value.is_null() {
+                if let Some(var) = var(name) {
+                    *value = Value::Value(Cow::Owned(var));
+                }
+            }
+        }
+        if config.port.is_null() {
+            if let Some(port) = var("PORT").and_then(|port| port.parse().ok()) {
+                config.port = Value::Value(port);
+            }
+        }
+        config.preset = Value::Null;
+        config
+    }
+
+    /// Configuration to connect to the jump host `[user@]host[:port]` of a `ProxyJump`
+    ///
+    /// The jump host is reached with the same credentials, unless the ssh config says otherwise.
+    fn jump_config(&self, proxy_jump: &str) -> Result<ConnectionSshConfig<'static>> {
+        if proxy_jump.contains(',') {
+            return Err(anyhow!(
+                "Multiple jump hosts are not supported in ProxyJump `{proxy_jump}`"
+            ));
+        }
+        let (user, host) = match proxy_jump.rsplit_once('@') {
+            Some((user, host)) => (Value::Value(Cow::Owned(user.to_owned())), host),
+            None => (Value::Null, proxy_jump),
+        };
+        let (host, port) = match host.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                Value::Value(
+                    port.parse()
+                        .map_err(|_| anyhow!("Invalid port in ProxyJump `{proxy_jump}`"))?,
+                ),
+            ),
+            None => (host, Value::Null),
+        };
+        Ok(ConnectionSshConfig {
+            host: Value::Value(Cow::Owned(host.to_owned())),
+            port,
+            user,
+            password: self.password.clone().extend(),
+            key: self.key.clone().extend(),
+            keyfile: self.keyfile.clone().extend(),
+            credential_cmd: self.credential_cmd.clone().extend(),
+            tofu: self.tofu,
+            known_hosts_file: self.known_hosts_file.clone().extend(),
+            window_size: self.window_size,
+            max_packet_size: self.max_packet_size,
+            use_ssh_config: Value::Value(true),
+            ..Default::default()
+        })
+    }
+
+    /// Key of the client in the pool: only the fields used to establish the connection are kept
+    fn client_key(&self) -> ConnectionSshConfig<'static> {
+        ConnectionSshConfig {
+            dir: Value::Null,
+            write_buffer_size: Value::Null,
+            max_concurrency: Value::Null,
+            sftp_retries: Value::Null,
+            locale: Value::Null,
+            path_prepend: Value::Null,
+            command_wrapper: Value::Null,
+            temp_dir: Value::Null,
+            ..self.with_preset()
+        }
+    }
+
+    fn is_windows(&self) -> bool {
+        self.target_os.as_str() == "windows"
+    }
+
+    fn sftp_retries(&self) -> u32 {
+        match self.sftp_retries {
+            Value::Value(retries) => retries.clamp(0, u32::MAX as i64) as u32,
+            _ => DEFAULT_SFTP_RETRIES,
+        }
+    }
+
+    /// Prefix the command with the update of `PATH`
+    fn with_path_prepend<'b>(&self, cmd: &'b str) -> Cow<'b, str> {
+        let dirs = self
+            .path_prepend
+            .iter()
+            .flatten()
+            .filter_map(|dir| dir.as_deref_option())
+            .collect::<Vec<_>>();
+        if dirs.is_empty() {
+            return Cow::Borrowed(cmd);
+        }
+        if self.is_windows() {
+            let dirs = dirs.join(";").replace('\'', "''");
+            Cow::Owned(format!("$env:PATH = '{dirs};' + $env:PATH\n{cmd}"))
+        } else {
+            let dirs = dirs
+                .iter()
+                .map(|dir| format!("'{}'", dir.replace('\'', r"'\''")))
+                .collect::<Vec<_>>()
+                .join(":");
+            Cow::Owned(format!("export PATH={dirs}:\"$PATH\"\n{cmd}"))
+        }
+    }
+
+    /// Path as understood by the SFTP server
+    ///
+    /// Windows OpenSSH expects `/C:/dir/file` instead of `C:\dir\file`
+    fn sftp_path<'b>(&self, path: &'b str) -> Cow<'b, str> {
+        if !self.is_windows() {
+            return Cow::Borrowed(path);
+        }
+        let path = path.replace('\\', "/");
+        match path.as_bytes() {
+            [drive, b':', ..] if drive.is_ascii_alphabetic() => Cow::Owned(format!("/{path}")),
+            _ => Cow::Owned(path),
+        }
+    }
+}
+
+impl From<Attrs> for FileInfo {
+    fn from(value: Attrs) -> Self {
+        let perms = value.perms.map_or(0, |perms| perms.bits());
+        let file_type = match perms & 0o170000 {
+            0o100000 => FileType::File,
+            0o040000 => FileType::Dir,
+            0o120000 => FileType::Symlink,
+            _ => FileType::Other,
+        };
+
+        Self {
+            size: value.size.unwrap_or(0),
+            mode: perms & 0o7777,
+            mtime: value.time.map_or(0, |time| time.mtime as u64),
+            file_type,
         }
     }
 }
@@ -100,8 +675,8 @@ impl<'a> ConnectionSshConfig<'a> {
 impl Connection for ConnectionSsh {
     const NAME: &'static str = "ssh";
     type Config<'a> = ConnectionSshConfig<'a>;
-    type Reader = File;
-    type Writer = File;
+    type Reader = SftpReader;
+    type Writer = SftpWriter;
 
     async fn execute<'a, 'b, I, K, V>(
         &self,
@@ -117,17 +692,104 @@ impl Connection for ConnectionSsh {
         K: AsRef<str> + Send + Sync + 'b,
         V: AsRef<str> + Send + Sync + 'b,
     {
-        let client = self.get_client(config).await?;
-        let result = client.execute(cmd, dir, env).await?;
-        Ok(result)
+        let mut stdout = Vec::new();
+        let result = self
+            .execute_piped(config, cmd, dir, env, &mut stdout)
+            .await?;
+        Ok(ExecutionResult {
+            stdout: String::from_utf8(stdout)?,
+            ..result
+        })
+    }
+
+    async fn execute_piped<'a, 'b, I, K, V>(
+        &self,
+        config: &Self::Config<'a>,
+        cmd: &str,
+        dir: &str,
+        env: I,
+        stdout: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<ExecutionResult>
+    where
+        'a: 'b,
+        I: IntoIterator<Item = (&'b K, &'b V)> + Send + Sync + 'b,
+        I::IntoIter: Send + Sync + 'b,
+        K: AsRef<str> + Send + Sync + 'b,
+        V: AsRef<str> + Send + Sync + 'b,
+    {
+        audit::record::<Self, _>(config, cmd, async move {
+            let dir = if dir.is_empty() {
+                config.dir.as_str()
+            } else {
+                dir
+            };
+            let (target, original) = (&Self::target(config), cmd);
+            // Commands get their own temporary directory, removed once they complete
+            let temp_dir = match config.temp_dir.as_deref_option() {
+                Some(base) => Some(self.make_temp(config, base, true).await?),
+                None => None,
+            };
+            let temp_vars: &[&str] = if config.is_windows() {
+                &["TEMP", "TMP"]
+            } else {
+                &["TMPDIR"]
+            };
+            let locale = config.locale.as_str();
+            let env = [("LANG", locale), ("LC_ALL", locale)]
+                .into_iter()
+                .filter(|_| !locale.is_empty())
+                .chain(
+                    temp_dir
+                        .iter()
+                        .flat_map(|dir| temp_vars.iter().map(move |&name| (name, dir.as_str()))),
+                )
+                .chain(env.into_iter().map(|(k, v)| (k.as_ref(), v.as_ref())))
+                .collect::<Vec<_>>();
+            let cmd = config.with_path_prepend(cmd);
+            let cmd = wrap_command(config.command_wrapper.as_deref_option(), &cmd);
+            let (cmd, env) = (&cmd, &env);
+            let _permit = limits::acquire_command(&Self::host(config)).await;
+            // A command is only retried if it could not start, so nothing has been written yet
+            let stdout = &Mutex::new(stdout);
+            let result = self
+                .with_client(config, false, |client| async move {
+                    // Faults are injected per attempt, so a dropped session is retried like a real one
+                    if let Some(result) = fault::inject(target, original).await {
+                        return result;
+                    }
+                    client
+                        .execute(
+                            cmd,
+                            dir,
+                            env.iter().map(|(k, v)| (k, v)),
+                            &mut **stdout.lock().await,
+                        )
+                        .await
+                })
+                .await;
+            if let Some(temp_dir) = &temp_dir {
+                if let Err(err) = self.delete_recursive(config, temp_dir).await {
+                    log::warn!("Could not remove temporary directory {temp_dir}: {err}");
+                }
+            }
+            result
+        })
+        .await
     }
 
     /// Return a reader to read a remote file
     async fn read<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<Self::Reader> {
-        let ssh = self.get_client(config).await?;
-        let sftp = SftpClient::new(&ssh.handle).await?;
+        let path = config.sftp_path(path).into_owned();
+        // The transfer counts as a single operation on the host until the reader is dropped
+        let permit = Self::acquire_host(&config.with_preset()).await;
+        let file = self.open_read(config, &path, 0).await?;
 
-        Ok(sftp.open_with_flags(path, PFlags::READ).await?)
+        let (connect, config_owned) = (self.clone(), config.clone().extend());
+        let reopen: reader::Reopen = Box::new(move |offset| {
+            let (connect, config, path) = (connect.clone(), config_owned.clone(), path.clone());
+            Box::pin(async move { connect.open_read(&config, &path, offset).await })
+        });
+        Ok(SftpReader::new(file, config.sftp_retries(), reopen, permit))
     }
 
     /// Return a writer to write a remote file
@@ -138,53 +800,301 @@ impl Connection for ConnectionSsh {
         mode: u32,
         overwrite: bool,
     ) -> Result<Self::Writer> {
-        let ssh = self.get_client(config).await?;
-        let sftp = SftpClient::new(&ssh.handle).await?;
+        let path = config.sftp_path(path);
+        let path = path.as_ref();
 
-        let mut flags = PFlags::WRITE | PFlags::CREATE;
-        if overwrite {
-            flags |= PFlags::TRUNCATE;
-        } else {
-            // Check if file exist in case the EXCLUDE flag is not taken into account
-            match sftp.lstat(path).await {
-                Ok(_) => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::AlreadyExists,
-                        "File already exists",
-                    )
-                    .into())
-                }
+        // The transfer counts as a single operation on the host until the writer is dropped
+        let permit = Self::acquire_host(&config.with_preset()).await;
+        let writer = self
+            .with_client_permitted(config, true, |ssh| async move {
+                Self::write_with(&ssh, config, path, mode, overwrite).await
+            })
+            .await?;
+        Ok(writer.holding(permit))
+    }
+
+    /// Get the information of a remote file
+    async fn stat<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<FileInfo> {
+        let path = config.sftp_path(path);
+        let path = path.as_ref();
+
+        self.with_client(config, true, |client| async move {
+            let sftp = client.sftp().await?;
+
+            match sftp.stat(path).await {
+                Ok(attrs) => Ok(attrs.into()),
                 Err(Error::Sftp(Status {
                     code: StatusCode::NoSuchFile,
                     ..
-                })) => (),
-                Err(err) => {
-                    return Err(err.into());
+                })) => {
+                    Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No such file").into())
                 }
+                Err(err) => Err(err.into()),
             }
-            flags |= PFlags::EXCLUDE;
-        }
+        })
+        .await
+    }
 
-        let file = sftp
-            .open_with_flags_attrs(
-                path,
-                flags,
-                Attrs {
-                    perms: Some(Permisions::from_bits_retain(mode)),
-                    ..Default::default()
-                },
-            )
+    /// List the entries of a remote directory
+    async fn read_dir<'a>(
+        &self,
+        config: &Self::Config<'a>,
+        path: &str,
+    ) -> Result<Vec<(String, FileInfo)>> {
+        let path = config.sftp_path(path);
+        let path = path.as_ref();
+
+        self.with_client(config, true, |client| async move {
+            let sftp = client.sftp().await?;
+
+            match sftp.readdir(path).await {
+                Ok(name) => Ok(name
+                    .0
+                    .into_iter()
+                    .map(|entry| (entry.filename.0, entry.attrs.into()))
+                    .filter(|(name, _)| name != "." && name != "..")
+                    .collect()),
+                Err(Error::Sftp(Status {
+                    code: StatusCode::NoSuchFile,
+                    ..
+                })) => Err(
+                    std::io::Error::new(std::io::ErrorKind::NotFound, "No such directory").into(),
+                ),
+                Err(err) => Err(err.into()),
+            }
+        })
+        .await
+    }
+
+    /// Rename a file
+    ///
+    /// Depending on the SFTP server, the rename might fail if `to` already exists
+    async fn rename<'a>(&self, config: &Self::Config<'a>, from: &str, to: &str) -> Result<()> {
+        let (from, to) = (config.sftp_path(from), config.sftp_path(to));
+        let (from, to) = (from.as_ref(), to.as_ref());
+
+        self.with_client(config, true, |client| async move {
+            let sftp = client.sftp().await?;
+            Ok(sftp.rename(from, to).await?)
+        })
+        .await
+    }
+
+    /// Copy a file
+    ///
+    /// SFTP has no copy primitive, so the copy is done remotely with `cp`
+    async fn copy<'a>(&self, config: &Self::Config<'a>, from: &str, to: &str) -> Result<()> {
+        let env = HashMap::from([("COPY_FROM", from), ("COPY_TO", to)]);
+        let cmd = if config.is_windows() {
+            "Copy-Item -LiteralPath $env:COPY_FROM -Destination $env:COPY_TO -Force"
+        } else {
+            r#"cp -p -- "$COPY_FROM" "$COPY_TO""#
+        };
+        let env = &env;
+        let result = self
+            .with_client(config, true, |client| async move {
+                client.execute(cmd, "", env, &mut tokio::io::sink()).await
+            })
             .await?;
 
-        Ok(file)
+        if result.status == 0 {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Could not copy `{from}` to `{to}` (status code {}): {}",
+                result.status,
+                result.stderr
+            ))
+        }
     }
 
     /// Delete a file
     async fn delete<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<()> {
+        let path = config.sftp_path(path);
+        let path = path.as_ref();
+
+        self.with_client(config, true, |client| async move {
+            let sftp = client.sftp().await?;
+            Ok(sftp.remove(path).await?)
+        })
+        .await
+    }
+
+    /// Delete a file, or a directory with all its content
+    ///
+    /// The directory is walked over SFTP, without executing any command
+    async fn delete_recursive<'a>(&self, config: &Self::Config<'a>, path: &str) -> Result<()> {
+        let path = config.sftp_path(path);
+        let path = path.as_ref();
+
+        self.with_client(config, true, |client| async move {
+            let sftp = client.sftp().await?;
+
+            let info: FileInfo = match sftp.lstat(path).await {
+                Ok(attrs) => attrs.into(),
+                Err(Error::Sftp(Status {
+                    code: StatusCode::NoSuchFile,
+                    ..
+                })) => {
+                    return Err(
+                        std::io::Error::new(std::io::ErrorKind::NotFound, "No such file").into(),
+                    )
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if info.file_type != FileType::Dir {
+                return Ok(sftp.remove(path).await?);
+            }
+
+            // A directory is removed once all its content has been removed
+            let mut stack = vec![(path.to_owned(), false)];
+            while let Some((dir, emptied)) = stack.pop() {
+                if emptied {
+                    sftp.rmdir(dir.as_str()).await?;
+                    continue;
+                }
+                stack.push((dir.clone(), true));
+                for entry in sftp.readdir(dir.as_str()).await?.0 {
+                    let name = entry.filename.0;
+                    if name == "." || name == ".." {
+                        continue;
+                    }
+                    let child = format!("{}/{name}", dir.trim_end_matches('/'));
+                    let info: FileInfo = entry.attrs.into();
+                    if info.file_type == FileType::Dir {
+                        stack.push((child, false));
+                    } else {
+                        sftp.remove(child.as_str()).await?;
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Create a unique temporary file or directory with `mktemp`, or `New-Item` on Windows
+    async fn make_temp<'a>(
+        &self,
+        config: &Self::Config<'a>,
+        base: &str,
+        directory: bool,
+    ) -> Result<String> {
+        let (cmd, kind) = match (config.is_windows(), directory) {
+            (true, true) => (MAKE_TEMP_POWERSHELL, "Directory"),
+            (true, false) => (MAKE_TEMP_POWERSHELL, "File"),
+            (false, true) => (MAKE_TEMP_POSIX, "-d"),
+            (false, false) => (MAKE_TEMP_POSIX, ""),
+        };
+        let env = HashMap::from([("TEMP_BASE", base), ("TEMP_KIND", kind)]);
+        let env = &env;
+        let (result, stdout) = self
+            .with_client(config, false, |client| async move {
+                let mut stdout = Vec::new();
+                let result = client.execute(cmd, "", env, &mut stdout).await?;
+                Ok((result, stdout))
+            })
+            .await?;
+
+        let stdout = String::from_utf8_lossy(&stdout);
+        let path = stdout.trim();
+        if result.status == 0 && !path.is_empty() {
+            Ok(path.to_owned())
+        } else {
+            Err(anyhow!(
+                "Could not create temporary file in `{base}` (status code {}): {}",
+                result.status,
+                result.stderr
+            ))
+        }
+    }
+
+    /// Establish the connection, and return the authentication method that was used
+    async fn connect<'a>(&self, config: &Self::Config<'a>) -> Result<&'static str> {
         let client = self.get_client(config).await?;
-        let client = SftpClient::new(&client.handle).await?;
+        Ok(client.auth_method)
+    }
+
+    /// Discover what the connection target supports
+    async fn capabilities<'a>(&self, config: &Self::Config<'a>) -> Result<Capabilities> {
+        let client = self.get_client(config).await?;
+        Ok(client.capabilities().await)
+    }
+
+    fn target<'a>(config: &Self::Config<'a>) -> String {
+        let config = config.with_preset();
+        let port = config.port.unwrap_or_default();
+        let port = if port == 0 { 22 } else { port };
+        let user = config.user.as_str();
+        if user.is_empty() {
+            format!("ssh://{}:{port}", config.host.as_str())
+        } else {
+            format!("ssh://{user}@{}:{port}", config.host.as_str())
+        }
+    }
+
+    /// The user is not part of the host, so the limits apply to all the users of a host
+    fn host<'a>(config: &Self::Config<'a>) -> String {
+        let config = config.with_preset();
+        let port = config.port.unwrap_or_default();
+        let port = if port == 0 { 22 } else { port };
+        format!("ssh://{}:{port}", config.host.as_str())
+    }
+
+    fn secrets<'a, 'b>(config: &'b Self::Config<'a>) -> Vec<Cow<'b, str>> {
+        let mut secrets = [&config.password, &config.key]
+            .into_iter()
+            .filter_map(|secret| secret.as_deref_option())
+            .filter(|secret| !secret.is_empty())
+            .map(Cow::Borrowed)
+            .collect::<Vec<_>>();
+        // Secrets of a preset are read from the environment
+        if config.preset.is_value() {
+            let resolved = config.with_preset();
+            secrets.extend(
+                [resolved.password, resolved.key]
+                    .into_iter()
+                    .filter_map(|secret| secret.as_deref_option().map(str::to_owned))
+                    .filter(|secret| !secret.is_empty())
+                    .map(Cow::Owned),
+            );
+        }
+        secrets
+    }
 
-        Ok(client.remove(path).await?)
+    fn has_unknown<'a>(config: &Self::Config<'a>) -> bool {
+        [
+            &config.host,
+            &config.user,
+            &config.password,
+            &config.key,
+            &config.keyfile,
+            &config.credential_cmd,
+            &config.dir,
+            &config.target_os,
+            &config.locale,
+            &config.known_hosts_file,
+            &config.command_wrapper,
+            &config.temp_dir,
+            &config.preset,
+        ]
+        .into_iter()
+        .any(Value::is_unknown)
+            || [
+                config.write_buffer_size,
+                config.max_concurrency,
+                config.window_size,
+                config.max_packet_size,
+                config.sftp_retries,
+            ]
+            .into_iter()
+            .any(|value| value.is_unknown())
+            || [config.login_shell, config.tofu, config.use_ssh_config]
+                .into_iter()
+                .any(|value| value.is_unknown())
+            || config.port.is_unknown()
+            || config.path_prepend.is_unknown()
+            || config.path_prepend.iter().flatten().any(Value::is_unknown)
     }
 
     /// Validate the state is valid
@@ -194,6 +1104,19 @@ impl Connection for ConnectionSsh {
         attr_path: AttributePath,
         config: &Self::Config<'a>,
     ) -> Option<()> {
+        if let Value::Value(preset) = &config.preset {
+            if preset
+                .strip_prefix("env:")
+                .is_none_or(|prefix| prefix.is_empty())
+            {
+                diags.error(
+                    "Invalid `preset`",
+                    format!("Preset must be of the form `env:PREFIX`, but was `{preset}`."),
+                    attr_path.clone().attribute("preset"),
+                );
+                return None;
+            }
+        }
         match &config.host {
             Value::Value(host) => {
                 if host.is_empty() {
@@ -201,21 +1124,108 @@ impl Connection for ConnectionSsh {
                     return None;
                 }
             }
+            Value::Null if config.preset.is_value() => {
+                if config.with_preset().host.is_null() {
+                    diags.error(
+                        "`hostname` cannot be null",
+                        "`host` is neither set nor given by the environment variables of the `preset`.",
+                        attr_path.attribute("host"),
+                    );
+                    return None;
+                }
+            }
             Value::Null => {
                 diags.error_short("`hostname` cannot be null", attr_path.attribute("host"));
                 return None;
             }
             Value::Unknown => (),
         }
-        Some(())
+        if let Value::Value(target_os) = &config.target_os {
+            if !matches!(target_os.as_ref(), "linux" | "windows") {
+                diags.error(
+                    "Invalid `target_os`",
+                    format!(
+                        "Target OS must be either `linux` or `windows`, but was `{target_os}`."
+                    ),
+                    attr_path.clone().attribute("target_os"),
+                );
+                return None;
+            }
+        }
+        if config.command_wrapper.is_value() && config.is_windows() {
+            diags.error_short(
+                "`command_wrapper` is not supported on Windows targets",
+                attr_path.clone().attribute("command_wrapper"),
+            );
+            return None;
+        }
+        validate_command_wrapper(diags, attr_path.clone(), &config.command_wrapper);
+        validate_temp_dir(diags, attr_path.clone(), &config.temp_dir);
+        if config.known_hosts_file.is_value()
+            && !config.tofu.is_unknown()
+            && !config.tofu.unwrap_or(false)
+        {
+            diags.warning(
+                "`known_hosts_file` is ignored",
+                "The known hosts file is only used when `tofu` is enabled.",
+                attr_path.clone().attribute("known_hosts_file"),
+            );
+        }
+        if let Value::Value(size) = config.write_buffer_size {
+            if size <= 0 {
+                diags.error(
+                    "Invalid `write_buffer_size`",
+                    format!("Write buffer size must be positive, but was {size}."),
+                    attr_path.clone().attribute("write_buffer_size"),
+                );
+                return None;
+            }
+        }
+        if let Value::Value(retries) = config.sftp_retries {
+            if retries < 0 {
+                diags.error(
+                    "Invalid `sftp_retries`",
+                    format!("Number of retries cannot be negative, but was {retries}."),
+                    attr_path.clone().attribute("sftp_retries"),
+                );
+                return None;
+            }
+        }
+        if let Value::Value(limit) = config.max_concurrency {
+            if limit < 0 {
+                diags.error(
+                    "Invalid `max_concurrency`",
+                    format!("Maximum concurrency cannot be negative, but was {limit}."),
+                    attr_path.clone().attribute("max_concurrency"),
+                );
+                return None;
+            }
+        }
+        for (name, size) in [
+            ("window_size", config.window_size),
+            ("max_packet_size", config.max_packet_size),
+        ] {
+            if let Value::Value(size) = size {
+                if size <= 0 || size > u32::MAX as i64 {
+                    diags.error(
+                        format!("Invalid `{name}`"),
+                        format!("Size must be between 1 and {}, but was {size}.", u32::MAX),
+                        attr_path.clone().attribute(name),
+                    );
+                    return None;
+                }
+            }
+        }
+        // Keys given by a preset are checked too
+        validate_key(diags, attr_path, &config.with_preset())
     }
 
     fn schema() -> HashMap<String, Attribute> {
         map! {
             "host" => Attribute {
                 attr_type: AttributeType::String,
-                description: Description::plain("Hostname to connect to"),
-                constraint: AttributeConstraint::Required,
+                description: Description::plain("Hostname to connect to (required unless given by `preset`)"),
+                constraint: AttributeConstraint::Optional,
                 ..Default::default()
             },
             "port" => Attribute {
@@ -248,8 +1258,177 @@ impl Connection for ConnectionSsh {
                 constraint: AttributeConstraint::Optional,
                 ..Default::default()
             },
+            "credential_cmd" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Local command whose JSON output supplies `password`, `key` and/or `passphrase` at connection time"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "dir" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Default directory where the commands are executed"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "target_os" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Operating system of the target: `linux` (default, any POSIX system) or `windows` (commands are run with PowerShell)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "locale" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Locale of the commands, set in `LANG` and `LC_ALL` (eg: `C.UTF-8`)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "path_prepend" => Attribute {
+                attr_type: AttributeType::List(AttributeType::String.into()),
+                description: Description::plain("Directories added in front of the `PATH` of the commands"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "tofu" => Attribute {
+                attr_type: AttributeType::Bool,
+                description: Description::plain("Trust on first use: record the host key on first connection, and refuse to connect if it changes afterwards (default: false, any host key is accepted)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "known_hosts_file" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Known hosts file where host keys are recorded with `tofu` (default: `~/.ssh/known_hosts`)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "use_ssh_config" => Attribute {
+                attr_type: AttributeType::Bool,
+                description: Description::plain("Use `~/.ssh/config` for the `HostName`, `User`, `Port`, `IdentityFile` and `ProxyJump` that are not given explicitly (default: false)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "login_shell" => Attribute {
+                attr_type: AttributeType::Bool,
+                description: Description::plain("Run the commands in a login shell (`bash -l`), so the profile of the user is loaded (default: false)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "command_wrapper" => command_wrapper_attribute(),
+            "preset" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Take the connection attributes missing from the block from the environment when connecting: `env:PREFIX` reads `PREFIX_HOST`, `PREFIX_PORT`, `PREFIX_USER`, `PREFIX_PASSWORD`, `PREFIX_KEY` and `PREFIX_KEYFILE`"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "temp_dir" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Base directory where every command gets its own temporary directory in `TMPDIR` (`TEMP` and `TMP` on Windows), removed when the command completes"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "write_buffer_size" => Attribute {
+                attr_type: AttributeType::Number,
+                description: Description::plain("Size of the buffer used to coalesce writes into large SFTP packets (default: 65536)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "max_concurrency" => Attribute {
+                attr_type: AttributeType::Number,
+                description: Description::plain("Maximum number of concurrent operations on the host, shared by all the resources connecting to it, to avoid tripping `MaxStartups` or `MaxSessions` of sshd. A file transfer counts as one operation until it completes. The first limit used for a host applies to all of them. 0 means unlimited (default: `GENERIC_PROVIDER_SSH_MAX_CONCURRENCY` environment variable, or unlimited)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "window_size" => Attribute {
+                attr_type: AttributeType::Number,
+                description: Description::plain("Size of the SSH channel window in bytes: larger windows improve throughput on high-latency links (default: 2097152)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "max_packet_size" => Attribute {
+                attr_type: AttributeType::Number,
+                description: Description::plain("Maximum size of the SSH channel packets in bytes (default: 32768)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "sftp_retries" => Attribute {
+                attr_type: AttributeType::Number,
+                description: Description::plain("Number of retries of the SFTP operations interrupted by a transient error, like a lost channel or an EOF in the middle of a transfer, with an exponential backoff. Interrupted reads resume from where they stopped (default: 3)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Check the key material early to report precise errors instead of a generic authentication failure
+fn validate_key(
+    diags: &mut Diagnostics,
+    attr_path: AttributePath,
+    config: &ConnectionSshConfig,
+) -> Option<()> {
+    // The passphrase might be given by the credential command, so the key cannot be decoded yet
+    let passphrase_known = !config.password.is_unknown() && config.credential_cmd.is_null();
+    let passphrase = config.password.as_deref_option().filter(|p| !p.is_empty());
+    let report_decode_error = |diags: &mut Diagnostics,
+                               err: russh_keys::Error,
+                               attr_path: AttributePath| {
+        match err {
+            russh_keys::Error::KeyIsEncrypted if !passphrase_known => (),
+            russh_keys::Error::KeyIsEncrypted => diags.error(
+                "Encrypted private key",
+                "The private key is encrypted, but no `password` was given to decrypt it.",
+                attr_path,
+            ),
+            err => diags.error(
+                "Invalid private key",
+                format!("The private key could not be decoded (expected OpenSSH, PEM or PKCS#8 format): {err}"),
+                attr_path,
+            ),
+        }
+    };
+
+    if let Value::Value(key) = &config.key {
+        if let Err(err) = russh_keys::decode_secret_key(key, passphrase) {
+            report_decode_error(diags, err, attr_path.clone().attribute("key"));
+            return None;
         }
     }
+
+    if let Value::Value(keyfile) = &config.keyfile {
+        let attr_path = attr_path.attribute("keyfile");
+        #[cfg_attr(not(target_family = "unix"), allow(unused_variables))]
+        let metadata = match std::fs::metadata(keyfile.as_ref()) {
+            Ok(metadata) => metadata,
+            // The key file might be generated during the apply, eg: by another resource
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Some(()),
+            Err(err) => {
+                diags.error(
+                    "Unreadable private key file",
+                    format!("Could not access `{keyfile}`: {err}"),
+                    attr_path,
+                );
+                return None;
+            }
+        };
+        #[cfg(target_family = "unix")]
+        {
+            let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o777;
+            if mode & 0o077 != 0 {
+                diags.warning(
+                    "Private key file is accessible by others",
+                    format!(
+                        "`{keyfile}` has mode {mode:o}, it should be readable only by its owner (600)."
+                    ),
+                    attr_path.clone(),
+                );
+            }
+        }
+        if let Err(err) = russh_keys::load_secret_key(keyfile.as_ref(), passphrase) {
+            report_decode_error(diags, err, attr_path);
+            return None;
+        }
+    }
+
+    Some(())
 }
 
 impl std::fmt::Debug for ConnectionSsh {
@@ -0,0 +1,115 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{pin::Pin, task::Poll};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use rusftp::client::File;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::utils::AsyncDrop;
+
+use super::is_transient_io;
+
+/// Reopen the remote file, positioned at the given offset
+pub(super) type Reopen = Box<dyn Fn(u64) -> BoxFuture<'static, Result<File>> + Send + Sync>;
+
+/// Reader of a remote file that resumes from the current offset when the transfer is interrupted
+///
+/// Long refreshes over flaky links would otherwise fail on a single lost channel.
+pub struct SftpReader {
+    file: Option<File>,
+    offset: u64,
+    retries: u32,
+    reopen: Reopen,
+    reopening: Option<BoxFuture<'static, Result<File>>>,
+    /// Permit of the target host, released once the file is read
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl SftpReader {
+    pub(super) fn new(
+        file: File,
+        retries: u32,
+        reopen: Reopen,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> Self {
+        Self {
+            file: Some(file),
+            offset: 0,
+            retries,
+            reopen,
+            reopening: None,
+            _permit: permit,
+        }
+    }
+}
+
+impl AsyncRead for SftpReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        loop {
+            if let Some(reopening) = &mut self.reopening {
+                let reopened = futures::ready!(reopening.as_mut().poll(cx));
+                self.reopening = None;
+                match reopened {
+                    Ok(file) => self.file = Some(file),
+                    Err(err) => return Poll::Ready(Err(std::io::Error::other(err))),
+                }
+            }
+            let Some(file) = &mut self.file else {
+                return Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::NotConnected,
+                    "The file has been closed",
+                )));
+            };
+            let filled = buf.filled().len();
+            match futures::ready!(Pin::new(file).poll_read(cx, buf)) {
+                Ok(()) => {
+                    self.offset += (buf.filled().len() - filled) as u64;
+                    return Poll::Ready(Ok(()));
+                }
+                Err(err) if self.retries > 0 && is_transient_io(&err) => {
+                    log::warn!(
+                        "SFTP read interrupted at offset {}, resuming: {err}",
+                        self.offset
+                    );
+                    self.retries -= 1;
+                    // The channel is gone, so the handle cannot be closed gracefully
+                    self.file = None;
+                    self.reopening = Some((self.reopen)(self.offset));
+                }
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncDrop for SftpReader {
+    async fn async_drop(&mut self) {
+        self.reopening = None;
+        if let Some(file) = &mut self.file {
+            file.async_drop().await;
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+/// Options of the OpenSSH client configuration that apply to a host
+///
+/// Only the options relevant to establish the connection are supported.
+/// As with OpenSSH, the first value found for an option is used.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct HostConfig {
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_files: Vec<String>,
+    pub proxy_jump: Option<String>,
+}
+
+impl HostConfig {
+    /// Load the options of `host` from `~/.ssh/config`
+    pub(super) async fn load(host: &str) -> Result<Self> {
+        let Some(path) = home_dir().map(|home| home.join(".ssh").join("config")) else {
+            return Ok(Default::default());
+        };
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => Self::parse(&content, host),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+            Err(err) => Err(anyhow!("Could not read {}: {err}", path.display())),
+        }
+    }
+
+    pub(crate) fn parse(content: &str, host: &str) -> Result<Self> {
+        let mut config = Self::default();
+        // Options before the first `Host` apply to all hosts
+        let mut active = true;
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, args) = line
+                .split_once(|c: char| c.is_whitespace() || c == '=')
+                .unwrap_or((line, ""));
+            let args = args.trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+            let arg = unquote(args.trim());
+
+            match keyword.to_lowercase().as_str() {
+                "host" => active = host_matches(host, args),
+                // Match conditions are not supported: their options are ignored
+                "match" => active = false,
+                _ if !active => (),
+                "hostname" => {
+                    config
+                        .hostname
+                        .get_or_insert_with(|| arg.replace("%h", host));
+                }
+                "user" => {
+                    config.user.get_or_insert_with(|| arg.to_owned());
+                }
+                "port" if config.port.is_none() => {
+                    config.port = Some(arg.parse().map_err(|_| {
+                        anyhow!("Invalid port `{arg}` in ssh config at line {}", i + 1)
+                    })?);
+                }
+                "identityfile" => config.identity_files.push(expand_home(arg)),
+                "proxyjump" => {
+                    config.proxy_jump.get_or_insert_with(|| arg.to_owned());
+                }
+                _ => (),
+            }
+        }
+
+        if config.proxy_jump.as_deref() == Some("none") {
+            config.proxy_jump = None;
+        }
+        Ok(config)
+    }
+}
+
+/// Hosts named in the `Host` lines, without the patterns
+pub(crate) fn hosts(content: &str) -> Vec<String> {
+    let mut hosts = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        let (keyword, args) = line
+            .split_once(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or((line, ""));
+        if !keyword.eq_ignore_ascii_case("host") {
+            continue;
+        }
+        let args = args.trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+        for host in args.split_whitespace().map(unquote) {
+            if !host.contains(['*', '?', '!']) && !hosts.iter().any(|known| known == host) {
+                hosts.push(host.to_owned());
+            }
+        }
+    }
+    hosts
+}
+
+/// Check if a host matches a list of `Host` patterns
+///
+/// A host matches if it matches at least one pattern, and no negated pattern.
+fn host_matches(host: &str, patterns: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split_whitespace().map(unquote) {
+        if let Some(pattern) = pattern.strip_prefix('!') {
+            if glob_matches(pattern, host) {
+                return false;
+            }
+        } else if glob_matches(pattern, host) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Match a text against a pattern with `*` and `?` wildcards
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c.eq_ignore_ascii_case(&text[t]) => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp + 1;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), home_dir()) {
+        (Some(path), Some(home)) => home.join(path).to_string_lossy().into_owned(),
+        _ => path.to_owned(),
+    }
+}
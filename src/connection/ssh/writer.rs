@@ -0,0 +1,89 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{pin::Pin, task::Poll};
+
+use async_trait::async_trait;
+use rusftp::client::File;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::utils::AsyncDrop;
+
+/// Default size of the write buffer
+pub(super) const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Writer to a remote file that coalesces small writes into large SFTP WRITE packets
+///
+/// Templated contents are written in many small chunks, and each chunk would otherwise
+/// require a full round trip to the server.
+pub struct SftpWriter {
+    inner: BufWriter<File>,
+    /// Permit of the target host, released once the file is written
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl SftpWriter {
+    pub(super) fn new(file: File, buffer_size: usize) -> Self {
+        Self {
+            inner: BufWriter::with_capacity(buffer_size, file),
+            _permit: None,
+        }
+    }
+
+    /// Keep the permit of the target host until the writer is dropped
+    pub(super) fn holding(self, permit: Option<OwnedSemaphorePermit>) -> Self {
+        Self {
+            _permit: permit,
+            ..self
+        }
+    }
+}
+
+impl AsyncWrite for SftpWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    /// Flush the buffer and close the remote file, reporting any failure
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl AsyncDrop for SftpWriter {
+    async fn async_drop(&mut self) {
+        if let Err(err) = self.inner.shutdown().await {
+            log::warn!("Could not close remote file: {err}");
+        }
+        self.inner.get_mut().async_drop().await;
+    }
+}
@@ -0,0 +1,92 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+
+use crate::connection::{
+    local::ConnectionLocal, parse_import_connection, shell_quote, ssh::ConnectionSsh, Connection,
+};
+use tf_provider::value::Value;
+
+const USAGE: &str =
+    "Usage: terraform-provider-generic exec [--connection <json>] [--dir <dir>] -- <cmd>...";
+
+/// Run a command outside of Terraform, with the same connection stack as the provider
+///
+/// Without `--connection`, the command is executed locally.
+/// Otherwise, `--connection` is the JSON representation of an SSH `connect` block.
+/// Each argument after `--` is quoted, so it reaches the remote command as a single word.
+/// Returns the exit status of the command.
+pub async fn exec(args: &[String]) -> Result<i32> {
+    let mut connection = None;
+    let mut dir = String::new();
+    let mut args = args.iter();
+
+    let cmd = loop {
+        match args.next().map(String::as_str) {
+            Some("--connection") => {
+                connection = Some(args.next().ok_or_else(|| anyhow!("{USAGE}"))?);
+            }
+            Some("--dir") => {
+                dir = args.next().ok_or_else(|| anyhow!("{USAGE}"))?.clone();
+            }
+            Some("--") => {
+                break args
+                    .map(|arg| shell_quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+            Some(arg) => return Err(anyhow!("Unexpected argument `{arg}`\n{USAGE}")),
+            None => return Err(anyhow!("Missing command\n{USAGE}")),
+        }
+    };
+
+    if cmd.is_empty() {
+        return Err(anyhow!("Missing command\n{USAGE}"));
+    }
+
+    match connection {
+        Some(json) => {
+            // The connection is never echoed back: it may contain credentials
+            let config = match parse_import_connection::<ConnectionSsh>(json) {
+                Ok((Value::Value(config), "")) => config,
+                Ok(_) => return Err(anyhow!("Invalid connection: expected a single json object")),
+                Err(err) => return Err(anyhow!("Invalid connection: {err}")),
+            };
+            run(ConnectionSsh::default(), &config, &cmd, &dir).await
+        }
+        None => run(ConnectionLocal::default(), &Default::default(), &cmd, &dir).await,
+    }
+}
+
+async fn run<'a, T: Connection>(
+    connect: T,
+    config: &T::Config<'a>,
+    cmd: &str,
+    dir: &str,
+) -> Result<i32> {
+    log::info!("Executing `{cmd}` on {}", T::target(config));
+    let result = connect
+        .execute(config, cmd, dir, std::iter::empty::<(&String, &String)>())
+        .await?;
+
+    std::io::stdout().write_all(result.stdout.as_bytes())?;
+    std::io::stderr().write_all(result.stderr.as_bytes())?;
+
+    Ok(result.status)
+}
@@ -0,0 +1,170 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use rand::{thread_rng, Rng};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::connection::{ExecutionResult, SessionLost};
+
+/// Fault injected into the commands matching the rule, as written in the scenario file
+///
+/// The scenario file is a JSON list of rules. The first matching rule applies, eg:
+/// `[{"target": "web-1", "cmd": "^systemctl", "latency_ms": 2000, "failure": {"type": "drop"}, "times": 1}]`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    /// Regular expression matched against the target of the connection (eg: `ssh://user@host:22`)
+    target: Option<String>,
+    /// Regular expression matched against the command
+    cmd: Option<String>,
+    /// Delay before executing the command, or before failing
+    #[serde(default)]
+    latency_ms: u64,
+    failure: Option<Failure>,
+    /// Number of executions affected by the rule (default: all)
+    times: Option<usize>,
+    /// Probability of a matching execution to be affected
+    #[serde(default = "always")]
+    probability: f64,
+}
+
+fn always() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum Failure {
+    /// The connection is lost before the command starts, like a session that cannot be opened
+    Drop,
+    /// The command exits with a status code instead of being executed
+    Exit {
+        status: i32,
+        #[serde(default)]
+        stderr: String,
+    },
+}
+
+#[derive(Debug)]
+struct Fault {
+    target: Option<Regex>,
+    cmd: Option<Regex>,
+    latency: Duration,
+    failure: Option<Failure>,
+    remaining: Option<usize>,
+    probability: f64,
+}
+
+lazy_static! {
+    static ref FAULTS: Mutex<Vec<Fault>> = Mutex::new(Vec::new());
+}
+
+/// Load the scenario file, or disable fault injection
+///
+/// Returns the number of rules loaded.
+pub fn configure(path: Option<&Path>) -> Result<usize> {
+    let faults = match path {
+        Some(path) => load(path)?,
+        None => Vec::new(),
+    };
+    let count = faults.len();
+    match FAULTS.lock() {
+        Ok(mut guard) => *guard = faults,
+        Err(poisoned) => *poisoned.into_inner() = faults,
+    }
+    Ok(count)
+}
+
+fn load(path: &Path) -> Result<Vec<Fault>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| anyhow!("Could not read {}: {err}", path.display()))?;
+    let rules: Vec<Rule> = serde_json::from_str(&content)
+        .map_err(|err| anyhow!("Invalid scenario {}: {err}", path.display()))?;
+    rules
+        .into_iter()
+        .enumerate()
+        .map(|(i, rule)| {
+            let regex = |pattern: Option<String>| {
+                pattern
+                    .map(|pattern| Regex::new(&pattern))
+                    .transpose()
+                    .map_err(|err| anyhow!("Invalid regex in rule {i}: {err}"))
+            };
+            if !(0.0..=1.0).contains(&rule.probability) {
+                return Err(anyhow!(
+                    "Probability of rule {i} must be between 0 and 1, but is {}",
+                    rule.probability
+                ));
+            }
+            Ok(Fault {
+                target: regex(rule.target)?,
+                cmd: regex(rule.cmd)?,
+                latency: Duration::from_millis(rule.latency_ms),
+                failure: rule.failure,
+                remaining: rule.times,
+                probability: rule.probability,
+            })
+        })
+        .collect()
+}
+
+/// Apply the first fault matching the command, if any
+///
+/// Returns the result of the command if it must not be executed.
+/// It is called by the connections, so the faults go through their own retries.
+pub async fn inject(target: &str, cmd: &str) -> Option<Result<ExecutionResult>> {
+    let (latency, failure) = {
+        let mut faults = match FAULTS.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let fault = faults.iter_mut().find(|fault| {
+            fault.remaining != Some(0)
+                && fault.target.as_ref().is_none_or(|re| re.is_match(target))
+                && fault.cmd.as_ref().is_none_or(|re| re.is_match(cmd))
+        })?;
+        if fault.probability < 1.0 && !thread_rng().gen_bool(fault.probability) {
+            return None;
+        }
+        if let Some(remaining) = &mut fault.remaining {
+            *remaining -= 1;
+        }
+        (fault.latency, fault.failure.clone())
+    };
+
+    log::warn!(
+        "Injecting fault into command on {target}: latency {latency:?}, failure {failure:?}"
+    );
+    tokio::time::sleep(latency).await;
+    match failure? {
+        Failure::Drop => Some(Err(SessionLost(anyhow!(
+            "connection to {target} dropped (injected fault)"
+        ))
+        .into())),
+        Failure::Exit { status, stderr } => Some(Ok(ExecutionResult {
+            status,
+            stdout: String::new(),
+            stderr,
+        })),
+    }
+}
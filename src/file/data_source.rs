@@ -15,6 +15,7 @@
 // limitations under the License.
 
 use std::fmt::Debug;
+use std::io::ErrorKind;
 
 use async_trait::async_trait;
 
@@ -25,20 +26,38 @@ use tokio::io::AsyncReadExt;
 use tf_provider::schema::{
     Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
 };
-use tf_provider::value::{self, Value, ValueEmpty, ValueString};
+use tf_provider::value::{self, Value, ValueBool, ValueEmpty, ValueNumber, ValueSet, ValueString};
 use tf_provider::{map, AttributePath, DataSource, Diagnostics};
 
-use crate::{connection::Connection, file::hash_stream::DefaultHashingStream, utils::AsyncDrop};
+use crate::{
+    connection::{Connection, FileType},
+    file::{
+        hash_stream::{HashingStream, ALGORITHMS},
+        report_failure,
+    },
+    utils::AsyncDrop,
+};
+
+/// Size of the chunks in which the file is read
+const DEFAULT_READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Size above which reading the file fails, when `max_size` is not set
+const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
 
 #[derive(Debug, Default)]
 pub struct GenericFileDataSource<T: Connection> {
     pub(super) sensitive: bool,
     pub(super) connect: T,
+    pub(super) read_buffer_size: usize,
 }
 
 impl<T: Connection> GenericFileDataSource<T> {
     pub fn new(sensitive: bool, connect: T) -> Self {
-        Self { sensitive, connect }
+        Self {
+            sensitive,
+            connect,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+        }
     }
 }
 
@@ -53,12 +72,20 @@ where
     pub connect: Value<T::Config<'a>>,
     pub content: Value<String>,
     pub content_base64: Value<String>,
+    pub max_size: ValueNumber,
+    pub truncate: ValueBool,
+    pub truncated: ValueBool,
+    pub algorithms: ValueSet<ValueString<'a>>,
     pub md5: ValueString<'a>,
     pub sha1: ValueString<'a>,
     pub sha256: ValueString<'a>,
     pub sha512: ValueString<'a>,
     pub sha256_base64: ValueString<'a>,
     pub sha512_base64: ValueString<'a>,
+    pub integrity: ValueString<'a>,
+    pub xxh3: ValueString<'a>,
+    pub crc32: ValueString<'a>,
+    pub size: ValueNumber,
 }
 
 #[async_trait]
@@ -97,6 +124,30 @@ where
                         sensitive: self.sensitive,
                         ..Default::default()
                     },
+                    "max_size" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Maximum number of bytes read from the file (default: 64 MiB)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "truncate" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Truncate the content to `max_size` bytes instead of failing when the file is larger. The fingerprints are then those of the truncated content (default: false)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "truncated" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Whether the content was truncated to `max_size` bytes"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "algorithms" => Attribute {
+                        attr_type: AttributeType::Set(AttributeType::String.into()),
+                        description: Description::plain("Algorithms of the fingerprints computed for the file: `md5`, `sha1`, `sha256`, `sha384` (`integrity`), `sha512`, `xxh3` or `crc32`. The fingerprints of the other algorithms are null (default: all)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
                     "md5" => Attribute {
                         attr_type: AttributeType::String,
                         description: Description::plain("MD5 fingerprint of the file (hex)"),
@@ -133,6 +184,30 @@ where
                         constraint: AttributeConstraint::Computed,
                         ..Default::default()
                     },
+                    "xxh3" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("XXH3 checksum of the file (hex), only suitable to detect transfer corruption"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "crc32" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("CRC32 checksum of the file (hex), only suitable to detect transfer corruption"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "size" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Size of the file in bytes, even when the content is truncated"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "integrity" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Subresource integrity of the file (`sha384-<base64>`), as expected by the `integrity` attribute of HTML tags"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
                 },
                 blocks: map! {
                     "connect" => NestedBlock::Optional(Block {
@@ -167,6 +242,34 @@ where
             Value::Unknown => (),
         }
 
+        for algorithm in config
+            .algorithms
+            .iter()
+            .flatten()
+            .filter_map(|algorithm| algorithm.as_deref_option())
+        {
+            if !ALGORITHMS.contains(&algorithm) {
+                diags.error(
+                    "Invalid algorithm",
+                    format!(
+                        "Algorithm should be one of {}, but is `{algorithm}`",
+                        ALGORITHMS.join(", ")
+                    ),
+                    AttributePath::new("algorithms"),
+                );
+            }
+        }
+
+        if let Value::Value(max_size) = config.max_size {
+            if max_size <= 0 {
+                diags.error(
+                    "Invalid `max_size`",
+                    format!("`max_size` must be positive, but was {max_size}."),
+                    AttributePath::new("max_size"),
+                );
+            }
+        }
+
         if diags.errors.is_empty() {
             Some(())
         } else {
@@ -184,41 +287,135 @@ where
         let connect_config = config.connect.as_ref().unwrap_or(&default_connect_config);
         let path = config.path.as_str();
 
+        let size = match self.connect.stat(connect_config, path).await {
+            Ok(info) => {
+                if info.file_type == FileType::Dir {
+                    diags.error_short("`path` is a directory", AttributePath::new("path"));
+                    return None;
+                }
+                info.size
+            }
+            Err(err) => match err.downcast_ref::<std::io::Error>() {
+                Some(err) if err.kind() == ErrorKind::NotFound => {
+                    diags.error_short("File does not exist", AttributePath::new("path"));
+                    return None;
+                }
+                _ => {
+                    report_failure(
+                        diags,
+                        &self.connect,
+                        &config.connect,
+                        "path",
+                        "Could not stat file",
+                        err,
+                    )
+                    .await;
+                    return None;
+                }
+            },
+        };
+
         let reader = match self.connect.read(connect_config, path).await {
             Ok(reader) => reader,
             Err(err) => {
-                diags.root_error("Could not read file", format!("{err}"));
+                report_failure(
+                    diags,
+                    &self.connect,
+                    &config.connect,
+                    "path",
+                    "Could not read file",
+                    err,
+                )
+                .await;
                 return None;
             }
         };
         tokio::pin!(reader);
-        let reader = DefaultHashingStream::new(reader);
+        let algorithms = ALGORITHMS
+            .into_iter()
+            .filter(|algorithm| {
+                config.algorithms.is_null()
+                    || config
+                        .algorithms
+                        .iter()
+                        .flatten()
+                        .any(|name| name.as_str() == *algorithm)
+            })
+            .collect::<Vec<_>>();
+        let reader = HashingStream::new(reader, &algorithms);
         tokio::pin!(reader);
 
+        let max_size = match config.max_size {
+            Value::Value(max_size) if max_size > 0 => max_size as u64,
+            _ => DEFAULT_MAX_SIZE,
+        };
+        let buffer_size = match self.read_buffer_size {
+            0 => DEFAULT_READ_BUFFER_SIZE,
+            size => size,
+        };
+
         let mut content = Vec::new();
+        let mut buffer = vec![0; buffer_size];
+        let mut truncated = false;
 
-        let read = reader.read_to_end(&mut content).await;
+        let read = async {
+            loop {
+                let remaining = max_size - content.len() as u64;
+                if remaining == 0 {
+                    // Probe past the limit without hashing, to know if the file is larger
+                    truncated = reader.inner.read(&mut buffer[..1]).await? > 0;
+                    break;
+                }
+                let len = buffer_size.min(remaining.try_into().unwrap_or(usize::MAX));
+                match reader.read(&mut buffer[..len]).await? {
+                    0 => break,
+                    n => content.extend_from_slice(&buffer[..n]),
+                }
+            }
+            Ok::<(), std::io::Error>(())
+        }
+        .await;
         reader.async_drop().await;
 
         if let Err(err) = read {
             diags.root_error("Could not read file", err.to_string());
             return None;
         }
+        if truncated && !config.truncate.unwrap_or(false) {
+            diags.error(
+                "File is too large",
+                format!("The file is larger than `max_size` ({max_size} bytes). Increase `max_size`, or set `truncate` to read only the beginning of the file."),
+                AttributePath::new("max_size"),
+            );
+            return None;
+        }
         let mut output = config;
 
+        output.truncated = Value::Value(truncated);
+
         output.content_base64 =
             Value::Value(base64::engine::general_purpose::STANDARD.encode(content.as_slice()));
         output.content = Value::Value(String::from_utf8_lossy(content.as_slice()).to_string());
 
-        let (md5, sha1, sha256, sha512) = reader.fingerprints_hex();
-        let (_, _, sha256_base64, sha512_base64) = reader.fingerprints_base64();
+        let fingerprints = reader.fingerprints();
+        let string = |fingerprint: Option<String>| {
+            fingerprint.map_or(Value::Null, |f| Value::Value(f.into()))
+        };
 
-        output.md5 = Value::Value(md5.into());
-        output.sha1 = Value::Value(sha1.into());
-        output.sha256 = Value::Value(sha256.into());
-        output.sha512 = Value::Value(sha512.into());
-        output.sha256_base64 = Value::Value(sha256_base64.into());
-        output.sha512_base64 = Value::Value(sha512_base64.into());
+        output.md5 = string(fingerprints.hex("md5"));
+        output.sha1 = string(fingerprints.hex("sha1"));
+        output.sha256 = string(fingerprints.hex("sha256"));
+        output.sha512 = string(fingerprints.hex("sha512"));
+        output.sha256_base64 = string(fingerprints.base64("sha256"));
+        output.sha512_base64 = string(fingerprints.base64("sha512"));
+        output.integrity = string(
+            fingerprints
+                .base64("sha384")
+                .map(|sha384| format!("sha384-{sha384}")),
+        );
+        output.xxh3 = string(fingerprints.hex("xxh3"));
+        output.crc32 = string(fingerprints.hex("crc32"));
+        output.size = Value::Value(size as i64);
 
         Some(output)
     }
@@ -0,0 +1,382 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+
+use base64::Engine;
+use crypto::{digest::Digest, sha2::Sha256};
+use tf_provider::value::Value;
+use tf_provider::Diagnostics;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::hash_stream::{Fingerprints, HashingStream};
+use super::resource::{computed_algorithms, GenericFileResource, ResourceState};
+use crate::audit;
+use crate::connection::{Connection, ShellKind};
+use crate::transfer::Progress;
+use crate::utils::AsyncDrop;
+
+/// Size of the blocks compared between the content and the remote file
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Print the sha256 of every block of the remote file, in order
+const HASH_BLOCKS: &str = r#"split -b "$DELTA_BLOCK_SIZE" --filter=sha256sum -- "$FILE""#;
+
+/// Copy the changed blocks from the patch file into the remote file, and print the sha256 of the result
+const APPLY_PATCH: &str = r#"set -e
+trap 'rm -f "$DELTA_PATCH"' EXIT
+printf '%s\n' "$DELTA_BLOCKS" | while read -r seek skip; do
+  [ -n "$seek" ] || continue
+  dd if="$DELTA_PATCH" of="$FILE" bs="$DELTA_BLOCK_SIZE" seek="$seek" skip="$skip" count=1 conv=notrunc status=none
+done
+truncate -s "$DELTA_SIZE" "$FILE"
+sha256sum -- "$FILE"
+"#;
+
+/// Content of the file, read block by block
+enum DeltaContent<'b> {
+    Memory(Cow<'b, [u8]>, usize),
+    File(File),
+}
+
+impl DeltaContent<'_> {
+    /// Read the next block, which is only shorter than `buf` at the end of the content
+    async fn read_block(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            DeltaContent::Memory(content, offset) => {
+                let n = (content.len() - *offset).min(buf.len());
+                buf[..n].copy_from_slice(&content[*offset..*offset + n]);
+                *offset += n;
+                Ok(n)
+            }
+            DeltaContent::File(file) => {
+                let mut n = 0;
+                while n < buf.len() {
+                    match file.read(&mut buf[n..]).await? {
+                        0 => break,
+                        read => n += read,
+                    }
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+
+/// Outcome of the comparison of the content with the blocks of the remote file
+enum Scan {
+    /// The remote file already has the content
+    Unchanged,
+    /// The changed blocks have been written to the patch, as listed by `blocks`
+    Patch { blocks: String, size: usize },
+    /// Most blocks changed, so the file is better written entirely
+    Rewrite,
+}
+
+/// Hash the content block by block, and write the blocks that differ from `remote` to `patch`
+///
+/// `blocks` lists the changed blocks as `<index in the file> <index in the patch>` lines.
+async fn scan_blocks<H, P>(
+    content: &mut DeltaContent<'_>,
+    remote: &[&str],
+    hashing: &mut H,
+    patch: &mut P,
+    progress: &mut Progress,
+) -> anyhow::Result<Scan>
+where
+    H: AsyncWrite + Unpin,
+    P: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; BLOCK_SIZE];
+    let mut blocks = String::new();
+    let mut count = 0;
+    let mut changed = 0;
+    let mut size = 0;
+    loop {
+        let n = content.read_block(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        let block = &buf[..n];
+        hashing.write_all(block).await?;
+        let mut digest = Sha256::new();
+        digest.input(block);
+        if remote.get(count).copied() != Some(digest.result_str().as_str()) {
+            blocks += &format!("{count} {changed}\n");
+            patch.write_all(block).await?;
+            changed += 1;
+            // Rewriting most of the file is faster without patching
+            if changed * 2 > remote.len().max(1) {
+                return Ok(Scan::Rewrite);
+            }
+        }
+        count += 1;
+        size += n;
+        progress.advance(n as u64);
+    }
+    if changed == 0 && count == remote.len() {
+        return Ok(Scan::Unchanged);
+    }
+    patch.flush().await?;
+    Ok(Scan::Patch { blocks, size })
+}
+
+impl<T: Connection> GenericFileResource<T> {
+    /// Update the remote file by only transferring the blocks that changed
+    ///
+    /// Returns `false` if the file must be written entirely instead.
+    pub(super) async fn write_delta(
+        &self,
+        diags: &mut Diagnostics,
+        state: &mut ResourceState<'_, T>,
+    ) -> Option<bool> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        match self.delta(connect_config, state).await {
+            Ok(Some((fingerprints, size))) => {
+                self.finish_write(diags, state, fingerprints, size).await?;
+                Some(true)
+            }
+            Ok(None) => Some(false),
+            Err(err) => {
+                log::warn!(
+                    "Could not update {} by blocks, writing it entirely: {err}",
+                    state.path.as_str()
+                );
+                Some(false)
+            }
+        }
+    }
+
+    /// Compare the blocks of the content with the remote file, and patch the ones that differ
+    ///
+    /// Returns the fingerprints of the content, or `None` if the file must be written entirely:
+    /// the content is not known beforehand, the target does not have the GNU tools, or most blocks changed.
+    async fn delta<'a>(
+        &self,
+        config: &T::Config<'a>,
+        state: &ResourceState<'_, T>,
+    ) -> anyhow::Result<Option<(Fingerprints, u64)>> {
+        let mut content = if let Value::Value(content) = &state.content {
+            DeltaContent::Memory(Cow::Borrowed(content.as_bytes()), 0)
+        } else if let Value::Value(base64) = &state.content_base64 {
+            let decoded = base64::engine::general_purpose::STANDARD.decode(base64.as_bytes())?;
+            DeltaContent::Memory(Cow::Owned(decoded), 0)
+        } else if let Value::Value(source) = &state.content_source {
+            DeltaContent::File(File::open(source.as_ref()).await?)
+        } else {
+            return Ok(None);
+        };
+        if self.connect.capabilities(config).await?.shell == ShellKind::PowerShell {
+            return Ok(None);
+        }
+
+        let path = state.path.as_str();
+        let resource = format!("{}_file", T::NAME);
+        let block_size = BLOCK_SIZE.to_string();
+        let env = [("FILE", path), ("DELTA_BLOCK_SIZE", block_size.as_str())];
+        let res = audit::execute(
+            &self.connect,
+            config,
+            HASH_BLOCKS,
+            "",
+            env.iter().map(|(k, v)| (k, v)),
+            &resource,
+            "write",
+        )
+        .await?;
+        if res.status != 0 {
+            log::warn!(
+                "Could not hash the blocks of {path}, writing it entirely: {}",
+                res.stderr.trim()
+            );
+            return Ok(None);
+        }
+        let remote = res
+            .stdout
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .collect::<Vec<_>>();
+
+        // Changed blocks are streamed to the patch file as they are found
+        let patch_path = format!("{path}.tf-delta");
+        let writer = self.connect.write(config, &patch_path, 0o600, true).await?;
+        tokio::pin!(writer);
+        let mut patch = HashingStream::new(writer, &[]);
+        let mut hashing = HashingStream::new(tokio::io::sink(), &computed_algorithms(state));
+        let total = match &content {
+            DeltaContent::Memory(content, _) => Some(content.len() as u64),
+            DeltaContent::File(file) => file.metadata().await.ok().map(|meta| meta.len()),
+        };
+        let mut progress = Progress::new(format!("Patching {path}"), total);
+        let scan = scan_blocks(
+            &mut content,
+            &remote,
+            &mut hashing,
+            &mut patch,
+            &mut progress,
+        )
+        .await;
+        // The patch is applied by a single command, whose progress cannot be followed
+        drop(progress);
+        let scan = match scan {
+            Ok(scan @ Scan::Patch { .. }) => {
+                patch.shutdown().await.map(|()| scan).map_err(Into::into)
+            }
+            scan => scan,
+        };
+        patch.async_drop().await;
+        let (blocks, size) = match scan {
+            Ok(Scan::Patch { blocks, size }) => (blocks, size),
+            Ok(Scan::Unchanged) => {
+                self.discard(config, &patch_path).await;
+                return Ok(Some((hashing.fingerprints(), hashing.size)));
+            }
+            Ok(Scan::Rewrite) => {
+                self.discard(config, &patch_path).await;
+                return Ok(None);
+            }
+            Err(err) => {
+                self.discard(config, &patch_path).await;
+                return Err(err);
+            }
+        };
+        let fingerprints = hashing.fingerprints();
+
+        let size = size.to_string();
+        let env = [
+            ("FILE", path),
+            ("DELTA_BLOCK_SIZE", block_size.as_str()),
+            ("DELTA_PATCH", patch_path.as_str()),
+            ("DELTA_BLOCKS", blocks.as_str()),
+            ("DELTA_SIZE", size.as_str()),
+        ];
+        let res = audit::execute(
+            &self.connect,
+            config,
+            APPLY_PATCH,
+            "",
+            env.iter().map(|(k, v)| (k, v)),
+            &resource,
+            "write",
+        )
+        .await?;
+        if res.status != 0 {
+            log::warn!(
+                "Could not patch {path}, writing it entirely: {}",
+                res.stderr.trim()
+            );
+            return Ok(None);
+        }
+        if res.stdout.split_whitespace().next() != fingerprints.hex("sha256").as_deref() {
+            log::warn!("Patched {path} does not have the expected sha256, writing it entirely");
+            return Ok(None);
+        }
+
+        Ok(Some((fingerprints, hashing.size)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use crypto::{digest::Digest, sha2::Sha256};
+
+    use super::{scan_blocks, DeltaContent, Scan, BLOCK_SIZE};
+    use crate::transfer::Progress;
+
+    /// Two and a half blocks of content
+    fn content() -> Vec<u8> {
+        (0..BLOCK_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn block_hashes(content: &[u8]) -> Vec<String> {
+        content
+            .chunks(BLOCK_SIZE)
+            .map(|block| {
+                let mut digest = Sha256::new();
+                digest.input(block);
+                digest.result_str()
+            })
+            .collect()
+    }
+
+    async fn scan(content: &[u8], remote: &[String]) -> (Scan, Vec<u8>) {
+        let remote = remote.iter().map(String::as_str).collect::<Vec<_>>();
+        let mut content = DeltaContent::Memory(Cow::Borrowed(content), 0);
+        let mut patch = Vec::new();
+        let mut progress = Progress::new("Patching", None);
+        let scan = scan_blocks(
+            &mut content,
+            &remote,
+            &mut tokio::io::sink(),
+            &mut patch,
+            &mut progress,
+        )
+        .await
+        .unwrap();
+        (scan, patch)
+    }
+
+    #[tokio::test]
+    async fn unchanged() {
+        let content = content();
+        let (scan, patch) = scan(&content, &block_hashes(&content)).await;
+        assert!(matches!(scan, Scan::Unchanged));
+        assert!(patch.is_empty());
+    }
+
+    #[tokio::test]
+    async fn changed_block() {
+        let remote = block_hashes(&content());
+        let mut content = content();
+        content[BLOCK_SIZE + 10] ^= 1;
+        let (scan, patch) = scan(&content, &remote).await;
+        let Scan::Patch { blocks, size } = scan else {
+            panic!("the file should be patched");
+        };
+        assert_eq!(blocks, "1 0\n");
+        assert_eq!(size, content.len());
+        assert_eq!(patch, &content[BLOCK_SIZE..2 * BLOCK_SIZE]);
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let content = content();
+        let mut remote = block_hashes(&content);
+        remote.push(remote[0].clone());
+        let (scan, patch) = scan(&content, &remote).await;
+        let Scan::Patch { blocks, size } = scan else {
+            panic!("the file should be patched");
+        };
+        assert_eq!(blocks, "");
+        assert_eq!(size, content.len());
+        assert!(patch.is_empty());
+    }
+
+    #[tokio::test]
+    async fn mostly_changed() {
+        let remote = block_hashes(&content());
+        let mut content = content();
+        content[0] ^= 1;
+        content[BLOCK_SIZE] ^= 1;
+        let (scan, _) = scan(&content, &remote).await;
+        assert!(matches!(scan, Scan::Rewrite));
+    }
+}
@@ -0,0 +1,306 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
+};
+use tf_provider::value::{self, Value, ValueEmpty, ValueList, ValueMap, ValueNumber, ValueString};
+use tf_provider::{map, AttributePath, DataSource, Diagnostics};
+
+use crate::{
+    connection::Connection,
+    file::{
+        fetch,
+        glob::{join, split_patterns, walk},
+        report_failure,
+    },
+};
+
+#[derive(Debug, Default)]
+pub struct GenericFileFetchDirDataSource<T: Connection> {
+    pub(super) connect: T,
+}
+
+impl<T: Connection> GenericFileFetchDirDataSource<T> {
+    pub fn new(connect: T) -> Self {
+        Self { connect }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct DataSourceState<'a, T>
+where
+    T: Connection,
+{
+    #[serde(borrow = "'a")]
+    pub root: ValueString<'a>,
+    pub destination: ValueString<'a>,
+    pub patterns: ValueList<ValueString<'a>>,
+    pub exclude: ValueList<ValueString<'a>>,
+    pub max_depth: ValueNumber,
+    pub manifest: ValueMap<'a, ValueString<'a>>,
+    #[serde(with = "value::serde_as_vec")]
+    pub connect: Value<T::Config<'a>>,
+}
+
+#[async_trait]
+impl<T> DataSource for GenericFileFetchDirDataSource<T>
+where
+    T: Connection,
+    T: Debug,
+    T: Clone,
+{
+    type State<'a> = DataSourceState<'a, T>;
+    type ProviderMetaState<'a> = ValueEmpty;
+
+    fn schema(&self, _diags: &mut Diagnostics) -> Option<Schema> {
+        Some(Schema {
+            version: 1,
+            block: Block {
+                version: 1,
+                attributes: map! {
+                    "root" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Remote directory to fetch"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "destination" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Local directory where the files are written, with the same layout as under `root`. The files are replaced once fetched entirely, and the local files that are not fetched anymore are kept"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "patterns" => Attribute {
+                        attr_type: AttributeType::List(AttributeType::String.into()),
+                        description: Description::plain("Glob patterns of the files to fetch relative to `root`: `*` and `?` match within a path component, `**` matches any number of directories (default: all the files)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "exclude" => Attribute {
+                        attr_type: AttributeType::List(AttributeType::String.into()),
+                        description: Description::plain("Glob patterns of the files to leave out, even if they match `patterns`"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "max_depth" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Maximum depth of the fetched files: 1 only fetches the files directly in `root` (default: no limit)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "manifest" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::String.into()),
+                        description: Description::plain("SHA256 (hex) of the fetched files, by path relative to `root`"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                },
+                blocks: map! {
+                    "connect" => NestedBlock::Optional(Block {
+                        attributes: T::schema(),
+                        description: Description::plain("Connection configuration"),
+                        ..Default::default()
+                    }),
+                },
+                description: Description::plain(
+                    "Copies the files of a remote directory to a local directory",
+                ),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn validate<'a>(&self, diags: &mut Diagnostics, config: Self::State<'a>) -> Option<()> {
+        if let Value::Value(connect) = &config.connect {
+            _ = self
+                .connect
+                .validate(diags, AttributePath::new("connect").index(0), connect)
+                .await;
+        }
+
+        for (attr, path) in [("root", &config.root), ("destination", &config.destination)] {
+            match path {
+                Value::Value(path) => {
+                    if path.is_empty() {
+                        diags.error_short(
+                            format!("`{attr}` should not be empty"),
+                            AttributePath::new(attr),
+                        );
+                    }
+                }
+                Value::Null => {
+                    diags.error_short(
+                        format!("`{attr}` should not be null"),
+                        AttributePath::new(attr),
+                    );
+                }
+                Value::Unknown => (),
+            }
+        }
+
+        for (attr, patterns) in [("patterns", &config.patterns), ("exclude", &config.exclude)] {
+            for (i, pattern) in patterns.iter().flatten().enumerate() {
+                match pattern {
+                    Value::Value(pattern) if pattern.is_empty() || pattern.starts_with('/') => {
+                        diags.error(
+                            "Invalid glob pattern",
+                            format!("Pattern should be a non empty path relative to `root`, but is `{pattern}`"),
+                            AttributePath::new(attr).index(i as i64),
+                        );
+                    }
+                    Value::Null => diags.error_short(
+                        "Glob pattern should not be null",
+                        AttributePath::new(attr).index(i as i64),
+                    ),
+                    _ => (),
+                }
+            }
+        }
+
+        if let Value::Value(max_depth) = config.max_depth {
+            if max_depth < 1 {
+                diags.error(
+                    "Invalid `max_depth`",
+                    format!("Maximum depth should be at least 1, but is {max_depth}"),
+                    AttributePath::new("max_depth"),
+                );
+            }
+        }
+
+        if diags.errors.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn read<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        config: Self::State<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<Self::State<'a>> {
+        let default_connect_config = Default::default();
+        let connect_config = config.connect.as_ref().unwrap_or(&default_connect_config);
+        let root = config.root.as_str().trim_end_matches('/');
+        let root = if root.is_empty() { "/" } else { root };
+        let destination = Path::new(config.destination.as_str());
+
+        let mut patterns = split_patterns(&config.patterns);
+        if config.patterns.is_null() {
+            patterns.push(vec!["**", "*"]);
+        }
+        let exclude = split_patterns(&config.exclude);
+        let max_depth = config
+            .max_depth
+            .as_ref_option()
+            .map(|&depth| depth as usize);
+
+        let paths = match walk(
+            &self.connect,
+            connect_config,
+            root,
+            &patterns,
+            &exclude,
+            max_depth,
+        )
+        .await
+        {
+            Ok(paths) => paths,
+            Err(err) => match err.downcast_ref::<std::io::Error>() {
+                Some(err) if err.kind() == ErrorKind::NotFound => {
+                    diags.error_short("Directory does not exist", AttributePath::new("root"));
+                    return None;
+                }
+                _ => {
+                    report_failure(
+                        diags,
+                        &self.connect,
+                        &config.connect,
+                        "root",
+                        "Could not list files",
+                        err,
+                    )
+                    .await;
+                    return None;
+                }
+            },
+        };
+
+        let mut manifest = BTreeMap::new();
+        for path in paths {
+            let Some(local) = local_path(destination, &path) else {
+                diags.root_error(
+                    format!("Could not fetch file `{path}`"),
+                    "The path of the file would be written outside of `destination`.",
+                );
+                return None;
+            };
+            match fetch(&self.connect, connect_config, &join(root, &path), &local).await {
+                Ok(sha256) => {
+                    manifest.insert(Cow::Owned(path), Value::Value(Cow::Owned(sha256)));
+                }
+                Err(err) => {
+                    diags.root_error(format!("Could not fetch file `{path}`"), format!("{err:#}"));
+                    return None;
+                }
+            }
+        }
+
+        let mut output = config;
+        output.manifest = Value::Value(manifest);
+
+        Some(output)
+    }
+}
+
+/// Local path of a fetched file, unless its remote path would escape `destination`, eg: with `..`
+fn local_path(destination: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then(|| destination.join(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::local_path;
+
+    #[test]
+    fn local_paths() {
+        let destination = Path::new("/tmp/fetched");
+        assert_eq!(
+            local_path(destination, "etc/hosts"),
+            Some(destination.join("etc/hosts"))
+        );
+        assert_eq!(local_path(destination, "../hosts"), None);
+        assert_eq!(local_path(destination, "etc/../../hosts"), None);
+        assert_eq!(local_path(destination, "/etc/hosts"), None);
+        assert_eq!(local_path(destination, "./hosts"), None);
+    }
+}
@@ -0,0 +1,450 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io::ErrorKind;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
+};
+use tf_provider::value::{self, Value, ValueEmpty, ValueList, ValueMap, ValueString};
+use tf_provider::{map, AttributePath, DataSource, Diagnostics};
+
+use crate::{
+    connection::{Connection, FileType},
+    file::{hash_stream::HashingStream, report_failure},
+    utils::AsyncDrop,
+};
+
+/// Algorithms that can be used to fingerprint the matched files
+const HASH_ALGORITHMS: [&str; 6] = ["md5", "sha1", "sha256", "sha512", "xxh3", "crc32"];
+
+#[derive(Debug, Default)]
+pub struct GenericFileGlobDataSource<T: Connection> {
+    pub(super) connect: T,
+}
+
+impl<T: Connection> GenericFileGlobDataSource<T> {
+    pub fn new(connect: T) -> Self {
+        Self { connect }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct DataSourceState<'a, T>
+where
+    T: Connection,
+{
+    #[serde(borrow = "'a")]
+    pub root: ValueString<'a>,
+    pub patterns: ValueList<ValueString<'a>>,
+    pub exclude: ValueList<ValueString<'a>>,
+    pub hash: ValueString<'a>,
+    pub paths: ValueList<ValueString<'a>>,
+    pub hashes: ValueMap<'a, ValueString<'a>>,
+    #[serde(with = "value::serde_as_vec")]
+    pub connect: Value<T::Config<'a>>,
+}
+
+#[async_trait]
+impl<T> DataSource for GenericFileGlobDataSource<T>
+where
+    T: Connection,
+    T: Debug,
+    T: Clone,
+{
+    type State<'a> = DataSourceState<'a, T>;
+    type ProviderMetaState<'a> = ValueEmpty;
+
+    fn schema(&self, _diags: &mut Diagnostics) -> Option<Schema> {
+        Some(Schema {
+            version: 1,
+            block: Block {
+                version: 1,
+                attributes: map! {
+                    "root" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Remote directory where the files are searched"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "patterns" => Attribute {
+                        attr_type: AttributeType::List(AttributeType::String.into()),
+                        description: Description::plain("Glob patterns of the files relative to `root`: `*` and `?` match within a path component, `**` matches any number of directories"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "exclude" => Attribute {
+                        attr_type: AttributeType::List(AttributeType::String.into()),
+                        description: Description::plain("Glob patterns of the files to leave out, even if they match `patterns`"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "hash" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Algorithm used to fingerprint the matched files in `hashes`: `md5`, `sha1`, `sha256`, `sha512`, `xxh3` or `crc32` (default: no fingerprint)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "paths" => Attribute {
+                        attr_type: AttributeType::List(AttributeType::String.into()),
+                        description: Description::plain("Sorted paths of the matched files, relative to `root`"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "hashes" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::String.into()),
+                        description: Description::plain("Fingerprints of the matched files (hex) with the `hash` algorithm, by path"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                },
+                blocks: map! {
+                    "connect" => NestedBlock::Optional(Block {
+                        attributes: T::schema(),
+                        description: Description::plain("Connection configuration"),
+                        ..Default::default()
+                    }),
+                },
+                description: Description::plain("Lists the remote files matching glob patterns"),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn validate<'a>(&self, diags: &mut Diagnostics, config: Self::State<'a>) -> Option<()> {
+        if let Value::Value(connect) = &config.connect {
+            _ = self
+                .connect
+                .validate(diags, AttributePath::new("connect").index(0), connect)
+                .await;
+        }
+
+        match &config.root {
+            Value::Value(root) => {
+                if root.is_empty() {
+                    diags.error_short("`root` should not be empty", AttributePath::new("root"));
+                }
+            }
+            Value::Null => {
+                diags.error_short("`root` should not be null", AttributePath::new("root"));
+            }
+            Value::Unknown => (),
+        }
+
+        for (attr, patterns) in [("patterns", &config.patterns), ("exclude", &config.exclude)] {
+            for (i, pattern) in patterns.iter().flatten().enumerate() {
+                match pattern {
+                    Value::Value(pattern) if pattern.is_empty() || pattern.starts_with('/') => {
+                        diags.error(
+                            "Invalid glob pattern",
+                            format!("Pattern should be a non empty path relative to `root`, but is `{pattern}`"),
+                            AttributePath::new(attr).index(i as i64),
+                        );
+                    }
+                    Value::Null => diags.error_short(
+                        "Glob pattern should not be null",
+                        AttributePath::new(attr).index(i as i64),
+                    ),
+                    _ => (),
+                }
+            }
+        }
+
+        if let Value::Value(hash) = &config.hash {
+            if !HASH_ALGORITHMS.contains(&hash.as_ref()) {
+                diags.error(
+                    "Invalid `hash`",
+                    format!(
+                        "Algorithm should be one of {}, but is `{hash}`",
+                        HASH_ALGORITHMS.join(", ")
+                    ),
+                    AttributePath::new("hash"),
+                );
+            }
+        }
+
+        if diags.errors.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn read<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        config: Self::State<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<Self::State<'a>> {
+        let default_connect_config = Default::default();
+        let connect_config = config.connect.as_ref().unwrap_or(&default_connect_config);
+        let root = config.root.as_str().trim_end_matches('/');
+        let root = if root.is_empty() { "/" } else { root };
+
+        let patterns = split_patterns(&config.patterns);
+        let exclude = split_patterns(&config.exclude);
+
+        let paths = match walk(
+            &self.connect,
+            connect_config,
+            root,
+            &patterns,
+            &exclude,
+            None,
+        )
+        .await
+        {
+            Ok(paths) => paths,
+            Err(err) => match err.downcast_ref::<std::io::Error>() {
+                Some(err) if err.kind() == ErrorKind::NotFound => {
+                    diags.error_short("Directory does not exist", AttributePath::new("root"));
+                    return None;
+                }
+                _ => {
+                    report_failure(
+                        diags,
+                        &self.connect,
+                        &config.connect,
+                        "root",
+                        "Could not list files",
+                        err,
+                    )
+                    .await;
+                    return None;
+                }
+            },
+        };
+
+        let mut hashes = BTreeMap::new();
+        if let Value::Value(hash) = &config.hash {
+            for path in &paths {
+                match self
+                    .fingerprint(connect_config, &join(root, path), hash)
+                    .await
+                {
+                    Ok(fingerprint) => {
+                        hashes.insert(
+                            Cow::Owned(path.clone()),
+                            Value::Value(Cow::Owned(fingerprint)),
+                        );
+                    }
+                    Err(err) => {
+                        diags.root_error(format!("Could not hash file `{path}`"), err.to_string());
+                        return None;
+                    }
+                }
+            }
+        }
+
+        let mut output = config;
+        output.paths = Value::Value(
+            paths
+                .into_iter()
+                .map(|path| Value::Value(Cow::Owned(path)))
+                .collect(),
+        );
+        output.hashes = Value::Value(hashes);
+
+        Some(output)
+    }
+}
+
+impl<T: Connection> GenericFileGlobDataSource<T> {
+    /// Compute the fingerprint of a remote file with the given algorithm
+    async fn fingerprint<'a>(
+        &self,
+        config: &T::Config<'a>,
+        path: &str,
+        algorithm: &str,
+    ) -> Result<String> {
+        let reader = self.connect.read(config, path).await?;
+        tokio::pin!(reader);
+        let reader = HashingStream::new(reader, &[algorithm]);
+        let writer = tokio::io::sink();
+        tokio::pin!(reader, writer);
+
+        let copy = tokio::io::copy(&mut reader, &mut writer).await;
+        reader.async_drop().await;
+        copy?;
+
+        Ok(reader.fingerprints().hex(algorithm).unwrap_or_default())
+    }
+}
+
+/// Find the files under `root` matching at least one pattern and no excluded pattern
+///
+/// Directories are only traversed as deep as the patterns can match, and paths have at most `max_depth` components if given.
+/// Symbolic links to directories are not followed.
+pub(super) async fn walk<'a, T: Connection>(
+    connect: &T,
+    config: &T::Config<'a>,
+    root: &str,
+    patterns: &[Vec<&str>],
+    exclude: &[Vec<&str>],
+    max_depth: Option<usize>,
+) -> Result<Vec<String>> {
+    let max_depth = patterns
+        .iter()
+        .map(|pattern| (!pattern.contains(&"**")).then_some(pattern.len()))
+        .try_fold(0, |max, depth| depth.map(|depth| max.max(depth)))
+        .into_iter()
+        .chain(max_depth)
+        .min();
+
+    let mut paths = Vec::new();
+    let mut dirs = vec![(String::new(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        for (name, info) in connect.read_dir(config, &join(root, &dir)).await? {
+            let path = if dir.is_empty() {
+                name
+            } else {
+                format!("{dir}/{name}")
+            };
+            let file_type = match info.file_type {
+                FileType::Symlink => match connect.stat(config, &join(root, &path)).await {
+                    Ok(info) if info.file_type == FileType::File => FileType::File,
+                    _ => FileType::Symlink,
+                },
+                file_type => file_type,
+            };
+            match file_type {
+                FileType::Dir if max_depth.is_none_or(|max| depth + 1 < max) => {
+                    dirs.push((path, depth + 1));
+                }
+                FileType::File => {
+                    let components = path.split('/').collect::<Vec<_>>();
+                    if patterns
+                        .iter()
+                        .any(|pattern| path_matches(pattern, &components))
+                        && !exclude
+                            .iter()
+                            .any(|pattern| path_matches(pattern, &components))
+                    {
+                        paths.push(path);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+pub(super) fn split_patterns<'a>(patterns: &'a ValueList<ValueString<'_>>) -> Vec<Vec<&'a str>> {
+    patterns
+        .iter()
+        .flatten()
+        .filter_map(|pattern| pattern.as_deref_option())
+        .map(|pattern| {
+            pattern
+                .split('/')
+                .filter(|component| !component.is_empty() && *component != ".")
+                .collect()
+        })
+        .collect()
+}
+
+pub(super) fn join(root: &str, path: &str) -> String {
+    match (root, path) {
+        (root, "") => root.to_owned(),
+        ("/", path) => format!("/{path}"),
+        (root, path) => format!("{root}/{path}"),
+    }
+}
+
+/// Match the components of a path against the components of a pattern
+fn path_matches(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| path_matches(rest, &path[i..])),
+        Some((component, rest)) => path
+            .split_first()
+            .is_some_and(|(name, path)| glob_matches(component, name) && path_matches(rest, path)),
+    }
+}
+
+/// Match a path component against a pattern with `*` and `?` wildcards
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp + 1;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_matches, join, path_matches};
+
+    #[test]
+    fn wildcards() {
+        assert!(glob_matches("*.conf", "nginx.conf"));
+        assert!(glob_matches("*.conf", ".conf"));
+        assert!(!glob_matches("*.conf", "nginx.conf.bak"));
+        assert!(glob_matches("log-?.txt", "log-1.txt"));
+        assert!(!glob_matches("log-?.txt", "log-12.txt"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+        assert!(glob_matches("**", ""));
+    }
+
+    #[test]
+    fn paths() {
+        assert!(path_matches(&["etc", "*.conf"], &["etc", "a.conf"]));
+        assert!(!path_matches(&["etc", "*.conf"], &["etc", "sub", "a.conf"]));
+        assert!(path_matches(&["**", "*.conf"], &["a.conf"]));
+        assert!(path_matches(&["**", "*.conf"], &["etc", "sub", "a.conf"]));
+        assert!(path_matches(&["etc", "**"], &["etc"]));
+        assert!(!path_matches(&["etc"], &["etc", "a.conf"]));
+        assert!(!path_matches(&["etc", "*"], &["etc"]));
+    }
+
+    #[test]
+    fn join_paths() {
+        assert_eq!(join("/", "etc"), "/etc");
+        assert_eq!(join("/srv", "etc"), "/srv/etc");
+        assert_eq!(join("/srv", ""), "/srv");
+    }
+}
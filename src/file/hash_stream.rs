@@ -14,137 +14,302 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{borrow::BorrowMut, pin::Pin, task::Poll};
+use std::{collections::BTreeMap, pin::Pin, task::Poll};
 
 use async_trait::async_trait;
 use crypto::{
     digest::Digest,
     md5::Md5,
     sha1::Sha1,
-    sha2::{Sha256, Sha512},
+    sha2::{Sha256, Sha384, Sha512},
 };
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::utils::AsyncDrop;
 
-pub(super) struct HashingStream<D, I> {
-    pub(super) digest: D,
-    pub(super) inner: I,
+/// XXH3 (64 bits) checksum, only suitable to detect accidental corruption
+pub(super) struct Xxh3(xxhash_rust::xxh3::Xxh3);
+
+impl Xxh3 {
+    pub(super) fn new() -> Self {
+        Self(xxhash_rust::xxh3::Xxh3::new())
+    }
 }
 
-macro_rules! impl_all {
-    ($x:ty ; $e:ident) => {$x};
-    ($($e:ident)+) => {
+impl Digest for Xxh3 {
+    fn input(&mut self, input: &[u8]) {
+        self.0.update(input);
+    }
+    fn result(&mut self, out: &mut [u8]) {
+        out.copy_from_slice(&self.0.digest().to_be_bytes());
+    }
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+    fn output_bits(&self) -> usize {
+        64
+    }
+    fn block_size(&self) -> usize {
+        64
+    }
+}
 
-        impl<$($e: Digest + Unpin,)+ Inner: AsyncRead + Unpin> AsyncRead for HashingStream<($($e,)+), Inner> {
-            #[allow(non_snake_case)]
-            fn poll_read(
-                mut self: std::pin::Pin<&mut Self>,
-                cx: &mut std::task::Context<'_>,
-                buf: &mut tokio::io::ReadBuf<'_>,
-            ) -> std::task::Poll<std::io::Result<()>> {
-                let poll = Pin::new(self.inner.borrow_mut()).poll_read(cx, buf);
+/// CRC32 (IEEE) checksum, only suitable to detect accidental corruption
+pub(super) struct Crc32(crc32fast::Hasher);
 
-                if let Poll::Ready(Ok(_)) = poll {
-                    let ($($e,)+) = &mut self.digest;
-                    $($e.input(buf.filled());)+
-                }
+impl Crc32 {
+    pub(super) fn new() -> Self {
+        Self(crc32fast::Hasher::new())
+    }
+}
 
-                poll
-            }
-        }
+impl Digest for Crc32 {
+    fn input(&mut self, input: &[u8]) {
+        self.0.update(input);
+    }
+    fn result(&mut self, out: &mut [u8]) {
+        out.copy_from_slice(&self.0.clone().finalize().to_be_bytes());
+    }
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+    fn output_bits(&self) -> usize {
+        32
+    }
+    fn block_size(&self) -> usize {
+        4
+    }
+}
 
-        impl<$($e: Digest + Unpin,)+ Inner: AsyncWrite + Unpin> AsyncWrite for HashingStream<($($e,)+), Inner> {
-            #[allow(non_snake_case)]
-            fn poll_write(
-                mut self: Pin<&mut Self>,
-                cx: &mut std::task::Context<'_>,
-                buf: &[u8],
-            ) -> Poll<Result<usize, std::io::Error>> {
-                let poll = Pin::new(self.inner.borrow_mut()).poll_write(cx, buf);
-
-                if let Poll::Ready(Ok(written)) = poll {
-                    let ($($e,)+) = &mut self.digest;
-                    $($e.input(&buf[0..written]);)+
-                }
-
-                poll
-            }
+/// Text encodings of the fingerprints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Encoding {
+    Hex,
+    /// RFC 4648 base32, with padding
+    Base32,
+    /// Base32 variant used by Nix in store paths and hashes
+    Nix32,
+    Base64,
+    /// URL-safe base64 without padding, as used by JWS
+    Base64Url,
+}
 
-            fn poll_flush(
-                mut self: Pin<&mut Self>,
-                cx: &mut std::task::Context<'_>,
-            ) -> Poll<Result<(), std::io::Error>> {
-                Pin::new(self.inner.borrow_mut()).poll_flush(cx)
-            }
+impl Encoding {
+    pub(super) const NAMES: [&'static str; 5] = ["hex", "base32", "nix32", "base64", "base64url"];
 
-            fn poll_shutdown(
-                mut self: Pin<&mut Self>,
-                cx: &mut std::task::Context<'_>,
-            ) -> Poll<Result<(), std::io::Error>> {
-                Pin::new(self.inner.borrow_mut()).poll_shutdown(cx)
-            }
+    pub(super) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "hex" => Some(Self::Hex),
+            "base32" => Some(Self::Base32),
+            "nix32" => Some(Self::Nix32),
+            "base64" => Some(Self::Base64),
+            "base64url" => Some(Self::Base64Url),
+            _ => None,
         }
+    }
 
-        impl<$($e: Digest + Unpin,)+ Inner> HashingStream<($($e,)+), Inner> {
-            #[allow(non_snake_case, dead_code)]
-            pub(super) fn fingerprints_hex(&mut self) -> ($(impl_all!(String; $e),)+) {
-                let ($($e,)+) = &mut self.digest;
-                ($($e.result_str(),)+)
-            }
+    pub(super) fn encode(self, bytes: &[u8]) -> String {
+        use base64::Engine;
+        match self {
+            Self::Hex => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+            Self::Base32 => base32(bytes),
+            Self::Nix32 => nix32(bytes),
+            Self::Base64 => base64::engine::general_purpose::STANDARD.encode(bytes),
+            Self::Base64Url => base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes),
         }
+    }
+}
+
+/// Decode a fingerprint encoded in hex, eg: from the state
+pub(super) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
 
-        impl<$($e: Digest + Unpin,)+ Inner> HashingStream<($($e,)+), Inner> {
-            #[allow(non_snake_case, dead_code)]
-            pub(super) fn fingerprints_base64(&mut self) -> ($(impl_all!(String; $e),)+) {
-                use base64;
-                let ($($e,)+) = &mut self.digest;
-                ($({
-                    let x = $e;
-                    let nbytes = x.output_bytes();
-                    let mut out = vec![0; nbytes];
-                    x.result(&mut out);
-                    base64::Engine::encode(&base64::engine::general_purpose::STANDARD, out.as_slice())
-                },)+)
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    for chunk in bytes.chunks(5) {
+        let mut block = [0u8; 5];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let bits = block.iter().fold(0u64, |bits, &b| (bits << 8) | b as u64);
+        // Number of characters carrying the bits of the chunk, the others are padding
+        let len = (chunk.len() * 8).div_ceil(5);
+        for i in 0..8 {
+            if i < len {
+                encoded.push(ALPHABET[((bits >> (35 - 5 * i)) & 31) as usize] as char);
+            } else {
+                encoded.push('=');
             }
         }
+    }
+    encoded
+}
+
+/// Base32 of Nix: its own alphabet, no padding, and the bytes are read from the end
+fn nix32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+    if bytes.is_empty() {
+        return String::new();
+    }
+    let len = (bytes.len() * 8 - 1) / 5 + 1;
+    (0..len)
+        .rev()
+        .map(|n| {
+            let (i, j) = (n * 5 / 8, n * 5 % 8);
+            let low = bytes[i] as u16 >> j;
+            let high = bytes.get(i + 1).map_or(0, |&b| (b as u16) << (8 - j));
+            ALPHABET[((low | high) & 31) as usize] as char
+        })
+        .collect()
+}
 
+/// Algorithms of the fingerprints a hashing stream can compute
+pub(super) const ALGORITHMS: [&str; 7] =
+    ["md5", "sha1", "sha256", "sha384", "sha512", "xxh3", "crc32"];
 
-    };
+fn new_digest(algorithm: &str) -> Option<Box<dyn Digest + Send + Sync>> {
+    Some(match algorithm {
+        "md5" => Box::new(Md5::new()),
+        "sha1" => Box::new(Sha1::new()),
+        "sha256" => Box::new(Sha256::new()),
+        "sha384" => Box::new(Sha384::new()),
+        "sha512" => Box::new(Sha512::new()),
+        "xxh3" => Box::new(Xxh3::new()),
+        "crc32" => Box::new(Crc32::new()),
+        _ => return None,
+    })
 }
 
-// impl_all!(A);
-// impl_all!(A B);
-// impl_all!(A B C);
-impl_all!(A B C D);
-// impl_all!(A B C D E);
-// impl_all!(A B C D E F);
-// impl_all!(A B C D E F G);
-// impl_all!(A B C D E F G H);
-// impl_all!(A B C D E F G H I);
-// impl_all!(A B C D E F G H I J);
-// impl_all!(A B C D E F G H I J K);
-// impl_all!(A B C D E F G H I J K L);
+/// Raw fingerprints of a content, by algorithm
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(super) struct Fingerprints(BTreeMap<&'static str, Vec<u8>>);
 
-pub(super) type DefaultHashingStream<Inner> = HashingStream<(Md5, Sha1, Sha256, Sha512), Inner>;
+impl FromIterator<(&'static str, Vec<u8>)> for Fingerprints {
+    fn from_iter<I: IntoIterator<Item = (&'static str, Vec<u8>)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Fingerprints {
+    /// Fingerprint with the given algorithm, if it has been computed
+    pub(super) fn get(&self, algorithm: &str, encoding: Encoding) -> Option<String> {
+        self.0.get(algorithm).map(|bytes| encoding.encode(bytes))
+    }
 
-impl<Inner> DefaultHashingStream<Inner> {
-    pub(super) fn new(inner: Inner) -> Self {
+    pub(super) fn hex(&self, algorithm: &str) -> Option<String> {
+        self.get(algorithm, Encoding::Hex)
+    }
+
+    pub(super) fn base64(&self, algorithm: &str) -> Option<String> {
+        self.get(algorithm, Encoding::Base64)
+    }
+}
+
+/// Stream computing the fingerprints of the data going through it
+///
+/// Only the algorithms given when it is created are computed, as hashing large files is expensive.
+pub(super) struct HashingStream<I> {
+    digests: Vec<(&'static str, Box<dyn Digest + Send + Sync>)>,
+    pub(super) inner: I,
+    /// Number of bytes hashed so far
+    pub(super) size: u64,
+}
+
+impl<I> HashingStream<I> {
+    pub(super) fn new(inner: I, algorithms: &[&str]) -> Self {
         Self {
-            digest: (Md5::new(), Sha1::new(), Sha256::new(), Sha512::new()),
+            digests: ALGORITHMS
+                .into_iter()
+                .filter(|algorithm| algorithms.contains(algorithm))
+                .filter_map(|algorithm| Some((algorithm, new_digest(algorithm)?)))
+                .collect(),
             inner,
+            size: 0,
+        }
+    }
+
+    fn input(&mut self, data: &[u8]) {
+        self.size += data.len() as u64;
+        for (_, digest) in &mut self.digests {
+            digest.input(data);
+        }
+    }
+
+    /// Fingerprints of the data hashed so far
+    pub(super) fn fingerprints(&mut self) -> Fingerprints {
+        Fingerprints(
+            self.digests
+                .iter_mut()
+                .map(|(algorithm, digest)| {
+                    let mut out = vec![0; digest.output_bytes()];
+                    digest.result(&mut out);
+                    (*algorithm, out)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl<Inner: AsyncRead + Unpin> AsyncRead for HashingStream<Inner> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(_)) = poll {
+            self.input(&buf.filled()[filled..]);
         }
+
+        poll
+    }
+}
+
+impl<Inner: AsyncWrite + Unpin> AsyncWrite for HashingStream<Inner> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(written)) = poll {
+            self.input(&buf[0..written]);
+        }
+
+        poll
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
     }
 }
 
 #[async_trait]
-impl<'a, Hash, Inner> AsyncDrop for HashingStream<Hash, std::pin::Pin<&'a mut Inner>>
+impl<Inner> AsyncDrop for HashingStream<std::pin::Pin<&mut Inner>>
 where
     Inner: AsyncDrop + Send,
-    Hash: Send,
 {
     async fn async_drop(&mut self) {
-        let inner = self.inner.borrow_mut().as_mut();
+        let inner = self.inner.as_mut();
         unsafe { inner.get_unchecked_mut().async_drop().await }
     }
 }
@@ -14,9 +14,234 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::io::ErrorKind;
+use std::path::Path;
+
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use tf_provider::value::Value;
+use tf_provider::{AttributePath, Diagnostics};
+
+use crate::{
+    connection::Connection,
+    limits,
+    transfer::{self, Progress},
+    utils::AsyncDrop,
+};
+
 mod data_source;
+mod delta;
+mod fetch_dir;
+mod glob;
 mod hash_stream;
 mod resource;
 
 pub use data_source::GenericFileDataSource;
+pub use fetch_dir::GenericFileFetchDirDataSource;
+pub use glob::GenericFileGlobDataSource;
 pub use resource::GenericFileResource;
+
+/// Copy a remote file to a local path, and return its SHA256 (hex)
+///
+/// The content is streamed to a temporary file next to `local`, only readable by its owner,
+/// which replaces `local` once complete: a failed transfer leaves `local` untouched.
+pub(crate) async fn fetch<'a, T: Connection>(
+    connect: &T,
+    config: &T::Config<'a>,
+    path: &str,
+    local: &Path,
+) -> anyhow::Result<String> {
+    if let Some(parent) = local
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let temp = temp_path(&local.to_string_lossy());
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let writer = options.open(&temp).await?;
+
+    let _permit = limits::acquire_transfer(&T::host(config)).await;
+    let reader = match connect.read(config, path).await {
+        Ok(reader) => reader,
+        Err(err) => {
+            _ = tokio::fs::remove_file(&temp).await;
+            return Err(err);
+        }
+    };
+    tokio::pin!(reader);
+    let reader = hash_stream::HashingStream::new(reader, &["sha256"]);
+    tokio::pin!(reader, writer);
+
+    let mut progress = Progress::new(format!("Fetching {path}"), None);
+    let copy = transfer::copy(&mut reader, &mut writer, &mut progress).await;
+    reader.async_drop().await;
+    let copy = match copy {
+        Ok(_) => writer.sync_all().await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = copy {
+        _ = tokio::fs::remove_file(&temp).await;
+        return Err(anyhow::Error::from(err).context(progress.to_string()));
+    }
+    if let Err(err) = tokio::fs::rename(&temp, local).await {
+        _ = tokio::fs::remove_file(&temp).await;
+        return Err(err.into());
+    }
+
+    Ok(reader.fingerprints().hex("sha256").unwrap_or_default())
+}
+
+/// Temporary path next to `path`, so it can be renamed over `path`
+fn temp_path(path: &str) -> String {
+    let suffix = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect::<String>();
+    format!("{path}.{suffix}.tmp")
+}
+
+/// Report a failed file operation on the file given by the attribute `path_attr`
+///
+/// The capabilities of the target are queried to give an actionable diagnostic when files cannot be transferred at all.
+/// Common failures are attached to the attribute to fix, with a hint.
+/// Connection failures are reported at the root when the `connect` block is not set, as there is no attribute to attach them to.
+async fn report_failure<'a, T: Connection>(
+    diags: &mut Diagnostics,
+    connect: &T,
+    connect_block: &Value<T::Config<'a>>,
+    path_attr: &'static str,
+    summary: &'static str,
+    err: anyhow::Error,
+) {
+    let connect_attr = |path: AttributePath| connect_block.is_value().then_some(path);
+    let connect_path = AttributePath::new("connect").index(0);
+    let (summary, detail, attr_path) = match classify_failure(&err) {
+        Some(Failure::Connection(hint)) => (
+            summary,
+            format!("{err:#}\n{hint}"),
+            connect_attr(connect_path.attribute("host")),
+        ),
+        Some(Failure::Authentication) => (
+            summary,
+            format!("{err:#}\nCheck `user` and the credentials of the connection (`password`, `key`, `keyfile` or `credential_cmd`)."),
+            connect_attr(connect_path.attribute("user")),
+        ),
+        Some(Failure::File(hint)) => (
+            summary,
+            format!("{err:#}\n{hint}"),
+            Some(AttributePath::new(path_attr)),
+        ),
+        None => {
+            let default_config = Default::default();
+            let config = connect_block.as_ref().unwrap_or(&default_config);
+            match connect.capabilities(config).await {
+                Ok(capabilities) if !capabilities.file_transfer => (
+                    "Target cannot transfer files",
+                    "File resources transfer files over SFTP, but the SFTP subsystem is not available on the target. Enable it in sshd_config (`Subsystem sftp ...`), or manage the file with a `cmd` resource instead.".to_owned(),
+                    connect_attr(connect_path),
+                ),
+                _ => (summary, format!("{err:#}"), None),
+            }
+        }
+    };
+    match attr_path {
+        Some(attr_path) => diags.error(summary, detail, attr_path),
+        None => diags.root_error(summary, detail),
+    }
+}
+
+/// Class of a failed file operation, with a hint to fix it
+enum Failure {
+    Connection(&'static str),
+    Authentication,
+    File(&'static str),
+}
+
+fn classify_failure(err: &anyhow::Error) -> Option<Failure> {
+    let io_kind = err
+        .chain()
+        .find_map(|err| err.downcast_ref::<std::io::Error>())
+        .map(std::io::Error::kind);
+    let messages = err
+        .chain()
+        .map(|err| err.to_string().to_lowercase())
+        .collect::<Vec<_>>();
+    let mentions = |text: &str| messages.iter().any(|message| message.contains(text));
+
+    if mentions("failed to lookup address") || mentions("name or service not known") {
+        Some(Failure::Connection(
+            "The host name could not be resolved: check `host`, and the DNS configuration of the machine running Terraform.",
+        ))
+    } else if matches!(
+        io_kind,
+        Some(ErrorKind::ConnectionRefused | ErrorKind::TimedOut | ErrorKind::HostUnreachable)
+    ) || mentions("connection refused")
+    {
+        Some(Failure::Connection(
+            "The host could not be reached: check `host` and `port`, and that no firewall blocks the connection.",
+        ))
+    } else if mentions("host key of") {
+        Some(Failure::Connection(
+            "The host key changed: if the host was reinstalled, remove its entry from the known hosts file.",
+        ))
+    } else if mentions("authentication failure") {
+        Some(Failure::Authentication)
+    } else if io_kind == Some(ErrorKind::PermissionDenied) || mentions("permission denied") {
+        Some(Failure::File(
+            "The connection user is not allowed to access the file: check the permissions of the file and of its parent directories, or connect as another user.",
+        ))
+    } else if mentions("no space left") || mentions("disk full") || mentions("quota exceeded") {
+        Some(Failure::File(
+            "The file system of the file is full: free some space on the target, or write the file elsewhere.",
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind};
+
+    use anyhow::anyhow;
+
+    use super::{classify_failure, Failure};
+
+    #[test]
+    fn connection_failures() {
+        let refused = anyhow::Error::from(Error::from(ErrorKind::ConnectionRefused))
+            .context("could not connect");
+        assert!(matches!(
+            classify_failure(&refused),
+            Some(Failure::Connection(_))
+        ));
+        let unresolved = anyhow!("failed to lookup address information: Name or service not known");
+        assert!(matches!(
+            classify_failure(&unresolved),
+            Some(Failure::Connection(hint)) if hint.contains("resolved")
+        ));
+        let auth = anyhow!("Authentication failure for root@host");
+        assert!(matches!(
+            classify_failure(&auth),
+            Some(Failure::Authentication)
+        ));
+    }
+
+    #[test]
+    fn file_failures() {
+        let denied = anyhow::Error::from(Error::from(ErrorKind::PermissionDenied))
+            .context("could not open /etc/shadow");
+        assert!(matches!(classify_failure(&denied), Some(Failure::File(_))));
+        let full = anyhow!("Failure: No space left on device");
+        assert!(matches!(
+            classify_failure(&full),
+            Some(Failure::File(hint)) if hint.contains("full")
+        ));
+        assert!(classify_failure(&anyhow!("unexpected end of file")).is_none());
+    }
+}
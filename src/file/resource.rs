@@ -14,11 +14,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::fmt::Debug;
 use std::io::ErrorKind;
+use std::process::Stdio;
 
 use async_trait::async_trait;
 use base64::Engine;
+use crypto::{digest::Digest, sha2::Sha256};
+use lazy_static::lazy_static;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
@@ -26,24 +30,44 @@ use serde::{Deserialize, Serialize};
 use tf_provider::schema::{
     Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
 };
-use tf_provider::value::{self, Value, ValueEmpty, ValueString};
+use tf_provider::value::{
+    self, Value, ValueBool, ValueEmpty, ValueMap, ValueNumber, ValueSet, ValueString,
+};
 use tf_provider::{map, AttributePath, Diagnostics, Resource};
 use tokio::fs::File;
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+use super::hash_stream::{decode_hex, Encoding, Fingerprints, HashingStream, ALGORITHMS};
+use super::{report_failure, temp_path};
+use crate::audit;
+use crate::connection::{parse_import_connection, Connection, FileType, ShellKind};
+use crate::limits;
+use crate::options::{report_read_only, SharedOptions};
+use crate::redact::Redactor;
+use crate::timeouts::{self, StateTimeouts, TIMEOUTS_BLOCK};
+use crate::transfer::{self, Progress};
+use crate::utils::{collect_stderr, AsyncDrop};
 
-use super::hash_stream::DefaultHashingStream;
-use crate::connection::Connection;
-use crate::utils::AsyncDrop;
+lazy_static! {
+    static ref SHA256SUMS_LOCK: Mutex<()> = Mutex::new(());
+}
 
 #[derive(Debug, Default)]
 pub struct GenericFileResource<T: Connection> {
+    pub(super) options: SharedOptions,
     pub(super) sensitive: bool,
     pub(super) connect: T,
 }
 
 impl<T: Connection> GenericFileResource<T> {
-    pub fn new(sensitive: bool, connect: T) -> Self {
-        Self { sensitive, connect }
+    pub fn new(options: SharedOptions, sensitive: bool, connect: T) -> Self {
+        Self {
+            options,
+            sensitive,
+            connect,
+        }
     }
 }
 
@@ -58,17 +82,40 @@ where
     pub content: ValueString<'a>,
     pub content_base64: ValueString<'a>,
     pub content_source: ValueString<'a>,
+    pub content_encrypted: ValueString<'a>,
+    pub decrypt_cmd: ValueString<'a>,
+    pub content_cmd: ValueString<'a>,
     pub mode: ValueString<'a>,
     pub overwrite: Value<bool>,
     pub keep: Value<bool>,
+    pub delete_recursive: ValueBool,
+    pub acl: ValueString<'a>,
+    pub owner_sid: ValueString<'a>,
+    pub sha256_file: ValueBool,
+    pub sha256sums: ValueString<'a>,
+    pub delta: ValueBool,
+    pub replace_on_change: ValueBool,
+    pub on_missing: ValueString<'a>,
+    pub ignore_missing_on_destroy: ValueBool,
     pub md5: ValueString<'a>,
     pub sha1: ValueString<'a>,
     pub sha256: ValueString<'a>,
     pub sha512: ValueString<'a>,
     pub sha256_base64: ValueString<'a>,
     pub sha512_base64: ValueString<'a>,
+    pub integrity: ValueString<'a>,
+    pub xxh3: ValueString<'a>,
+    pub crc32: ValueString<'a>,
+    pub size: ValueNumber,
+    pub algorithms: ValueSet<ValueString<'a>>,
+    pub encoding: ValueMap<'a, ValueString<'a>>,
+    pub digests: ValueMap<'a, ValueString<'a>>,
+    pub verify: ValueMap<'a, ValueString<'a>>,
+    pub verified: ValueBool,
     #[serde(with = "value::serde_as_vec")]
     pub connect: Value<T::Config<'a>>,
+    #[serde(with = "value::serde_as_vec")]
+    pub timeouts: Value<StateTimeouts<'a>>,
 }
 
 #[async_trait]
@@ -120,9 +167,27 @@ where
                         sensitive: self.sensitive,
                         ..Default::default()
                     },
+                    "content_encrypted" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Encrypted content of the remote file (eg: armored age or GPG), decrypted with `decrypt_cmd` while writing"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "decrypt_cmd" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Local command that reads `content_encrypted` on stdin and writes the plain content on stdout (eg: `age -d -i key.txt`, `gpg --decrypt`). The file is only replaced once the command succeeds"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "content_cmd" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Command executed through the connection whose stdout is the content of the remote file, executed again only when the command changes. The output is streamed to the file, so it can be large or binary. The file is only replaced once the command succeeds"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
                     "mode" => Attribute {
                         attr_type: AttributeType::String,
-                        description: Description::plain("Content of the remote file"),
+                        description: Description::plain("Permissions of the remote file in octal, or `preserve` to keep the permissions of an existing file"),
                         constraint: AttributeConstraint::OptionalComputed,
                         ..Default::default()
                     },
@@ -138,6 +203,60 @@ where
                         constraint: AttributeConstraint::OptionalComputed,
                         ..Default::default()
                     },
+                    "acl" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Access control list of the remote file in SDDL form (eg: `D:PAI(A;;FA;;;SY)(A;;FA;;;BA)`), applied with `Set-Acl` on Windows targets instead of `mode`"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "owner_sid" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("SID of the owner of the remote file (eg: `S-1-5-32-544`), on Windows targets"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "delete_recursive" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("On destroy, also delete the path if it has been replaced by a directory, with all its content (default: false)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "sha256_file" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Also write the SHA256 fingerprint of the file to `<path>.sha256`, in the `sha256sum` format and with the mode of the file (default: false)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "sha256sums" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Remote `SHA256SUMS` file where the entry of the file is added or replaced, and removed on destroy or when it changes"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "delta" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("On update, only transfer the blocks of 1 MiB that changed, instead of the whole file. Requires GNU `split`, `dd` and `truncate` on the target, and `content`, `content_base64` or `content_source` (default: false)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "replace_on_change" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Replace the file (delete, then create) when its content changes, instead of overwriting it in place (default: false)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "on_missing" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("What to do when the file is missing on refresh: `recreate` drops the resource from the state so it is planned for creation again, `error` fails the refresh (default: `recreate`)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "ignore_missing_on_destroy" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Succeed with a warning when the file cannot be deleted but does not exist anymore, eg: because its directory has been removed by a host cleanup. Unreachable targets are handled by the `force_destroy_when_unreachable` provider option (default: false)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
                     "md5" => Attribute {
                         attr_type: AttributeType::String,
                         description: Description::plain("MD5 fingerprint of the file (hex)"),
@@ -174,6 +293,60 @@ where
                         constraint: AttributeConstraint::Computed,
                         ..Default::default()
                     },
+                    "xxh3" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("XXH3 checksum of the file (hex), only suitable to detect transfer corruption"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "crc32" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("CRC32 checksum of the file (hex), only suitable to detect transfer corruption"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "integrity" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Subresource integrity of the file (`sha384-<base64>`), as expected by the `integrity` attribute of HTML tags"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "size" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Size of the file in bytes"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "algorithms" => Attribute {
+                        attr_type: AttributeType::Set(AttributeType::String.into()),
+                        description: Description::plain("Algorithms of the fingerprints computed for the file: `md5`, `sha1`, `sha256`, `sha384` (`integrity`), `sha512`, `xxh3` or `crc32`. The fingerprints of the other algorithms are null. SHA256 and the algorithms of `verify` and `encoding` are always computed (default: all)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "encoding" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::String.into()),
+                        description: Description::plain("Encodings of the fingerprints exposed in `digests`, by algorithm (`md5`, `sha1`, `sha256`, `sha384`, `sha512`, `xxh3` or `crc32`): `hex`, `base32` (RFC 4648), `nix32` (as in Nix hashes), `base64` or `base64url` (without padding, as in JWS)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "digests" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::String.into()),
+                        description: Description::plain("Fingerprints of the file in the encodings chosen by `encoding`, by algorithm"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "verify" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::String.into()),
+                        description: Description::plain("Expected fingerprints of the file (hex), by algorithm: `md5`, `sha1`, `sha256`, `sha512`, `xxh3` or `crc32`. The file is replaced if it does not match anymore when refreshed"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "verified" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Whether the remote file matched `verify` when it was last read"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
                 },
                 blocks: map! {
                     "connect" => NestedBlock::Optional(Block {
@@ -181,6 +354,7 @@ where
                         description: Description::plain("Connection configuration"),
                         ..Default::default()
                     }),
+                    "timeouts" => TIMEOUTS_BLOCK.clone(),
                 },
                 description: Description::plain("Reads a remote file"),
                 ..Default::default()
@@ -195,6 +369,11 @@ where
                 .validate(diags, AttributePath::new("connect").index(0), connect)
                 .await;
         }
+        timeouts::validate(
+            diags,
+            AttributePath::new("timeouts").index(0),
+            &config.timeouts,
+        );
 
         match &config.path {
             Value::Value(path) => {
@@ -208,19 +387,114 @@ where
             Value::Unknown => (),
         }
 
+        if config.sha256sums.as_deref_option() == Some("") {
+            diags.error_short(
+                "`sha256sums` should not be empty",
+                AttributePath::new("sha256sums"),
+            );
+        }
+
         let nb_values = config.content.is_value() as i32
             + config.content_base64.is_value() as i32
-            + config.content_source.is_value() as i32;
+            + config.content_source.is_value() as i32
+            + config.content_encrypted.is_value() as i32
+            + config.content_cmd.is_value() as i32;
         let nb_unknowns = config.content.is_unknown() as i32
             + config.content_base64.is_unknown() as i32
-            + config.content_source.is_unknown() as i32;
+            + config.content_source.is_unknown() as i32
+            + config.content_encrypted.is_unknown() as i32
+            + config.content_cmd.is_unknown() as i32;
 
         if !matches!((nb_values, nb_unknowns), (1, _) | (0, 1..)) {
-            diags.root_error("Invalid content specification", "Exactly one of `content`, `content_base64`, and `content_source` must be given. The others must be null.");
+            diags.root_error("Invalid content specification", "Exactly one of `content`, `content_base64`, `content_source`, `content_encrypted`, and `content_cmd` must be given. The others must be null.");
+        }
+
+        match (&config.content_encrypted, &config.decrypt_cmd) {
+            (Value::Null, Value::Value(_)) => diags.warning(
+                "Unused `decrypt_cmd`",
+                "`decrypt_cmd` is ignored when `content_encrypted` is not given.",
+                AttributePath::new("decrypt_cmd"),
+            ),
+            (Value::Value(_), Value::Null) => diags.error_short(
+                "`decrypt_cmd` is required to decrypt `content_encrypted`",
+                AttributePath::new("decrypt_cmd"),
+            ),
+            _ => (),
+        }
+
+        for (algorithm, fingerprint) in config.verify.iter().flatten() {
+            if !VERIFY_ALGORITHMS.contains(&algorithm.as_ref()) {
+                diags.error(
+                    "Invalid `verify` algorithm",
+                    format!(
+                        "Algorithm should be one of {}, but is `{algorithm}`",
+                        VERIFY_ALGORITHMS.join(", ")
+                    ),
+                    AttributePath::new("verify").key(algorithm.to_string()),
+                );
+            } else if fingerprint.as_str().is_empty() {
+                diags.error_short(
+                    "`verify` fingerprint should not be empty",
+                    AttributePath::new("verify").key(algorithm.to_string()),
+                );
+            }
+        }
+
+        if let Value::Value(on_missing) = &config.on_missing {
+            if !matches!(on_missing.as_ref(), "recreate" | "error") {
+                diags.error(
+                    "Invalid `on_missing`",
+                    format!("`on_missing` should be either `recreate` or `error`, but is `{on_missing}`"),
+                    AttributePath::new("on_missing"),
+                );
+            }
+        }
+
+        for algorithm in config
+            .algorithms
+            .iter()
+            .flatten()
+            .filter_map(|algorithm| algorithm.as_deref_option())
+        {
+            if !ALGORITHMS.contains(&algorithm) {
+                diags.error(
+                    "Invalid algorithm",
+                    format!(
+                        "Algorithm should be one of {}, but is `{algorithm}`",
+                        ALGORITHMS.join(", ")
+                    ),
+                    AttributePath::new("algorithms"),
+                );
+            }
+        }
+
+        for (algorithm, encoding) in config.encoding.iter().flatten() {
+            if !ALGORITHMS.contains(&algorithm.as_ref()) {
+                diags.error(
+                    "Invalid `encoding` algorithm",
+                    format!(
+                        "Algorithm should be one of {}, but is `{algorithm}`",
+                        ALGORITHMS.join(", ")
+                    ),
+                    AttributePath::new("encoding").key(algorithm.to_string()),
+                );
+            } else if let Value::Value(name) = encoding {
+                if Encoding::parse(name).is_none() {
+                    diags.error(
+                        "Invalid `encoding`",
+                        format!(
+                            "Encoding should be one of {}, but is `{name}`",
+                            Encoding::NAMES.join(", ")
+                        ),
+                        AttributePath::new("encoding").key(algorithm.to_string()),
+                    );
+                }
+            }
         }
 
         if let Value::Value(mode) = &config.mode {
             match isize::from_str_radix(mode.as_ref(), 8) {
+                _ if mode == "preserve" => (),
                 Ok(0..=4095) => (),
                 Ok(_) => diags.error("Invalid `mode`", format!("Mode should be an octal number between 0000 and 7777, but is {mode}"), AttributePath::new("mode")),
                 Err(err) => diags.error("Invalid `mode`", format!("Mode should be an octal number between 0000 and 7777, but is {mode}\n{err}"), AttributePath::new("mode")),
@@ -241,57 +515,136 @@ where
         private_state: Self::PrivateState<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
-        let default_connect_config = Default::default();
-        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+        let timeout = timeouts::get(&state.timeouts, "read");
+        let result = timeouts::run(timeout, async {
+            let default_connect_config = Default::default();
+            let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
 
-        let reader = match self.connect.read(connect_config, state.path.as_str()).await {
-            Ok(writer) => writer,
-            Err(err) => match err.downcast_ref::<std::io::Error>() {
-                Some(err) if err.kind() == ErrorKind::NotFound => {
+            let size = match self.connect.stat(connect_config, state.path.as_str()).await {
+                Ok(info) => {
+                    if info.file_type == FileType::Dir {
+                        diags.error_short("`path` is a directory", AttributePath::new("path"));
+                        return None;
+                    }
+                    info.size
+                }
+                Err(err) => match err.downcast_ref::<std::io::Error>() {
+                    Some(err) if err.kind() == ErrorKind::NotFound => {
+                        report_missing(diags, &state);
+                        return None;
+                    }
+                    _ => {
+                        report_failure(
+                            diags,
+                            &self.connect,
+                            &state.connect,
+                            "path",
+                            "Could not stat file",
+                            err,
+                        )
+                        .await;
+                        return None;
+                    }
+                },
+            };
+
+            // Refreshing many files at once shares the links to the hosts
+            let _permit = limits::acquire_transfer(&T::host(connect_config)).await;
+            let reader = match self.connect.read(connect_config, state.path.as_str()).await {
+                Ok(reader) => reader,
+                // The file has been removed since it was stat'ed
+                Err(err)
+                    if err
+                        .downcast_ref::<std::io::Error>()
+                        .is_some_and(|err| err.kind() == ErrorKind::NotFound) =>
+                {
+                    report_missing(diags, &state);
                     return None;
                 }
-                _ => {
-                    diags.root_error("Could not open file for reading", err.to_string());
+                Err(err) => {
+                    report_failure(
+                        diags,
+                        &self.connect,
+                        &state.connect,
+                        "path",
+                        "Could not open file for reading",
+                        err,
+                    )
+                    .await;
                     return None;
                 }
-            },
-        };
-        tokio::pin!(reader);
+            };
+            tokio::pin!(reader);
 
-        let reader = DefaultHashingStream::new(reader);
+            // Modifications are detected with SHA256: the other fingerprints are only computed
+            // when `verify` needs them, or to fill in a state written by an older version
+            let algorithms = computed_algorithms(&state)
+                .into_iter()
+                .filter(|algorithm| {
+                    *algorithm == "sha256"
+                        || state
+                            .verify
+                            .iter()
+                            .flatten()
+                            .any(|(name, _)| &**name == *algorithm)
+                        || fingerprint_missing(&state, algorithm)
+                })
+                .collect::<Vec<_>>();
+            let reader = HashingStream::new(reader, &algorithms);
 
-        let writer = tokio::io::sink();
-        tokio::pin!(reader, writer);
+            let writer = tokio::io::sink();
+            tokio::pin!(reader, writer);
 
-        let copy = tokio::io::copy(&mut reader, &mut writer).await;
-        reader.async_drop().await;
+            let mut progress = Progress::new(format!("Reading {}", state.path.as_str()), Some(size));
+            let copy = transfer::copy(&mut reader, &mut writer, &mut progress).await;
+            reader.async_drop().await;
 
-        match &copy {
-            Ok(_) => {
-                let (md5, sha1, sha256, sha512) = reader.fingerprints_hex();
-                let (_, _, sha256_base64, sha512_base64) = reader.fingerprints_base64();
-
-                if md5 != state.md5.as_str()
-                    || sha1 != state.sha1.as_str()
-                    || sha256 != state.sha256.as_str()
-                    || sha512 != state.sha512.as_str()
-                    || sha256_base64 != state.sha256_base64.as_str()
-                    || sha512_base64 != state.sha512_base64.as_str()
-                {
-                    state.md5 = Value::Null;
-                    state.sha1 = Value::Null;
-                    state.sha256 = Value::Null;
-                    state.sha512 = Value::Null;
-                    state.sha256_base64 = Value::Null;
-                    state.sha512_base64 = Value::Null;
+            match copy {
+                Ok(_) => {
+                    let fingerprints = reader.fingerprints();
+
+                    if state.verify.is_value() {
+                        let mismatches = verify_mismatches(&state.verify, &fingerprints);
+                        if !mismatches.is_empty() {
+                            diags.warning(
+                                "File does not match `verify`",
+                                format!(
+                                    "The remote file has been modified ({} mismatch) and will be replaced.",
+                                    mismatches.join(", ")
+                                ),
+                                AttributePath::new("verify"),
+                            );
+                        }
+                        state.verified = Value::Value(mismatches.is_empty());
+                    }
+
+                    let unchanged = fingerprints
+                        .hex("sha256")
+                        .is_some_and(|sha256| sha256 == state.sha256.as_str())
+                        && (state.size.is_null() || state.size == Value::Value(reader.size as i64));
+                    if unchanged {
+                        set_fingerprints(&mut state, &fingerprints, reader.size, true);
+                    } else {
+                        reset_fingerprints(&mut state, false);
+                    }
+                }
+                Err(err) => {
+                    report_failure(
+                        diags,
+                        &self.connect,
+                        &state.connect,
+                        "path",
+                        "Could not read file",
+                        anyhow::Error::from(err).context(progress.to_string()),
+                    )
+                    .await;
                 }
             }
-            Err(err) => {
-                diags.root_error("Could not read file", err.to_string());
-            }
-        }
 
-        Some((state, private_state))
+            Some((state, private_state))
+        })
+        .await;
+        timeouts::report(diags, "read", result)
     }
 
     async fn plan_create<'a>(
@@ -328,19 +681,64 @@ where
         if config_state.content_source.is_null() {
             state.content_source = Value::Null;
         }
-        if state.content != prior_state.content
-            || state.content_base64 != prior_state.content_base64
-            || state.content_source != prior_state.content_source
+        let mut replace = vec![];
+        let changed = [
+            ("content", state.content != prior_state.content),
+            (
+                "content_base64",
+                state.content_base64 != prior_state.content_base64,
+            ),
+            (
+                "content_source",
+                state.content_source != prior_state.content_source,
+            ),
+            (
+                "content_encrypted",
+                state.content_encrypted != prior_state.content_encrypted,
+            ),
+            ("content_cmd", state.content_cmd != prior_state.content_cmd),
+        ];
+        if changed.iter().any(|(_, changed)| *changed) {
+            // An imported file is adopted as is if it already has the planned content
+            let adopted = is_imported(&prior_state)
+                && state.mode == prior_state.mode
+                && planned_sha256(&state)
+                    .await
+                    .is_some_and(|sha256| sha256 == prior_state.sha256.as_str());
+            if !adopted {
+                reset_fingerprints(&mut state, true);
+
+                if state.replace_on_change.unwrap_or(false) {
+                    replace.extend(
+                        changed
+                            .iter()
+                            .filter(|(_, changed)| *changed)
+                            .map(|(name, _)| AttributePath::new(*name)),
+                    );
+                }
+            }
+        }
+        if computed_algorithms(&state) != computed_algorithms(&prior_state) {
+            // The fingerprints of the other algorithms are computed when the file is written again
+            reset_fingerprints(&mut state, true);
+        }
+        if state.encoding != prior_state.encoding && !state.digests.is_unknown() {
+            // The fingerprints are known, only their encoding changes
+            state.digests = encoded_digests(&state, &state_fingerprints(&state));
+        }
+        if prior_state.verified == Value::Value(false) {
+            // The file has been tampered with since it was written
+            replace.push(AttributePath::new("verified"));
+            state.verified = Value::Unknown;
+        } else if state.verify != prior_state.verify
+            || state.connect.is_unknown()
+            || state.connect.as_ref_option().is_some_and(T::has_unknown)
         {
-            state.md5 = Value::Unknown;
-            state.sha1 = Value::Unknown;
-            state.sha256 = Value::Unknown;
-            state.sha512 = Value::Unknown;
-            state.sha256_base64 = Value::Unknown;
-            state.sha512_base64 = Value::Unknown;
+            // The file is verified again on a target that is not known yet
+            state.verified = Value::Unknown;
         }
         self.normalize(&mut state);
-        Some((state, prior_private_state, vec![]))
+        Some((state, prior_private_state, replace))
     }
 
     async fn plan_destroy<'a>(
@@ -361,38 +759,88 @@ where
         planned_private_state: Self::PrivateState<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
-        let mut state = planned_state;
-        self.normalize(&mut state);
+        let timeout = timeouts::get(&planned_state.timeouts, "create");
+        let result = timeouts::run(timeout, async {
+            if self.options.get().read_only {
+                report_read_only(diags, "create");
+                return None;
+            }
+            let mut state = planned_state;
+            self.normalize(&mut state);
 
-        let overwrite = state.overwrite.unwrap_or(false);
-        self.write_file(diags, &mut state, overwrite).await?;
+            let overwrite = state.overwrite.unwrap_or(false);
+            self.write_file(diags, &mut state, overwrite).await?;
 
-        Some((state, planned_private_state))
+            Some((state, planned_private_state))
+        })
+        .await;
+        timeouts::report(diags, "create", result)
     }
     async fn update<'a>(
         &self,
         diags: &mut Diagnostics,
-        _prior_state: Self::State<'a>,
+        prior_state: Self::State<'a>,
         planned_state: Self::State<'a>,
         config_state: Self::State<'a>,
         planned_private_state: Self::PrivateState<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
-        let mut state = planned_state;
-        if config_state.content.is_null() {
-            state.content = Value::Null;
-        }
-        if config_state.content_base64.is_null() {
-            state.content_base64 = Value::Null;
-        }
-        if config_state.content_source.is_null() {
-            state.content_source = Value::Null;
-        }
-        self.normalize(&mut state);
+        let timeout = timeouts::get(&planned_state.timeouts, "update");
+        let result = timeouts::run(timeout, async {
+            if self.options.get().read_only {
+                report_read_only(diags, "update");
+                return None;
+            }
+            let mut state = planned_state;
+            if config_state.content.is_null() {
+                state.content = Value::Null;
+            }
+            if config_state.content_base64.is_null() {
+                state.content_base64 = Value::Null;
+            }
+            if config_state.content_source.is_null() {
+                state.content_source = Value::Null;
+            }
+            self.normalize(&mut state);
+
+            // The checksums that are not written anymore would describe a stale content
+            let moved = state.path != prior_state.path;
+            self.remove_checksums(
+                diags,
+                &prior_state,
+                moved || !state.sha256_file.unwrap_or(false),
+                moved || state.sha256sums != prior_state.sha256sums,
+            )
+            .await;
 
-        self.write_file(diags, &mut state, true).await?;
+            // The fingerprints are only known if an imported file already has the planned content
+            if is_imported(&prior_state)
+                && state.sha256.is_value()
+                && state.sha256 == prior_state.sha256
+            {
+                let mismatches = verify_mismatches(&state.verify, &state_fingerprints(&state));
+                if mismatches.is_empty() {
+                    self.apply_acl(diags, &state).await?;
+                    self.write_checksums(diags, &state).await?;
+                    state.verified = Value::Value(true);
+                    return Some((state, planned_private_state));
+                }
+            }
+
+            if state.delta.unwrap_or(false)
+                && state.path == prior_state.path
+                && state.mode == prior_state.mode
+                && self.write_delta(diags, &mut state).await?
+            {
+                return Some((state, planned_private_state));
+            }
+
+            self.write_file(diags, &mut state, true).await?;
 
-        Some((state, planned_private_state))
+            Some((state, planned_private_state))
+        })
+        .await;
+        timeouts::report(diags, "update", result)
     }
     async fn destroy<'a>(
         &self,
@@ -401,26 +849,194 @@ where
         _planned_private_state: Self::PrivateState<'a>,
         _provider_meta_state: Self::ProviderMetaState<'a>,
     ) -> Option<()> {
+        let timeout = timeouts::get(&state.timeouts, "delete");
+        let result = timeouts::run(timeout, async {
+            if self.options.get().read_only {
+                report_read_only(diags, "destroy");
+                return None;
+            }
+            let default_connect_config = Default::default();
+            let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+            if self.options.get().force_destroy_when_unreachable {
+                if let Err(err) = self.connect.connect(connect_config).await {
+                    let redactor = Redactor::for_connection::<T>(connect_config);
+                    diags.root_warning(
+                        "Target is unreachable, file removed from the state",
+                        format!(
+                            "Target: {}\n{}",
+                            T::target(connect_config),
+                            redactor.redact(&err.to_string())
+                        ),
+                    );
+                    return Some(());
+                }
+            }
+
+            let path = state.path.as_str();
+            let deleted = if state.delete_recursive.unwrap_or(false) {
+                self.connect.delete_recursive(connect_config, path).await
+            } else {
+                self.connect.delete(connect_config, path).await
+            };
+            self.remove_checksums(diags, &state, true, true).await;
+            let Err(err) = deleted else {
+                return Some(());
+            };
+            let not_found = |err: &anyhow::Error| {
+                err.downcast_ref::<std::io::Error>()
+                    .is_some_and(|err| err.kind() == ErrorKind::NotFound)
+            };
+            // Deleting a file whose parent directory has been removed can fail otherwise
+            let missing = not_found(&err)
+                || (state.ignore_missing_on_destroy.unwrap_or(false)
+                    && self
+                        .connect
+                        .stat(connect_config, path)
+                        .await
+                        .is_err_and(|err| not_found(&err)));
+            if missing {
+                diags.root_warning("File has already been deleted", err.to_string());
+                Some(())
+            } else {
+                diags.root_warning("Could not delete file", err.to_string());
+                None
+            }
+        })
+        .await;
+        timeouts::report(diags, "delete", result)
+    }
+
+    /// Import an existing file from an id `[<connection json>,]<path>`
+    ///
+    /// The file is left untouched: its fingerprints are computed to detect if it must be rewritten.
+    async fn import<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        id: String,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let (connect, path) = match parse_import_id::<T>(&id) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                diags.root_error(
+                    "Invalid import id",
+                    format!("Import id should be `[<connection json>,]<path>`: {err}"),
+                );
+                return None;
+            }
+        };
+
+        let mut state = Self::State {
+            id: Value::Value(
+                thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(30)
+                    .map(char::from)
+                    .collect(),
+            ),
+            path: Value::Value(Cow::Owned(path)),
+            content: Value::Null,
+            content_base64: Value::Null,
+            content_source: Value::Null,
+            content_encrypted: Value::Null,
+            decrypt_cmd: Value::Null,
+            content_cmd: Value::Null,
+            mode: Value::Null,
+            overwrite: Value::Value(false),
+            keep: Value::Value(false),
+            delete_recursive: Value::Null,
+            acl: Value::Null,
+            owner_sid: Value::Null,
+            sha256_file: Value::Null,
+            sha256sums: Value::Null,
+            delta: Value::Null,
+            replace_on_change: Value::Null,
+            on_missing: Value::Null,
+            ignore_missing_on_destroy: Value::Null,
+            md5: Value::Null,
+            sha1: Value::Null,
+            sha256: Value::Null,
+            sha512: Value::Null,
+            sha256_base64: Value::Null,
+            sha512_base64: Value::Null,
+            integrity: Value::Null,
+            xxh3: Value::Null,
+            crc32: Value::Null,
+            size: Value::Null,
+            algorithms: Value::Null,
+            encoding: Value::Null,
+            digests: Value::Null,
+            verify: Value::Null,
+            verified: Value::Null,
+            connect,
+            timeouts: Value::Null,
+        };
+
         let default_connect_config = Default::default();
         let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+        let path = state.path.as_str();
 
-        match self
-            .connect
-            .delete(connect_config, state.path.as_str())
-            .await
-        {
-            Ok(_) => Some(()),
-            Err(err) => match err.downcast_ref::<std::io::Error>() {
-                Some(err) if err.kind() == ErrorKind::NotFound => {
-                    diags.root_warning("File has already been deleted", err.to_string());
-                    Some(())
-                }
-                _ => {
-                    diags.root_warning("Could not delete file", err.to_string());
-                    None
-                }
-            },
+        let info = match self.connect.stat(connect_config, path).await {
+            Ok(info) if info.file_type == FileType::Dir => {
+                diags.root_error_short("Imported path is a directory");
+                return None;
+            }
+            Ok(info) => info,
+            Err(err) => {
+                report_failure(
+                    diags,
+                    &self.connect,
+                    &state.connect,
+                    "path",
+                    "Could not stat file",
+                    err,
+                )
+                .await;
+                return None;
+            }
+        };
+
+        let reader = match self.connect.read(connect_config, path).await {
+            Ok(reader) => reader,
+            Err(err) => {
+                report_failure(
+                    diags,
+                    &self.connect,
+                    &state.connect,
+                    "path",
+                    "Could not open file for reading",
+                    err,
+                )
+                .await;
+                return None;
+            }
+        };
+        tokio::pin!(reader);
+        let reader = HashingStream::new(reader, &computed_algorithms(&state));
+        let writer = tokio::io::sink();
+        tokio::pin!(reader, writer);
+
+        let mut progress = Progress::new(format!("Reading {path}"), Some(info.size));
+        let copy = transfer::copy_with_progress(&mut reader, &mut writer, &mut progress).await;
+        reader.async_drop().await;
+        if let Err(err) = copy {
+            report_failure(
+                diags,
+                &self.connect,
+                &state.connect,
+                "path",
+                "Could not read file",
+                anyhow::Error::from(err).context(progress.to_string()),
+            )
+            .await;
+            return None;
         }
+
+        state.mode = Value::Value(format!("{:04o}", info.mode).into());
+        let fingerprints = reader.fingerprints();
+        set_fingerprints(&mut state, &fingerprints, reader.size, false);
+
+        Some((state, Default::default()))
     }
 }
 
@@ -429,23 +1045,31 @@ impl<T: Connection> GenericFileResource<T> {
         if state.id.is_null() {
             state.id = Value::Unknown;
         }
-        if state.md5.is_null() {
-            state.md5 = Value::Unknown;
-        }
-        if state.sha1.is_null() {
-            state.sha1 = Value::Unknown;
-        }
-        if state.sha256.is_null() {
-            state.sha256 = Value::Unknown;
+        // Fingerprints of the algorithms that are not computed stay null
+        let algorithms = computed_algorithms(state);
+        for (algorithm, attribute) in [
+            ("md5", &mut state.md5),
+            ("sha1", &mut state.sha1),
+            ("sha256", &mut state.sha256),
+            ("sha512", &mut state.sha512),
+            ("sha256", &mut state.sha256_base64),
+            ("sha512", &mut state.sha512_base64),
+            ("sha384", &mut state.integrity),
+            ("xxh3", &mut state.xxh3),
+            ("crc32", &mut state.crc32),
+        ] {
+            if attribute.is_null() && algorithms.contains(&algorithm) {
+                *attribute = Value::Unknown;
+            }
         }
-        if state.sha512.is_null() {
-            state.sha512 = Value::Unknown;
+        if state.size.is_null() {
+            state.size = Value::Unknown;
         }
-        if state.sha256_base64.is_null() {
-            state.sha256_base64 = Value::Unknown;
+        if state.digests.is_null() {
+            state.digests = Value::Unknown;
         }
-        if state.sha512_base64.is_null() {
-            state.sha512_base64 = Value::Unknown;
+        if state.verified.is_null() {
+            state.verified = Value::Unknown;
         }
         if !state.mode.is_value() {
             let mode = if self.sensitive { "0600" } else { "0666" };
@@ -458,6 +1082,84 @@ impl<T: Connection> GenericFileResource<T> {
             state.keep = Value::Value(false);
         }
     }
+    /// Set the owner and the access control list of the file on Windows targets
+    async fn apply_acl(&self, diags: &mut Diagnostics, state: &ResourceState<'_, T>) -> Option<()> {
+        if state.acl.is_null() && state.owner_sid.is_null() {
+            return Some(());
+        }
+
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+        let redactor = Redactor::for_connection::<T>(connect_config);
+
+        match self.connect.capabilities(connect_config).await {
+            Ok(capabilities) if capabilities.shell == ShellKind::PowerShell => (),
+            Ok(_) => {
+                diags.root_error(
+                    "`acl` and `owner_sid` are only supported on Windows targets",
+                    format!(
+                        "Use `mode` to set the permissions of the file on {}.",
+                        T::target(connect_config)
+                    ),
+                );
+                return None;
+            }
+            Err(err) => {
+                diags.root_error(
+                    "Could not detect the shell of the target",
+                    redactor.redact(&err.to_string()).into_owned(),
+                );
+                return None;
+            }
+        }
+
+        let env = [
+            ("FILE_PATH", state.path.as_str()),
+            ("FILE_ACL", state.acl.as_str()),
+            ("FILE_OWNER_SID", state.owner_sid.as_str()),
+        ];
+        let script = r#"$acl = Get-Acl -LiteralPath $env:FILE_PATH
+if ($env:FILE_ACL) {
+    $acl.SetSecurityDescriptorSddlForm($env:FILE_ACL, 'Access')
+}
+if ($env:FILE_OWNER_SID) {
+    $acl.SetOwner([System.Security.Principal.SecurityIdentifier]::new($env:FILE_OWNER_SID))
+}
+Set-Acl -LiteralPath $env:FILE_PATH -AclObject $acl"#;
+        match audit::execute(
+            &self.connect,
+            connect_config,
+            script,
+            "",
+            env.iter().map(|(k, v)| (k, v)),
+            &format!("{}_file", T::NAME),
+            "write",
+        )
+        .await
+        {
+            Ok(res) if res.status == 0 => Some(()),
+            Ok(res) => {
+                diags.error(
+                    format!(
+                        "Could not set the ACL of the file (status code {})",
+                        res.status
+                    ),
+                    redactor.redact(&res.stderr).into_owned(),
+                    AttributePath::new("acl"),
+                );
+                None
+            }
+            Err(err) => {
+                diags.error(
+                    "Could not set the ACL of the file",
+                    redactor.redact(&err.to_string()).into_owned(),
+                    AttributePath::new("acl"),
+                );
+                None
+            }
+        }
+    }
+
     async fn write_file<'a>(
         &self,
         diags: &mut Diagnostics,
@@ -477,34 +1179,28 @@ impl<T: Connection> GenericFileResource<T> {
         let default_connect_config = Default::default();
         let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
 
-        let writer = match self
-            .connect
-            .write(
-                connect_config,
-                state.path.as_str(),
-                u32::from_str_radix(state.mode.as_str(), 8).unwrap_or(0o666),
-                overwrite,
-            )
-            .await
-        {
-            Ok(writer) => writer,
-            Err(err) => {
-                log::error!("Could not open file for writing: {err}");
-                diags.root_error("Could not open file for writing", err.to_string());
-                return None;
+        let default_mode = if self.sensitive { 0o600 } else { 0o666 };
+        let mode = if state.mode.as_str() == "preserve" {
+            // Files are overwritten in place, so the owner is kept as well
+            match self.connect.stat(connect_config, state.path.as_str()).await {
+                Ok(info) => info.mode,
+                Err(_) => default_mode,
             }
+        } else {
+            u32::from_str_radix(state.mode.as_str(), 8).unwrap_or(default_mode)
         };
-        tokio::pin!(writer);
-
-        let mut writer = DefaultHashingStream::new(writer);
 
         enum Content<'b> {
             Raw(&'b [u8]),
             Base64(Vec<u8>),
             File(File),
+            Decrypted(Child, ChildStdout),
+            Command(&'b str),
         }
 
-        let content = if let Value::Value(content) = &state.content {
+        let content = if let Value::Value(cmd) = &state.content_cmd {
+            Content::Command(cmd)
+        } else if let Value::Value(content) = &state.content {
             Content::Raw(content.as_bytes())
         } else if let Value::Value(base64) = &state.content_base64 {
             match base64::engine::general_purpose::STANDARD.decode(base64.as_bytes()) {
@@ -516,7 +1212,6 @@ impl<T: Connection> GenericFileResource<T> {
                         err.to_string(),
                         AttributePath::new("content_base64"),
                     );
-                    writer.async_drop().await;
                     return None;
                 }
             }
@@ -530,54 +1225,681 @@ impl<T: Connection> GenericFileResource<T> {
                         err.to_string(),
                         AttributePath::new("content_source"),
                     );
-                    writer.async_drop().await;
+                    return None;
+                }
+            }
+        } else if let Value::Value(encrypted) = &state.content_encrypted {
+            match spawn_decrypt(state.decrypt_cmd.as_str(), encrypted.as_bytes().to_vec()) {
+                Ok((child, stdout)) => Content::Decrypted(child, stdout),
+                Err(err) => {
+                    log::error!("Could not run decryption command: {err}");
+                    diags.error(
+                        "Could not run decryption command",
+                        err.to_string(),
+                        AttributePath::new("decrypt_cmd"),
+                    );
                     return None;
                 }
             }
         } else {
             log::error!("No content provided");
             diags.root_error_short("No content provided");
-            writer.async_drop().await;
             return None;
         };
 
+        // Content streamed from a command is only complete once the command succeeds:
+        // it is written to a temporary file that replaces the file afterwards
+        let path = state.path.as_str();
+        let temp = matches!(content, Content::Decrypted(..) | Content::Command(_))
+            .then(|| temp_path(path));
+        if temp.is_some() && !overwrite && self.connect.stat(connect_config, path).await.is_ok() {
+            report_failure(
+                diags,
+                &self.connect,
+                &state.connect,
+                "path",
+                "Could not open file for writing",
+                std::io::Error::new(ErrorKind::AlreadyExists, format!("{path} already exists"))
+                    .into(),
+            )
+            .await;
+            return None;
+        }
+
+        let writer = match self
+            .connect
+            .write(
+                connect_config,
+                temp.as_deref().unwrap_or(path),
+                mode,
+                overwrite || temp.is_some(),
+            )
+            .await
+        {
+            Ok(writer) => writer,
+            Err(err) => {
+                log::error!("Could not open file for writing: {err}");
+                report_failure(
+                    diags,
+                    &self.connect,
+                    &state.connect,
+                    "path",
+                    "Could not open file for writing",
+                    err,
+                )
+                .await;
+                return None;
+            }
+        };
+        tokio::pin!(writer);
+
+        let mut writer = HashingStream::new(writer, &computed_algorithms(state));
+
         enum ContentReader<'b> {
             Raw(&'b [u8]),
             File(File),
+            Stdout(ChildStdout),
         }
 
+        let mut decrypt_child = None;
+        let mut command = None;
         let mut content = match content {
             Content::Raw(raw) => ContentReader::Raw(raw),
             Content::Base64(ref decoded) => ContentReader::Raw(decoded.as_slice()),
             Content::File(file) => ContentReader::File(file),
+            Content::Decrypted(mut child, stdout) => {
+                let stderr = collect_stderr(&mut child);
+                decrypt_child = Some((child, stderr));
+                ContentReader::Stdout(stdout)
+            }
+            // The command writes its output to the file itself
+            Content::Command(cmd) => {
+                command = Some(cmd);
+                ContentReader::Raw(&[])
+            }
+        };
+
+        let total = match &content {
+            ContentReader::Raw(raw) => Some(raw.len() as u64),
+            ContentReader::File(file) => file.metadata().await.ok().map(|meta| meta.len()),
+            ContentReader::Stdout(_) => None,
         };
+        let mut progress = Progress::new(format!("Writing {path}"), total);
 
         let reader = match &mut content {
             ContentReader::Raw(raw) => raw as &mut (dyn AsyncRead + Send + Unpin),
             ContentReader::File(file) => file as &mut (dyn AsyncRead + Send + Unpin),
+            ContentReader::Stdout(stdout) => stdout as &mut (dyn AsyncRead + Send + Unpin),
         };
 
-        let write = tokio::io::copy(reader, &mut writer).await;
+        // The stdout of the command is streamed as raw bytes, so large or binary outputs are never collected
+        let executed = match command {
+            Some(cmd) => Some(
+                audit::execute_piped(
+                    &self.connect,
+                    connect_config,
+                    cmd,
+                    &format!("{}_file", T::NAME),
+                    "write",
+                    &mut writer,
+                )
+                .await,
+            ),
+            None => None,
+        };
+        let write = match transfer::copy_with_progress(reader, &mut writer, &mut progress).await {
+            Ok(written) => writer.shutdown().await.map(|()| written),
+            Err(err) => Err(err),
+        };
         writer.async_drop().await;
 
         match write {
             Ok(_) => (),
             Err(err) => {
-                diags.root_error("Could not write to file", err.to_string());
+                if let Some(temp) = &temp {
+                    self.discard(connect_config, temp).await;
+                }
+                report_failure(
+                    diags,
+                    &self.connect,
+                    &state.connect,
+                    "path",
+                    "Could not write to file",
+                    anyhow::Error::from(err).context(progress.to_string()),
+                )
+                .await;
                 return None;
             }
         };
 
-        let (md5, sha1, sha256, sha512) = writer.fingerprints_hex();
-        let (_, _, sha256_base64, sha512_base64) = writer.fingerprints_base64();
+        if let Some((mut child, stderr)) = decrypt_child {
+            let failure = match child.wait().await {
+                Ok(status) if status.success() => None,
+                Ok(status) => Some((
+                    format!("`decrypt_cmd` failed with {status}"),
+                    String::from_utf8_lossy(&stderr.await.unwrap_or_default()).into_owned(),
+                )),
+                Err(err) => Some((
+                    "Could not wait for `decrypt_cmd`".to_owned(),
+                    err.to_string(),
+                )),
+            };
+            if let Some((summary, detail)) = failure {
+                if let Some(temp) = &temp {
+                    self.discard(connect_config, temp).await;
+                }
+                diags.error(summary, detail, AttributePath::new("decrypt_cmd"));
+                return None;
+            }
+        }
+
+        if let Some(executed) = executed {
+            let redactor = Redactor::for_connection::<T>(connect_config);
+            let failure = match executed {
+                Ok(res) if res.status == 0 => None,
+                Ok(res) => Some((
+                    format!("`content_cmd` failed with status code: {}", res.status),
+                    redactor.redact(&res.stderr).into_owned(),
+                )),
+                Err(err) => Some((
+                    "Could not run `content_cmd`".to_owned(),
+                    format!(
+                        "Target: {}\n{}",
+                        T::target(connect_config),
+                        redactor.redact(&format!("{err:#}"))
+                    ),
+                )),
+            };
+            if let Some((summary, detail)) = failure {
+                if let Some(temp) = &temp {
+                    self.discard(connect_config, temp).await;
+                }
+                diags.error(summary, detail, AttributePath::new("content_cmd"));
+                return None;
+            }
+        }
+
+        if let Some(temp) = &temp {
+            if let Err(err) = self.replace(connect_config, temp, path).await {
+                self.discard(connect_config, temp).await;
+                report_failure(
+                    diags,
+                    &self.connect,
+                    &state.connect,
+                    "path",
+                    "Could not replace file",
+                    err,
+                )
+                .await;
+                return None;
+            }
+        }
+
+        self.finish_write(diags, state, writer.fingerprints(), writer.size)
+            .await
+    }
+
+    /// Check and record the fingerprints of the content written to the file
+    pub(super) async fn finish_write(
+        &self,
+        diags: &mut Diagnostics,
+        state: &mut ResourceState<'_, T>,
+        fingerprints: Fingerprints,
+        size: u64,
+    ) -> Option<()> {
+        self.apply_acl(diags, state).await?;
+
+        let mismatches = verify_mismatches(&state.verify, &fingerprints);
+        if !mismatches.is_empty() {
+            diags.error(
+                "Written file does not match `verify`",
+                format!(
+                    "The content written to the remote file does not have the expected fingerprint ({} mismatch).",
+                    mismatches.join(", ")
+                ),
+                AttributePath::new("verify"),
+            );
+            return None;
+        }
+        state.verified = Value::Value(true);
+
+        set_fingerprints(state, &fingerprints, size, false);
+
+        self.write_checksums(diags, state).await
+    }
+
+    /// Write the SHA256 fingerprint of the file to `<path>.sha256` and to the `sha256sums` file
+    async fn write_checksums(
+        &self,
+        diags: &mut Diagnostics,
+        state: &ResourceState<'_, T>,
+    ) -> Option<()> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+        let path = state.path.as_str();
+        let entry = format!("{}  {}\n", state.sha256.as_str(), file_name(path));
 
-        state.md5 = Value::Value(md5.into());
-        state.sha1 = Value::Value(sha1.into());
-        state.sha256 = Value::Value(sha256.into());
-        state.sha512 = Value::Value(sha512.into());
-        state.sha256_base64 = Value::Value(sha256_base64.into());
-        state.sha512_base64 = Value::Value(sha512_base64.into());
+        if state.sha256_file.unwrap_or(false) {
+            let sidecar = format!("{path}.sha256");
+            // The checksum is as readable as the file itself
+            let mode = match self.connect.stat(connect_config, path).await {
+                Ok(info) => info.mode,
+                Err(_) => 0o644,
+            };
+            if let Err(err) = self
+                .write_whole(connect_config, &sidecar, mode, entry.as_bytes())
+                .await
+            {
+                diags.error(
+                    "Could not write checksum file",
+                    format!("Could not write `{sidecar}`: {err}"),
+                    AttributePath::new("sha256_file"),
+                );
+                return None;
+            }
+        }
 
+        if let Value::Value(sums) = &state.sha256sums {
+            if let Err(err) = self
+                .update_sha256sums(connect_config, sums, path, Some(&entry))
+                .await
+            {
+                diags.error(
+                    "Could not update checksum file",
+                    format!("Could not update `{sums}`: {err}"),
+                    AttributePath::new("sha256sums"),
+                );
+                return None;
+            }
+        }
         Some(())
     }
+
+    /// Remove the checksum file of the file, and its entry in the `sha256sums` file, if they were written
+    ///
+    /// Failures are only warnings, as the file itself is not affected.
+    async fn remove_checksums(
+        &self,
+        diags: &mut Diagnostics,
+        state: &ResourceState<'_, T>,
+        sidecar: bool,
+        sums: bool,
+    ) {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+        let path = state.path.as_str();
+        if sidecar && state.sha256_file.unwrap_or(false) {
+            match self
+                .connect
+                .delete(connect_config, &format!("{path}.sha256"))
+                .await
+            {
+                Ok(_) => (),
+                Err(err) => match err.downcast_ref::<std::io::Error>() {
+                    Some(err) if err.kind() == ErrorKind::NotFound => (),
+                    _ => diags.root_warning("Could not delete checksum file", err.to_string()),
+                },
+            }
+        }
+        if let (true, Value::Value(sums)) = (sums, &state.sha256sums) {
+            if let Err(err) = self
+                .update_sha256sums(connect_config, sums, path, None)
+                .await
+            {
+                diags.root_warning(
+                    format!("Could not remove the file from `{sums}`"),
+                    err.to_string(),
+                );
+            }
+        }
+    }
+
+    /// Replace the entry of a file in a `SHA256SUMS` file, or remove it if `entry` is `None`
+    async fn update_sha256sums<'a>(
+        &self,
+        config: &T::Config<'a>,
+        sums: &str,
+        path: &str,
+        entry: Option<&str>,
+    ) -> anyhow::Result<()> {
+        // Resources sharing a `SHA256SUMS` file must not lose the entries of the others
+        let _lock = SHA256SUMS_LOCK.lock().await;
+        let name = file_name(path);
+        let content = match self.read_whole(config, sums).await {
+            Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+            Err(err) => match err.downcast_ref::<std::io::Error>() {
+                Some(err) if err.kind() == ErrorKind::NotFound => String::new(),
+                _ => return Err(err),
+            },
+        };
+        let mut updated = content
+            .lines()
+            .filter(|line| {
+                // Entries are `<hash>  <name>`, or `<hash> *<name>` in binary mode
+                line.split_once(' ')
+                    .map(|(_, entry_name)| entry_name.trim_start_matches([' ', '*']))
+                    != Some(name)
+            })
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
+        updated += entry.unwrap_or_default();
+        if updated != content {
+            self.write_whole(config, sums, 0o644, updated.as_bytes())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Replace the file at `path` by the temporary file `temp`
+    ///
+    /// Some SFTP servers cannot rename over an existing file, so the file is removed first if the rename fails.
+    async fn replace<'a>(
+        &self,
+        config: &T::Config<'a>,
+        temp: &str,
+        path: &str,
+    ) -> anyhow::Result<()> {
+        if self.connect.rename(config, temp, path).await.is_ok() {
+            return Ok(());
+        }
+        if let Err(err) = self.connect.delete(config, path).await {
+            log::debug!("Could not remove {path} before replacing it: {err}");
+        }
+        self.connect.rename(config, temp, path).await
+    }
+
+    /// Remove a temporary file whose content is not used
+    pub(super) async fn discard<'a>(&self, config: &T::Config<'a>, temp: &str) {
+        if let Err(err) = self.connect.delete(config, temp).await {
+            log::warn!("Could not remove temporary file {temp}: {err}");
+        }
+    }
+
+    async fn read_whole<'a>(&self, config: &T::Config<'a>, path: &str) -> anyhow::Result<Vec<u8>> {
+        let reader = self.connect.read(config, path).await?;
+        tokio::pin!(reader);
+        let mut reader = HashingStream::new(reader, &[]);
+        let mut content = Vec::new();
+        let read = reader.read_to_end(&mut content).await;
+        reader.async_drop().await;
+        read?;
+        Ok(content)
+    }
+
+    pub(super) async fn write_whole<'a>(
+        &self,
+        config: &T::Config<'a>,
+        path: &str,
+        mode: u32,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        let writer = self.connect.write(config, path, mode, true).await?;
+        tokio::pin!(writer);
+        let mut writer = HashingStream::new(writer, &[]);
+        let write = match writer.write_all(content).await {
+            Ok(()) => writer.shutdown().await,
+            Err(err) => Err(err),
+        };
+        writer.async_drop().await;
+        write?;
+        Ok(())
+    }
+}
+
+/// Name of the file, as written in the checksum files
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+/// Split an import id into its connection configuration and its path
+fn parse_import_id<'a, T: Connection>(id: &str) -> anyhow::Result<(Value<T::Config<'a>>, String)> {
+    let (connect, path) = parse_import_connection::<T>(id)?;
+    if path.is_empty() {
+        return Err(anyhow::anyhow!("path is empty"));
+    }
+    Ok((connect, path.to_owned()))
+}
+
+/// Check if the state comes from an import, and has never been written by the provider
+fn is_imported<T: Connection>(state: &ResourceState<'_, T>) -> bool {
+    state.content.is_null()
+        && state.content_base64.is_null()
+        && state.content_source.is_null()
+        && state.content_encrypted.is_null()
+        && state.content_cmd.is_null()
+        && state.sha256.is_value()
+}
+
+/// SHA256 fingerprint (hex) of the planned content, if it can be known before writing the file
+async fn planned_sha256<T: Connection>(state: &ResourceState<'_, T>) -> Option<String> {
+    let content = if let Value::Value(content) = &state.content {
+        Cow::Borrowed(content.as_bytes())
+    } else if let Value::Value(base64) = &state.content_base64 {
+        Cow::Owned(
+            base64::engine::general_purpose::STANDARD
+                .decode(base64.as_bytes())
+                .ok()?,
+        )
+    } else if let Value::Value(filename) = &state.content_source {
+        Cow::Owned(tokio::fs::read(filename.as_ref()).await.ok()?)
+    } else {
+        // Encrypted content and command outputs are only known when written
+        return None;
+    };
+    let mut hasher = Sha256::new();
+    hasher.input(&content);
+    Some(hasher.result_str())
+}
+
+/// Algorithms of the fingerprints that can be checked with `verify`
+const VERIFY_ALGORITHMS: [&str; 6] = ["md5", "sha1", "sha256", "sha512", "xxh3", "crc32"];
+
+/// Report a file missing on refresh, according to `on_missing`
+///
+/// Without any error, the resource is dropped from the state and planned for creation again.
+fn report_missing<T: Connection>(diags: &mut Diagnostics, state: &ResourceState<'_, T>) {
+    if state.on_missing.as_str() == "error" {
+        diags.error(
+            "File does not exist",
+            format!(
+                "`{}` has been removed outside of Terraform.",
+                state.path.as_str()
+            ),
+            AttributePath::new("path"),
+        );
+    }
+}
+
+/// Fingerprints of the file in the encodings chosen by `encoding`
+fn encoded_digests<'a, T: Connection>(
+    state: &ResourceState<'a, T>,
+    fingerprints: &Fingerprints,
+) -> ValueMap<'a, ValueString<'a>> {
+    let encoding = match &state.encoding {
+        Value::Value(encoding) => encoding,
+        Value::Null => return Value::Value(Default::default()),
+        Value::Unknown => return Value::Unknown,
+    };
+    if encoding.values().any(Value::is_unknown) {
+        return Value::Unknown;
+    }
+    Value::Value(
+        encoding
+            .iter()
+            .filter_map(|(algorithm, name)| {
+                let encoding = Encoding::parse(name.as_deref_option()?)?;
+                let fingerprint = fingerprints.get(algorithm, encoding)?;
+                Some((algorithm.clone(), Value::Value(fingerprint.into())))
+            })
+            .collect(),
+    )
+}
+
+/// Raw fingerprint of the file, decoded from its hex or integrity form in the state
+fn state_fingerprint<T: Connection>(
+    state: &ResourceState<'_, T>,
+    algorithm: &str,
+) -> Option<Vec<u8>> {
+    let hex = match algorithm {
+        "md5" => &state.md5,
+        "sha1" => &state.sha1,
+        "sha256" => &state.sha256,
+        "sha512" => &state.sha512,
+        "xxh3" => &state.xxh3,
+        "crc32" => &state.crc32,
+        "sha384" => {
+            let integrity = state.integrity.as_deref_option()?.strip_prefix("sha384-")?;
+            return base64::engine::general_purpose::STANDARD
+                .decode(integrity)
+                .ok();
+        }
+        _ => return None,
+    };
+    decode_hex(hex.as_deref_option().filter(|hex| !hex.is_empty())?)
+}
+
+/// Fingerprints of the file, decoded from the state
+fn state_fingerprints<T: Connection>(state: &ResourceState<'_, T>) -> Fingerprints {
+    ALGORITHMS
+        .into_iter()
+        .filter_map(|algorithm| Some((algorithm, state_fingerprint(state, algorithm)?)))
+        .collect()
+}
+
+/// Algorithms of the fingerprints computed for the file
+///
+/// Those of `algorithms` (default: all), SHA256, and the ones needed by `verify` and `encoding`.
+pub(super) fn computed_algorithms<T: Connection>(
+    state: &ResourceState<'_, T>,
+) -> Vec<&'static str> {
+    ALGORITHMS
+        .into_iter()
+        .filter(|algorithm| {
+            *algorithm == "sha256"
+                || state.algorithms.is_null()
+                || state
+                    .algorithms
+                    .iter()
+                    .flatten()
+                    .any(|name| &**name == *algorithm)
+                || [&state.verify, &state.encoding]
+                    .into_iter()
+                    .flat_map(|map| map.iter().flatten())
+                    .any(|(name, _)| &**name == *algorithm)
+        })
+        .collect()
+}
+
+/// Whether an attribute of the fingerprint is missing from the state, eg: written by an older version
+fn fingerprint_missing<T: Connection>(state: &ResourceState<'_, T>, algorithm: &str) -> bool {
+    let attributes = match algorithm {
+        "md5" => [&state.md5, &state.md5],
+        "sha1" => [&state.sha1, &state.sha1],
+        "sha256" => [&state.sha256, &state.sha256_base64],
+        "sha384" => [&state.integrity, &state.integrity],
+        "sha512" => [&state.sha512, &state.sha512_base64],
+        "xxh3" => [&state.xxh3, &state.xxh3],
+        "crc32" => [&state.crc32, &state.crc32],
+        _ => return false,
+    };
+    attributes.into_iter().any(Value::is_null)
+}
+
+/// Set the fingerprint attributes of the state, the ones whose algorithm has not been computed are null
+///
+/// With `missing_only`, only the attributes without a value are set.
+pub(super) fn set_fingerprints<T: Connection>(
+    state: &mut ResourceState<'_, T>,
+    fingerprints: &Fingerprints,
+    size: u64,
+    missing_only: bool,
+) {
+    for (attribute, fingerprint) in [
+        (&mut state.md5, fingerprints.hex("md5")),
+        (&mut state.sha1, fingerprints.hex("sha1")),
+        (&mut state.sha256, fingerprints.hex("sha256")),
+        (&mut state.sha512, fingerprints.hex("sha512")),
+        (&mut state.sha256_base64, fingerprints.base64("sha256")),
+        (&mut state.sha512_base64, fingerprints.base64("sha512")),
+        (
+            &mut state.integrity,
+            fingerprints
+                .base64("sha384")
+                .map(|sha384| format!("sha384-{sha384}")),
+        ),
+        (&mut state.xxh3, fingerprints.hex("xxh3")),
+        (&mut state.crc32, fingerprints.hex("crc32")),
+    ] {
+        if !missing_only || attribute.is_null() {
+            *attribute =
+                fingerprint.map_or(Value::Null, |fingerprint| Value::Value(fingerprint.into()));
+        }
+    }
+    if !missing_only || state.size.is_null() {
+        state.size = Value::Value(size as i64);
+    }
+    if !missing_only || state.digests.is_null() {
+        state.digests = encoded_digests(state, fingerprints);
+    }
+}
+
+/// Set all the fingerprint attributes to null when the file has been modified, or unknown when it is written again
+fn reset_fingerprints<T: Connection>(state: &mut ResourceState<'_, T>, unknown: bool) {
+    for attribute in [
+        &mut state.md5,
+        &mut state.sha1,
+        &mut state.sha256,
+        &mut state.sha512,
+        &mut state.sha256_base64,
+        &mut state.sha512_base64,
+        &mut state.integrity,
+        &mut state.xxh3,
+        &mut state.crc32,
+    ] {
+        *attribute = if unknown { Value::Unknown } else { Value::Null };
+    }
+    state.size = if unknown { Value::Unknown } else { Value::Null };
+    state.digests = if unknown { Value::Unknown } else { Value::Null };
+}
+
+/// List the algorithms of `verify` whose expected fingerprint differs from the computed one
+fn verify_mismatches(verify: &ValueMap<ValueString>, fingerprints: &Fingerprints) -> Vec<String> {
+    verify
+        .iter()
+        .flatten()
+        .filter(|(algorithm, expected)| {
+            let fingerprint = fingerprints.hex(algorithm);
+            expected.as_deref_option().is_some_and(|expected| {
+                fingerprint.is_none_or(|f| !expected.eq_ignore_ascii_case(&f))
+            })
+        })
+        .map(|(algorithm, _)| algorithm.to_string())
+        .collect()
+}
+
+/// Spawn the local decryption command, feeding it the encrypted content
+///
+/// The plain content is only ever streamed from the stdout of the command to a temporary remote file.
+fn spawn_decrypt(cmd: &str, encrypted: Vec<u8>) -> std::io::Result<(Child, ChildStdout)> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(std::io::Error::other("decryption command is not piped"));
+    };
+
+    // The encrypted content is written concurrently to avoid a deadlock on full pipes
+    tokio::spawn(async move {
+        if let Err(err) = stdin.write_all(&encrypted).await {
+            log::error!("Could not send encrypted content to decryption command: {err}");
+        }
+    });
+
+    Ok((child, stdout))
 }
@@ -14,29 +14,154 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::path::PathBuf;
+use std::time::Duration;
+
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
-use tf_provider::schema::{Block, Description, Schema};
-use tf_provider::{map, value::ValueEmpty, Diagnostics, Provider};
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
+};
+use tf_provider::value::{self, Value, ValueBool, ValueEmpty, ValueNumber, ValueString};
+use tf_provider::{map, AttributePath, Diagnostics, Provider};
 
 use crate::{
+    audit,
+    check::GenericCheckDataSource,
     cmd::{GenericCmdDataSource, GenericCmdResource},
-    connection::{local::ConnectionLocal, ssh::ConnectionSsh},
-    file::{GenericFileDataSource, GenericFileResource},
+    connection::{kubernetes::ConnectionKubernetes, local::ConnectionLocal, ssh::ConnectionSsh},
+    fault,
+    file::{
+        GenericFileDataSource, GenericFileFetchDirDataSource, GenericFileGlobDataSource,
+        GenericFileResource,
+    },
+    inventory::GenericInventoryDataSource,
+    limits::{self, Limits},
+    options::{env_flag, ProviderOptions, SharedOptions},
+    system::{
+        GenericAuthorizedKeyResource, GenericHostsEntryResource, GenericMountResource,
+        GenericSysctlResource,
+    },
 };
 
 #[derive(Debug, Default, Clone)]
-pub struct GenericProvider {}
+pub struct GenericProvider {
+    options: SharedOptions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct GenericProviderConfig<'a> {
+    pub dry_run: ValueBool,
+    pub read_only: ValueBool,
+    pub force_destroy_when_unreachable: ValueBool,
+    pub data_source_timeout: ValueNumber,
+    pub read_concurrency: ValueNumber,
+    #[serde(borrow = "'a")]
+    pub audit_log: ValueString<'a>,
+    #[serde(with = "value::serde_as_vec")]
+    pub rate_limit: Value<RateLimitConfig>,
+    #[serde(with = "value::serde_as_vec")]
+    pub transfer: Value<TransferConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub per_host: ValueNumber,
+    pub per_second: ValueNumber,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct TransferConfig {
+    pub streams_per_host: ValueNumber,
+    pub bandwidth: ValueNumber,
+}
 
 #[async_trait]
 impl Provider for GenericProvider {
-    type Config<'a> = ValueEmpty;
+    type Config<'a> = GenericProviderConfig<'a>;
     type MetaState<'a> = ValueEmpty;
 
     fn schema(&self, _diags: &mut Diagnostics) -> Option<Schema> {
         Some(Schema {
             version: 1,
             block: Block {
+                attributes: map! {
+                    "dry_run" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Report the commands that would be executed by `create`, `update` and `destroy` instead of executing them: these operations then fail, leaving the state unchanged. Reads keep the previous values (default: `GENERIC_PROVIDER_DRY_RUN` environment variable)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "read_only" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Fail the `create`, `update` and `destroy` of all the resources instead of executing them, so the targets cannot be modified, eg: in plan pipelines (default: `GENERIC_PROVIDER_READ_ONLY` environment variable)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "force_destroy_when_unreachable" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Remove the `cmd` and `file` resources from the state with a warning, without executing anything, when their target cannot be reached on destroy, eg: when the VM backing them no longer exists (default: `GENERIC_PROVIDER_FORCE_DESTROY_WHEN_UNREACHABLE` environment variable)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "data_source_timeout" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Default timeout in seconds of the commands of `cmd` data sources (default: no timeout)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "read_concurrency" => Attribute {
+                        attr_type: AttributeType::Number,
+                        description: Description::plain("Maximum number of `read` commands running at the same time across all resources and data sources, given to the hosts in turn so a slow host does not delay the others (default: unlimited)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "audit_log" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("File where a JSON line is appended for every executed command, with its timestamp, resource type, phase, target, command hash, exit code and duration (default: `GENERIC_PROVIDER_AUDIT_LOG` environment variable, or no audit log)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                },
+                blocks: map! {
+                    "rate_limit" => NestedBlock::Optional(Block {
+                        attributes: map! {
+                            "per_host" => Attribute {
+                                attr_type: AttributeType::Number,
+                                description: Description::plain("Maximum number of commands running at the same time on each SSH host (default: unlimited)"),
+                                constraint: AttributeConstraint::Optional,
+                                ..Default::default()
+                            },
+                            "per_second" => Attribute {
+                                attr_type: AttributeType::Number,
+                                description: Description::plain("Maximum number of commands started per second on each SSH host (default: unlimited)"),
+                                constraint: AttributeConstraint::Optional,
+                                ..Default::default()
+                            },
+                        },
+                        description: Description::plain("Limits on the commands executed on each SSH host, for targets that throttle or lock accounts on bursts of sessions"),
+                        ..Default::default()
+                    }),
+                    "transfer" => NestedBlock::Optional(Block {
+                        attributes: map! {
+                            "streams_per_host" => Attribute {
+                                attr_type: AttributeType::Number,
+                                description: Description::plain("Maximum number of files downloaded at the same time from each host to hash them during refresh (default: unlimited)"),
+                                constraint: AttributeConstraint::Optional,
+                                ..Default::default()
+                            },
+                            "bandwidth" => Attribute {
+                                attr_type: AttributeType::Number,
+                                description: Description::plain("Maximum number of bytes per second downloaded across all the hosts to hash the files during refresh (default: unlimited)"),
+                                constraint: AttributeConstraint::Optional,
+                                ..Default::default()
+                            },
+                        },
+                        description: Description::plain("Scheduling of the file downloads used to verify the hashes of the `file` resources during refresh, so they use the links efficiently without saturating them"),
+                        ..Default::default()
+                    }),
+                },
                 description: Description::plain("generic"),
                 ..Default::default()
             },
@@ -45,18 +170,145 @@ impl Provider for GenericProvider {
 
     async fn validate<'a>(
         &self,
-        _diags: &mut tf_provider::Diagnostics,
-        _config: Self::Config<'a>,
+        diags: &mut tf_provider::Diagnostics,
+        config: Self::Config<'a>,
     ) -> Option<()> {
+        if let Value::Value(timeout) = config.data_source_timeout {
+            if timeout <= 0 {
+                diags.error(
+                    "Invalid `data_source_timeout`",
+                    format!("Timeout must be positive, but was {timeout}."),
+                    AttributePath::new("data_source_timeout"),
+                );
+                return None;
+            }
+        }
+        if let Value::Value(concurrency) = config.read_concurrency {
+            if concurrency <= 0 {
+                diags.error(
+                    "Invalid `read_concurrency`",
+                    format!("Concurrency must be positive, but was {concurrency}."),
+                    AttributePath::new("read_concurrency"),
+                );
+                return None;
+            }
+        }
+        if let Value::Value(rate_limit) = &config.rate_limit {
+            for (name, limit) in [
+                ("per_host", rate_limit.per_host),
+                ("per_second", rate_limit.per_second),
+            ] {
+                if let Value::Value(limit) = limit {
+                    if limit <= 0 || limit > u32::MAX as i64 {
+                        diags.error(
+                            format!("Invalid `rate_limit.{name}`"),
+                            format!("Limit must be positive, but was {limit}."),
+                            AttributePath::new("rate_limit").index(0).attribute(name),
+                        );
+                        return None;
+                    }
+                }
+            }
+        }
+        if let Value::Value(transfer) = &config.transfer {
+            for (name, limit) in [
+                ("streams_per_host", transfer.streams_per_host),
+                ("bandwidth", transfer.bandwidth),
+            ] {
+                if let Value::Value(limit) = limit {
+                    if limit <= 0 {
+                        diags.error(
+                            format!("Invalid `transfer.{name}`"),
+                            format!("Limit must be positive, but was {limit}."),
+                            AttributePath::new("transfer").index(0).attribute(name),
+                        );
+                        return None;
+                    }
+                }
+            }
+        }
         Some(())
     }
 
     async fn configure<'a>(
         &self,
-        _diags: &mut tf_provider::Diagnostics,
+        diags: &mut tf_provider::Diagnostics,
         _terraform_version: String,
-        _config: Self::Config<'a>,
+        config: Self::Config<'a>,
     ) -> Option<()> {
+        let audit_log = match &config.audit_log {
+            Value::Value(path) => Some(PathBuf::from(&**path)),
+            _ => std::env::var_os("GENERIC_PROVIDER_AUDIT_LOG")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+        };
+        if let Err(err) = audit::configure(audit_log.as_deref()).await {
+            diags.error(
+                "Could not open audit log",
+                err.to_string(),
+                AttributePath::new("audit_log"),
+            );
+            return None;
+        }
+
+        // Failure injection is only meant for tests, so it is not part of the configuration
+        let scenario = std::env::var_os("GENERIC_PROVIDER_FAULTS")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        match fault::configure(scenario.as_deref()) {
+            Ok(0) => (),
+            Ok(count) => diags.root_warning(
+                "Failure injection is enabled",
+                format!("{count} fault rules are loaded from `GENERIC_PROVIDER_FAULTS`: commands might be delayed or fail on purpose."),
+            ),
+            Err(err) => {
+                diags.root_error("Could not load failure scenario", err.to_string());
+                return None;
+            }
+        }
+
+        let rate_limit = config.rate_limit.as_ref_option();
+        let transfer = config.transfer.as_ref_option();
+        let conflict = limits::configure(Limits {
+            commands_per_host: rate_limit
+                .and_then(|rate_limit| rate_limit.per_host.as_ref_option())
+                .map(|&n| n as usize),
+            commands_per_second: rate_limit
+                .and_then(|rate_limit| rate_limit.per_second.as_ref_option())
+                .map(|&n| n as u32),
+            transfers_per_host: transfer
+                .and_then(|transfer| transfer.streams_per_host.as_ref_option())
+                .map(|&n| n as usize),
+            bandwidth: transfer
+                .and_then(|transfer| transfer.bandwidth.as_ref_option())
+                .map(|&n| n as u64),
+            reads: config.read_concurrency.as_ref_option().map(|&n| n as usize),
+        });
+        if conflict {
+            diags.root_warning(
+                "Conflicting provider limits",
+                "Another configuration of the provider set different `rate_limit`, `transfer` or `read_concurrency` limits. These limits are shared by all the configurations (eg: aliases), and this configuration replaces them for all of them.",
+            );
+        }
+
+        self.options.set(ProviderOptions {
+            dry_run: match config.dry_run {
+                Value::Value(dry_run) => dry_run,
+                _ => env_flag("GENERIC_PROVIDER_DRY_RUN"),
+            },
+            read_only: match config.read_only {
+                Value::Value(read_only) => read_only,
+                _ => env_flag("GENERIC_PROVIDER_READ_ONLY"),
+            },
+            data_source_timeout: match config.data_source_timeout {
+                Value::Value(timeout) => Some(Duration::from_secs(timeout as u64)),
+                _ => None,
+            },
+            force_destroy_when_unreachable: match config.force_destroy_when_unreachable {
+                Value::Value(force) => force,
+                _ => env_flag("GENERIC_PROVIDER_FORCE_DESTROY_WHEN_UNREACHABLE"),
+            },
+        });
         Some(())
     }
 
@@ -65,12 +317,18 @@ impl Provider for GenericProvider {
         _diags: &mut Diagnostics,
     ) -> Option<std::collections::HashMap<String, Box<dyn tf_provider::DynamicResource>>> {
         Some(map! {
-            "local_cmd" => GenericCmdResource::new(ConnectionLocal::default()),
-            "ssh_cmd"   => GenericCmdResource::new(ConnectionSsh::default()),
-            "local_file" => GenericFileResource::new(false, ConnectionLocal::default()),
-            "ssh_file"   => GenericFileResource::new(false, ConnectionSsh::default()),
-            "local_sensitive_file" => GenericFileResource::new(true, ConnectionLocal::default()),
-            "ssh_sensitive_file"   => GenericFileResource::new(true, ConnectionSsh::default()),
+            "local_cmd" => GenericCmdResource::new(self.options.clone(), ConnectionLocal::default()),
+            "ssh_cmd"   => GenericCmdResource::new(self.options.clone(), ConnectionSsh::default()),
+            "k8s_cmd"   => GenericCmdResource::new(self.options.clone(), ConnectionKubernetes::default()),
+            "local_file" => GenericFileResource::new(self.options.clone(), false, ConnectionLocal::default()),
+            "ssh_file"   => GenericFileResource::new(self.options.clone(), false, ConnectionSsh::default()),
+            "k8s_file"   => GenericFileResource::new(self.options.clone(), false, ConnectionKubernetes::default()),
+            "local_sensitive_file" => GenericFileResource::new(self.options.clone(), true, ConnectionLocal::default()),
+            "ssh_sensitive_file"   => GenericFileResource::new(self.options.clone(), true, ConnectionSsh::default()),
+            "ssh_sysctl" => GenericSysctlResource::new(self.options.clone(), ConnectionSsh::default()),
+            "ssh_hosts_entry" => GenericHostsEntryResource::new(self.options.clone(), ConnectionSsh::default()),
+            "ssh_authorized_key" => GenericAuthorizedKeyResource::new(self.options.clone(), ConnectionSsh::default()),
+            "ssh_mount" => GenericMountResource::new(self.options.clone(), ConnectionSsh::default()),
         })
     }
 
@@ -79,12 +337,18 @@ impl Provider for GenericProvider {
         _diags: &mut Diagnostics,
     ) -> Option<std::collections::HashMap<String, Box<dyn tf_provider::DynamicDataSource>>> {
         Some(map! {
-            "local_cmd" => GenericCmdDataSource::new(ConnectionLocal::default()),
-            "ssh_cmd"   => GenericCmdDataSource::new(ConnectionSsh::default()),
+            "local_cmd" => GenericCmdDataSource::new(self.options.clone(), ConnectionLocal::default()),
+            "ssh_cmd"   => GenericCmdDataSource::new(self.options.clone(), ConnectionSsh::default()),
             "local_file" => GenericFileDataSource::new(false, ConnectionLocal::default()),
             "ssh_file"   => GenericFileDataSource::new(false, ConnectionSsh::default()),
             "local_sensitive_file" => GenericFileDataSource::new(true, ConnectionLocal::default()),
             "ssh_sensitive_file"   => GenericFileDataSource::new(true, ConnectionSsh::default()),
+            "local_file_glob" => GenericFileGlobDataSource::new(ConnectionLocal::default()),
+            "ssh_file_glob"   => GenericFileGlobDataSource::new(ConnectionSsh::default()),
+            "local_fetch_dir" => GenericFileFetchDirDataSource::new(ConnectionLocal::default()),
+            "ssh_fetch_dir"   => GenericFileFetchDirDataSource::new(ConnectionSsh::default()),
+            "ssh_check" => GenericCheckDataSource::new(ConnectionSsh::default()),
+            "inventory" => GenericInventoryDataSource::new(),
         })
     }
 }
@@ -0,0 +1,683 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, Schema,
+};
+use tf_provider::value::{Value, ValueEmpty, ValueList, ValueMap, ValueString};
+use tf_provider::{map, AttributePath, DataSource, Diagnostics};
+
+use crate::connection::ssh::ssh_config::{self, HostConfig};
+use crate::parse::{ini, unquote, yaml};
+
+const FORMATS: [&str; 3] = ["ssh_config", "ini", "yaml"];
+
+/// Keys of an Ansible inventory, and the connection attributes they are given as
+const ANSIBLE_KEYS: [(&str, &str); 4] = [
+    ("ansible_host", "host"),
+    ("ansible_port", "port"),
+    ("ansible_user", "user"),
+    ("ansible_ssh_private_key_file", "keyfile"),
+];
+
+#[derive(Debug, Default, Clone)]
+pub struct GenericInventoryDataSource;
+
+impl GenericInventoryDataSource {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct DataSourceState<'a> {
+    #[serde(borrow = "'a")]
+    pub path: ValueString<'a>,
+    pub format: ValueString<'a>,
+    pub hosts: ValueMap<'a, ValueMap<'a, ValueString<'a>>>,
+    pub groups: ValueMap<'a, ValueList<ValueString<'a>>>,
+}
+
+/// Hosts with their connection attributes, and groups of hosts
+#[derive(Debug, Default)]
+struct Inventory {
+    hosts: BTreeMap<String, BTreeMap<String, String>>,
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+#[async_trait]
+impl DataSource for GenericInventoryDataSource {
+    type State<'a> = DataSourceState<'a>;
+    type ProviderMetaState<'a> = ValueEmpty;
+
+    fn schema(&self, _diags: &mut Diagnostics) -> Option<Schema> {
+        Some(Schema {
+            version: 1,
+            block: Block {
+                version: 1,
+                attributes: map! {
+                    "path" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Path of the inventory file, on the machine running Terraform"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "format" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Format of the inventory file: `ssh_config`, `ini` or `yaml` (Ansible style) (default: from the extension of the file, `ssh_config` otherwise)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "hosts" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::Map(AttributeType::String.into()).into()),
+                        description: Description::plain("Connection attributes by host name (`host`, `port`, `user`, `keyfile`...), to be used in `connect` blocks"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "groups" => Attribute {
+                        attr_type: AttributeType::Map(AttributeType::List(AttributeType::String.into()).into()),
+                        description: Description::plain("Host names by group, including the hosts of their child groups, for `ini` and `yaml` inventories"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                },
+                description: Description::plain(
+                    "Hosts of an SSH config, or of an INI or YAML inventory file",
+                ),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn validate<'a>(&self, diags: &mut Diagnostics, config: Self::State<'a>) -> Option<()> {
+        match &config.path {
+            Value::Value(path) => {
+                if path.is_empty() {
+                    diags.error_short("`path` should not be empty", AttributePath::new("path"));
+                }
+            }
+            Value::Null => {
+                diags.error_short("`path` should not be null", AttributePath::new("path"));
+            }
+            Value::Unknown => (),
+        }
+
+        if let Value::Value(format) = &config.format {
+            if !FORMATS.contains(&format.as_ref()) {
+                diags.error(
+                    "Invalid `format`",
+                    format!(
+                        "Format should be one of {}, but is `{format}`",
+                        FORMATS.join(", ")
+                    ),
+                    AttributePath::new("format"),
+                );
+            }
+        }
+
+        if diags.errors.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn read<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        config: Self::State<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<Self::State<'a>> {
+        let path = config.path.as_str();
+        let format = match config.format.as_deref_option() {
+            Some(format) => format,
+            None if path.ends_with(".ini") => "ini",
+            None if path.ends_with(".yml") || path.ends_with(".yaml") => "yaml",
+            None => "ssh_config",
+        };
+
+        let content = match tokio::fs::read_to_string(path).await {
+            Ok(content) => content,
+            Err(err) => {
+                diags.error(
+                    "Could not read inventory",
+                    format!("Could not read `{path}`: {err}"),
+                    AttributePath::new("path"),
+                );
+                return None;
+            }
+        };
+        let inventory = match format {
+            "ini" => parse_ini(&content),
+            "yaml" => parse_yaml(&content),
+            _ => parse_ssh_config(&content),
+        };
+        let inventory = match inventory {
+            Ok(inventory) => inventory,
+            Err(err) => {
+                diags.error(
+                    "Invalid inventory",
+                    format!("Could not parse `{path}` as `{format}`: {err}"),
+                    AttributePath::new("path"),
+                );
+                return None;
+            }
+        };
+
+        let mut output = config;
+        output.hosts = Value::Value(
+            inventory
+                .hosts
+                .into_iter()
+                .map(|(name, attributes)| {
+                    (
+                        Cow::Owned(name),
+                        Value::Value(
+                            attributes
+                                .into_iter()
+                                .map(|(k, v)| (Cow::Owned(k), Value::Value(Cow::Owned(v))))
+                                .collect(),
+                        ),
+                    )
+                })
+                .collect(),
+        );
+        output.groups = Value::Value(
+            inventory
+                .groups
+                .into_iter()
+                .map(|(name, hosts)| {
+                    (
+                        Cow::Owned(name),
+                        Value::Value(
+                            hosts
+                                .into_iter()
+                                .map(|host| Value::Value(Cow::Owned(host)))
+                                .collect(),
+                        ),
+                    )
+                })
+                .collect(),
+        );
+
+        Some(output)
+    }
+}
+
+/// Hosts of the `Host` lines of an SSH config, with the options that apply to them
+///
+/// Patterns with wildcards or negations are not hosts.
+fn parse_ssh_config(content: &str) -> Result<Inventory> {
+    let mut inventory = Inventory::default();
+    for alias in ssh_config::hosts(content) {
+        let config = HostConfig::parse(content, &alias)?;
+        let mut attributes = BTreeMap::new();
+        attributes.insert(
+            "host".to_owned(),
+            config.hostname.unwrap_or_else(|| alias.clone()),
+        );
+        if let Some(port) = config.port {
+            attributes.insert("port".to_owned(), port.to_string());
+        }
+        if let Some(user) = config.user {
+            attributes.insert("user".to_owned(), user);
+        }
+        if let Some(keyfile) = config.identity_files.into_iter().next() {
+            attributes.insert("keyfile".to_owned(), keyfile);
+        }
+        inventory.hosts.insert(alias, attributes);
+    }
+    Ok(inventory)
+}
+
+/// Hosts of an Ansible style INI inventory: `name [key=value...]` lines in `[group]` sections
+///
+/// `[group:children]` sections list the child groups of `group`, and `[group:vars]` sections its variables.
+fn parse_ini(content: &str) -> Result<Inventory> {
+    let mut hosts = BTreeMap::new();
+    let mut groups = BTreeMap::<String, Group>::new();
+    // Hosts before the first section are ungrouped
+    let mut section = IniSection::Hosts(None);
+    for line in ini::lines(content) {
+        let (number, line) = line.map_err(|err| anyhow!(err))?;
+        let line = match line {
+            ini::Line::Section(name) => {
+                section = match name.split_once(':') {
+                    None => IniSection::Hosts(Some(name.to_owned())),
+                    Some((group, "children")) => IniSection::Children(group.to_owned()),
+                    Some((group, "vars")) => IniSection::Vars(group.to_owned()),
+                    Some(_) => return Err(anyhow!("Unknown section `[{name}]` at line {number}")),
+                };
+                if let IniSection::Hosts(Some(group))
+                | IniSection::Children(group)
+                | IniSection::Vars(group) = &section
+                {
+                    groups.entry(group.clone()).or_default();
+                }
+                continue;
+            }
+            ini::Line::Entry(line) => line,
+        };
+        match &section {
+            IniSection::Hosts(group) => {
+                let host = parse_ini_host(&mut hosts, line, number)?;
+                if let Some(group) = group {
+                    let hosts = &mut groups.entry(group.clone()).or_default().hosts;
+                    if !hosts.contains(&host) {
+                        hosts.push(host);
+                    }
+                }
+            }
+            IniSection::Children(group) => {
+                groups.entry(line.to_owned()).or_default();
+                let children = &mut groups.entry(group.clone()).or_default().children;
+                if !children.iter().any(|child| child == line) {
+                    children.push(line.to_owned());
+                }
+            }
+            IniSection::Vars(group) => {
+                let (key, value) = line.split_once('=').ok_or_else(|| {
+                    anyhow!("Expected `key=value` at line {number}, but got `{line}`")
+                })?;
+                groups.entry(group.clone()).or_default().vars.insert(
+                    attribute_name(key.trim()).to_owned(),
+                    unquote(value.trim()).into_owned(),
+                );
+            }
+        }
+    }
+    Ok(resolve(hosts, groups))
+}
+
+/// Section of an INI inventory, and the group it belongs to
+enum IniSection {
+    Hosts(Option<String>),
+    Children(String),
+    Vars(String),
+}
+
+fn parse_ini_host(hosts: &mut Attributes, line: &str, number: usize) -> Result<String> {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or_default().to_owned();
+    let attributes = hosts.entry(name.clone()).or_default();
+    for word in words {
+        let (key, value) = word
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected `key=value` at line {number}, but got `{word}`"))?;
+        attributes.insert(attribute_name(key).to_owned(), unquote(value).into_owned());
+    }
+    Ok(name)
+}
+
+/// Hosts of an Ansible style YAML inventory, with nested `hosts`, `children` and `vars`, eg:
+///
+/// ```yaml
+/// all:
+///   hosts:
+///     web-1:
+///       ansible_host: 10.0.0.1
+///   children:
+///     db:
+///       hosts:
+///         db-1:
+///       vars:
+///         ansible_user: admin
+/// ```
+///
+/// Top-level entries that are not groups are hosts with their attributes: `web-1: {host: 10.0.0.1}`.
+fn parse_yaml(content: &str) -> Result<Inventory> {
+    let entries = match yaml::parse(content).map_err(|err| anyhow!(err))? {
+        Json::Object(entries) => entries,
+        Json::Null => Default::default(),
+        _ => {
+            return Err(anyhow!(
+                "The inventory should be a mapping of groups or hosts"
+            ))
+        }
+    };
+    let mut hosts = BTreeMap::new();
+    let mut groups = BTreeMap::new();
+    for (name, value) in entries {
+        let is_group = name == "all"
+            || value
+                .as_object()
+                .is_some_and(|entries| GROUP_KEYS.iter().any(|key| entries.contains_key(*key)));
+        if is_group {
+            parse_yaml_group(&mut hosts, &mut groups, name, value)?;
+        } else {
+            parse_yaml_host(&mut hosts, name, value)?;
+        }
+    }
+    Ok(resolve(hosts, groups))
+}
+
+/// Keys of the mapping of an Ansible group
+const GROUP_KEYS: [&str; 3] = ["hosts", "children", "vars"];
+
+fn parse_yaml_group(
+    hosts: &mut Attributes,
+    groups: &mut BTreeMap<String, Group>,
+    name: String,
+    value: Json,
+) -> Result<()> {
+    let mut group = Group::default();
+    let entries = match value {
+        Json::Object(entries) => entries,
+        Json::Null => Default::default(),
+        _ => return Err(anyhow!("Group `{name}` should be a mapping")),
+    };
+    for (key, value) in entries {
+        match (key.as_str(), value) {
+            (key, Json::Null) if GROUP_KEYS.contains(&key) => (),
+            ("hosts", Json::Object(members)) => {
+                for (host, attributes) in members {
+                    group.hosts.push(host.clone());
+                    parse_yaml_host(hosts, host, attributes)?;
+                }
+            }
+            ("children", Json::Object(children)) => {
+                for (child, value) in children {
+                    group.children.push(child.clone());
+                    parse_yaml_group(hosts, groups, child, value)?;
+                }
+            }
+            ("vars", Json::Object(vars)) => {
+                for (key, value) in vars {
+                    if let Some(value) = yaml_scalar(&name, &key, value)? {
+                        group.vars.insert(attribute_name(&key).to_owned(), value);
+                    }
+                }
+            }
+            (key, _) if GROUP_KEYS.contains(&key) => {
+                return Err(anyhow!("`{key}` of group `{name}` should be a mapping"))
+            }
+            (key, _) => return Err(anyhow!("Unexpected `{key}` in group `{name}`")),
+        }
+    }
+    // A group can be listed under several parents
+    let existing = groups.entry(name).or_default();
+    existing.hosts.extend(group.hosts);
+    existing.children.extend(group.children);
+    existing.vars.extend(group.vars);
+    Ok(())
+}
+
+fn parse_yaml_host(hosts: &mut Attributes, name: String, value: Json) -> Result<()> {
+    let attributes = match value {
+        Json::Object(attributes) => attributes,
+        Json::Null => Default::default(),
+        _ => return Err(anyhow!("Host `{name}` should be a mapping of attributes")),
+    };
+    let mut resolved = BTreeMap::new();
+    for (key, value) in attributes {
+        if let Some(value) = yaml_scalar(&name, &key, value)? {
+            resolved.insert(attribute_name(&key).to_owned(), value);
+        }
+    }
+    hosts.entry(name).or_default().extend(resolved);
+    Ok(())
+}
+
+/// Value of an attribute of a YAML inventory, or `None` if it is null
+fn yaml_scalar(owner: &str, key: &str, value: Json) -> Result<Option<String>> {
+    match value {
+        Json::Null => Ok(None),
+        Json::String(value) => Ok(Some(value)),
+        Json::Bool(value) => Ok(Some(value.to_string())),
+        Json::Number(value) => Ok(Some(value.to_string())),
+        _ => Err(anyhow!("Attribute `{key}` of `{owner}` should be a scalar")),
+    }
+}
+
+/// Attributes by host name
+type Attributes = BTreeMap<String, BTreeMap<String, String>>;
+
+/// Ansible group, before the hosts of its children and its variables are resolved
+#[derive(Debug, Default)]
+struct Group {
+    hosts: Vec<String>,
+    children: Vec<String>,
+    vars: BTreeMap<String, String>,
+}
+
+/// Add the hosts of child groups to their parents, and the variables of the groups to their hosts
+///
+/// Host attributes take precedence over group variables, and the variables of a group over the ones of its parents.
+/// The `all` group contains every host.
+fn resolve(mut hosts: Attributes, groups: BTreeMap<String, Group>) -> Inventory {
+    for (name, attributes) in &mut hosts {
+        let mut seen = BTreeSet::new();
+        let mut level = groups
+            .iter()
+            .filter(|(_, group)| group.hosts.contains(name))
+            .map(|(group, _)| group.as_str())
+            .collect::<Vec<_>>();
+        level.push("all");
+        while !level.is_empty() {
+            let mut parents = Vec::new();
+            for group in level {
+                if !seen.insert(group) {
+                    continue;
+                }
+                if let Some(vars) = groups.get(group).map(|group| &group.vars) {
+                    for (key, value) in vars {
+                        attributes
+                            .entry(key.clone())
+                            .or_insert_with(|| value.clone());
+                    }
+                }
+                parents.extend(
+                    groups
+                        .iter()
+                        .filter(|(_, parent)| parent.children.iter().any(|child| child == group))
+                        .map(|(parent, _)| parent.as_str()),
+                );
+            }
+            level = parents;
+        }
+        attributes
+            .entry("host".to_owned())
+            .or_insert_with(|| name.clone());
+    }
+
+    let groups = groups
+        .keys()
+        .map(|name| {
+            let members = if name == "all" {
+                hosts.keys().cloned().collect()
+            } else {
+                let mut members = Vec::new();
+                collect_members(&groups, name, &mut BTreeSet::new(), &mut members);
+                members
+            };
+            (name.clone(), members)
+        })
+        .collect();
+    Inventory { hosts, groups }
+}
+
+/// Hosts of a group and of its children, recursively
+fn collect_members<'g>(
+    groups: &'g BTreeMap<String, Group>,
+    name: &'g str,
+    seen: &mut BTreeSet<&'g str>,
+    members: &mut Vec<String>,
+) {
+    if !seen.insert(name) {
+        return;
+    }
+    let Some(group) = groups.get(name) else {
+        return;
+    };
+    for host in &group.hosts {
+        if !members.contains(host) {
+            members.push(host.clone());
+        }
+    }
+    for child in &group.children {
+        collect_members(groups, child, seen, members);
+    }
+}
+
+/// Name of the connection attribute given by an inventory variable
+fn attribute_name(key: &str) -> &str {
+    ANSIBLE_KEYS
+        .iter()
+        .find(|(ansible, _)| *ansible == key)
+        .map_or(key, |(_, attribute)| attribute)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{parse_ini, parse_yaml, Inventory};
+
+    fn host<'a>(inventory: &'a Inventory, name: &str) -> BTreeMap<&'a str, &'a str> {
+        inventory.hosts[name]
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn yaml_groups() {
+        let inventory = parse_yaml(
+            "all:
+  hosts:
+    web-1: # the first web server
+      ansible_host: 10.0.0.1
+      ansible_port: 2222
+  vars:
+    ansible_user: root
+  children:
+    db:
+      hosts:
+        db-1:
+        db-2:
+          ansible_user: postgres
+      vars:
+        ansible_user: admin # not root
+    backend:
+      children:
+        db:
+",
+        )
+        .unwrap();
+        assert_eq!(
+            host(&inventory, "web-1"),
+            BTreeMap::from([("host", "10.0.0.1"), ("port", "2222"), ("user", "root")])
+        );
+        assert_eq!(
+            host(&inventory, "db-1"),
+            BTreeMap::from([("host", "db-1"), ("user", "admin")])
+        );
+        assert_eq!(
+            host(&inventory, "db-2"),
+            BTreeMap::from([("host", "db-2"), ("user", "postgres")])
+        );
+        assert_eq!(inventory.groups["db"], ["db-1", "db-2"]);
+        assert_eq!(inventory.groups["backend"], ["db-1", "db-2"]);
+        assert_eq!(inventory.groups["all"], ["db-1", "db-2", "web-1"]);
+    }
+
+    #[test]
+    fn yaml_hosts() {
+        let inventory =
+            parse_yaml("web-1:\n  host: 10.0.0.1 # comment\n  user: admin\nweb-2: {}\n").unwrap();
+        assert_eq!(
+            host(&inventory, "web-1"),
+            BTreeMap::from([("host", "10.0.0.1"), ("user", "admin")])
+        );
+        assert_eq!(
+            host(&inventory, "web-2"),
+            BTreeMap::from([("host", "web-2")])
+        );
+        assert!(inventory.groups.is_empty());
+    }
+
+    #[test]
+    fn yaml_errors() {
+        assert!(parse_yaml("- web-1\n").is_err());
+        assert!(parse_yaml("web-1: 10.0.0.1\n").is_err());
+        assert!(parse_yaml("all:\n  hosts:\n    web-1:\n      tags: [a, b]\n").is_err());
+        assert!(parse_yaml("all:\n  host: web-1\n").is_err());
+    }
+
+    #[test]
+    fn ini_groups() {
+        let inventory = parse_ini(
+            "bastion ansible_host=192.0.2.1
+
+[web]
+web-1 ansible_host=10.0.0.1 ansible_user=deploy
+web-2
+
+[db]
+; comment
+db-1 ansible_port=2222
+
+[prod:children]
+web
+db
+
+[prod:vars]
+ansible_user = admin
+ansible_ssh_private_key_file=\"~/.ssh/prod\"
+",
+        )
+        .unwrap();
+        assert_eq!(
+            host(&inventory, "bastion"),
+            BTreeMap::from([("host", "192.0.2.1")])
+        );
+        assert_eq!(
+            host(&inventory, "web-1"),
+            BTreeMap::from([
+                ("host", "10.0.0.1"),
+                ("keyfile", "~/.ssh/prod"),
+                ("user", "deploy"),
+            ])
+        );
+        assert_eq!(
+            host(&inventory, "db-1"),
+            BTreeMap::from([
+                ("host", "db-1"),
+                ("keyfile", "~/.ssh/prod"),
+                ("port", "2222"),
+                ("user", "admin"),
+            ])
+        );
+        assert_eq!(inventory.groups["web"], ["web-1", "web-2"]);
+        assert_eq!(inventory.groups["prod"], ["web-1", "web-2", "db-1"]);
+    }
+
+    #[test]
+    fn ini_errors() {
+        assert!(parse_ini("[web\nweb-1\n").is_err());
+        assert!(parse_ini("[web]\nweb-1 port\n").is_err());
+        assert!(parse_ini("[web:unknown]\n").is_err());
+        assert!(parse_ini("[web:vars]\nport\n").is_err());
+    }
+}
@@ -0,0 +1,273 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Provider-wide limits on what is done on the hosts, set when the provider is configured
+///
+/// The limits are shared by the whole process: when several configurations of the provider
+/// (eg: aliases) set different limits, the last one configured applies to all of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of commands running at the same time on a host
+    pub commands_per_host: Option<usize>,
+    /// Maximum number of commands started per second on a host
+    pub commands_per_second: Option<u32>,
+    /// Maximum number of transfers at the same time from a host
+    pub transfers_per_host: Option<usize>,
+    /// Maximum number of bytes per second across all the transfers
+    pub bandwidth: Option<u64>,
+    /// Maximum number of `read` commands running at the same time, across all the hosts
+    pub reads: Option<usize>,
+}
+
+/// Limiters of a host, shared by all the connections reaching it
+#[derive(Default)]
+struct Host {
+    /// Operations allowed by the `max_concurrency` of the connections, with the limit it was created with
+    operations: Option<(usize, Arc<Semaphore>)>,
+    commands: Option<Arc<Semaphore>>,
+    transfers: Option<Arc<Semaphore>>,
+    /// Earliest instant the next command can start
+    next_start: Option<Instant>,
+    /// Reads waiting for their turn
+    reads: VecDeque<oneshot::Sender<ReadPermit>>,
+}
+
+#[derive(Default)]
+struct Limiter {
+    limits: Limits,
+    /// Whether a provider configuration already set the limits
+    configured: bool,
+    hosts: HashMap<String, Host>,
+    /// Instant the bytes already transferred are paid for, with respect to the bandwidth cap
+    next_free: Option<Instant>,
+    running_reads: usize,
+    /// Hosts with waiting reads, in the order they will be served
+    read_turns: VecDeque<String>,
+}
+
+lazy_static! {
+    /// Limiter shared by all the resources and provider instances
+    ///
+    /// Resources have their own connection, so the limiters must outlive them.
+    static ref LIMITER: Mutex<Limiter> = Default::default();
+}
+
+fn lock() -> MutexGuard<'static, Limiter> {
+    match LIMITER.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl Limiter {
+    fn host(&mut self, host: &str) -> &mut Host {
+        self.hosts.entry(host.to_owned()).or_default()
+    }
+
+    /// Give the free slots to the waiting reads, one host at a time
+    ///
+    /// Terraform refreshes many resources concurrently, each one reading its outputs on its own,
+    /// so the reads of a slow host must not delay the refresh of the resources of the other hosts.
+    fn dispatch_reads(&mut self) {
+        while self
+            .limits
+            .reads
+            .is_none_or(|limit| self.running_reads < limit)
+        {
+            let Some(name) = self.read_turns.pop_front() else {
+                return;
+            };
+            let Some(host) = self.hosts.get_mut(&name) else {
+                continue;
+            };
+            let next = host.reads.pop_front();
+            if !host.reads.is_empty() {
+                self.read_turns.push_back(name);
+            }
+            if let Some(sender) = next {
+                self.running_reads += 1;
+                if let Err(permit) = sender.send(ReadPermit(())) {
+                    // The read has been cancelled while waiting: the slot is still free.
+                    // Dropping the permit would lock the limiter again.
+                    std::mem::forget(permit);
+                    self.running_reads -= 1;
+                }
+            }
+        }
+    }
+}
+
+/// Set the limits, when the provider is configured
+///
+/// The permits already given are kept until they are released.
+/// Returns `true` if another configuration had already set different limits, which are replaced.
+pub fn configure(limits: Limits) -> bool {
+    let mut limiter = lock();
+    let configured = std::mem::replace(&mut limiter.configured, true);
+    if limiter.limits == limits {
+        return false;
+    }
+    limiter.limits = limits;
+    limiter.next_free = None;
+    for host in limiter.hosts.values_mut() {
+        // Created again with the new limits when needed
+        host.commands = None;
+        host.transfers = None;
+        host.next_start = None;
+    }
+    if limits.reads.is_none() {
+        // Dropping the waiting senders lets the reads run without a permit
+        limiter.read_turns.clear();
+        for host in limiter.hosts.values_mut() {
+            host.reads.clear();
+        }
+    } else {
+        limiter.dispatch_reads();
+    }
+    configured
+}
+
+/// Wait until an operation is allowed to run on the host, according to the `max_concurrency` of the connection
+///
+/// The limit applies to the host, whatever the configuration reaching it: the first limit is used.
+pub async fn acquire_operation(host: &str, limit: usize) -> Option<OwnedSemaphorePermit> {
+    let operations = {
+        let mut limiter = lock();
+        let (current, operations) = limiter
+            .host(host)
+            .operations
+            .get_or_insert_with(|| (limit, Arc::new(Semaphore::new(limit))));
+        if *current != limit {
+            log::warn!(
+                "Conflicting `max_concurrency` for {host}: {limit} is ignored, {current} is used"
+            );
+        }
+        operations.clone()
+    };
+    operations.acquire_owned().await.ok()
+}
+
+/// Wait until a command is allowed to start on the host
+///
+/// The returned permit must be kept until the command completes.
+pub async fn acquire_command(host: &str) -> Option<OwnedSemaphorePermit> {
+    let commands = {
+        let mut limiter = lock();
+        let limit = limiter.limits.commands_per_host;
+        limit.map(|limit| {
+            limiter
+                .host(host)
+                .commands
+                .get_or_insert_with(|| Arc::new(Semaphore::new(limit)))
+                .clone()
+        })
+    };
+    let permit = match commands {
+        Some(commands) => commands.acquire_owned().await.ok(),
+        None => None,
+    };
+
+    let start = {
+        let mut limiter = lock();
+        let Some(per_second) = limiter.limits.commands_per_second else {
+            return permit;
+        };
+        let next_start = &mut limiter.host(host).next_start;
+        let start = next_start.map_or(Instant::now(), |next| next.max(Instant::now()));
+        *next_start = Some(start + Duration::from_secs(1) / per_second);
+        start
+    };
+    tokio::time::sleep_until(start).await;
+
+    permit
+}
+
+/// Wait until a transfer is allowed to start from the host
+///
+/// The returned permit must be kept until the transfer completes.
+pub async fn acquire_transfer(host: &str) -> Option<OwnedSemaphorePermit> {
+    let transfers = {
+        let mut limiter = lock();
+        let limit = limiter.limits.transfers_per_host?;
+        limiter
+            .host(host)
+            .transfers
+            .get_or_insert_with(|| Arc::new(Semaphore::new(limit)))
+            .clone()
+    };
+    transfers.acquire_owned().await.ok()
+}
+
+/// Wait until the bytes transferred fit in the bandwidth cap
+pub async fn throttle(bytes: usize) {
+    let until = {
+        let mut limiter = lock();
+        let Some(bandwidth) = limiter.limits.bandwidth else {
+            return;
+        };
+        let now = Instant::now();
+        let start = limiter
+            .next_free
+            .map_or(now, |next_free| next_free.max(now));
+        let until = start + Duration::from_secs_f64(bytes as f64 / bandwidth as f64);
+        limiter.next_free = Some(until);
+        until
+    };
+    tokio::time::sleep_until(until).await;
+}
+
+/// Permission to run a read command, released when dropped
+#[derive(Debug)]
+pub struct ReadPermit(());
+
+impl Drop for ReadPermit {
+    fn drop(&mut self) {
+        let mut limiter = lock();
+        limiter.running_reads = limiter.running_reads.saturating_sub(1);
+        limiter.dispatch_reads();
+    }
+}
+
+/// Wait for the turn of a read command on the host
+///
+/// The returned permit must be kept until the command completes.
+pub async fn acquire_read(host: &str) -> Option<ReadPermit> {
+    let receiver = {
+        let mut limiter = lock();
+        let limit = limiter.limits.reads?;
+        if limiter.running_reads < limit && limiter.read_turns.is_empty() {
+            limiter.running_reads += 1;
+            return Some(ReadPermit(()));
+        }
+        let (sender, receiver) = oneshot::channel();
+        let reads = &mut limiter.host(host).reads;
+        let first = reads.is_empty();
+        reads.push_back(sender);
+        if first {
+            limiter.read_turns.push_back(host.to_owned());
+        }
+        receiver
+    };
+    receiver.await.ok()
+}
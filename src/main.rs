@@ -18,13 +18,31 @@ use anyhow::Result;
 use generic_provider::GenericProvider;
 use tf_provider::serve;
 
+mod audit;
+mod check;
 mod cmd;
 mod connection;
+mod exec;
+mod fault;
 mod file;
 mod generic_provider;
+mod inventory;
+mod limits;
+mod options;
+mod parse;
+mod redact;
+mod system;
+mod timeouts;
+mod transfer;
 mod utils;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some("exec") {
+        let status = exec::exec(&args[1..]).await?;
+        std::process::exit(status);
+    }
+
     serve("generic", GenericProvider::default()).await
 }
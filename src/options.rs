@@ -0,0 +1,70 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tf_provider::Diagnostics;
+
+/// Provider-wide options, set when the provider is configured
+#[derive(Debug, Default, Clone)]
+pub struct ProviderOptions {
+    /// Log the commands that would be executed instead of executing them
+    pub dry_run: bool,
+    /// Fail instead of executing the commands modifying the targets
+    pub read_only: bool,
+    /// Default maximum duration of the commands of data sources
+    pub data_source_timeout: Option<Duration>,
+    /// Remove the resources from the state without destroying them when their target cannot be reached
+    pub force_destroy_when_unreachable: bool,
+}
+
+/// Handle to the provider options shared between the provider and its resources
+///
+/// Resources are created before the provider is configured,
+/// so they must look the options up when they are used.
+#[derive(Debug, Default, Clone)]
+pub struct SharedOptions(Arc<RwLock<ProviderOptions>>);
+
+impl SharedOptions {
+    pub fn get(&self) -> ProviderOptions {
+        match self.0.read() {
+            Ok(options) => options.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn set(&self, options: ProviderOptions) {
+        match self.0.write() {
+            Ok(mut guard) => *guard = options,
+            Err(poisoned) => *poisoned.into_inner() = options,
+        }
+    }
+}
+
+/// Report an attempt to modify a target while the provider is read-only
+pub fn report_read_only(diags: &mut Diagnostics, phase: &str) {
+    diags.root_error(
+        format!("Read-only mode: `{phase}` is not allowed"),
+        "The provider is configured with `read_only`, so the targets cannot be modified.",
+    );
+}
+
+/// Check if a boolean environment variable is enabled
+pub fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .is_ok_and(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+}
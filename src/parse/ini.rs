@@ -0,0 +1,69 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Meaningful line of an INI document
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Line<'a> {
+    /// `[name]` header of a section, with the name trimmed
+    Section(&'a str),
+    /// Any other line, trimmed
+    Entry(&'a str),
+}
+
+/// Lines of an INI document, with their number
+///
+/// Empty lines and comments, starting with `#` or `;`, are skipped.
+pub(crate) fn lines(content: &str) -> impl Iterator<Item = Result<(usize, Line<'_>), String>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with(['#', ';']))
+        .map(|(number, line)| match line.strip_prefix('[') {
+            Some(section) => match section.strip_suffix(']') {
+                Some(name) => Ok((number, Line::Section(name.trim()))),
+                None => Err(format!("Line {number}: missing `]`.")),
+            },
+            None => Ok((number, Line::Entry(line))),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lines, Line};
+
+    #[test]
+    fn sections_and_entries() {
+        let ini = "# comment\ntop = 1\n\n[ first ]\n; comment\n  key=value  \n[second:vars]\n";
+        assert_eq!(
+            lines(ini).collect::<Result<Vec<_>, _>>().unwrap(),
+            [
+                (2, Line::Entry("top = 1")),
+                (4, Line::Section("first")),
+                (6, Line::Entry("key=value")),
+                (7, Line::Section("second:vars")),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_section() {
+        assert_eq!(
+            lines("[group\nhost").next(),
+            Some(Err("Line 1: missing `]`.".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,57 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub(crate) mod ini;
+pub(crate) mod yaml;
+
+/// Remove the quotes around a value, processing the escapes of double quotes
+pub(crate) fn unquote(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.len() >= 2 && s.starts_with('\'') && s.ends_with('\'') {
+        return s[1..s.len() - 1].replace("''", "'").into();
+    }
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        let mut unquoted = String::with_capacity(s.len());
+        let mut chars = s[1..s.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unquoted.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => unquoted.push('\n'),
+                Some('t') => unquoted.push('\t'),
+                Some(c) => unquoted.push(c),
+                None => unquoted.push('\\'),
+            }
+        }
+        return unquoted.into();
+    }
+    s.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unquote;
+
+    #[test]
+    fn unquote_values() {
+        assert_eq!(unquote("plain"), "plain");
+        assert_eq!(unquote("'it''s'"), "it's");
+        assert_eq!(unquote(r#""a\tb\n\"c\"""#), "a\tb\n\"c\"");
+        assert_eq!(unquote("'unterminated"), "'unterminated");
+        assert_eq!(unquote("\""), "\"");
+    }
+}
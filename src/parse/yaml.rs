@@ -0,0 +1,101 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde_json::Value;
+
+/// Parse a YAML document into its JSON equivalent
+///
+/// Anchors and aliases are resolved, and mappings must have string keys.
+pub(crate) fn parse(output: &str) -> Result<Value, String> {
+    serde_yaml::from_str(output).map_err(|err| format!("Invalid YAML: {err}."))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn nested_mappings() {
+        let yaml = "a: 1\nb:\n  c: true\n  d: ~\n  e:\n    f: text\n";
+        assert_eq!(
+            parse(yaml).unwrap(),
+            json!({"a": 1, "b": {"c": true, "d": null, "e": {"f": "text"}}})
+        );
+    }
+
+    #[test]
+    fn sequences() {
+        let yaml =
+            "items:\n- 1\n- two\n-\n  - nested\nobjects:\n  - name: a\n    value: 1\n  - name: b\n";
+        assert_eq!(
+            parse(yaml).unwrap(),
+            json!({
+                "items": [1, "two", ["nested"]],
+                "objects": [{"name": "a", "value": 1}, {"name": "b"}],
+            })
+        );
+    }
+
+    #[test]
+    fn comments_and_documents() {
+        let yaml = "---\n# header\na: 1 # trailing\nb: 'not # a comment'\nc: a#b\n...\n";
+        assert_eq!(
+            parse(yaml).unwrap(),
+            json!({"a": 1, "b": "not # a comment", "c": "a#b"})
+        );
+        assert_eq!(parse("# only a comment\n").unwrap(), json!(null));
+    }
+
+    #[test]
+    fn quoted_scalars_and_keys() {
+        let yaml = "\"a: b\": \"x\\ty\"\n'c': 'it''s'\nd: \"1\"\nurl: http://example.com\n";
+        assert_eq!(
+            parse(yaml).unwrap(),
+            json!({"a: b": "x\ty", "c": "it's", "d": "1", "url": "http://example.com"})
+        );
+    }
+
+    #[test]
+    fn flow_collections() {
+        let yaml = "list: [1, \"a, b\", 'c', [d, e]]\nmap: {x: 1, y: \"p, q\", z: {w: true}}\n";
+        assert_eq!(
+            parse(yaml).unwrap(),
+            json!({
+                "list": [1, "a, b", "c", ["d", "e"]],
+                "map": {"x": 1, "y": "p, q", "z": {"w": true}},
+            })
+        );
+    }
+
+    #[test]
+    fn block_scalars_and_aliases() {
+        let yaml = "literal: |-\n  a\n  b\nfolded: >\n  c\n  d\nbase: &base {x: 1}\ncopy: *base\n";
+        assert_eq!(
+            parse(yaml).unwrap(),
+            json!({"literal": "a\nb", "folded": "c d\n", "base": {"x": 1}, "copy": {"x": 1}})
+        );
+    }
+
+    #[test]
+    fn invalid_documents() {
+        assert!(parse("a: *missing\n").is_err());
+        assert!(parse("a:\n\tb: 1\n").is_err());
+        assert!(parse("a: 1\n  b: 2\n").is_err());
+        assert!(parse("a: 1\nnot a mapping\n").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    borrow::Cow,
+    sync::{PoisonError, RwLock},
+};
+
+use crate::connection::{Connection, ExecutionResult};
+
+/// Replacement for secret values
+const MASK: &str = "***";
+
+/// Secrets shorter than this are not redacted, as they would mask too much unrelated text
+const MIN_SECRET_LEN: usize = 4;
+
+/// Secrets only known once a connection is established (eg: fetched by a `credential_cmd`)
+///
+/// They are redacted by every `Redactor`, including the ones created before the connection.
+static FETCHED_SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Filter that scrubs secret values from texts before they reach diagnostics
+#[derive(Debug, Default, Clone)]
+pub struct Redactor {
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    /// Redactor for the secrets of a connection
+    pub fn for_connection<'a, T: Connection>(config: &T::Config<'a>) -> Self {
+        let mut redactor = Self::default();
+        for secret in T::secrets(config) {
+            redactor.add(&secret);
+        }
+        redactor
+    }
+
+    /// Register a secret value
+    ///
+    /// Multi-line secrets (like private keys) are also registered line by line,
+    /// so partial outputs are redacted too.
+    pub fn add(&mut self, secret: &str) {
+        add_secret(&mut self.secrets, secret);
+    }
+
+    /// Register a secret value for all the redactors of the process
+    pub fn add_fetched(secret: &str) {
+        let mut secrets = FETCHED_SECRETS
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        add_secret(&mut secrets, secret);
+    }
+
+    pub fn redact<'b>(&self, text: &'b str) -> Cow<'b, str> {
+        let fetched = FETCHED_SECRETS
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut secrets = self
+            .secrets
+            .iter()
+            .chain(fetched.iter())
+            .collect::<Vec<_>>();
+        // Longest secrets first, so a secret containing another one is fully masked
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+
+        let mut text = Cow::Borrowed(text);
+        for secret in secrets {
+            if text.contains(secret.as_str()) {
+                text = Cow::Owned(text.replace(secret.as_str(), MASK));
+            }
+        }
+        text
+    }
+
+    pub fn redact_result(&self, result: ExecutionResult) -> ExecutionResult {
+        ExecutionResult {
+            status: result.status,
+            stdout: self.redact(&result.stdout).into_owned(),
+            stderr: self.redact(&result.stderr).into_owned(),
+        }
+    }
+}
+
+/// Add a secret to a list, with each of its lines if it spans several
+fn add_secret(secrets: &mut Vec<String>, secret: &str) {
+    let mut add = |secret: &str| {
+        if secret.len() >= MIN_SECRET_LEN && !secrets.iter().any(|s| s == secret) {
+            secrets.push(secret.to_owned());
+        }
+    };
+    add(secret);
+    if secret.contains('\n') {
+        secret.lines().map(str::trim).for_each(add);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redactor;
+
+    #[test]
+    fn fetched_secrets_are_redacted_by_existing_redactors() {
+        let mut redactor = Redactor::default();
+        redactor.add("config-password");
+        Redactor::add_fetched("fetched-password");
+        assert_eq!(
+            redactor.redact("config-password fetched-password abc"),
+            "*** *** abc"
+        );
+    }
+}
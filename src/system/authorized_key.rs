@@ -0,0 +1,414 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
+};
+use tf_provider::value::{self, Value, ValueEmpty, ValueString};
+use tf_provider::{map, AttributePath, Diagnostics, Resource};
+
+use crate::connection::Connection;
+use crate::options::SharedOptions;
+
+use super::{check_writable, locked, run_script};
+
+const LOCATE_SCRIPT: &str = r#"if [ -z "$AUTHKEY_FILE" ]; then
+  home=$(getent passwd "$AUTHKEY_USER" | cut -d: -f6)
+  if [ -z "$home" ]; then
+    echo "User $AUTHKEY_USER does not exist" >&2
+    exit 1
+  fi
+  AUTHKEY_FILE="$home/.ssh/authorized_keys"
+fi
+"#;
+
+const READ_SCRIPT: &str = r#"[ -f "$AUTHKEY_FILE" ] || exit 0
+awk -v blob="$AUTHKEY_BLOB" '{ for (i = 1; i <= NF; i++) if ($i == blob) { print; exit } }' "$AUTHKEY_FILE"
+"#;
+
+const CREATE_SCRIPT: &str = r#"if [ ! -f "$AUTHKEY_FILE" ]; then
+  dir=$(dirname "$AUTHKEY_FILE")
+  if [ ! -d "$dir" ]; then
+    mkdir -p "$dir" && chmod 700 "$dir" && chown "$AUTHKEY_USER:" "$dir" || exit
+  fi
+  touch "$AUTHKEY_FILE" && chmod 600 "$AUTHKEY_FILE" && chown "$AUTHKEY_USER:" "$AUTHKEY_FILE" || exit
+fi
+"#;
+
+const APPLY_SCRIPT: &str = r#"tmp=$(mktemp) || exit
+if ! awk -v blob="$AUTHKEY_BLOB" '{ for (i = 1; i <= NF; i++) if ($i == blob) next; print }' "$AUTHKEY_FILE" > "$tmp"; then
+  rm -f "$tmp"
+  exit 1
+fi
+if [ -n "$AUTHKEY_LINE" ]; then
+  printf '%s\n' "$AUTHKEY_LINE" >> "$tmp"
+fi
+cat "$tmp" > "$AUTHKEY_FILE"
+status=$?
+rm -f "$tmp"
+exit $status
+"#;
+
+#[derive(Debug, Default)]
+pub struct GenericAuthorizedKeyResource<T: Connection> {
+    pub(super) options: SharedOptions,
+    pub(super) connect: T,
+}
+
+impl<T: Connection> GenericAuthorizedKeyResource<T> {
+    pub fn new(options: SharedOptions, connect: T) -> Self {
+        Self { options, connect }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ResourceState<'a, T>
+where
+    T: Connection,
+{
+    #[serde(borrow = "'a")]
+    pub id: ValueString<'a>,
+    pub user: ValueString<'a>,
+    pub key: ValueString<'a>,
+    pub options: ValueString<'a>,
+    pub authorized_keys_file: ValueString<'a>,
+    #[serde(with = "value::serde_as_vec")]
+    pub connect: Value<T::Config<'a>>,
+}
+
+/// Split a public key into its type, its base64 blob and its optional comment
+fn parse_key(key: &str) -> Option<(&str, &str, &str)> {
+    let key = key.trim();
+    let (key_type, rest) = key.split_once(char::is_whitespace)?;
+    let rest = rest.trim_start();
+    let (blob, comment) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((key_type, blob, comment.trim()))
+}
+
+impl<'a, T: Connection> ResourceState<'a, T> {
+    fn line(&self) -> String {
+        let key = self.key.as_str().trim();
+        match self.options.as_str() {
+            "" => key.to_owned(),
+            options => format!("{options} {key}"),
+        }
+    }
+    fn blob(&self) -> &str {
+        parse_key(self.key.as_str()).map_or("", |(_, blob, _)| blob)
+    }
+}
+
+#[async_trait]
+impl<T> Resource for GenericAuthorizedKeyResource<T>
+where
+    T: Connection,
+    T: Debug,
+    T: Clone,
+{
+    type State<'a> = ResourceState<'a, T>;
+    type PrivateState<'a> = ValueEmpty;
+    type ProviderMetaState<'a> = ValueEmpty;
+
+    fn schema(&self, _diags: &mut Diagnostics) -> Option<Schema> {
+        Some(Schema {
+            version: 1,
+            block: Block {
+                attributes: map! {
+                    "id" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Id of the authorized key"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "user" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("User allowed to log in with the key"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "key" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Public key in the OpenSSH format (eg: ssh-ed25519 AAAA... comment)"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "options" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Options prepended to the key (eg: command=\"...\",no-pty)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                    "authorized_keys_file" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Path of the authorized_keys file (default: ~user/.ssh/authorized_keys)"),
+                        constraint: AttributeConstraint::Optional,
+                        ..Default::default()
+                    },
+                },
+                blocks: map! {
+                    "connect" => NestedBlock::Optional(Block {
+                        attributes: T::schema(),
+                        description: Description::plain("Connection configuration"),
+                        ..Default::default()
+                    }),
+                },
+                description: Description::plain(
+                    "Single public key in the authorized_keys file of a user",
+                ),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn validate<'a>(&self, diags: &mut Diagnostics, config: Self::State<'a>) -> Option<()> {
+        if let Value::Value(connect) = &config.connect {
+            _ = self
+                .connect
+                .validate(diags, AttributePath::new("connect").index(0), connect)
+                .await;
+        }
+
+        match &config.user {
+            Value::Value(user) => {
+                if user.is_empty() {
+                    diags.error_short("`user` should not be empty", AttributePath::new("user"));
+                }
+            }
+            Value::Null => {
+                diags.error_short("`user` should not be null", AttributePath::new("user"));
+            }
+            Value::Unknown => (),
+        }
+
+        match &config.key {
+            Value::Value(key) => match parse_key(key) {
+                Some((_, blob, _))
+                    if base64::engine::general_purpose::STANDARD
+                        .decode(blob)
+                        .is_ok() => {}
+                _ => diags.error(
+                    "Invalid `key`",
+                    "The key should be a public key in the OpenSSH format: `<type> <base64> [comment]`.",
+                    AttributePath::new("key"),
+                ),
+            },
+            Value::Null => {
+                diags.error_short("`key` should not be null", AttributePath::new("key"));
+            }
+            Value::Unknown => (),
+        }
+
+        if let Value::Value(options) = &config.options {
+            if options.contains('\n') {
+                diags.error_short(
+                    "`options` should not contain newlines",
+                    AttributePath::new("options"),
+                );
+            }
+        }
+
+        if diags.errors.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn read<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        mut state: Self::State<'a>,
+        private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        let env = BTreeMap::from([
+            ("AUTHKEY_USER", state.user.as_str()),
+            ("AUTHKEY_FILE", state.authorized_keys_file.as_str()),
+            ("AUTHKEY_BLOB", state.blob()),
+        ]);
+        let output = run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_authorized_key",
+            "read",
+            &format!("{LOCATE_SCRIPT}{READ_SCRIPT}"),
+            &env,
+            AttributePath::new("key"),
+        )
+        .await?;
+
+        let line = output.trim_end();
+        let (key_type, blob, _) = parse_key(state.key.as_str()).unwrap_or_default();
+        match line.find(&format!("{key_type} {blob}")) {
+            Some(index) if !line.is_empty() => {
+                let options = line[..index].trim_end();
+                if options != state.options.as_str() {
+                    state.options = Value::Value(options.to_owned().into());
+                }
+            }
+            // A missing key is reported as an empty key to force the key to be added again
+            _ => state.key = Value::Value(Default::default()),
+        }
+
+        Some((state, private_state))
+    }
+
+    async fn plan_create<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        proposed_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let mut state = proposed_state;
+        self.normalize(&mut state);
+        Some((state, Default::default()))
+    }
+    async fn plan_update<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        prior_state: Self::State<'a>,
+        proposed_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        prior_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(
+        Self::State<'a>,
+        Self::PrivateState<'a>,
+        Vec<tf_provider::AttributePath>,
+    )> {
+        let mut state = proposed_state;
+        self.normalize(&mut state);
+
+        let mut trigger_replace = Vec::new();
+        if state.user != prior_state.user {
+            trigger_replace.push(AttributePath::new("user"));
+        }
+        if state.authorized_keys_file != prior_state.authorized_keys_file {
+            trigger_replace.push(AttributePath::new("authorized_keys_file"));
+        }
+        if state.blob() != prior_state.blob() {
+            trigger_replace.push(AttributePath::new("key"));
+        }
+        Some((state, prior_private_state, trigger_replace))
+    }
+
+    async fn plan_destroy<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        _prior_state: Self::State<'a>,
+        prior_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<Self::PrivateState<'a>> {
+        Some(prior_private_state)
+    }
+
+    async fn create<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        planned_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        check_writable(diags, &self.options, "create")?;
+        let mut state = planned_state;
+        self.normalize(&mut state);
+        let line = state.line();
+        self.apply(diags, &state, &line, "create").await?;
+        Some((state, planned_private_state))
+    }
+    async fn update<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        _prior_state: Self::State<'a>,
+        planned_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        check_writable(diags, &self.options, "update")?;
+        let mut state = planned_state;
+        self.normalize(&mut state);
+        let line = state.line();
+        self.apply(diags, &state, &line, "update").await?;
+        Some((state, planned_private_state))
+    }
+    async fn destroy<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: Self::State<'a>,
+        _planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<()> {
+        check_writable(diags, &self.options, "destroy")?;
+        self.apply(diags, &state, "", "destroy").await
+    }
+}
+
+impl<T: Connection> GenericAuthorizedKeyResource<T> {
+    fn normalize(&self, state: &mut ResourceState<'_, T>) {
+        state.id = match (&state.user, parse_key(state.key.as_str())) {
+            (Value::Value(user), Some((_, blob, _))) => {
+                Value::Value(format!("{user}:{blob}").into())
+            }
+            _ => Value::Unknown,
+        };
+    }
+    async fn apply<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: &ResourceState<'a, T>,
+        line: &str,
+        phase: &str,
+    ) -> Option<()> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        let env = BTreeMap::from([
+            ("AUTHKEY_USER", state.user.as_str()),
+            ("AUTHKEY_FILE", state.authorized_keys_file.as_str()),
+            ("AUTHKEY_BLOB", state.blob()),
+            ("AUTHKEY_LINE", line),
+        ]);
+        run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_authorized_key",
+            phase,
+            &format!(
+                "{LOCATE_SCRIPT}{CREATE_SCRIPT}{}",
+                locked("AUTHKEY_FILE", APPLY_SCRIPT)
+            ),
+            &env,
+            AttributePath::new("key"),
+        )
+        .await?;
+        Some(())
+    }
+}
@@ -0,0 +1,410 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
+};
+use tf_provider::value::{self, Value, ValueEmpty, ValueList, ValueString};
+use tf_provider::{map, AttributePath, Diagnostics, Resource};
+
+use crate::connection::Connection;
+use crate::options::SharedOptions;
+use crate::utils::DisplayJoinable;
+
+use super::{check_writable, locked, run_script};
+
+const READ_SCRIPT: &str = r#"awk -v begin="$HOSTS_BEGIN" -v end="$HOSTS_END" '$0 == end { skip = 0 } skip { print } $0 == begin { skip = 1 }' "$HOSTS_FILE""#;
+
+const APPLY_SCRIPT: &str = r#"tmp=$(mktemp) || exit
+if ! awk -v begin="$HOSTS_BEGIN" -v end="$HOSTS_END" '$0 == begin { skip = 1 } !skip { print } $0 == end { skip = 0 }' "$HOSTS_FILE" > "$tmp"; then
+  rm -f "$tmp"
+  exit 1
+fi
+if [ -n "$HOSTS_LINE" ]; then
+  printf '%s\n%s\n%s\n' "$HOSTS_BEGIN" "$HOSTS_LINE" "$HOSTS_END" >> "$tmp"
+fi
+cat "$tmp" > "$HOSTS_FILE"
+status=$?
+rm -f "$tmp"
+exit $status
+"#;
+
+#[derive(Debug, Default)]
+pub struct GenericHostsEntryResource<T: Connection> {
+    pub(super) options: SharedOptions,
+    pub(super) connect: T,
+}
+
+impl<T: Connection> GenericHostsEntryResource<T> {
+    pub fn new(options: SharedOptions, connect: T) -> Self {
+        Self { options, connect }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ResourceState<'a, T>
+where
+    T: Connection,
+{
+    #[serde(borrow = "'a")]
+    pub id: ValueString<'a>,
+    pub ip: ValueString<'a>,
+    pub hostnames: ValueList<ValueString<'a>>,
+    pub hosts_file: ValueString<'a>,
+    #[serde(with = "value::serde_as_vec")]
+    pub connect: Value<T::Config<'a>>,
+}
+
+impl<'a, T: Connection> ResourceState<'a, T> {
+    fn line(&self) -> String {
+        let hostnames_default = Default::default();
+        let hostnames = self.hostnames.as_ref().unwrap_or(&hostnames_default);
+        format!(
+            "{} {}",
+            self.ip.as_str(),
+            hostnames.iter().map(|h| h.as_str()).join_with(" ")
+        )
+    }
+    fn markers(&self) -> (String, String) {
+        let id = self.id.as_str();
+        (
+            format!("# BEGIN generic_hosts_entry {id}"),
+            format!("# END generic_hosts_entry {id}"),
+        )
+    }
+}
+
+#[async_trait]
+impl<T> Resource for GenericHostsEntryResource<T>
+where
+    T: Connection,
+    T: Debug,
+    T: Clone,
+{
+    type State<'a> = ResourceState<'a, T>;
+    type PrivateState<'a> = ValueEmpty;
+    type ProviderMetaState<'a> = ValueEmpty;
+
+    fn schema(&self, _diags: &mut Diagnostics) -> Option<Schema> {
+        Some(Schema {
+            version: 1,
+            block: Block {
+                attributes: map! {
+                    "id" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Id of the hosts entry, used in the marker comments"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "ip" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("IP address of the entry"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "hostnames" => Attribute {
+                        attr_type: AttributeType::List(AttributeType::String.into()),
+                        description: Description::plain("Hostnames mapped to the IP address"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "hosts_file" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Path of the hosts file (default: /etc/hosts)"),
+                        constraint: AttributeConstraint::OptionalComputed,
+                        ..Default::default()
+                    },
+                },
+                blocks: map! {
+                    "connect" => NestedBlock::Optional(Block {
+                        attributes: T::schema(),
+                        description: Description::plain("Connection configuration"),
+                        ..Default::default()
+                    }),
+                },
+                description: Description::plain(
+                    "Single host mapping line in a hosts file, delimited by marker comments",
+                ),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn validate<'a>(&self, diags: &mut Diagnostics, config: Self::State<'a>) -> Option<()> {
+        if let Value::Value(connect) = &config.connect {
+            _ = self
+                .connect
+                .validate(diags, AttributePath::new("connect").index(0), connect)
+                .await;
+        }
+
+        match &config.ip {
+            Value::Value(ip) => {
+                if ip.is_empty() {
+                    diags.error_short("`ip` should not be empty", AttributePath::new("ip"));
+                } else if ip.parse::<std::net::IpAddr>().is_err() {
+                    diags.error(
+                        "Invalid `ip`",
+                        format!("`{ip}` is not a valid IP address."),
+                        AttributePath::new("ip"),
+                    );
+                }
+            }
+            Value::Null => {
+                diags.error_short("`ip` should not be null", AttributePath::new("ip"));
+            }
+            Value::Unknown => (),
+        }
+
+        match &config.hostnames {
+            Value::Value(hostnames) => {
+                if hostnames.is_empty() {
+                    diags.error_short(
+                        "`hostnames` should not be empty",
+                        AttributePath::new("hostnames"),
+                    );
+                }
+                for (i, hostname) in hostnames.iter().enumerate() {
+                    let attr_path = AttributePath::new("hostnames").index(i as i64);
+                    match hostname {
+                        Value::Value(hostname) => {
+                            if hostname.is_empty()
+                                || hostname.contains(|c: char| c.is_whitespace() || c == '#')
+                            {
+                                diags.error(
+                                    "Invalid hostname",
+                                    format!("`{hostname}` is not a valid hostname."),
+                                    attr_path,
+                                );
+                            }
+                        }
+                        Value::Null => {
+                            diags.error_short("Hostname should not be null", attr_path);
+                        }
+                        Value::Unknown => (),
+                    }
+                }
+            }
+            Value::Null => {
+                diags.error_short(
+                    "`hostnames` should not be null",
+                    AttributePath::new("hostnames"),
+                );
+            }
+            Value::Unknown => (),
+        }
+
+        if let Value::Value(hosts_file) = &config.hosts_file {
+            if hosts_file.is_empty() {
+                diags.error_short(
+                    "`hosts_file` should not be empty",
+                    AttributePath::new("hosts_file"),
+                );
+            }
+        }
+
+        if diags.errors.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn read<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        mut state: Self::State<'a>,
+        private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        let (begin, end) = state.markers();
+        let env = BTreeMap::from([
+            ("HOSTS_BEGIN", begin.as_str()),
+            ("HOSTS_END", end.as_str()),
+            ("HOSTS_FILE", state.hosts_file.as_str()),
+        ]);
+        let output = run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_hosts_entry",
+            "read",
+            READ_SCRIPT,
+            &env,
+            AttributePath::new("hosts_file"),
+        )
+        .await?;
+
+        let mut fields = output.lines().next().unwrap_or_default().split_whitespace();
+        let ip = fields.next().unwrap_or_default().to_owned();
+        let hostnames = fields
+            .map(|hostname| Value::Value(hostname.to_owned().into()))
+            .collect();
+
+        // A missing entry is reported as an empty ip to force the entry to be written again
+        state.ip = Value::Value(ip.into());
+        state.hostnames = Value::Value(hostnames);
+
+        Some((state, private_state))
+    }
+
+    async fn plan_create<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        proposed_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let mut state = proposed_state;
+        self.normalize(&mut state);
+        Some((state, Default::default()))
+    }
+    async fn plan_update<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        prior_state: Self::State<'a>,
+        proposed_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        prior_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(
+        Self::State<'a>,
+        Self::PrivateState<'a>,
+        Vec<tf_provider::AttributePath>,
+    )> {
+        let mut state = proposed_state;
+        self.normalize(&mut state);
+
+        let mut trigger_replace = Vec::new();
+        if state.hosts_file != prior_state.hosts_file {
+            trigger_replace.push(AttributePath::new("hosts_file"));
+        }
+        Some((state, prior_private_state, trigger_replace))
+    }
+
+    async fn plan_destroy<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        _prior_state: Self::State<'a>,
+        prior_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<Self::PrivateState<'a>> {
+        Some(prior_private_state)
+    }
+
+    async fn create<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        planned_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        check_writable(diags, &self.options, "create")?;
+        let mut state = planned_state;
+        self.normalize(&mut state);
+        if !state.id.is_value() {
+            state.id = ValueString::Value(
+                thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(30)
+                    .map(char::from)
+                    .collect(),
+            );
+        }
+        let line = state.line();
+        self.apply(diags, &state, &line, "create").await?;
+        Some((state, planned_private_state))
+    }
+    async fn update<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        _prior_state: Self::State<'a>,
+        planned_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        check_writable(diags, &self.options, "update")?;
+        let mut state = planned_state;
+        self.normalize(&mut state);
+        let line = state.line();
+        self.apply(diags, &state, &line, "update").await?;
+        Some((state, planned_private_state))
+    }
+    async fn destroy<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: Self::State<'a>,
+        _planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<()> {
+        check_writable(diags, &self.options, "destroy")?;
+        self.apply(diags, &state, "", "destroy").await
+    }
+}
+
+impl<T: Connection> GenericHostsEntryResource<T> {
+    fn normalize(&self, state: &mut ResourceState<'_, T>) {
+        if state.id.is_null() {
+            state.id = Value::Unknown;
+        }
+        if !state.hosts_file.is_value() {
+            state.hosts_file = Value::Value("/etc/hosts".into());
+        }
+    }
+    async fn apply<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: &ResourceState<'a, T>,
+        line: &str,
+        phase: &str,
+    ) -> Option<()> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        let (begin, end) = state.markers();
+        let env = BTreeMap::from([
+            ("HOSTS_BEGIN", begin.as_str()),
+            ("HOSTS_END", end.as_str()),
+            ("HOSTS_FILE", state.hosts_file.as_str()),
+            ("HOSTS_LINE", line),
+        ]);
+        run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_hosts_entry",
+            phase,
+            &locked("HOSTS_FILE", APPLY_SCRIPT),
+            &env,
+            AttributePath::new("hosts_file"),
+        )
+        .await?;
+        Some(())
+    }
+}
@@ -0,0 +1,101 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use tf_provider::{AttributePath, Diagnostics};
+
+use crate::audit;
+use crate::connection::Connection;
+use crate::options::{report_read_only, SharedOptions};
+use crate::redact::Redactor;
+
+mod authorized_key;
+mod hosts_entry;
+mod mount;
+mod sysctl;
+
+pub use authorized_key::GenericAuthorizedKeyResource;
+pub use hosts_entry::GenericHostsEntryResource;
+pub use mount::GenericMountResource;
+pub use sysctl::GenericSysctlResource;
+
+/// Wrap a script editing the file of the environment variable `file`, so concurrent edits do not lose each other's changes
+///
+/// The file is locked with `flock` while the script runs, when the target has it.
+/// Failures of the script still abort the scripts that follow.
+fn locked(file: &str, script: &str) -> String {
+    format!("(\ncommand -v flock > /dev/null && {{ flock 9 || exit; }}\n{script}) 9>> \"${file}\" || exit\n")
+}
+
+/// Report the modification of a target while the provider is read-only
+///
+/// Returns `None` in that case, so the phase can stop before running any script.
+fn check_writable(diags: &mut Diagnostics, options: &SharedOptions, phase: &str) -> Option<()> {
+    if options.get().read_only {
+        report_read_only(diags, phase);
+        None
+    } else {
+        Some(())
+    }
+}
+
+/// Execute a builtin script over the connection
+///
+/// Parameters are given to the script through environment variables to avoid any quoting issue.
+/// Returns the stdout of the script if it succeeded, and reports the failure otherwise.
+#[allow(clippy::too_many_arguments)]
+async fn run_script<'a, T: Connection>(
+    diags: &mut Diagnostics,
+    connect: &T,
+    config: &T::Config<'a>,
+    resource: &str,
+    phase: &str,
+    script: &str,
+    env: &BTreeMap<&str, &str>,
+    attr_path: AttributePath,
+) -> Option<String> {
+    let redactor = Redactor::for_connection::<T>(config);
+    match audit::execute(connect, config, script, "", env, resource, phase).await {
+        Ok(res) => {
+            if res.status == 0 {
+                if !res.stderr.is_empty() {
+                    diags.warning(
+                        format!("`{phase}` succeeded but stderr was not empty"),
+                        redactor.redact(&res.stderr).into_owned(),
+                        attr_path,
+                    );
+                }
+                Some(res.stdout)
+            } else {
+                diags.error(
+                    format!("`{phase}` failed with status code: {}", res.status),
+                    redactor.redact(&res.stderr).into_owned(),
+                    attr_path,
+                );
+                None
+            }
+        }
+        Err(err) => {
+            diags.error(
+                format!("Failed to {phase} resource"),
+                redactor.redact(&err.to_string()).into_owned(),
+                attr_path,
+            );
+            None
+        }
+    }
+}
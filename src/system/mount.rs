@@ -0,0 +1,429 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
+};
+use tf_provider::value::{self, Value, ValueEmpty, ValueString};
+use tf_provider::{map, AttributePath, Diagnostics, Resource};
+
+use crate::connection::Connection;
+use crate::options::SharedOptions;
+
+use super::{check_writable, locked, run_script};
+
+const READ_SCRIPT: &str = r#"awk -v target="$MOUNT_PATH" '$1 !~ /^#/ && $2 == target { print $1, $3, $4; exit }' "$MOUNT_FSTAB"
+findmnt -rn --mountpoint "$MOUNT_PATH" -o FSTYPE || true
+"#;
+
+const FSTAB_SCRIPT: &str = r#"tmp=$(mktemp) || exit
+if ! awk -v target="$MOUNT_PATH" '$1 ~ /^#/ || $2 != target { print }' "$MOUNT_FSTAB" > "$tmp"; then
+  rm -f "$tmp"
+  exit 1
+fi
+if [ -n "$MOUNT_LINE" ]; then
+  printf '%s\n' "$MOUNT_LINE" >> "$tmp"
+fi
+cat "$tmp" > "$MOUNT_FSTAB"
+status=$?
+rm -f "$tmp"
+[ $status = 0 ] || exit $status
+"#;
+
+const MOUNT_SCRIPT: &str = r#"mkdir -p "$MOUNT_PATH" || exit
+if findmnt -rn --mountpoint "$MOUNT_PATH" > /dev/null; then
+  if [ "$MOUNT_REMOUNT" = true ]; then
+    umount "$MOUNT_PATH" && mount "$MOUNT_PATH"
+  else
+    mount -o "remount,$MOUNT_OPTIONS" "$MOUNT_PATH"
+  fi
+else
+  mount "$MOUNT_PATH"
+fi
+"#;
+
+const UMOUNT_SCRIPT: &str = r#"if findmnt -rn --mountpoint "$MOUNT_PATH" > /dev/null; then
+  umount "$MOUNT_PATH" || exit
+fi
+"#;
+
+#[derive(Debug, Default)]
+pub struct GenericMountResource<T: Connection> {
+    pub(super) options: SharedOptions,
+    pub(super) connect: T,
+}
+
+impl<T: Connection> GenericMountResource<T> {
+    pub fn new(options: SharedOptions, connect: T) -> Self {
+        Self { options, connect }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ResourceState<'a, T>
+where
+    T: Connection,
+{
+    #[serde(borrow = "'a")]
+    pub id: ValueString<'a>,
+    pub path: ValueString<'a>,
+    pub device: ValueString<'a>,
+    pub fstype: ValueString<'a>,
+    pub options: ValueString<'a>,
+    pub fstab_file: ValueString<'a>,
+    #[serde(with = "value::serde_as_vec")]
+    pub connect: Value<T::Config<'a>>,
+}
+
+impl<'a, T: Connection> ResourceState<'a, T> {
+    fn line(&self) -> String {
+        format!(
+            "{} {} {} {} 0 0",
+            self.device.as_str(),
+            self.path.as_str(),
+            self.fstype.as_str(),
+            self.options.as_str(),
+        )
+    }
+}
+
+#[async_trait]
+impl<T> Resource for GenericMountResource<T>
+where
+    T: Connection,
+    T: Debug,
+    T: Clone,
+{
+    type State<'a> = ResourceState<'a, T>;
+    type PrivateState<'a> = ValueEmpty;
+    type ProviderMetaState<'a> = ValueEmpty;
+
+    fn schema(&self, _diags: &mut Diagnostics) -> Option<Schema> {
+        Some(Schema {
+            version: 1,
+            block: Block {
+                attributes: map! {
+                    "id" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Id of the mount"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "path" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Mount point"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "device" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Device to mount (eg: /dev/sdb1, UUID=..., server:/export)"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "fstype" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Filesystem type"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "options" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Mount options (default: defaults)"),
+                        constraint: AttributeConstraint::OptionalComputed,
+                        ..Default::default()
+                    },
+                    "fstab_file" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Path of the fstab file (default: /etc/fstab)"),
+                        constraint: AttributeConstraint::OptionalComputed,
+                        ..Default::default()
+                    },
+                },
+                blocks: map! {
+                    "connect" => NestedBlock::Optional(Block {
+                        attributes: T::schema(),
+                        description: Description::plain("Connection configuration"),
+                        ..Default::default()
+                    }),
+                },
+                description: Description::plain("Filesystem present in fstab and mounted"),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn validate<'a>(&self, diags: &mut Diagnostics, config: Self::State<'a>) -> Option<()> {
+        if let Value::Value(connect) = &config.connect {
+            _ = self
+                .connect
+                .validate(diags, AttributePath::new("connect").index(0), connect)
+                .await;
+        }
+
+        for (name, value, required) in [
+            ("path", &config.path, true),
+            ("device", &config.device, true),
+            ("fstype", &config.fstype, true),
+            ("options", &config.options, false),
+            ("fstab_file", &config.fstab_file, false),
+        ] {
+            let attr_path = AttributePath::new(name);
+            match value {
+                Value::Value(value) => {
+                    if value.is_empty() {
+                        diags.error_short(format!("`{name}` should not be empty"), attr_path);
+                    } else if value.contains(char::is_whitespace) {
+                        diags.error_short(
+                            format!("`{name}` should not contain whitespaces"),
+                            attr_path,
+                        );
+                    }
+                }
+                Value::Null => {
+                    if required {
+                        diags.error_short(format!("`{name}` should not be null"), attr_path);
+                    }
+                }
+                Value::Unknown => (),
+            }
+        }
+
+        if let Value::Value(path) = &config.path {
+            if !path.starts_with('/') {
+                diags.error_short("`path` should be absolute", AttributePath::new("path"));
+            }
+        }
+
+        if diags.errors.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn read<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        mut state: Self::State<'a>,
+        private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        let env = BTreeMap::from([
+            ("MOUNT_PATH", state.path.as_str()),
+            ("MOUNT_FSTAB", state.fstab_file.as_str()),
+        ]);
+        let output = run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_mount",
+            "read",
+            READ_SCRIPT,
+            &env,
+            AttributePath::new("path"),
+        )
+        .await?;
+
+        let mut lines = output.lines();
+        let mut fstab = lines.next().unwrap_or_default().split_whitespace();
+        let live_fstype = lines.next().unwrap_or_default().trim();
+
+        match (fstab.next(), fstab.next(), fstab.next()) {
+            (Some(device), Some(fstype), Some(options)) if !live_fstype.is_empty() => {
+                if device != state.device.as_str() {
+                    state.device = Value::Value(device.to_owned().into());
+                }
+                if fstype != state.fstype.as_str() {
+                    state.fstype = Value::Value(fstype.to_owned().into());
+                } else if fstype != "auto" && live_fstype != fstype {
+                    // Mounted filesystem does not match fstab
+                    state.fstype = Value::Value(live_fstype.to_owned().into());
+                }
+                if options != state.options.as_str() {
+                    state.options = Value::Value(options.to_owned().into());
+                }
+            }
+            // Missing from fstab or not mounted: report an empty device to force the mount again
+            _ => state.device = Value::Value(Default::default()),
+        }
+
+        Some((state, private_state))
+    }
+
+    async fn plan_create<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        proposed_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let mut state = proposed_state;
+        self.normalize(&mut state);
+        Some((state, Default::default()))
+    }
+    async fn plan_update<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        prior_state: Self::State<'a>,
+        proposed_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        prior_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(
+        Self::State<'a>,
+        Self::PrivateState<'a>,
+        Vec<tf_provider::AttributePath>,
+    )> {
+        let mut state = proposed_state;
+        self.normalize(&mut state);
+
+        let mut trigger_replace = Vec::new();
+        if state.path != prior_state.path {
+            trigger_replace.push(AttributePath::new("path"));
+        }
+        if state.fstab_file != prior_state.fstab_file {
+            trigger_replace.push(AttributePath::new("fstab_file"));
+        }
+        Some((state, prior_private_state, trigger_replace))
+    }
+
+    async fn plan_destroy<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        _prior_state: Self::State<'a>,
+        prior_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<Self::PrivateState<'a>> {
+        Some(prior_private_state)
+    }
+
+    async fn create<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        planned_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        check_writable(diags, &self.options, "create")?;
+        let mut state = planned_state;
+        self.normalize(&mut state);
+        self.apply(diags, &state, true, "create").await?;
+        Some((state, planned_private_state))
+    }
+    async fn update<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        prior_state: Self::State<'a>,
+        planned_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        check_writable(diags, &self.options, "update")?;
+        let mut state = planned_state;
+        self.normalize(&mut state);
+        // Options can be changed with a remount, but a new device requires a full mount
+        let remount = state.device != prior_state.device || state.fstype != prior_state.fstype;
+        self.apply(diags, &state, remount, "update").await?;
+        Some((state, planned_private_state))
+    }
+    async fn destroy<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: Self::State<'a>,
+        _planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<()> {
+        check_writable(diags, &self.options, "destroy")?;
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        let env = BTreeMap::from([
+            ("MOUNT_PATH", state.path.as_str()),
+            ("MOUNT_FSTAB", state.fstab_file.as_str()),
+            ("MOUNT_LINE", ""),
+        ]);
+        run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_mount",
+            "destroy",
+            &format!("{UMOUNT_SCRIPT}{}", locked("MOUNT_FSTAB", FSTAB_SCRIPT)),
+            &env,
+            AttributePath::new("path"),
+        )
+        .await?;
+        Some(())
+    }
+}
+
+impl<T: Connection> GenericMountResource<T> {
+    fn normalize(&self, state: &mut ResourceState<'_, T>) {
+        state.id = match &state.path {
+            Value::Value(path) => Value::Value(path.clone()),
+            _ => Value::Unknown,
+        };
+        if !state.options.is_value() {
+            state.options = Value::Value("defaults".into());
+        }
+        if !state.fstab_file.is_value() {
+            state.fstab_file = Value::Value("/etc/fstab".into());
+        }
+    }
+    async fn apply<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: &ResourceState<'a, T>,
+        remount: bool,
+        phase: &str,
+    ) -> Option<()> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        let line = state.line();
+        let env = BTreeMap::from([
+            ("MOUNT_PATH", state.path.as_str()),
+            ("MOUNT_FSTAB", state.fstab_file.as_str()),
+            ("MOUNT_OPTIONS", state.options.as_str()),
+            ("MOUNT_LINE", line.as_str()),
+            ("MOUNT_REMOUNT", if remount { "true" } else { "false" }),
+        ]);
+        run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_mount",
+            phase,
+            &format!("{}{MOUNT_SCRIPT}", locked("MOUNT_FSTAB", FSTAB_SCRIPT)),
+            &env,
+            AttributePath::new("path"),
+        )
+        .await?;
+        Some(())
+    }
+}
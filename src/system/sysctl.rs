@@ -0,0 +1,386 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock, Schema,
+};
+use tf_provider::value::{self, Value, ValueBool, ValueEmpty, ValueString};
+use tf_provider::{map, AttributePath, Diagnostics, Resource};
+
+use crate::connection::Connection;
+use crate::options::SharedOptions;
+
+use super::{check_writable, run_script};
+
+const READ_SCRIPT: &str = r#"value=$(sysctl -n "$SYSCTL_NAME") || exit
+if [ -f "$SYSCTL_FILE" ] && [ "$(cat "$SYSCTL_FILE")" = "$SYSCTL_NAME = $SYSCTL_VALUE" ]; then
+  printf 'true\n%s' "$value"
+else
+  printf 'false\n%s' "$value"
+fi
+"#;
+
+const APPLY_SCRIPT: &str = r#"sysctl -w "$SYSCTL_NAME=$SYSCTL_VALUE" > /dev/null || exit
+if [ "$SYSCTL_PERSIST" = true ]; then
+  mkdir -p "$(dirname "$SYSCTL_FILE")" || exit
+  printf '%s = %s\n' "$SYSCTL_NAME" "$SYSCTL_VALUE" > "$SYSCTL_FILE"
+else
+  rm -f "$SYSCTL_FILE"
+fi
+"#;
+
+const DESTROY_SCRIPT: &str = r#"rm -f "$SYSCTL_FILE""#;
+
+#[derive(Debug, Default)]
+pub struct GenericSysctlResource<T: Connection> {
+    pub(super) options: SharedOptions,
+    pub(super) connect: T,
+}
+
+impl<T: Connection> GenericSysctlResource<T> {
+    pub fn new(options: SharedOptions, connect: T) -> Self {
+        Self { options, connect }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ResourceState<'a, T>
+where
+    T: Connection,
+{
+    #[serde(borrow = "'a")]
+    pub id: ValueString<'a>,
+    pub name: ValueString<'a>,
+    pub value: ValueString<'a>,
+    pub persist: ValueBool,
+    pub conf_file: ValueString<'a>,
+    #[serde(with = "value::serde_as_vec")]
+    pub connect: Value<T::Config<'a>>,
+}
+
+impl<'a, T: Connection> ResourceState<'a, T> {
+    fn env(&self) -> BTreeMap<&str, &str> {
+        let persist = if self.persist.unwrap_or(true) {
+            "true"
+        } else {
+            "false"
+        };
+        BTreeMap::from([
+            ("SYSCTL_NAME", self.name.as_str()),
+            ("SYSCTL_VALUE", self.value.as_str()),
+            ("SYSCTL_PERSIST", persist),
+            ("SYSCTL_FILE", self.conf_file.as_str()),
+        ])
+    }
+}
+
+#[async_trait]
+impl<T> Resource for GenericSysctlResource<T>
+where
+    T: Connection,
+    T: Debug,
+    T: Clone,
+{
+    type State<'a> = ResourceState<'a, T>;
+    type PrivateState<'a> = ValueEmpty;
+    type ProviderMetaState<'a> = ValueEmpty;
+
+    fn schema(&self, _diags: &mut Diagnostics) -> Option<Schema> {
+        Some(Schema {
+            version: 1,
+            block: Block {
+                attributes: map! {
+                    "id" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Id of the sysctl resource"),
+                        constraint: AttributeConstraint::Computed,
+                        ..Default::default()
+                    },
+                    "name" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Name of the kernel parameter (eg: net.ipv4.ip_forward)"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "value" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("Value of the kernel parameter"),
+                        constraint: AttributeConstraint::Required,
+                        ..Default::default()
+                    },
+                    "persist" => Attribute {
+                        attr_type: AttributeType::Bool,
+                        description: Description::plain("Whether the value should be persisted in `conf_file` to survive reboots (default: true)"),
+                        constraint: AttributeConstraint::OptionalComputed,
+                        ..Default::default()
+                    },
+                    "conf_file" => Attribute {
+                        attr_type: AttributeType::String,
+                        description: Description::plain("File where the value is persisted (default: /etc/sysctl.d/90-<name>.conf)"),
+                        constraint: AttributeConstraint::OptionalComputed,
+                        ..Default::default()
+                    },
+                },
+                blocks: map! {
+                    "connect" => NestedBlock::Optional(Block {
+                        attributes: T::schema(),
+                        description: Description::plain("Connection configuration"),
+                        ..Default::default()
+                    }),
+                },
+                description: Description::plain("Kernel parameter set at runtime with `sysctl -w` and persisted in a sysctl.d file"),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn validate<'a>(&self, diags: &mut Diagnostics, config: Self::State<'a>) -> Option<()> {
+        if let Value::Value(connect) = &config.connect {
+            _ = self
+                .connect
+                .validate(diags, AttributePath::new("connect").index(0), connect)
+                .await;
+        }
+
+        match &config.name {
+            Value::Value(name) => {
+                if name.is_empty() {
+                    diags.error_short("`name` should not be empty", AttributePath::new("name"));
+                } else if !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '/' | '*'))
+                {
+                    diags.error(
+                        "Invalid `name`",
+                        format!("`{name}` is not a valid kernel parameter name."),
+                        AttributePath::new("name"),
+                    );
+                }
+            }
+            Value::Null => {
+                diags.error_short("`name` should not be null", AttributePath::new("name"));
+            }
+            Value::Unknown => (),
+        }
+
+        if config.value.is_null() {
+            diags.error_short("`value` should not be null", AttributePath::new("value"));
+        }
+
+        if let Value::Value(conf_file) = &config.conf_file {
+            if conf_file.is_empty() {
+                diags.error_short(
+                    "`conf_file` should not be empty",
+                    AttributePath::new("conf_file"),
+                );
+            }
+        }
+
+        if diags.errors.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn read<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        mut state: Self::State<'a>,
+        private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        let output = run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_sysctl",
+            "read",
+            READ_SCRIPT,
+            &state.env(),
+            AttributePath::new("value"),
+        )
+        .await?;
+
+        let (persisted, live) = output.split_once('\n').unwrap_or((output.as_str(), ""));
+        let live = live.trim_end_matches('\n');
+
+        // sysctl separates multiple fields with tabs: compare fields instead of the raw strings
+        if !live
+            .split_whitespace()
+            .eq(state.value.as_str().split_whitespace())
+        {
+            state.value = Value::Value(live.to_owned().into());
+        }
+        if state.persist.unwrap_or(true) && persisted != "true" {
+            state.persist = Value::Value(false);
+        }
+
+        Some((state, private_state))
+    }
+
+    async fn plan_create<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        proposed_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        let mut state = proposed_state;
+        self.normalize(&mut state);
+        Some((state, Default::default()))
+    }
+    async fn plan_update<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        prior_state: Self::State<'a>,
+        proposed_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        prior_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(
+        Self::State<'a>,
+        Self::PrivateState<'a>,
+        Vec<tf_provider::AttributePath>,
+    )> {
+        let mut state = proposed_state;
+        self.normalize(&mut state);
+
+        let mut trigger_replace = Vec::new();
+        if state.name != prior_state.name {
+            trigger_replace.push(AttributePath::new("name"));
+        }
+        if state.conf_file != prior_state.conf_file {
+            trigger_replace.push(AttributePath::new("conf_file"));
+        }
+        Some((state, prior_private_state, trigger_replace))
+    }
+
+    async fn plan_destroy<'a>(
+        &self,
+        _diags: &mut Diagnostics,
+        _prior_state: Self::State<'a>,
+        prior_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<Self::PrivateState<'a>> {
+        Some(prior_private_state)
+    }
+
+    async fn create<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        planned_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        check_writable(diags, &self.options, "create")?;
+        let mut state = planned_state;
+        self.normalize(&mut state);
+        self.apply(diags, &state, "create").await?;
+        Some((state, planned_private_state))
+    }
+    async fn update<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        _prior_state: Self::State<'a>,
+        planned_state: Self::State<'a>,
+        _config_state: Self::State<'a>,
+        planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<(Self::State<'a>, Self::PrivateState<'a>)> {
+        check_writable(diags, &self.options, "update")?;
+        let mut state = planned_state;
+        self.normalize(&mut state);
+        self.apply(diags, &state, "update").await?;
+        Some((state, planned_private_state))
+    }
+    async fn destroy<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: Self::State<'a>,
+        _planned_private_state: Self::PrivateState<'a>,
+        _provider_meta_state: Self::ProviderMetaState<'a>,
+    ) -> Option<()> {
+        check_writable(diags, &self.options, "destroy")?;
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_sysctl",
+            "destroy",
+            DESTROY_SCRIPT,
+            &state.env(),
+            AttributePath::new("conf_file"),
+        )
+        .await?;
+        Some(())
+    }
+}
+
+impl<T: Connection> GenericSysctlResource<T> {
+    fn normalize(&self, state: &mut ResourceState<'_, T>) {
+        state.id = match &state.name {
+            Value::Value(name) => Value::Value(name.clone()),
+            _ => Value::Unknown,
+        };
+        if state.persist.is_null() {
+            state.persist = Value::Value(true);
+        }
+        if state.conf_file.is_null() {
+            state.conf_file = match &state.name {
+                Value::Value(name) => {
+                    Value::Value(format!("/etc/sysctl.d/90-{}.conf", name.replace('/', ".")).into())
+                }
+                _ => Value::Unknown,
+            };
+        }
+    }
+    async fn apply<'a>(
+        &self,
+        diags: &mut Diagnostics,
+        state: &ResourceState<'a, T>,
+        phase: &str,
+    ) -> Option<()> {
+        let default_connect_config = Default::default();
+        let connect_config = state.connect.as_ref().unwrap_or(&default_connect_config);
+
+        run_script(
+            diags,
+            &self.connect,
+            connect_config,
+            "ssh_sysctl",
+            phase,
+            APPLY_SCRIPT,
+            &state.env(),
+            AttributePath::new("value"),
+        )
+        .await?;
+        Some(())
+    }
+}
@@ -0,0 +1,182 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use futures::Future;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tf_provider::schema::{
+    Attribute, AttributeConstraint, AttributeType, Block, Description, NestedBlock,
+};
+use tf_provider::value::{Value, ValueString};
+use tf_provider::{map, AttributePath, Diagnostics};
+
+/// Deadlines of the phases of a resource, as in the `timeouts` block of the official providers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct StateTimeouts<'a> {
+    #[serde(borrow = "'a")]
+    pub create: ValueString<'a>,
+    pub update: ValueString<'a>,
+    pub delete: ValueString<'a>,
+    pub read: ValueString<'a>,
+}
+
+lazy_static! {
+    pub static ref TIMEOUTS_BLOCK: NestedBlock = NestedBlock::Optional(Block {
+        attributes: map! {
+            "create" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Maximum duration of the creation, eg: `30s`, `10m` or `1h30m` (default: no timeout)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "update" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Maximum duration of an update, eg: `30s`, `10m` or `1h30m` (default: no timeout)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "delete" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Maximum duration of the deletion, eg: `30s`, `10m` or `1h30m` (default: no timeout)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+            "read" => Attribute {
+                attr_type: AttributeType::String,
+                description: Description::plain("Maximum duration of a refresh, eg: `30s`, `10m` or `1h30m` (default: no timeout)"),
+                constraint: AttributeConstraint::Optional,
+                ..Default::default()
+            },
+        },
+        description: Description::plain("Timeouts of the operations on the resource"),
+        ..Default::default()
+    });
+}
+
+impl StateTimeouts<'_> {
+    fn phase(&self, phase: &str) -> &ValueString<'_> {
+        match phase {
+            "create" => &self.create,
+            "update" => &self.update,
+            "delete" => &self.delete,
+            _ => &self.read,
+        }
+    }
+}
+
+/// Check the durations of a `timeouts` block are valid
+pub fn validate(
+    diags: &mut Diagnostics,
+    attr_path: AttributePath,
+    timeouts: &Value<StateTimeouts>,
+) {
+    let Value::Value(timeouts) = timeouts else {
+        return;
+    };
+    for phase in ["create", "update", "delete", "read"] {
+        if let Value::Value(duration) = timeouts.phase(phase) {
+            if let Err(err) = parse_duration(duration) {
+                diags.error(
+                    format!("Invalid `timeouts.{phase}`"),
+                    err,
+                    attr_path.clone().attribute(phase),
+                );
+            }
+        }
+    }
+}
+
+/// Timeout of a phase (`create`, `update`, `delete` or `read`), if any
+pub fn get(timeouts: &Value<StateTimeouts>, phase: &str) -> Option<Duration> {
+    let duration = timeouts.as_ref_option()?.phase(phase).as_ref_option()?;
+    parse_duration(duration).ok()
+}
+
+/// Run the operation of a phase, cancelling it if it takes longer than its timeout
+///
+/// When the timeout is reached, commands in progress are killed and transfers are interrupted.
+pub async fn run<F, T>(timeout: Option<Duration>, operation: F) -> Result<Option<T>, Duration>
+where
+    F: Future<Output = Option<T>>,
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, operation)
+            .await
+            .map_err(|_| timeout),
+        None => Ok(operation.await),
+    }
+}
+
+/// Report the timeout of a phase, as returned by `run`
+pub fn report<T>(
+    diags: &mut Diagnostics,
+    phase: &str,
+    result: Result<Option<T>, Duration>,
+) -> Option<T> {
+    match result {
+        Ok(result) => result,
+        Err(timeout) => {
+            diags.error(
+                format!("`{phase}` timed out"),
+                format!(
+                    "The operation did not complete within {} seconds.",
+                    timeout.as_secs_f64()
+                ),
+                AttributePath::new("timeouts")
+                    .index(0)
+                    .attribute(phase.to_owned()),
+            );
+            None
+        }
+    }
+}
+
+/// Parse a duration in the format of Terraform, like `1h30m`, `90s` or `1.5h`
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!("`{duration}` is not a valid duration: it must be a sequence of numbers with a unit among `h`, `m`, `s` and `ms`, eg: `1h30m`.")
+    };
+    if duration.is_empty() {
+        return Err(invalid());
+    }
+    let mut total = 0.0;
+    let mut rest = duration;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(invalid)?;
+        let number = rest[..number_end].parse::<f64>().map_err(|_| invalid())?;
+        rest = &rest[number_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit_end..];
+        total += number * seconds;
+    }
+    if total <= 0.0 {
+        return Err(format!("`{duration}` must be a positive duration."));
+    }
+    Ok(Duration::from_secs_f64(total))
+}
@@ -0,0 +1,184 @@
+// This file is part of the terraform-provider-generic project
+//
+// Copyright (C) ANEO, 2024-2024. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::limits;
+
+/// Size of the chunks accounted against the bandwidth cap
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Progress is logged every time this share of the transfer is done (in percent)...
+const PROGRESS_STEP: u64 = 5;
+/// ... or when no progress has been logged for this long
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Progress of a transfer, logged periodically so long transfers do not look hung
+///
+/// The progress is also logged by a timer, so a transfer stalled waiting for data is reported as well.
+#[derive(Debug)]
+pub struct Progress {
+    state: Arc<Mutex<ProgressState>>,
+    timer: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+struct ProgressState {
+    label: String,
+    total: Option<u64>,
+    transferred: u64,
+    reported: u64,
+    reported_at: Instant,
+}
+
+impl ProgressState {
+    fn report(&mut self) {
+        log::info!("{}: {self}", self.label);
+        self.reported = self.transferred;
+        self.reported_at = Instant::now();
+    }
+}
+
+fn lock(state: &Mutex<ProgressState>) -> MutexGuard<'_, ProgressState> {
+    match state.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl Progress {
+    /// Track the transfer of `total` bytes, if known in advance
+    pub fn new(label: impl Into<String>, total: Option<u64>) -> Self {
+        let state = Arc::new(Mutex::new(ProgressState {
+            label: label.into(),
+            total,
+            transferred: 0,
+            reported: 0,
+            reported_at: Instant::now(),
+        }));
+        let timer = tokio::spawn({
+            let state = state.clone();
+            async move {
+                loop {
+                    let next = lock(&state).reported_at + PROGRESS_INTERVAL;
+                    tokio::time::sleep_until(next).await;
+                    let mut guard = lock(&state);
+                    if guard.reported_at.elapsed() >= PROGRESS_INTERVAL {
+                        guard.report();
+                    }
+                }
+            }
+        });
+        Self { state, timer }
+    }
+
+    /// Account `bytes` more bytes, and log the progress at every step
+    pub fn advance(&mut self, bytes: u64) {
+        let mut state = lock(&self.state);
+        state.transferred += bytes;
+        let step = state.total.map(|total| total * PROGRESS_STEP / 100);
+        if step.is_some_and(|step| state.transferred - state.reported >= step.max(1)) {
+            state.report();
+        }
+    }
+
+    /// Number of bytes transferred so far
+    pub fn transferred(&self) -> u64 {
+        lock(&self.state).transferred
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.timer.abort();
+    }
+}
+
+impl std::fmt::Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", lock(&self.state))
+    }
+}
+
+impl std::fmt::Display for ProgressState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.total {
+            Some(total) if total > 0 => write!(
+                f,
+                "{} of {total} bytes transferred ({}%)",
+                self.transferred,
+                self.transferred * 100 / total
+            ),
+            _ => write!(f, "{} bytes transferred", self.transferred),
+        }
+    }
+}
+
+/// Copy a stream like `tokio::io::copy`, within the bandwidth cap
+pub async fn copy<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    progress: &mut Progress,
+) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    copy_chunks(reader, writer, progress, true).await
+}
+
+/// Copy a stream like `tokio::io::copy`, only reporting its progress
+pub async fn copy_with_progress<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    progress: &mut Progress,
+) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    copy_chunks(reader, writer, progress, false).await
+}
+
+async fn copy_chunks<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    progress: &mut Progress,
+    throttled: bool,
+) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buffer).await?;
+        if n == 0 {
+            writer.flush().await?;
+            return Ok(progress.transferred());
+        }
+        if throttled {
+            limits::throttle(n).await;
+        }
+        writer.write_all(&buffer[..n]).await?;
+        progress.advance(n as u64);
+    }
+}
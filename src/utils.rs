@@ -14,11 +14,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use tokio::io::AsyncReadExt;
+use tokio::process::Child;
+use tokio::task::JoinHandle;
 
-use tf_provider::{schema::Schema, value::Value, AttributePath, Diagnostics};
+use tf_provider::{
+    schema::Schema,
+    value::{Value, ValueList},
+    AttributePath, Diagnostics,
+};
+
+use crate::cmd::StateTunnel;
 
 pub(crate) trait WithSchema {
     fn schema() -> Schema;
@@ -36,6 +47,15 @@ pub(crate) trait WithNormalize {
 pub(crate) trait WithCmd {
     fn cmd(&self) -> &str;
     fn dir(&self) -> &str;
+    /// Command as executed, with its priority settings applied
+    fn prioritized_cmd(&self) -> Cow<'_, str>;
+    /// Positional parameters of the command, `$1`, `$2`...
+    fn args(&self) -> Vec<&str>;
+    /// Whether the command runs in a transient unit, surviving the loss of the connection
+    fn transient_unit(&self) -> bool;
+    /// Interval and timeout of the polling, when the command is detached
+    fn detach(&self) -> Option<(Duration, Option<Duration>)>;
+    fn tunnels(&self) -> &ValueList<Value<StateTunnel<'_>>>;
 }
 
 impl<T: WithCmd> WithCmd for Value<T> {
@@ -45,11 +65,33 @@ impl<T: WithCmd> WithCmd for Value<T> {
     fn dir(&self) -> &str {
         self.as_ref().map_or("", WithCmd::dir)
     }
+    fn prioritized_cmd(&self) -> Cow<'_, str> {
+        self.as_ref()
+            .map_or(Cow::Borrowed(""), WithCmd::prioritized_cmd)
+    }
+    fn args(&self) -> Vec<&str> {
+        self.as_ref().map_or(Vec::new(), WithCmd::args)
+    }
+    fn transient_unit(&self) -> bool {
+        self.as_ref().map_or(false, WithCmd::transient_unit)
+    }
+    fn detach(&self) -> Option<(Duration, Option<Duration>)> {
+        self.as_ref().map_or(None, WithCmd::detach)
+    }
+    fn tunnels(&self) -> &ValueList<Value<StateTunnel<'_>>> {
+        self.as_ref().map_or(&Value::Null, WithCmd::tunnels)
+    }
 }
 
 pub(crate) trait WithRead: WithCmd {
     fn strip_trailing_newline(&self) -> bool;
     fn faillible(&self) -> bool;
+    fn pattern(&self) -> &str;
+    fn trim(&self) -> bool;
+    fn format(&self) -> &str;
+    fn transform(&self) -> &str;
+    fn default_value(&self) -> Option<&str>;
+    fn to_file(&self) -> &str;
 }
 
 impl<T: WithRead> WithRead for Value<T> {
@@ -59,6 +101,24 @@ impl<T: WithRead> WithRead for Value<T> {
     fn faillible(&self) -> bool {
         self.as_ref().map_or(true, WithRead::faillible)
     }
+    fn pattern(&self) -> &str {
+        self.as_ref().map_or("", WithRead::pattern)
+    }
+    fn trim(&self) -> bool {
+        self.as_ref().map_or(false, WithRead::trim)
+    }
+    fn format(&self) -> &str {
+        self.as_ref().map_or("", WithRead::format)
+    }
+    fn transform(&self) -> &str {
+        self.as_ref().map_or("", WithRead::transform)
+    }
+    fn default_value(&self) -> Option<&str> {
+        self.as_ref().map_or(None, WithRead::default_value)
+    }
+    fn to_file(&self) -> &str {
+        self.as_ref().map_or("", WithRead::to_file)
+    }
 }
 
 pub(crate) trait WithEnv {
@@ -126,3 +186,19 @@ where
 pub trait AsyncDrop {
     async fn async_drop(&mut self) -> () {}
 }
+
+/// Collect the stderr of a child while its stdout is being read
+///
+/// Reading stderr only after stdout would block the child as soon as stderr fills the pipe.
+pub(crate) fn collect_stderr(child: &mut Child) -> JoinHandle<Vec<u8>> {
+    let stderr = child.stderr.take();
+    tokio::spawn(async move {
+        let mut buffer = Vec::new();
+        if let Some(mut stderr) = stderr {
+            if let Err(err) = stderr.read_to_end(&mut buffer).await {
+                log::warn!("Could not read stderr of command: {err}");
+            }
+        }
+        buffer
+    })
+}